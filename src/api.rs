@@ -7,18 +7,19 @@
  * - 获取统计数据
  * 
  * 主要组件:
- * - get_account_balance函数 (第37-55行): 查询账户余额
- * - get_account_transactions函数 (第57-117行): 查询账户的交易历史
- * - get_transaction_by_index函数 (第119-135行): 查询特定交易详情
- * - get_latest_transactions函数 (第137-166行): 获取最新的交易记录
- * - get_latest_transaction_index函数 (第168-186行): 获取最新的交易索引
- * - search_transactions函数 (第188-220行): 多条件查询交易
- * - get_all_accounts函数 (第222-253行): 获取所有账户列表
- * - get_total_supply函数 (第255-264行): 获取代币总供应量
- * - get_transaction_count函数 (第266-275行): 统计交易总数
- * - get_account_count函数 (第277-286行): 统计账户总数
- * - get_active_accounts函数 (第288-347行): 获取活跃账户列表
- * - get_transactions_by_index_range函数 (第350-396行): 按索引范围批量获取交易
+ * - get_account_balance函数: 查询账户余额
+ * - get_account_transactions函数: 查询账户的交易历史（支持游标分页）
+ * - get_transaction_by_index函数: 查询特定交易详情
+ * - get_latest_transactions函数: 获取最新的交易记录
+ * - get_latest_transaction_index函数: 获取最新的交易索引
+ * - search_transactions函数: 多条件查询交易
+ * - get_all_accounts函数: 获取所有账户列表（支持游标分页）
+ * - get_total_supply函数: 获取代币总供应量
+ * - get_transaction_count函数: 统计交易总数
+ * - get_account_count函数: 统计账户总数
+ * - get_active_accounts函数: 获取活跃账户列表
+ * - get_transactions_by_index_range函数: 按索引范围批量获取交易
+ * - time_index_range函数: 将时间区间转换为交易索引区间，时间过滤据此使用交易索引查询
  */
 
 use std::error::Error;
@@ -32,15 +33,19 @@ use futures::stream::TryStreamExt;
 use mongodb::options::FindOneOptions;
 use crate::db::supply;
 use crate::db::transactions as tx_db;
+use crate::db::cold_storage::cold_storage_for;
+use crate::db::account_transactions::get_account_transaction_indices;
+use crate::utils::{parse_token_amount, to_sortable_amount};
+use crate::pagination::{keyset_filter, query_sort, Cursor, Direction};
 
-/// API模块，提供所有对外查询功能
-/// 包括地址、交易和余额的相关查询
+// API模块，提供所有对外查询功能
+// 包括地址、交易和余额的相关查询
 
 /// 查询账户余额
 pub async fn get_account_balance(
     balances_col: &Collection<Document>,
     account: &str,
-) -> Result<String, Box<dyn Error>> {
+) -> Result<String, Box<dyn Error + Send + Sync>> {
     let normalized_account = normalize_account_id(account);
     debug!("查询账户 {} 余额", normalized_account);
     
@@ -58,55 +63,48 @@ pub async fn get_account_balance(
 
 /// 查询账户的交易历史
 pub async fn get_account_transactions(
-    accounts_col: &Collection<Document>,
+    account_tx_col: &Collection<Document>,
     tx_col: &Collection<Document>,
     account: &str,
     limit: Option<i64>,
     skip: Option<i64>,
-) -> Result<Vec<Transaction>, Box<dyn Error>> {
+    cursor: Option<&Cursor>,
+    index_range: Option<Document>,
+) -> Result<Vec<Transaction>, Box<dyn Error + Send + Sync>> {
     let normalized_account = normalize_account_id(account);
     debug!("查询账户 {} 的交易历史", normalized_account);
     
-    // 从账户集合获取交易索引列表
-    let account_doc = match accounts_col
-        .find_one(doc! { "account": &normalized_account }, None)
-        .await?
-    {
-        Some(doc) => doc,
-        None => return Ok(Vec::new()), // 账户不存在，返回空列表
+    // 设置分页参数
+    let limit_val = limit.unwrap_or(50).max(0) as u64;
+    let skip_val = skip.unwrap_or(0).max(0) as u64;
+    
+    // 游标分页：向后翻页查询更小的索引，向前翻页按索引升序查询更大的索引；
+    // 游标条件（$lt / $gt）与时间条件对应的索引区间（$gte / $lte）合并
+    let (index_range, ascending) = match cursor {
+        Some(cursor) => {
+            let keyset = keyset_filter(&[("index", -1)], cursor)?;
+            let mut range = index_range.unwrap_or_default();
+            if let Ok(bound) = keyset.get_document("index") {
+                range.extend(bound.clone());
+            }
+            (Some(range), cursor.direction == Direction::Prev)
+        },
+        None => (index_range, false),
     };
     
-    let indices = match account_doc.get_array("transaction_indices") {
-        Ok(indices) => indices.clone(),
-        Err(_) => return Ok(Vec::new()), // 没有交易记录
-    };
+    // 从账户-交易边集合分页获取交易索引
+    let tx_indices = get_account_transaction_indices(
+        account_tx_col, &normalized_account, index_range, ascending, skip_val, limit_val
+    ).await?;
     
-    if indices.is_empty() {
-        return Ok(Vec::new());
+    if tx_indices.is_empty() {
+        return Ok(Vec::new()); // 账户不存在或没有交易记录
     }
     
-    // 将BSON数组转换为i64数组
-    let tx_indices: Vec<i64> = indices.iter()
-        .filter_map(|idx| idx.as_i64())
-        .collect();
-    
-    // 设置分页参数
-    let limit_val = limit.unwrap_or(50);
-    let skip_val = skip.unwrap_or(0);
-    
-    // 获取交易记录
-    let options = FindOptions::builder()
-        .sort(doc! { "index": -1 })
-        .limit(limit_val)
-        .skip(Some(skip_val as u64))
-        .build();
+    let tx_indices: Vec<i64> = tx_indices.iter().map(|i| *i as i64).collect();
     
-    let transactions_cursor = tx_col
-        .find(doc! { "index": { "$in": &tx_indices } }, options)
-        .await?;
-    
-    // 收集符合条件的交易
-    let doc_transactions: Vec<Document> = transactions_cursor.try_collect().await?;
+    // 获取交易记录（已归档的交易从冷存储读取）
+    let doc_transactions = tx_db::find_transactions_by_indices(tx_col, &tx_indices, ascending).await?;
     
     // 将Document转换为Transaction
     let mut transactions: Vec<Transaction> = Vec::with_capacity(doc_transactions.len());
@@ -122,10 +120,10 @@ pub async fn get_account_transactions(
 pub async fn get_transaction_by_index(
     tx_col: &Collection<Document>,
     index: u64,
-) -> Result<Option<Transaction>, Box<dyn Error>> {
+) -> Result<Option<Transaction>, Box<dyn Error + Send + Sync>> {
     debug!("查询交易索引 {} 的详情", index);
     
-    let tx_doc = tx_col.find_one(doc! { "index": index as i64 }, None).await?;
+    let tx_doc = tx_db::find_transaction_by_index(tx_col, index).await?;
     
     match tx_doc {
         Some(doc) => {
@@ -141,7 +139,7 @@ pub async fn get_transaction_by_index(
 pub async fn get_latest_transactions(
     tx_col: &Collection<Document>,
     limit: Option<i64>,
-) -> Result<Vec<Transaction>, Box<dyn Error>> {
+) -> Result<Vec<Transaction>, Box<dyn Error + Send + Sync>> {
     let limit_val = limit.unwrap_or(20); // 默认获取20条
     debug!("获取最新的 {} 条交易", limit_val);
     
@@ -171,35 +169,63 @@ pub async fn get_latest_transactions(
 #[allow(dead_code)]
 pub async fn get_latest_transaction_index(
     tx_col: &Collection<Document>,
-) -> Result<Option<u64>, Box<dyn Error>> {
+) -> Result<Option<u64>, Box<dyn Error + Send + Sync>> {
     debug!("获取最新交易索引");
     
     let find_opts = FindOneOptions::builder()
         .sort(doc! { "index": -1 })
         .build();
     
+    let mut latest = None;
     if let Some(doc) = tx_col.find_one(doc! {}, find_opts).await? {
-        if let Some(index) = doc.get_i64("index").ok() {
-            return Ok(Some(index as u64));
+        if let Ok(index) = doc.get_i64("index") {
+            latest = Some(index as u64);
         }
     }
     
-    Ok(None)
+    let archived = cold_storage_for(tx_col).and_then(|storage| storage.archived_until());
+    Ok(latest.max(archived))
+}
+
+/// 根据代币单位的金额上下限构建 amount_sort 过滤条件
+///
+/// 两个参数都为空时返回 None。
+pub fn build_amount_filter(
+    min_amount: Option<&str>,
+    max_amount: Option<&str>,
+    decimals: u8,
+) -> Result<Option<Document>, Box<dyn Error + Send + Sync>> {
+    let mut range = Document::new();
+    if let Some(min) = min_amount {
+        let min_units = parse_token_amount(min, decimals)?;
+        range.insert("$gte", to_sortable_amount(&min_units));
+    }
+    if let Some(max) = max_amount {
+        let max_units = parse_token_amount(max, decimals)?;
+        range.insert("$lte", to_sortable_amount(&max_units));
+    }
+    
+    if range.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(doc! { "amount_sort": range }))
+    }
 }
 
 /// 搜索交易（多条件查询）
 pub async fn search_transactions(
     tx_col: &Collection<Document>,
     query: Document,
+    sort: Document,
     limit: Option<i64>,
     skip: Option<i64>,
-) -> Result<Vec<Transaction>, Box<dyn Error>> {
+) -> Result<Vec<Transaction>, Box<dyn Error + Send + Sync>> {
     let limit_val = limit.unwrap_or(50);
     let skip_val = skip.unwrap_or(0);
     debug!("搜索交易，条件：{:?}, 限制：{}, 跳过：{}", query, limit_val, skip_val);
     
     let options = FindOptions::builder()
-        .sort(doc! { "index": -1 })
+        .sort(sort)
         .limit(limit_val)
         .skip(Some(skip_val as u64))
         .build();
@@ -226,20 +252,27 @@ pub async fn get_all_accounts(
     accounts_col: &Collection<Document>,
     limit: Option<i64>,
     skip: Option<i64>,
-) -> Result<Vec<String>, Box<dyn Error>> {
+    cursor: Option<&Cursor>,
+) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
     let limit_val = limit.unwrap_or(100);
     let skip_val = skip.unwrap_or(0);
     debug!("获取所有账户，限制：{}, 跳过：{}", limit_val, skip_val);
     
+    // 按账户升序排列，游标分页时从上一页的最后（或第一个）账户继续
+    let sort_fields = [("account", 1)];
+    let filter = match cursor {
+        Some(cursor) => keyset_filter(&sort_fields, cursor)?,
+        None => doc! {},
+    };
     let options = FindOptions::builder()
-        .sort(doc! { "account": 1 })
+        .sort(query_sort(&sort_fields, cursor))
         .limit(limit_val)
         .skip(Some(skip_val as u64))
         .projection(doc! { "account": 1, "_id": 0 })
         .build();
     
     let accounts_cursor = accounts_col
-        .find(doc! {}, options)
+        .find(filter, options)
         .await?;
     
     // 收集账户列表
@@ -257,7 +290,7 @@ pub async fn get_all_accounts(
 /// 获取代币总供应量（通过所有账户余额计算）
 pub async fn get_total_supply(
     supply_col: &Collection<Document>,
-) -> Result<String, Box<dyn Error>> {
+) -> Result<String, Box<dyn Error + Send + Sync>> {
     debug!("获取代币总供应量");
     if let Some(value) = supply::get_stored_total_supply(supply_col).await? {
         return Ok(value);
@@ -268,18 +301,20 @@ pub async fn get_total_supply(
 /// 统计交易总数
 pub async fn get_transaction_count(
     tx_col: &Collection<Document>,
-) -> Result<u64, Box<dyn Error>> {
+) -> Result<u64, Box<dyn Error + Send + Sync>> {
     debug!("统计交易总数");
     
     let count = tx_col.count_documents(doc! {}, None).await?;
+    // 加上已归档到冷存储的交易
+    let archived = cold_storage_for(tx_col).map(|storage| storage.archived_count()).unwrap_or(0);
     
-    Ok(count)
+    Ok(count + archived)
 }
 
 /// 统计账户总数
 pub async fn get_account_count(
     accounts_col: &Collection<Document>,
-) -> Result<u64, Box<dyn Error>> {
+) -> Result<u64, Box<dyn Error + Send + Sync>> {
     debug!("统计账户总数");
     
     let count = accounts_col.count_documents(doc! {}, None).await?;
@@ -291,7 +326,7 @@ pub async fn get_account_count(
 pub async fn get_active_accounts(
     tx_col: &Collection<Document>,
     limit: Option<i64>,
-) -> Result<Vec<String>, Box<dyn Error>> {
+) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
     let limit_val = limit.unwrap_or(1000); // 默认获取最近1000条交易
     debug!("获取活跃账户（最近 {} 条交易）", limit_val);
     
@@ -362,7 +397,7 @@ pub async fn get_transactions_by_index_range(
     start_index: u64,
     end_index: u64,
     limit: Option<i64>,
-) -> Result<Vec<Transaction>, Box<dyn Error>> {
+) -> Result<Vec<Transaction>, Box<dyn Error + Send + Sync>> {
     // 最大允许返回 300 条
     const MAX_LIMIT: i64 = 300;
     let limit_val = limit.unwrap_or(MAX_LIMIT).min(MAX_LIMIT);
@@ -394,3 +429,61 @@ pub async fn get_transactions_by_index_range(
 
     Ok(txs)
 }
+
+/// 时间条件对应的交易索引区间
+#[derive(Debug, Clone)]
+pub enum TimeIndexRange {
+    /// 未指定时间条件
+    Unbounded,
+    /// 交易索引条件，例如 `{"$gte": 100, "$lte": 200}`
+    Range(Document),
+    /// 时间区间内没有交易
+    Empty,
+}
+
+impl TimeIndexRange {
+    /// 交易索引上的查询条件，时间区间内没有交易时返回不匹配任何索引的条件（`$in: []`），
+    /// 可以与分页游标的 $lt / $gt 条件合并
+    pub fn into_condition(self) -> Option<Document> {
+        match self {
+            TimeIndexRange::Unbounded => None,
+            TimeIndexRange::Range(range) => Some(range),
+            TimeIndexRange::Empty => Some(doc! { "$in": [] }),
+        }
+    }
+}
+
+/// 将时间区间 [from_time, to_time]（纳秒，含两端）转换为交易索引区间
+///
+/// 账本交易的时间戳随索引单调不减，时间区间对应一段连续的索引：起点为第一笔不早于 from_time 的交易，
+/// 终点为第一笔晚于 to_time 的交易的前一笔。转换后按交易索引（或账户-交易边上的索引）查询即可。
+pub async fn time_index_range(
+    tx_col: &Collection<Document>,
+    from_time: Option<u64>,
+    to_time: Option<u64>,
+) -> Result<TimeIndexRange, Box<dyn Error + Send + Sync>> {
+    let mut range = Document::new();
+    if let Some(from_time) = from_time {
+        match tx_db::find_index_at_time(tx_col, from_time).await? {
+            Some(start) => { range.insert("$gte", start as i64); },
+            None => return Ok(TimeIndexRange::Empty),
+        }
+    }
+    if let Some(to_time) = to_time {
+        // 没有晚于 to_time 的交易时不限制终点
+        if let Some(after) = tx_db::find_index_at_time(tx_col, to_time.saturating_add(1)).await? {
+            if after == 0 {
+                return Ok(TimeIndexRange::Empty);
+            }
+            range.insert("$lte", (after - 1) as i64);
+        }
+    }
+    debug!("时间区间 {:?} - {:?} 对应的交易索引条件: {:?}", from_time, to_time, range);
+
+    if let (Ok(start), Ok(end)) = (range.get_i64("$gte"), range.get_i64("$lte")) {
+        if start > end {
+            return Ok(TimeIndexRange::Empty);
+        }
+    }
+    Ok(if range.is_empty() { TimeIndexRange::Unbounded } else { TimeIndexRange::Range(range) })
+}
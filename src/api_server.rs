@@ -6,6 +6,18 @@
  * - 提供账户信息查询API
  * - 提供数据统计API
  * - 支持多代币并发查询
+ * - 提供需要 Bearer 令牌的管理接口，在运行时增删、暂停和恢复代币
+ * - CORS 开关、分页上限和管理令牌按请求读取共享配置，配置热更新后立即生效
+ * - 收到停止信号后不再接受新连接，进行中的请求处理完成后退出
+ * - 提供服务状态接口，返回各代币的同步进度、IC 节点健康状态和限流熔断状态
+ * - 高级搜索只接受类型化的搜索条件（见 search），不再透传原始 MongoDB 查询
+ * - 列表接口支持游标分页（见 pagination），响应中返回 page 字段，skip 分页仅用于兼容
+ * - 时间统一以纳秒存储、以 RFC 3339 输出；交易列表和账户交易历史支持 from_time / to_time 过滤，
+ *   提供按时间查找交易索引的接口
+ * - 通过 SSE（/api/stream）和 WebSocket（/api/ws）推送新提交的交易，支持按条件订阅和断线续传
+ * - 提供同步进度接口，返回各代币的同步阶段、落后量、吞吐量和预计剩余时间（见 sync::progress）
+ * - 提供 Prometheus 格式的 /metrics 指标接口
+ * - 只读 API 实例（serve 命令）不连接 IC 网络，管理接口只能查看代币列表
  * 
  * 主要组件:
  * - transaction_to_bson函数 (第34-104行): 将交易对象转换为BSON格式
@@ -17,6 +29,11 @@
  *   - start: 启动API服务器
  *   - build_routes: 构建API路由
  * - 各API处理函数 (第369-975行): 实现不同API端点的具体业务逻辑
+ * - 管理接口处理函数: 代币列表、添加、暂停、恢复和移除（见 sync::manager）
+ * - parse_page_cursor / page_link_base函数: 解析分页游标，生成 next / prev 链接
+ * - parse_time_range / resolve_time_range函数: 解析 from_time / to_time 并转换为交易索引区间
+ * - insert_time函数: 写入纳秒时间戳及其 RFC 3339 格式
+ * - handle_sse_stream / handle_ws_stream函数: SSE 和 WebSocket 新交易推送（见 stream）
  */
 
 use std::sync::Arc;
@@ -26,9 +43,16 @@ use mongodb::bson::{doc, Document};
 use serde::{Serialize, Deserialize};
 use log::{info, error, debug};
 use futures::stream::StreamExt;
+use futures::SinkExt;
 use crate::db::DbConnection;
 use crate::api;
-use crate::models::Transaction;
+use crate::models::{ApiServerConfig, TokenConfig, TokenSource, Transaction};
+use crate::sync::manager::TokenManager;
+use crate::ic::endpoints::EndpointPool;
+use crate::search::{self, SearchPlan, SearchRequest};
+use crate::stream::{self, StreamFilter, StreamParams};
+use crate::pagination::{self, Cursor, PageInfo};
+use crate::utils::{nanos_to_rfc3339, parse_time};
 use crate::error::{ApiError, handle_rejection, map_db_error};
 
 /// 辅助函数：将Transaction对象转换为BSON Document
@@ -49,18 +73,12 @@ fn transaction_to_bson(tx: &Transaction, token_symbol: &str, token_name: &str) -
     } else {
         doc.insert("index", mongodb::bson::Bson::Int64(0));
     }
-    doc.insert("timestamp", mongodb::bson::Bson::Int64(tx.timestamp as i64));
+    // 时间戳为纳秒，同时返回 RFC 3339 格式的日期时间，方便前端显示
+    insert_time(&mut doc, "timestamp", "datetime", tx.timestamp);
     doc.insert("kind", &tx.kind);
     doc.insert("token", token_symbol);
     doc.insert("token_name", token_name);
     
-    // 添加ISO格式的日期时间，方便前端显示
-    // 时间戳是秒级，需要转换为毫秒级
-    let datetime = chrono::DateTime::from_timestamp(tx.timestamp as i64, 0)
-        .map(|dt| dt.to_rfc3339())
-        .unwrap_or_default();
-    doc.insert("datetime", datetime);
-    
     // 根据交易类型添加特定字段
     if tx.kind == "transfer" {
         if let Some(transfer) = &tx.transfer {
@@ -87,6 +105,9 @@ fn transaction_to_bson(tx: &Transaction, token_symbol: &str, token_name: &str) -
                     }
                 }
             }
+            if let Some(created_at_time) = transfer.created_at_time {
+                insert_time(&mut doc, "created_at_time", "created_at_datetime", created_at_time);
+            }
         }
     } else if tx.kind == "mint" {
         if let Some(mint) = &tx.mint {
@@ -107,7 +128,7 @@ fn transaction_to_bson(tx: &Transaction, token_symbol: &str, token_name: &str) -
                 }
             }
             if let Some(created_at_time) = mint.created_at_time {
-                doc.insert("created_at_time", mongodb::bson::Bson::Int64(created_at_time as i64));
+                insert_time(&mut doc, "created_at_time", "created_at_datetime", created_at_time);
             }
         }
     } else if tx.kind == "burn" {
@@ -133,7 +154,7 @@ fn transaction_to_bson(tx: &Transaction, token_symbol: &str, token_name: &str) -
             }
 
             if let Some(created_at_time) = burn.created_at_time {
-                doc.insert("created_at_time", mongodb::bson::Bson::Int64(created_at_time as i64));
+                insert_time(&mut doc, "created_at_time", "created_at_datetime", created_at_time);
             }
         }
     } else if tx.kind == "approve" {
@@ -152,7 +173,7 @@ fn transaction_to_bson(tx: &Transaction, token_symbol: &str, token_name: &str) -
                 doc.insert("expected_allowance", expected.to_string());
             }
             if let Some(expires_at) = approve.expires_at {
-                doc.insert("expires_at", mongodb::bson::Bson::Int64(expires_at as i64));
+                insert_time(&mut doc, "expires_at", "expires_at_datetime", expires_at);
             }
             if let Some(memo) = &approve.memo {
                 let hex_memo = memo.iter()
@@ -167,7 +188,7 @@ fn transaction_to_bson(tx: &Transaction, token_symbol: &str, token_name: &str) -
                 }
             }
             if let Some(created_at_time) = approve.created_at_time {
-                doc.insert("created_at_time", mongodb::bson::Bson::Int64(created_at_time as i64));
+                insert_time(&mut doc, "created_at_time", "created_at_datetime", created_at_time);
             }
         }
     }
@@ -175,6 +196,12 @@ fn transaction_to_bson(tx: &Transaction, token_symbol: &str, token_name: &str) -
     doc
 }
 
+/// 写入纳秒时间戳及其 RFC 3339 格式，所有接口返回的时间都使用这一格式
+fn insert_time(doc: &mut Document, field: &str, datetime_field: &str, nanos: u64) {
+    doc.insert(field, mongodb::bson::Bson::Int64(nanos as i64));
+    doc.insert(datetime_field, nanos_to_rfc3339(nanos));
+}
+
 /// API服务器结构体
 /// 
 /// 提供了REST API接口用于查询账户余额、交易历史等信息。
@@ -182,10 +209,17 @@ fn transaction_to_bson(tx: &Transaction, token_symbol: &str, token_name: &str) -
 pub struct ApiServer {
     /// 数据库连接
     db_conn: Arc<DbConnection>,
-    /// 支持的代币配置列表
-    tokens: Vec<crate::models::TokenConfig>,
+    /// 代币管理器，提供当前支持的代币列表并执行管理操作
+    manager: Arc<TokenManager>,
+    /// API服务器配置，配置热更新时由 reload 模块替换
+    settings: SharedApiConfig,
+    /// IC 节点列表，状态接口返回节点健康状态
+    ic_endpoints: Option<Arc<EndpointPool>>,
 }
 
+/// 可在运行时更新的 API 服务器配置
+pub type SharedApiConfig = Arc<std::sync::RwLock<ApiServerConfig>>;
+
 /// API查询参数
 /// 
 /// 支持分页和代币选择的查询参数
@@ -193,10 +227,52 @@ pub struct ApiServer {
 pub struct QueryParams {
     /// 返回结果的最大条目数（可选，默认值根据不同API而异）
     pub limit: Option<i64>,
-    /// 跳过的条目数，用于分页（可选，默认为0）
+    /// 跳过的条目数，用于分页（可选，默认为0；保留用于兼容，建议使用 cursor）
+    pub skip: Option<i64>,
+    /// 分页游标，取自上一次响应的 page.next_cursor / page.prev_cursor（不能与 skip 同时使用）
+    pub cursor: Option<String>,
+    /// 要查询的代币符号（可选，默认使用配置的第一个代币）
+    pub token: Option<String>,
+    /// 起始时间（含），纳秒时间戳或 RFC 3339（交易列表和账户交易历史支持）
+    pub from_time: Option<String>,
+    /// 结束时间（含），纳秒时间戳或 RFC 3339
+    pub to_time: Option<String>,
+}
+
+/// 按时间查找交易索引的查询参数
+#[derive(Debug, Deserialize, Clone)]
+pub struct BlockAtTimeParams {
+    /// 要查询的代币符号（可选，默认使用配置的第一个代币）
+    pub token: Option<String>,
+    /// 时间，纳秒时间戳或 RFC 3339
+    pub time: String,
+}
+
+/// 高级搜索查询参数
+/// 
+/// 在通用分页参数基础上支持按金额过滤和排序，金额以代币单位表示（如 "1000.5"），
+/// 服务端按代币小数位换算为最小单位后与 amount_sort 字段比较；
+/// 请求体（见 search::SearchRequest）中指定的同名字段优先
+#[derive(Debug, Deserialize, Clone)]
+pub struct SearchParams {
+    /// 返回结果的最大条目数（可选，默认50）
+    pub limit: Option<i64>,
+    /// 跳过的条目数，用于分页（可选，默认为0；保留用于兼容，建议使用 cursor）
     pub skip: Option<i64>,
+    /// 分页游标，取自上一次响应的 page.next_cursor / page.prev_cursor
+    pub cursor: Option<String>,
     /// 要查询的代币符号（可选，默认使用配置的第一个代币）
     pub token: Option<String>,
+    /// 最小金额（含），代币单位
+    pub min_amount: Option<String>,
+    /// 最大金额（含），代币单位
+    pub max_amount: Option<String>,
+    /// 排序方式: index_desc（默认）、amount_desc、amount_asc
+    pub sort: Option<String>,
+    /// 起始时间（含），纳秒时间戳或 RFC 3339
+    pub from_time: Option<String>,
+    /// 结束时间（含），纳秒时间戳或 RFC 3339
+    pub to_time: Option<String>,
 }
 
 /// 通用API响应结构
@@ -210,6 +286,9 @@ pub struct ApiResponse<T> {
     pub data: Option<T>,
     /// 错误信息，失败时包含错误详情
     pub error: Option<String>,
+    /// 分页信息，仅列表接口返回
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page: Option<PageInfo>,
 }
 
 impl<T> ApiResponse<T> {
@@ -219,15 +298,23 @@ impl<T> ApiResponse<T> {
             code: 200,
             data: Some(data),
             error: None,
+            page: None,
         }
     }
 
+    /// 附加分页信息
+    pub fn with_page(mut self, page: PageInfo) -> Self {
+        self.page = Some(page);
+        self
+    }
+
     /// 创建一个错误响应，包含错误信息
     pub fn error(msg: &str) -> Self {
         Self {
             code: 400,
             data: None,
             error: Some(msg.to_string()),
+            page: None,
         }
     }
     
@@ -238,6 +325,7 @@ impl<T> ApiResponse<T> {
             code,
             data: None,
             error: Some(msg.to_string()),
+            page: None,
         }
     }
 }
@@ -247,14 +335,18 @@ impl ApiServer {
     /// 
     /// # 参数
     /// * `db_conn` - 数据库连接实例
-    /// * `tokens` - 支持的代币配置列表
+    /// * `manager` - 代币管理器
+    /// * `settings` - API服务器配置（CORS、分页上限、管理令牌）
+    /// * `ic_endpoints` - IC 节点列表（只读 API 实例不连接 IC 网络，为 None）
     /// 
     /// # 返回
     /// 返回一个新的ApiServer实例
-    pub fn new(db_conn: DbConnection, tokens: Vec<crate::models::TokenConfig>) -> Self {
+    pub fn new(db_conn: DbConnection, manager: Arc<TokenManager>, settings: SharedApiConfig, ic_endpoints: Option<Arc<EndpointPool>>) -> Self {
         Self {
             db_conn: Arc::new(db_conn),
-            tokens,
+            manager,
+            settings,
+            ic_endpoints,
         }
     }
 
@@ -274,19 +366,30 @@ impl ApiServer {
         // 添加CORS支持
         let cors = warp::cors()
             .allow_any_origin()
-            .allow_methods(vec!["GET", "POST", "OPTIONS"])
+            .allow_methods(vec!["GET", "POST", "DELETE", "OPTIONS"])
             .allow_headers(vec!["Content-Type", "Authorization", "Accept"]);
 
-        // 整合所有路由
-        let routes = api_routes
-            .with(cors)
-            .with(warp::log("api"))
-            .recover(handle_rejection);  // 添加统一错误处理
+        // 按请求读取 cors_enabled：开启时走带 CORS 的路由，关闭时走普通路由。
+        // 两条路由各自做统一错误处理，避免一条路由的拒绝导致请求在另一条路由上再执行一次
+        let settings = self.settings.clone();
+        let cors_switch = warp::any()
+            .and_then(move || {
+                let enabled = settings.read().unwrap().cors_enabled;
+                async move {
+                    if enabled { Ok(()) } else { Err(warp::reject::not_found()) }
+                }
+            })
+            .untuple_one();
+        let routes = cors_switch
+            .and(api_routes.clone().recover(handle_rejection).with(cors))
+            .or(api_routes.recover(handle_rejection))
+            .with(warp::log("api"));
 
-        // 启动服务器
-        warp::serve(routes)
-            .run(([0, 0, 0, 0], port))
-            .await;
+        // 启动服务器；收到停止信号后不再接受新连接，等待进行中的请求完成
+        let (_addr, server) = warp::serve(routes)
+            .try_bind_with_graceful_shutdown(([0, 0, 0, 0], port), crate::shutdown::requested())?;
+        server.await;
+        info!("API服务器已停止");
 
         Ok(())
     }
@@ -294,13 +397,13 @@ impl ApiServer {
     /// 构建API路由
     pub fn build_routes(&self) -> BoxedFilter<(impl Reply,)> {
         let db_conn = self.db_conn.clone();
-        let tokens = self.tokens.clone();
+        let manager = self.manager.clone();
 
         // 获取已支持的代币列表
         let supported_tokens = warp::path!("api" / "tokens")
             .and(warp::get())
             .map(move || {
-                let token_list: Vec<_> = tokens.iter().map(|t| {
+                let token_list: Vec<_> = manager.tokens().iter().map(|t| {
                     doc! {
                         "symbol": &t.symbol,
                         "name": &t.name,
@@ -313,132 +416,197 @@ impl ApiServer {
             });
 
         // 获取账户余额
-        let tokens_for_balance = self.tokens.clone();
         let balance = warp::path!("api" / "balance" / String)
             .and(warp::get())
-            .and(warp::query::<QueryParams>())
+            .and(with_query(self.settings.clone()))
             .and(with_db(db_conn.clone()))
-            .and(warp::any().map(move || tokens_for_balance.clone()))
+            .and(with_tokens(self.manager.clone()))
             .and_then(|account, params, db, tokens| async move {
                 handle_get_balance(account, params, db, tokens).await
             });
 
         // 获取账户交易历史
-        let tokens_for_transactions = self.tokens.clone();
         let transactions = warp::path!("api" / "transactions" / String)
             .and(warp::get())
-            .and(warp::query::<QueryParams>())
+            .and(with_query(self.settings.clone()))
             .and(with_db(db_conn.clone()))
-            .and(warp::any().map(move || tokens_for_transactions.clone()))
+            .and(with_tokens(self.manager.clone()))
             .and_then(|account, params, db, tokens| async move {
                 handle_get_account_transactions(account, params, db, tokens).await
             });
 
         // 获取特定交易详情
-        let tokens_for_transaction = self.tokens.clone();
         let transaction = warp::path!("api" / "transaction" / u64)
             .and(warp::get())
-            .and(warp::query::<QueryParams>())
+            .and(with_query(self.settings.clone()))
             .and(with_db(db_conn.clone()))
-            .and(warp::any().map(move || tokens_for_transaction.clone()))
+            .and(with_tokens(self.manager.clone()))
             .and_then(|index, params, db, tokens| async move {
                 handle_get_transaction(index, params, db, tokens).await
             });
 
         // 获取最新交易
-        let tokens_for_latest = self.tokens.clone();
         let latest_transactions = warp::path!("api" / "latest_transactions")
             .and(warp::get())
-            .and(warp::query::<QueryParams>())
+            .and(with_query(self.settings.clone()))
             .and(with_db(db_conn.clone()))
-            .and(warp::any().map(move || tokens_for_latest.clone()))
+            .and(with_tokens(self.manager.clone()))
             .and_then(|params, db, tokens| async move {
                 handle_get_latest_transactions(params, db, tokens).await
             });
 
+        // 按时间查找交易索引
+        let block_at_time = warp::path!("api" / "block_at_time")
+            .and(warp::get())
+            .and(warp::query::<BlockAtTimeParams>())
+            .and(with_db(db_conn.clone()))
+            .and(with_tokens(self.manager.clone()))
+            .and_then(|params, db, tokens| async move {
+                handle_get_block_at_time(params, db, tokens).await
+            });
+
+        // 新交易推送流（SSE），断线重连时浏览器自动携带 Last-Event-ID
+        let sse_stream = warp::path!("api" / "stream")
+            .and(warp::get())
+            .and(warp::query::<StreamParams>())
+            .and(warp::header::optional::<u64>("last-event-id"))
+            .and(with_db(db_conn.clone()))
+            .and(with_tokens(self.manager.clone()))
+            .and_then(|params, last_event_id, db, tokens| async move {
+                handle_sse_stream(params, last_event_id, db, tokens).await
+            });
+
+        // 新交易推送流（WebSocket）
+        let ws = warp::path!("api" / "ws")
+            .and(warp::ws())
+            .and(warp::query::<StreamParams>())
+            .and(with_db(db_conn.clone()))
+            .and(with_tokens(self.manager.clone()))
+            .and_then(|upgrade, params, db, tokens| async move {
+                handle_ws_stream(upgrade, params, db, tokens).await
+            });
+
         // 获取交易总数
-        let tokens_for_count = self.tokens.clone();
         let tx_count = warp::path!("api" / "tx_count")
             .and(warp::get())
-            .and(warp::query::<QueryParams>())
+            .and(with_query(self.settings.clone()))
             .and(with_db(db_conn.clone()))
-            .and(warp::any().map(move || tokens_for_count.clone()))
+            .and(with_tokens(self.manager.clone()))
             .and_then(|params, db, tokens| async move {
                 handle_get_transaction_count(params, db, tokens).await
             });
 
         // 获取账户总数
-        let tokens_for_accounts = self.tokens.clone();
         let account_count = warp::path!("api" / "account_count")
             .and(warp::get())
-            .and(warp::query::<QueryParams>())
+            .and(with_query(self.settings.clone()))
             .and(with_db(db_conn.clone()))
-            .and(warp::any().map(move || tokens_for_accounts.clone()))
+            .and(with_tokens(self.manager.clone()))
             .and_then(|params, db, tokens| async move {
                 handle_get_account_count(params, db, tokens).await
             });
 
         // 获取代币总供应量
-        let tokens_for_supply = self.tokens.clone();
         let total_supply = warp::path!("api" / "total_supply")
             .and(warp::get())
-            .and(warp::query::<QueryParams>())
+            .and(with_query(self.settings.clone()))
             .and(with_db(db_conn.clone()))
-            .and(warp::any().map(move || tokens_for_supply.clone()))
+            .and(with_tokens(self.manager.clone()))
             .and_then(|params, db, tokens| async move {
                 handle_get_total_supply(params, db, tokens).await
             });
 
         // 获取账户列表
-        let tokens_for_account_list = self.tokens.clone();
         let accounts = warp::path!("api" / "accounts")
             .and(warp::get())
-            .and(warp::query::<QueryParams>())
+            .and(with_query(self.settings.clone()))
             .and(with_db(db_conn.clone()))
-            .and(warp::any().map(move || tokens_for_account_list.clone()))
+            .and(with_tokens(self.manager.clone()))
             .and_then(|params, db, tokens| async move {
                 handle_get_accounts(params, db, tokens).await
             });
 
         // 获取活跃账户
-        let tokens_for_active = self.tokens.clone();
         let active_accounts = warp::path!("api" / "active_accounts")
             .and(warp::get())
-            .and(warp::query::<QueryParams>())
+            .and(with_query(self.settings.clone()))
             .and(with_db(db_conn.clone()))
-            .and(warp::any().map(move || tokens_for_active.clone()))
+            .and(with_tokens(self.manager.clone()))
             .and_then(|params, db, tokens| async move {
                 handle_get_active_accounts(params, db, tokens).await
             });
 
         // 高级搜索
-        let tokens_for_search = self.tokens.clone();
+        let settings = self.settings.clone();
         let search = warp::path!("api" / "search")
             .and(warp::post())
+            .and(warp::query::<SearchParams>())
             .and(warp::body::json())
+            .and(warp::any().map(move || settings.clone()))
             .and(with_db(db_conn.clone()))
-            .and(warp::any().map(move || tokens_for_search.clone()))
-            .and_then(|query, db, tokens| async move {
-                handle_search_transactions(query, db, tokens).await
+            .and(with_tokens(self.manager.clone()))
+            .and_then(|params, request, settings, db, tokens| async move {
+                handle_search_transactions(params, request, settings, db, tokens).await
             });
 
         // 根据索引范围批量获取交易
-        let tokens_for_range = self.tokens.clone();
         let transactions_by_range = warp::path!("api" / "transactions_by_range" / u64 / u64)
             .and(warp::get())
-            .and(warp::query::<QueryParams>())
+            .and(with_query(self.settings.clone()))
             .and(with_db(db_conn.clone()))
-            .and(warp::any().map(move || tokens_for_range.clone()))
+            .and(with_tokens(self.manager.clone()))
             .and_then(|start, end, params, db, tokens| async move {
                 handle_get_transactions_by_range(start, end, params, db, tokens).await
             });
 
+        // 查看交易索引缺口
+        let sync_gaps = warp::path!("api" / "sync" / "gaps")
+            .and(warp::get())
+            .and(with_query(self.settings.clone()))
+            .and(with_db(db_conn.clone()))
+            .and(with_tokens(self.manager.clone()))
+            .and_then(|params, db, tokens| async move {
+                handle_get_sync_gaps(params, db, tokens).await
+            });
+
+        // 同步进度：阶段、链上最新索引、落后量、吞吐量和预计剩余时间
+        let sync_status = warp::path!("api" / "sync" / "status")
+            .and(warp::get())
+            .and(with_query(self.settings.clone()))
+            .and(with_db(db_conn.clone()))
+            .and(with_manager(self.manager.clone()))
+            .and_then(|params, db, manager| async move {
+                handle_get_sync_status(params, db, manager).await
+            });
+
+        // 服务状态：同步进度和 IC 节点健康状态
+        let ic_endpoints = self.ic_endpoints.clone();
+        let status = warp::path!("api" / "status")
+            .and(warp::get())
+            .and(with_db(db_conn.clone()))
+            .and(with_manager(self.manager.clone()))
+            .and(warp::any().map(move || ic_endpoints.clone()))
+            .and_then(handle_get_status);
+
+        // Prometheus 指标：IC 节点健康、限流和熔断状态
+        let ic_endpoints = self.ic_endpoints.clone();
+        let metrics = warp::path!("metrics")
+            .and(warp::get())
+            .map(move || {
+                let endpoints = ic_endpoints.as_ref().map(|pool| pool.render_metrics()).unwrap_or_default();
+                let body = format!("{}{}{}", endpoints, crate::ic::limiter::render_metrics(), stream::render_metrics());
+                warp::reply::with_header(body, "content-type", "text/plain; version=0.0.4; charset=utf-8")
+            });
+
         // 合并所有路由
         supported_tokens
             .or(balance)
             .or(transactions)
             .or(transaction)
             .or(latest_transactions)
+            .or(block_at_time)
+            .or(sse_stream)
+            .or(ws)
             .or(tx_count)
             .or(account_count)
             .or(total_supply)
@@ -446,6 +614,61 @@ impl ApiServer {
             .or(active_accounts)
             .or(search)
             .or(transactions_by_range)
+            .or(sync_gaps)
+            .or(sync_status)
+            .or(status)
+            .or(metrics)
+            .or(self.admin_routes())
+            .boxed()
+    }
+
+    /// 构建管理接口路由，所有请求都需要 `Authorization: Bearer <admin_token>`
+    fn admin_routes(&self) -> BoxedFilter<(impl Reply,)> {
+        let settings = self.settings.clone();
+
+        // 代币注册表
+        let list_tokens = warp::path!("api" / "admin" / "tokens")
+            .and(warp::get())
+            .and(with_admin_auth(settings.clone()))
+            .and(with_manager(self.manager.clone()))
+            .and_then(handle_admin_list_tokens);
+
+        // 添加代币
+        let add_token = warp::path!("api" / "admin" / "tokens")
+            .and(warp::post())
+            .and(with_admin_auth(settings.clone()))
+            .and(warp::body::content_length_limit(64 * 1024))
+            .and(warp::body::json::<TokenConfig>())
+            .and(with_manager(self.manager.clone()))
+            .and_then(handle_admin_add_token);
+
+        // 暂停代币同步
+        let pause_token = warp::path!("api" / "admin" / "tokens" / String / "pause")
+            .and(warp::post())
+            .and(with_admin_auth(settings.clone()))
+            .and(with_manager(self.manager.clone()))
+            .and_then(handle_admin_pause_token);
+
+        // 恢复代币同步
+        let resume_token = warp::path!("api" / "admin" / "tokens" / String / "resume")
+            .and(warp::post())
+            .and(with_admin_auth(settings.clone()))
+            .and(with_manager(self.manager.clone()))
+            .and_then(handle_admin_resume_token);
+
+        // 移除代币
+        let remove_token = warp::path!("api" / "admin" / "tokens" / String)
+            .and(warp::delete())
+            .and(with_admin_auth(settings))
+            .and(warp::query::<RemoveTokenParams>())
+            .and(with_manager(self.manager.clone()))
+            .and_then(handle_admin_remove_token);
+
+        list_tokens
+            .or(add_token)
+            .or(pause_token)
+            .or(resume_token)
+            .or(remove_token)
             .boxed()
     }
 }
@@ -489,10 +712,121 @@ fn find_token<'a>(
     }
 }
 
-// 辅助函数：将代币列表注入到处理函数
-#[allow(dead_code)]
-fn with_tokens(tokens: Vec<crate::models::TokenConfig>) -> impl Filter<Extract = (Vec<crate::models::TokenConfig>,), Error = std::convert::Infallible> + Clone {
-    warp::any().map(move || tokens.clone())
+/// 辅助函数：部分索引起点，写入响应的 partial_since 字段
+///
+/// 代币从配置的起点开始索引时返回 { index, timestamp }，表示余额和交易历史只包含此后的数据；
+/// 完整索引时返回 null。
+async fn partial_since(db_conn: &DbConnection, token_symbol: &str) -> mongodb::bson::Bson {
+    match crate::db::sync_status::get_partial_start(&db_conn.sync_status_col, token_symbol).await {
+        Ok(Some(start)) => mongodb::bson::Bson::Document(start.to_document()),
+        Ok(None) => mongodb::bson::Bson::Null,
+        Err(e) => {
+            error!("读取部分索引起点失败: {}", e);
+            mongodb::bson::Bson::Null
+        }
+    }
+}
+
+// 辅助函数：将当前的代币列表注入到处理函数，每个请求读取一次，运行时增删的代币立即生效
+fn with_tokens(manager: Arc<TokenManager>) -> impl Filter<Extract = (Vec<crate::models::TokenConfig>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || manager.tokens())
+}
+
+// 辅助函数：解析通用查询参数，limit 不超过配置的 max_page_size
+fn with_query(settings: SharedApiConfig) -> impl Filter<Extract = (QueryParams,), Error = Rejection> + Clone {
+    warp::query::<QueryParams>()
+        .map(move |mut params: QueryParams| {
+            params.limit = clamp_limit(&settings, params.limit);
+            params
+        })
+}
+
+/// 按配置的 max_page_size 限制 limit；未指定 limit 时保持各接口自己的默认值
+fn clamp_limit(settings: &SharedApiConfig, limit: Option<i64>) -> Option<i64> {
+    match (limit, settings.read().unwrap().max_page_size) {
+        (Some(limit), Some(max)) => Some(limit.min(max)),
+        (limit, _) => limit,
+    }
+}
+
+/// 解析列表接口的分页游标，游标无效、不属于当前列表或与 skip 同时使用时返回 400
+fn parse_page_cursor(params: &QueryParams, scope: &str) -> Result<Option<Cursor>, Rejection> {
+    pagination::parse_cursor(params.cursor.as_deref(), params.skip, scope)
+        .map_err(|e| warp::reject::custom(ApiError::InvalidQuery(e.to_string())))
+}
+
+/// 列表接口 next / prev 链接的基础部分：请求路径加上代币、limit 和时间参数（时间统一为纳秒）
+fn page_link_base(path: &str, token_symbol: &str, limit: i64, from_time: Option<u64>, to_time: Option<u64>) -> String {
+    let mut base = format!("{}?token={}&limit={}", path, token_symbol, limit);
+    if let Some(from_time) = from_time {
+        base.push_str(&format!("&from_time={}", from_time));
+    }
+    if let Some(to_time) = to_time {
+        base.push_str(&format!("&to_time={}", to_time));
+    }
+    base
+}
+
+/// 解析 from_time / to_time 查询参数，格式无效或起始时间晚于结束时间时返回 400
+fn parse_time_range(params: &QueryParams) -> Result<(Option<u64>, Option<u64>), Rejection> {
+    let parse = |value: &Option<String>, field: &str| value.as_deref()
+        .map(parse_time)
+        .transpose()
+        .map_err(|e| warp::reject::custom(ApiError::InvalidQuery(format!("{} {}", field, e))));
+    let from_time = parse(&params.from_time, "from_time")?;
+    let to_time = parse(&params.to_time, "to_time")?;
+    if let (Some(from), Some(to)) = (from_time, to_time) {
+        if from > to {
+            return Err(warp::reject::custom(ApiError::InvalidQuery(
+                format!("from_time ({}) 不能晚于 to_time ({})", from, to)
+            )));
+        }
+    }
+    Ok((from_time, to_time))
+}
+
+/// 将时间区间转换为交易索引区间
+async fn resolve_time_range(
+    tx_col: &mongodb::Collection<Document>,
+    from_time: Option<u64>,
+    to_time: Option<u64>,
+) -> Result<api::TimeIndexRange, Rejection> {
+    api::time_index_range(tx_col, from_time, to_time).await
+        .map_err(|e| warp::reject::custom(map_db_error(e)))
+}
+
+// 辅助函数：将代币管理器注入到管理接口处理函数
+fn with_manager(manager: Arc<TokenManager>) -> impl Filter<Extract = (Arc<TokenManager>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || manager.clone())
+}
+
+/// 辅助函数：校验管理接口的 Bearer 令牌
+///
+/// 未配置 admin_token 时管理接口不可用（403），令牌缺失或不匹配时返回 401。
+fn with_admin_auth(settings: SharedApiConfig) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and_then(move |header: Option<String>| {
+            let admin_token = settings.read().unwrap().admin_token.clone();
+            async move {
+                let expected = match admin_token.as_deref() {
+                    Some(token) if !token.is_empty() => token.to_string(),
+                    _ => return Err(warp::reject::custom(
+                        ApiError::Forbidden("管理接口未启用，请在配置中设置 api_server.admin_token".to_string())
+                    )),
+                };
+                let provided = header.as_deref().and_then(|h| h.strip_prefix("Bearer "));
+                match provided {
+                    Some(provided) if constant_time_eq(provided.as_bytes(), expected.as_bytes()) => Ok(()),
+                    _ => Err(warp::reject::custom(ApiError::Unauthorized("缺少或无效的管理令牌".to_string()))),
+                }
+            }
+        })
+        .untuple_one()
+}
+
+/// 逐字节比较令牌，耗时与第一个不同字节的位置无关
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
 }
 
 /// 处理函数：获取账户余额
@@ -524,19 +858,30 @@ async fn handle_get_balance(
     debug!("使用代币: {}", token.symbol);
     
     // 从数据库中获取该代币的集合
-    let collections = db_conn.collections.get(&token.symbol)
+    let collections = db_conn.token_collections(&token.symbol)
         .ok_or_else(|| warp::reject::custom(
             ApiError::TokenError(format!("未找到代币 {} 的数据库集合", token.symbol))
         ))?;
     
     match api::get_account_balance(&collections.balances_col, &account).await {
         Ok(balance) => {
+            let mut partial = partial_since(&db_conn, &token.symbol).await;
+            if let mongodb::bson::Bson::Document(partial_doc) = &mut partial {
+                // 期初余额写入前，余额只包含起点之后的交易
+                let seeded = collections.accounts_col
+                    .find_one(doc! { "account": &account, "opening_balance": { "$exists": true } }, None)
+                    .await
+                    .map(|d| d.is_some())
+                    .unwrap_or(false);
+                partial_doc.insert("opening_balance_seeded", seeded);
+            }
             let response = ApiResponse::success(doc! {
                 "account": account.clone(),
                 "balance": balance.clone(),
                 "token": token.symbol.clone(),
                 "token_name": token.name.clone(),
                 "decimals": token.decimals.unwrap_or(8) as i32,
+                "partial_since": partial,
             });
             info!("API响应成功: 获取账户余额 - account: {}, balance: {}, token: {}", 
                   account, balance, token.symbol);
@@ -581,19 +926,39 @@ async fn handle_get_account_transactions(
     
     // 从数据库中获取该代币的集合
     // 从数据库中获取该代币的集合
-    let collections = db_conn.collections.get(&token.symbol)
+    let collections = db_conn.token_collections(&token.symbol)
         .ok_or_else(|| warp::reject::custom(
             ApiError::TokenError(format!("未找到代币 {} 的数据库集合", token.symbol))
         ))?;
     
+    // 游标绑定到账户，避免在其他账户的交易历史中使用
+    let scope = format!("account_transactions:{}", account);
+    let cursor = parse_page_cursor(&params, &scope)?;
+    let limit = params.limit.unwrap_or(50).max(0);
+    let (from_time, to_time) = parse_time_range(&params)?;
+    
+    // 时间条件转换为交易索引区间，沿账户-交易边的索引查询
+    let index_range = resolve_time_range(&collections.tx_col, from_time, to_time).await?.into_condition();
+    
+    // 多查询一条用于判断是否还有下一页
     match api::get_account_transactions(
-        &collections.accounts_col,
+        &collections.account_tx_col,
         &collections.tx_col,
         &account,
-        params.limit,
+        Some(limit + 1),
         params.skip,
+        cursor.as_ref(),
+        index_range,
     ).await {
         Ok(transactions) => {
+            let (transactions, page) = pagination::paginate(
+                transactions, limit, cursor.as_ref(), params.skip.unwrap_or(0) > 0, &scope,
+                |tx| (tx.index.map(|index| index as i64), None),
+            );
+            let page = page.with_links(&page_link_base(
+                &format!("/api/transactions/{}", account), &token.symbol, limit, from_time, to_time,
+            ));
+            
             // 将交易数据转换为可序列化的格式
             let tx_docs = transactions.iter()
                 .map(|tx| transaction_to_bson(tx, &token.symbol, &token.name))
@@ -603,8 +968,9 @@ async fn handle_get_account_transactions(
                 "total": tx_docs.len() as i32,
                 "account": account.clone(),
                 "token": token.symbol.clone(),
-                "limit": params.limit.unwrap_or(50),
+                "limit": limit,
                 "skip": params.skip.unwrap_or(0),
+                "partial_since": partial_since(&db_conn, &token.symbol).await,
             };
             
             let response_data = doc! {
@@ -612,7 +978,7 @@ async fn handle_get_account_transactions(
                 "meta": meta
             };
             
-            let response = ApiResponse::success(response_data);
+            let response = ApiResponse::success(response_data).with_page(page);
             info!("API响应成功: 获取账户交易历史 - account: {}, count: {}, token: {}", 
                  account, transactions.len(), token.symbol);
             Ok(warp::reply::json(&response))
@@ -646,24 +1012,42 @@ async fn handle_get_latest_transactions(
     let tx_col = db_conn.get_transactions_collection(&token.symbol);
     
     // 设置分页参数
-    let limit = params.limit.unwrap_or(20).min(100); // 最多返回100条记录
+    let limit = params.limit.unwrap_or(20).clamp(0, 100); // 最多返回100条记录
+    let scope = "latest_transactions";
+    let cursor = parse_page_cursor(&params, scope)?;
+    let (from_time, to_time) = parse_time_range(&params)?;
     
-    // 构建查询过滤器和选项
-    let filter = doc! {};
+    // 构建查询过滤器和选项（按索引降序，游标分页时从游标索引继续）
+    let sort_fields = [("index", -1)];
+    let mut conditions = Vec::new();
+    if let Some(cursor) = &cursor {
+        conditions.push(pagination::keyset_filter(&sort_fields, cursor)
+            .map_err(|e| warp::reject::custom(ApiError::InvalidQuery(e.to_string())))?);
+    }
+    // 时间条件转换为交易索引区间
+    if let Some(range) = resolve_time_range(&tx_col, from_time, to_time).await?.into_condition() {
+        conditions.push(doc! { "index": range });
+    }
+    let filter = match conditions.len() {
+        0 => doc! {},
+        1 => conditions.remove(0),
+        _ => doc! { "$and": conditions },
+    };
     let options = mongodb::options::FindOptions::builder()
-        .sort(doc! { "index": -1 }) // 按索引降序排序
-        .limit(limit)
+        .sort(pagination::query_sort(&sort_fields, cursor.as_ref()))
+        .skip(params.skip.map(|skip| skip.max(0) as u64))
+        .limit(limit + 1) // 多查询一条用于判断是否还有下一页
         .build();
     
     // 执行查询
-    let mut cursor = tx_col.find(filter, options).await
+    let mut results = tx_col.find(filter, options).await
         .map_err(|e| warp::reject::custom(
             ApiError::Database(format!("查询交易失败: {}", e))
         ))?;
     
     // 收集结果
     let mut transactions = Vec::new();
-    while let Some(result) = cursor.next().await {
+    while let Some(result) = results.next().await {
         match result {
             Ok(doc) => transactions.push(doc),
             Err(e) => {
@@ -675,11 +1059,139 @@ async fn handle_get_latest_transactions(
         }
     }
     
+    let (transactions, page) = pagination::paginate(
+        transactions, limit, cursor.as_ref(), params.skip.unwrap_or(0) > 0, scope,
+        |tx| (tx.get_i64("index").ok(), None),
+    );
+    let page = page.with_links(&page_link_base("/api/latest_transactions", &token.symbol, limit, from_time, to_time));
+    
     // 构建响应
-    let response = ApiResponse::success(transactions);
+    let response = ApiResponse::success(transactions).with_page(page);
     Ok(warp::reply::json(&response))
 }
 
+/// 处理函数：按时间查找交易索引
+///
+/// 返回第一笔时间戳不早于指定时间的交易索引及该交易，指定时间之后没有交易时返回 404
+async fn handle_get_block_at_time(
+    params: BlockAtTimeParams,
+    db_conn: Arc<DbConnection>,
+    tokens: Vec<crate::models::TokenConfig>,
+) -> Result<impl Reply, Rejection> {
+    info!("API请求: 按时间查找交易索引 - time: {}, token: {:?}", params.time, params.token);
+
+    let token = find_token(&tokens, params.token.as_deref())?;
+    let time = parse_time(&params.time)
+        .map_err(|e| warp::reject::custom(ApiError::InvalidQuery(format!("time {}", e))))?;
+    let collections = db_conn.token_collections(&token.symbol)
+        .ok_or_else(|| warp::reject::custom(
+            ApiError::TokenError(format!("未找到代币 {} 的数据库集合", token.symbol))
+        ))?;
+
+    let index = crate::db::transactions::find_index_at_time(&collections.tx_col, time).await
+        .map_err(|e| warp::reject::custom(map_db_error(e)))?
+        .ok_or_else(|| warp::reject::custom(ApiError::NotFound(
+            format!("{} 之后没有已索引的交易 (token: {})", nanos_to_rfc3339(time), token.symbol)
+        )))?;
+    let transaction = api::get_transaction_by_index(&collections.tx_col, index).await
+        .map_err(|e| warp::reject::custom(map_db_error(e)))?
+        .map(|tx| transaction_to_bson(&tx, &token.symbol, &token.name));
+
+    info!("API响应成功: 按时间查找交易索引 - time: {}, index: {}, token: {}", time, index, token.symbol);
+    Ok(warp::reply::json(&ApiResponse::success(doc! {
+        "token": token.symbol.clone(),
+        "time": time as i64,
+        "datetime": nanos_to_rfc3339(time),
+        "index": index as i64,
+        "transaction": transaction,
+    })))
+}
+
+/// 校验推送流的订阅参数，返回代币配置、集合和订阅条件
+fn prepare_stream<'a>(
+    params: &StreamParams,
+    db_conn: &DbConnection,
+    tokens: &'a [crate::models::TokenConfig],
+) -> Result<(&'a crate::models::TokenConfig, crate::db::TokenCollections, StreamFilter), Rejection> {
+    let token = find_token(tokens, params.token.as_deref())?;
+    let collections = db_conn.token_collections(&token.symbol)
+        .ok_or_else(|| warp::reject::custom(
+            ApiError::TokenError(format!("未找到代币 {} 的数据库集合", token.symbol))
+        ))?;
+    let filter = StreamFilter::build(params, token.decimals.unwrap_or(8))
+        .map_err(|e| warp::reject::custom(ApiError::InvalidQuery(e.to_string())))?;
+    Ok((token, collections, filter))
+}
+
+/// 处理函数：SSE 推送新交易
+///
+/// 每笔交易为一个 `transaction` 事件，事件 id 为交易索引；重连时 Last-Event-ID 优先于 from_index
+async fn handle_sse_stream(
+    params: StreamParams,
+    last_event_id: Option<u64>,
+    db_conn: Arc<DbConnection>,
+    tokens: Vec<crate::models::TokenConfig>,
+) -> Result<impl Reply, Rejection> {
+    let (token, collections, filter) = prepare_stream(&params, &db_conn, &tokens)?;
+    let from_index = last_event_id.map(|index| index + 1).or(params.from_index);
+    info!("API请求: SSE 交易推送 - token: {}, 条件: {:?}, from_index: {:?}", token.symbol, params, from_index);
+
+    let receiver = stream::subscribe(collections, token.symbol.clone(), filter, from_index, stream::Transport::Sse);
+    let (symbol, name) = (token.symbol.clone(), token.name.clone());
+    let events = futures::stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|tx| (tx, receiver))
+    }).map(move |tx| {
+        let tx_doc = transaction_to_bson(&tx, &symbol, &name);
+        Ok::<_, std::convert::Infallible>(warp::sse::Event::default()
+            .event("transaction")
+            .id(tx.index.unwrap_or(0).to_string())
+            .data(serde_json::to_string(&tx_doc).unwrap_or_default()))
+    });
+    Ok(warp::sse::reply(warp::sse::keep_alive().stream(events)))
+}
+
+/// 处理函数：WebSocket 推送新交易
+///
+/// 每笔交易为一条文本消息 `{"event": "transaction", "id": 交易索引, "data": {...}}`；客户端发送的消息被忽略
+async fn handle_ws_stream(
+    upgrade: warp::ws::Ws,
+    params: StreamParams,
+    db_conn: Arc<DbConnection>,
+    tokens: Vec<crate::models::TokenConfig>,
+) -> Result<impl Reply, Rejection> {
+    // 在升级连接之前校验参数，无效时返回 400
+    let (token, collections, filter) = prepare_stream(&params, &db_conn, &tokens)?;
+    info!("API请求: WebSocket 交易推送 - token: {}, 条件: {:?}", token.symbol, params);
+    let (symbol, name) = (token.symbol.clone(), token.name.clone());
+
+    Ok(upgrade.on_upgrade(move |socket| async move {
+        let (mut outgoing, mut incoming) = socket.split();
+        let mut receiver = stream::subscribe(collections, symbol.clone(), filter, params.from_index, stream::Transport::WebSocket);
+        loop {
+            tokio::select! {
+                message = incoming.next() => match message {
+                    Some(Ok(message)) if message.is_close() => break,
+                    Some(Ok(_)) => {},
+                    Some(Err(_)) | None => break,
+                },
+                tx = receiver.recv() => {
+                    let Some(tx) = tx else { break };
+                    let message = serde_json::json!({
+                        "event": "transaction",
+                        "id": tx.index,
+                        "data": transaction_to_bson(&tx, &symbol, &name),
+                    });
+                    if outgoing.send(warp::ws::Message::text(message.to_string())).await.is_err() {
+                        break;
+                    }
+                },
+            }
+        }
+        let _ = outgoing.close().await;
+        debug!("{}: WebSocket 交易推送连接已关闭", symbol);
+    }))
+}
+
 /// 处理函数：获取特定交易详情
 ///
 /// # 参数
@@ -703,7 +1215,7 @@ async fn handle_get_transaction(
     debug!("使用代币: {}", token.symbol);
     
     // 从数据库中获取该代币的集合
-    let collections = db_conn.collections.get(&token.symbol)
+    let collections = db_conn.token_collections(&token.symbol)
         .ok_or_else(|| warp::reject::custom(
             ApiError::TokenError(format!("未找到代币 {} 的数据库集合", token.symbol))
         ))?;
@@ -755,7 +1267,7 @@ async fn handle_get_transaction_count(
     debug!("使用代币: {}", token.symbol);
     
     // 从数据库中获取该代币的集合
-    let collections = db_conn.collections.get(&token.symbol)
+    let collections = db_conn.token_collections(&token.symbol)
         .ok_or_else(|| warp::reject::custom(
             ApiError::TokenError(format!("未找到代币 {} 的数据库集合", token.symbol))
         ))?;
@@ -766,6 +1278,7 @@ async fn handle_get_transaction_count(
                 "count": count as i64,
                 "token": token.symbol.clone(),
                 "token_name": token.name.clone(),
+                "partial_since": partial_since(&db_conn, &token.symbol).await,
             };
             
             let response = ApiResponse::success(response_data);
@@ -808,7 +1321,7 @@ async fn handle_get_account_count(
     };
     
     // 从数据库中获取该代币的集合
-    let collections = match db_conn.collections.get(&token.symbol) {
+    let collections = match db_conn.token_collections(&token.symbol) {
         Some(cols) => cols,
         None => {
             let msg = format!("未找到代币 {} 的数据库集合", token.symbol);
@@ -860,7 +1373,7 @@ async fn handle_get_total_supply(
     };
     
     // 从数据库中获取该代币的集合
-    let collections = match db_conn.collections.get(&token.symbol) {
+    let collections = match db_conn.token_collections(&token.symbol) {
         Some(cols) => cols,
         None => {
             let msg = format!("未找到代币 {} 的数据库集合", token.symbol);
@@ -910,7 +1423,7 @@ async fn handle_get_accounts(
     };
     
     // 从数据库中获取该代币的集合
-    let collections = match db_conn.collections.get(&token.symbol) {
+    let collections = match db_conn.token_collections(&token.symbol) {
         Some(cols) => cols,
         None => {
             let msg = format!("未找到代币 {} 的数据库集合", token.symbol);
@@ -919,9 +1432,19 @@ async fn handle_get_accounts(
         }
     };
     
-    match api::get_all_accounts(&collections.accounts_col, params.limit, params.skip).await {
+    let scope = "accounts";
+    let cursor = parse_page_cursor(&params, scope)?;
+    let limit = params.limit.unwrap_or(100).max(0);
+    
+    // 多查询一条用于判断是否还有下一页
+    match api::get_all_accounts(&collections.accounts_col, Some(limit + 1), params.skip, cursor.as_ref()).await {
         Ok(accounts) => {
-            let response = ApiResponse::success(accounts.clone());
+            let (accounts, page) = pagination::paginate(
+                accounts, limit, cursor.as_ref(), params.skip.unwrap_or(0) > 0, scope,
+                |account| (None, Some(account.clone())),
+            );
+            let page = page.with_links(&page_link_base("/api/accounts", &token.symbol, limit, None, None));
+            let response = ApiResponse::success(accounts.clone()).with_page(page);
             info!("API响应成功: 获取账户列表 - 返回账户数: {}", accounts.len());
             Ok(warp::reply::json(&response))
         },
@@ -962,7 +1485,7 @@ async fn handle_get_active_accounts(
     };
     
     // 从数据库中获取该代币的集合
-    let collections = match db_conn.collections.get(&token.symbol) {
+    let collections = match db_conn.token_collections(&token.symbol) {
         Some(cols) => cols,
         None => {
             let msg = format!("未找到代币 {} 的数据库集合", token.symbol);
@@ -986,73 +1509,59 @@ async fn handle_get_active_accounts(
 }
 
 // 处理函数：高级搜索交易
+//
+// 请求体为类型化的搜索条件（见 search::SearchRequest），查询参数中的 token、limit、skip、
+// min_amount、max_amount、sort 作为请求体未指定时的默认值；条件无效时返回 400。
 async fn handle_search_transactions(
-    query: Document,
+    params: SearchParams,
+    request: SearchRequest,
+    settings: SharedApiConfig,
     db_conn: Arc<DbConnection>,
     tokens: Vec<crate::models::TokenConfig>,
 ) -> Result<impl Reply, Rejection> {
-    // 创建默认查询参数
-    let params = QueryParams {
-        limit: Some(50),
-        skip: Some(0),
-        token: None,
-    };
-    info!("API响应: 高级搜索交易 - 查询条件: {:?}", query);
-    
-    // 默认限制和偏移量
-    let limit = params.limit.or_else(|| Some(50));
-    let skip = params.skip.or_else(|| Some(0));
-    
-    // 获取查询参数中的token或者默认第一个代币
-    let token_symbol = params.token.as_deref();
-    let token = match token_symbol {
-        Some(symbol) => tokens.iter().find(|t| t.symbol == symbol),
-        None => tokens.first()
-    };
-    
-    // 如果找不到代币，返回错误
-    let token = match token {
-        Some(t) => t,
-        None => {
-            let msg = match token_symbol {
-                Some(s) => format!("未找到指定的代币: {}", s),
-                None => "系统未配置任何代币".to_string()
-            };
-            error!("API错误: {}", msg);
-            return Ok(warp::reply::json(&ApiResponse::<Document>::error(&msg)));
-        }
-    };
-    
-    // 从数据库中获取该代币的集合
-    let collections = match db_conn.collections.get(&token.symbol) {
-        Some(cols) => cols,
-        None => {
-            let msg = format!("未找到代币 {} 的数据库集合", token.symbol);
-            error!("API错误: {}", msg);
-            return Ok(warp::reply::json(&ApiResponse::<Document>::error(&msg)));
+    // 请求体优先，未指定的字段使用查询参数
+    let mut request = request;
+    request.token = request.token.or(params.token);
+    request.limit = clamp_limit(&settings, request.limit.or(params.limit));
+    request.skip = request.skip.or(params.skip);
+    request.cursor = request.cursor.or(params.cursor);
+    request.min_amount = request.min_amount.or(params.min_amount);
+    request.from_time = request.from_time.or(params.from_time.map(search::TimeValue::Text));
+    request.to_time = request.to_time.or(params.to_time.map(search::TimeValue::Text));
+    request.max_amount = request.max_amount.or(params.max_amount);
+    request.sort = request.sort.or(params.sort);
+    info!("API请求: 高级搜索交易 - 条件: {:?}", request);
+
+    let token = find_token(&tokens, request.token.as_deref())?;
+    let collections = db_conn.token_collections(&token.symbol)
+        .ok_or_else(|| warp::reject::custom(ApiError::TokenError(format!("未找到代币 {} 的数据库集合", token.symbol))))?;
+
+    let plan = match SearchPlan::build(&request, token.decimals.unwrap_or(8)) {
+        Ok(plan) => plan,
+        Err(e) => {
+            error!("API错误: 高级搜索交易 - 条件无效: {}", e);
+            return Err(warp::reject::custom(ApiError::InvalidQuery(e.to_string())));
         }
     };
 
-    match api::search_transactions(&collections.tx_col, query.clone(), limit, skip).await {
-        Ok(transactions) => {
-            // 将Transaction对象转换为可序列化的文档
-            let tx_docs: Vec<Document> = transactions.iter()
+    match search::execute(&collections, &plan).await {
+        Ok(result) => {
+            let tx_docs: Vec<Document> = result.transactions.iter()
                 .map(|tx| transaction_to_bson(tx, &token.symbol, &token.name))
                 .collect();
-            
-            let response = ApiResponse::success(doc! {
-                "query": query.clone(),
+            let query = mongodb::bson::to_document(&request).unwrap_or_default();
+            info!("API响应成功: 高级搜索交易 - token: {}, 返回交易数: {}", token.symbol, tx_docs.len());
+            Ok(warp::reply::json(&ApiResponse::success(doc! {
+                "query": query,
+                "count": tx_docs.len() as i64,
+                "truncated": result.truncated,
+                "partial": result.partial,
                 "transactions": tx_docs,
-                "count": (transactions.len() as i64),
-            });
-            info!("API响应成功: 高级搜索交易 - 查询条件: {:?}, 返回交易数: {}", 
-                  query, transactions.len());
-            Ok(warp::reply::json(&response))
+            }).with_page(result.page)))
         },
         Err(e) => {
-            let response = ApiResponse::<Vec<String>>::error(&e.to_string());
-            error!("API响应错误: 高级搜索交易 - 查询条件: {:?}, error: {}", query, e);
-            Ok(warp::reply::json(&response))
+            error!("API响应错误: 高级搜索交易 - error: {}", e);
+            Err(warp::reject::custom(map_db_error(e)))
         }
     }
 }
@@ -1088,7 +1597,7 @@ async fn handle_get_transactions_by_range(
     };
     
     // 从数据库中获取该代币的集合
-    let collections = match db_conn.collections.get(&token.symbol) {
+    let collections = match db_conn.token_collections(&token.symbol) {
         Some(cols) => cols,
         None => {
             let msg = format!("未找到代币 {} 的数据库集合", token.symbol);
@@ -1107,6 +1616,7 @@ async fn handle_get_transactions_by_range(
             let response = ApiResponse::success(doc! {
                 "start": (start as i64),
                 "end": (end as i64),
+                "partial_since": partial_since(&db_conn, &token.symbol).await,
                 "transactions": tx_docs,
                 "count": (transactions.len() as i64),
             });
@@ -1121,3 +1631,329 @@ async fn handle_get_transactions_by_range(
         }
     }
 }
+
+/// 处理函数：获取最近一次交易索引缺口扫描结果
+///
+/// # 参数
+/// * `params` - 查询参数，包括可选的token
+/// * `db_conn` - 数据库连接
+/// * `tokens` - 代币配置列表
+///
+/// # 返回
+/// 成功时返回扫描区间和缺口列表，尚未扫描时 scanned_at 为 null
+async fn handle_get_sync_gaps(
+    params: QueryParams,
+    db_conn: Arc<DbConnection>,
+    tokens: Vec<crate::models::TokenConfig>,
+) -> Result<impl Reply, Rejection> {
+    info!("API请求: 获取交易缺口 - token: {:?}", params.token);
+
+    let token = find_token(&tokens, params.token.as_deref())?;
+
+    match crate::db::sync_status::get_gap_scan(&db_conn.sync_status_col, &token.symbol).await {
+        Ok(scan) => {
+            let response_data = match scan {
+                Some(scan) => {
+                    let gaps: Vec<Document> = scan.gaps.iter().map(|g| doc! {
+                        "start": g.start as i64,
+                        "end": g.end as i64,
+                        "count": g.count() as i64,
+                        "source": &g.source,
+                        "detected_at": g.detected_at,
+                        "attempts": g.attempts as i64,
+                        "max_attempts": crate::sync::gaps::MAX_BACKFILL_ATTEMPTS as i64,
+                        "last_error": g.last_error.clone(),
+                    }).collect();
+                    doc! {
+                        "token": token.symbol.clone(),
+                        "scanned_at": scan.scanned_at,
+                        "from_index": scan.from_index as i64,
+                        "to_index": scan.to_index as i64,
+                        "missing_count": scan.gaps.iter().map(|g| g.count()).sum::<u64>() as i64,
+                        "gaps": gaps,
+                    }
+                },
+                None => doc! {
+                    "token": token.symbol.clone(),
+                    "scanned_at": mongodb::bson::Bson::Null,
+                    "missing_count": 0i64,
+                    "gaps": Vec::<Document>::new(),
+                },
+            };
+            info!("API响应成功: 获取交易缺口 - token: {}", token.symbol);
+            Ok(warp::reply::json(&ApiResponse::success(response_data)))
+        },
+        Err(e) => {
+            error!("API响应错误: 获取交易缺口 - error: {}", e);
+            Err(warp::reject::custom(map_db_error(e)))
+        }
+    }
+}
+
+/// 处理同步进度查询请求
+///
+/// # 参数
+/// * `params` - 查询参数，指定 token 时只返回该代币，否则返回全部代币
+/// * `db_conn` - 数据库连接
+/// * `manager` - 代币管理器
+///
+/// # 返回
+/// 每个代币的同步模式、当前阶段、链上日志长度、落后的交易数量、吞吐量和预计剩余时间；
+/// 进度由同步进程定期写入，超过 60 秒未更新时 stale 为 true
+async fn handle_get_sync_status(
+    params: QueryParams,
+    db_conn: Arc<DbConnection>,
+    manager: Arc<TokenManager>,
+) -> Result<impl Reply, Rejection> {
+    info!("API请求: 获取同步进度 - token: {:?}", params.token);
+
+    let all_tokens = manager.tokens();
+    let tokens = match params.token.as_deref() {
+        Some(symbol) => vec![find_token(&all_tokens, Some(symbol))?.clone()],
+        None => all_tokens,
+    };
+
+    let mut token_docs = Vec::new();
+    for token in &tokens {
+        match sync_progress_document(&db_conn, &manager, token).await {
+            Ok(token_doc) => token_docs.push(token_doc),
+            Err(e) => {
+                error!("API响应错误: 获取同步进度 - error: {}", e);
+                return Err(warp::reject::custom(map_db_error(e)));
+            },
+        }
+    }
+
+    info!("API响应成功: 获取同步进度 - {} 个代币", token_docs.len());
+    Ok(warp::reply::json(&ApiResponse::success(doc! { "tokens": token_docs })))
+}
+
+/// 辅助函数：单个代币的同步进度
+async fn sync_progress_document(
+    db_conn: &DbConnection,
+    manager: &TokenManager,
+    token: &TokenConfig,
+) -> Result<Document, Box<dyn std::error::Error + Send + Sync>> {
+    use mongodb::bson::Bson;
+    use crate::sync::progress::{lag_of, STALE_AFTER_SECS};
+
+    let status = manager.entry(&token.symbol)
+        .map(|entry| entry.status.as_str())
+        .unwrap_or("unknown");
+    let sync = crate::db::sync_status::get_sync_status(&db_conn.sync_status_col, &token.symbol).await?;
+    let progress = crate::db::sync_status::get_progress(&db_conn.sync_status_col, &token.symbol).await?;
+    let now = chrono::Utc::now().timestamp();
+
+    let log_length = progress.as_ref()
+        .and_then(|p| p.get_i64("log_length").ok())
+        .map(|length| length as u64);
+    // 增量同步时以同步游标为准，初始同步时使用进度中已处理到的索引
+    let synced_index = match &sync {
+        Some(sync) if sync.sync_mode == "incremental" => Some(sync.last_synced_index),
+        _ => progress.as_ref()
+            .and_then(|p| p.get_i64("block_index").ok())
+            .map(|index| index as u64),
+    };
+    let lag = log_length.map(|length| lag_of(length, synced_index) as i64);
+    let stale = progress.as_ref()
+        .and_then(|p| p.get_i64("updated_at").ok())
+        .map(|updated_at| now - updated_at > STALE_AFTER_SECS);
+
+    let mut token_doc = doc! {
+        "token": &token.symbol,
+        "status": status,
+        "sync_mode": sync.as_ref().map(|s| s.sync_mode.clone()),
+        "last_synced_index": sync.as_ref().map(|s| s.last_synced_index as i64),
+        "last_balance_calculated_index": sync.as_ref().and_then(|s| s.last_balance_calculated_index).map(|index| index as i64),
+        "phase": progress.as_ref().and_then(|p| p.get_str("phase").ok()).map(|phase| phase.to_string()),
+        "log_length": log_length.map(|length| length as i64),
+        "lag": lag,
+        "throughput_per_sec": progress.as_ref().and_then(|p| p.get("throughput_per_sec").cloned()),
+        "eta_secs": progress.as_ref().and_then(|p| p.get("eta_secs").cloned()),
+        "stale": stale,
+        "progress": progress.clone().map(Bson::Document),
+    };
+
+    // 分片回填进行中时附加工作单元进度，吞吐量按计划创建以来已完成的交易数计算
+    let backfill = match crate::db::backfill_jobs::get_plan(&db_conn.backfill_col, &token.symbol).await? {
+        Some(plan) if !plan.get_bool("finished").unwrap_or(false) => {
+            let units = crate::db::backfill_jobs::get_progress(&db_conn.backfill_col, &token.symbol).await?;
+            let total_blocks = (plan.get_i64("end").unwrap_or(0) - plan.get_i64("start").unwrap_or(0) + 1).max(0);
+            let elapsed = (now - plan.get_i64("created_at").unwrap_or(now)).max(1);
+            let throughput = units.transactions as f64 / elapsed as f64;
+            let remaining = (total_blocks - units.transactions as i64).max(0);
+            let eta_secs = if remaining == 0 {
+                Bson::Int64(0)
+            } else if throughput > 0.0 {
+                Bson::Int64((remaining as f64 / throughput).ceil() as i64)
+            } else {
+                Bson::Null
+            };
+            let mut backfill_doc = units.to_document();
+            backfill_doc.insert("total_blocks", total_blocks);
+            backfill_doc.insert("throughput_per_sec", (throughput * 10.0).round() / 10.0);
+            backfill_doc.insert("eta_secs", eta_secs);
+            Bson::Document(backfill_doc)
+        },
+        _ => Bson::Null,
+    };
+    token_doc.insert("backfill", backfill);
+    Ok(token_doc)
+}
+
+/// 处理服务状态查询请求
+async fn handle_get_status(
+    db_conn: Arc<DbConnection>,
+    manager: Arc<TokenManager>,
+    ic_endpoints: Option<Arc<EndpointPool>>,
+) -> Result<impl Reply, Rejection> {
+    info!("API请求: 获取服务状态");
+
+    let mut tokens = Vec::new();
+    for token in manager.tokens() {
+        let status = manager.entry(&token.symbol)
+            .map(|entry| entry.status.as_str())
+            .unwrap_or("unknown");
+        let mut token_doc = doc! { "token": &token.symbol, "status": status };
+        match crate::db::sync_status::get_sync_status(&db_conn.sync_status_col, &token.symbol).await {
+            Ok(Some(sync)) => {
+                token_doc.insert("sync_mode", sync.sync_mode);
+                token_doc.insert("last_synced_index", sync.last_synced_index as i64);
+                token_doc.insert("last_synced_timestamp", sync.last_synced_timestamp as i64);
+                token_doc.insert("last_balance_calculated_index", sync.last_balance_calculated_index.map(|index| index as i64));
+                token_doc.insert("updated_at", sync.updated_at);
+            },
+            Ok(None) => {
+                token_doc.insert("sync_mode", mongodb::bson::Bson::Null);
+            },
+            Err(e) => {
+                error!("API响应错误: 获取服务状态 - error: {}", e);
+                return Err(warp::reject::custom(map_db_error(e)));
+            },
+        }
+        tokens.push(token_doc);
+    }
+
+    let response_data = doc! {
+        "tokens": tokens,
+        "read_only": manager.is_read_only(),
+        "lease": crate::db::leases::status_document(),
+        "ic": ic_endpoints.map(|pool| pool.status_document()),
+        "limiter": crate::ic::limiter::status_document(),
+    };
+    info!("API响应成功: 获取服务状态");
+    Ok(warp::reply::json(&ApiResponse::success(response_data)))
+}
+
+/// 移除代币的查询参数
+#[derive(Debug, Deserialize, Clone)]
+pub struct RemoveTokenParams {
+    /// 是否同时删除该代币的集合、冷存储和同步状态（默认 false，只停止同步并从列表移除）
+    pub drop_data: Option<bool>,
+}
+
+/// 管理接口：获取代币注册表
+///
+/// # 返回
+/// 每个代币的配置、状态（active / paused）、来源（config / api）以及同步任务是否在运行
+async fn handle_admin_list_tokens(manager: Arc<TokenManager>) -> Result<impl Reply, Rejection> {
+    info!("管理API请求: 获取代币注册表");
+    let tokens = manager.registry_view().await;
+    Ok(warp::reply::json(&ApiResponse::success(tokens)))
+}
+
+/// 管理接口：添加代币
+///
+/// # 参数
+/// * `token` - 代币配置，格式与 config.toml 中的 [[tokens]] 相同
+/// * `manager` - 代币管理器
+///
+/// # 返回
+/// 成功时返回注册表记录，同步任务在后台执行初始同步后开始增量同步
+async fn handle_admin_add_token(token: TokenConfig, manager: Arc<TokenManager>) -> Result<impl Reply, Rejection> {
+    info!("管理API请求: 添加代币 - symbol: {}, canister_id: {}", token.symbol, token.canister_id);
+    require_writable(&manager)?;
+    match manager.add_token(token, TokenSource::Api).await {
+        Ok(entry) => {
+            info!("管理API响应成功: 添加代币 - symbol: {}", entry.token.symbol);
+            Ok(warp::reply::json(&ApiResponse::success(entry.to_document())))
+        },
+        Err(e) => {
+            error!("管理API响应错误: 添加代币 - error: {}", e);
+            Err(warp::reject::custom(ApiError::TokenError(e.to_string())))
+        }
+    }
+}
+
+/// 管理接口：暂停代币同步，已索引的数据仍可查询
+async fn handle_admin_pause_token(symbol: String, manager: Arc<TokenManager>) -> Result<impl Reply, Rejection> {
+    info!("管理API请求: 暂停代币 - symbol: {}", symbol);
+    require_writable(&manager)?;
+    require_registered(&manager, &symbol)?;
+    match manager.pause_token(&symbol).await {
+        Ok(()) => Ok(warp::reply::json(&ApiResponse::success(doc! { "symbol": &symbol, "status": "paused" }))),
+        Err(e) => {
+            error!("管理API响应错误: 暂停代币 - error: {}", e);
+            Err(warp::reject::custom(ApiError::Internal(e.to_string())))
+        }
+    }
+}
+
+/// 管理接口：恢复代币同步
+async fn handle_admin_resume_token(symbol: String, manager: Arc<TokenManager>) -> Result<impl Reply, Rejection> {
+    info!("管理API请求: 恢复代币 - symbol: {}", symbol);
+    require_writable(&manager)?;
+    require_registered(&manager, &symbol)?;
+    match manager.resume_token(&symbol).await {
+        Ok(()) => Ok(warp::reply::json(&ApiResponse::success(doc! { "symbol": &symbol, "status": "active" }))),
+        Err(e) => {
+            error!("管理API响应错误: 恢复代币 - error: {}", e);
+            Err(warp::reject::custom(ApiError::Internal(e.to_string())))
+        }
+    }
+}
+
+/// 管理接口：移除代币
+///
+/// # 参数
+/// * `symbol` - 代币符号
+/// * `params` - drop_data=true 时同时删除该代币的全部数据
+/// * `manager` - 代币管理器
+async fn handle_admin_remove_token(
+    symbol: String,
+    params: RemoveTokenParams,
+    manager: Arc<TokenManager>,
+) -> Result<impl Reply, Rejection> {
+    let drop_data = params.drop_data.unwrap_or(false);
+    info!("管理API请求: 移除代币 - symbol: {}, drop_data: {}", symbol, drop_data);
+    require_writable(&manager)?;
+    require_registered(&manager, &symbol)?;
+    match manager.remove_token(&symbol, drop_data).await {
+        Ok(()) => Ok(warp::reply::json(&ApiResponse::success(doc! {
+            "symbol": &symbol,
+            "status": "removed",
+            "data_dropped": drop_data,
+        }))),
+        Err(e) => {
+            error!("管理API响应错误: 移除代币 - error: {}", e);
+            Err(warp::reject::custom(ApiError::Internal(e.to_string())))
+        }
+    }
+}
+
+/// 辅助函数：只读 API 实例没有同步任务，不能修改代币，返回 409
+fn require_writable(manager: &TokenManager) -> Result<(), Rejection> {
+    if manager.is_read_only() {
+        return Err(warp::reject::custom(ApiError::Conflict(
+            "只读 API 实例不能修改代币，请在同步进程（run 命令）上执行".to_string())));
+    }
+    Ok(())
+}
+
+/// 辅助函数：确认代币在注册表中，否则返回 404
+fn require_registered(manager: &TokenManager, symbol: &str) -> Result<(), Rejection> {
+    match manager.entry(symbol) {
+        Some(_) => Ok(()),
+        None => Err(warp::reject::custom(ApiError::NotFound(format!("代币 {} 不存在", symbol)))),
+    }
+}
@@ -4,18 +4,19 @@
  * - 获取归档信息
  * - 查询交易历史
  * - 处理交易数据
- * - 实现容错和重试机制
+ * - 实现容错和重试机制（调用经过全局限流和熔断，见 ic::client）
  * 
  * 主要组件:
  * - fetch_archives函数 (第35-58行): 获取归档canister信息
  * - fetch_archive_transactions函数 (第60-193行): 从归档canister获取历史交易
  *   - 尝试多种解码方式适应不同格式 (第105-159行)
- *   - 实现重试机制 (第89-101行, 第160-193行)
  * - fetch_ledger_transactions函数 (第196-283行): 从主账本获取交易
  *   - 尝试多种解码方式 (第202-257行)
  *   - 处理交易索引和日志长度 (第206-215行)
  * - get_first_transaction_index函数 (第286-342行): 获取区块链上的第一个交易索引
  * - test_archive_transactions函数 (第345-496行): 测试归档canister可用性
+ * - get_ledger_log_length函数: 获取主账本当前的日志长度
+ * - fetch_balance_of函数: 查询账户当前余额
  */
 
 use std::error::Error;
@@ -25,26 +26,23 @@ use candid::{Encode, Decode};
 use num_traits::ToPrimitive;
 use log::{info, error, warn, debug};
 use crate::models::{
-    ArchivesResult, ArchiveInfo, GetTransactionsArg, Transaction, 
+    Account, ArchivesResult, ArchiveInfo, GetTransactionsArg, Transaction, 
     LedgerGetTransactionsResult, SimpleTransactionRange,
     TransactionList
 };
 use crate::utils::create_error;
-use tokio::time::Duration;
+use crate::ic::client::{query, query_with_retry};
 
 /// 查询archives方法获取归档信息
 pub async fn fetch_archives(
     agent: &Agent,
     canister_id: &Principal,
-) -> Result<Vec<ArchiveInfo>, Box<dyn Error>> {
+) -> Result<Vec<ArchiveInfo>, Box<dyn Error + Send + Sync>> {
     info!("获取归档信息...");
     
     let empty_tuple = ();
     let arg_bytes = Encode!(&empty_tuple)?;
-    let response = agent.query(canister_id, "archives")
-        .with_arg(arg_bytes)
-        .call()
-        .await?;
+    let response = query(agent, canister_id, "archives", &arg_bytes).await?;
     
     let archives_result: ArchivesResult = Decode!(&response, ArchivesResult)?;
     
@@ -63,7 +61,7 @@ pub async fn fetch_archive_transactions(
     archive_canister_id: &Principal,
     start: u64,
     length: u64,
-) -> Result<Vec<Transaction>, Box<dyn Error>> {
+) -> Result<Vec<Transaction>, Box<dyn Error + Send + Sync>> {
     debug!("从归档canister获取交易: start={}, length={}", start, length);
     
     if length == 0 {
@@ -86,111 +84,92 @@ pub async fn fetch_archive_transactions(
     
     debug!("调用归档canister: {}", archive_canister_id);
     
-    // 添加重试逻辑
-    let max_retries = 3;
-    let mut retry_count = 0;
-    let mut last_error = None;
-    
-    while retry_count < max_retries {
-        match agent.query(archive_canister_id, "get_transactions")
-            .with_arg(arg_bytes.clone())
-            .call()
-            .await {
-            Ok(response) => {
-                debug!("收到归档canister响应，长度: {} 字节", response.len());
-                
-                // 尝试多种可能的结构解码方式
-                
-                // 1. 首先尝试解码为SimpleTransactionRange（调整顺序，优先尝试）
-                debug!("尝试解码为SimpleTransactionRange...");
-                if let Ok(range) = Decode!(&response, SimpleTransactionRange) {
-                    let tx_count = range.transactions.len();
-                    debug!("成功解码为SimpleTransactionRange，交易数量: {}", tx_count);
-                    
-                    // 输出精简信息到命令行
-                    if tx_count > 0 {
-                        let end = start + tx_count as u64 - 1;
-                        info!("成功获取到归档交易批次：{}-{}，使用SimpleTransactionRange解码，已保存到数据库", start, end);
-                    }
-                    
-                    // 给交易添加索引信息
-                    let mut indexed_transactions = Vec::new();
-                    for (i, mut tx) in range.transactions.into_iter().enumerate() {
-                        let index = start + i as u64;
-                        tx.index = Some(index);
-                        indexed_transactions.push(tx);
-                    }
-                    
-                    return Ok(indexed_transactions);
-                }
-                
-                // 2. 尝试解码为TransactionList(Vec<Transaction>)
-                debug!("尝试解码为TransactionList...");
-                if let Ok(list) = Decode!(&response, TransactionList) {
-                    let tx_count = list.0.len();
-                    debug!("成功解码为TransactionList，交易数量: {}", tx_count);
-                    
-                    // 输出精简信息到命令行
-                    if tx_count > 0 {
-                        let end = start + tx_count as u64 - 1;
-                        info!("成功获取到归档交易批次：{}-{}，使用TransactionList解码，已保存到数据库", start, end);
-                    }
-                    
-                    // 给交易添加索引信息
-                    let mut indexed_transactions = Vec::new();
-                    for (i, mut tx) in list.0.into_iter().enumerate() {
-                        let index = start + i as u64;
-                        tx.index = Some(index);
-                        indexed_transactions.push(tx);
-                    }
-                    
-                    return Ok(indexed_transactions);
-                }
-                
-                // 3. 尝试直接解码为Vec<Transaction>
-                debug!("尝试解码为Vec<Transaction>...");
-                if let Ok(transactions) = Decode!(&response, Vec<Transaction>) {
-                    let tx_count = transactions.len();
-                    debug!("成功解码为Vec<Transaction>，交易数量: {}", tx_count);
-                    
-                    if tx_count > 0 {
-                        let end = start + tx_count as u64 - 1;
-                        info!("成功获取到归档交易批次：{}-{}，使用Vec<Transaction>解码，已保存到数据库", start, end);
-                    }
-                    
-                    // 给交易添加索引信息
-                    let mut indexed_transactions = Vec::new();
-                    for (i, mut tx) in transactions.into_iter().enumerate() {
-                        let index = start + i as u64;
-                        tx.index = Some(index);
-                        indexed_transactions.push(tx);
-                    }
-                    
-                    return Ok(indexed_transactions);
-                }
-                
-                // 所有解码方法都失败，但API调用成功了，重试可能没用
-                debug!("所有解码方法都失败，返回空交易列表");
-                error!("解码错误：归档交易批次 {}-{} 所有解码方式均失败，API调用成功但无法解析响应数据，已跳过此批次", 
-                      start, start + length - 1);
-                return Ok(Vec::new());
-            },
-            Err(e) => {
-                retry_count += 1;
-                last_error = Some(e);
-                let wait_time = Duration::from_secs(2 * retry_count); // 指数退避
-                warn!("网络错误：调用归档canister失败 (尝试 {}/{}): {}，等待 {:?} 后重试", 
-                    retry_count, max_retries, last_error.as_ref().unwrap(), wait_time);
-                tokio::time::sleep(wait_time).await;
-            }
+    let response = match query_with_retry(agent, archive_canister_id, "get_transactions", &arg_bytes, "调用归档canister").await {
+        Ok(response) => response,
+        Err(e) => {
+            error!("网络错误：调用归档canister {} 失败，无法获取交易批次 {}-{}: {}", 
+                  archive_canister_id, start, start + length - 1, e);
+            return Err(e);
+        }
+    };
+    
+    debug!("收到归档canister响应，长度: {} 字节", response.len());
+    
+    // 尝试多种可能的结构解码方式
+    
+    // 1. 首先尝试解码为SimpleTransactionRange（调整顺序，优先尝试）
+    debug!("尝试解码为SimpleTransactionRange...");
+    if let Ok(range) = Decode!(&response, SimpleTransactionRange) {
+        let tx_count = range.transactions.len();
+        debug!("成功解码为SimpleTransactionRange，交易数量: {}", tx_count);
+        
+        // 输出精简信息到命令行
+        if tx_count > 0 {
+            let end = start + tx_count as u64 - 1;
+            info!("成功获取到归档交易批次：{}-{}，使用SimpleTransactionRange解码，已保存到数据库", start, end);
+        }
+        
+        // 给交易添加索引信息
+        let mut indexed_transactions = Vec::new();
+        for (i, mut tx) in range.transactions.into_iter().enumerate() {
+            let index = start + i as u64;
+            tx.index = Some(index);
+            indexed_transactions.push(tx);
+        }
+        
+        return Ok(indexed_transactions);
+    }
+    
+    // 2. 尝试解码为TransactionList(Vec<Transaction>)
+    debug!("尝试解码为TransactionList...");
+    if let Ok(list) = Decode!(&response, TransactionList) {
+        let tx_count = list.0.len();
+        debug!("成功解码为TransactionList，交易数量: {}", tx_count);
+        
+        // 输出精简信息到命令行
+        if tx_count > 0 {
+            let end = start + tx_count as u64 - 1;
+            info!("成功获取到归档交易批次：{}-{}，使用TransactionList解码，已保存到数据库", start, end);
+        }
+        
+        // 给交易添加索引信息
+        let mut indexed_transactions = Vec::new();
+        for (i, mut tx) in list.0.into_iter().enumerate() {
+            let index = start + i as u64;
+            tx.index = Some(index);
+            indexed_transactions.push(tx);
         }
+        
+        return Ok(indexed_transactions);
     }
     
-    // 如果达到最大重试次数仍然失败
-    error!("网络错误：达到最大重试次数 ({}), 调用归档canister {} 失败，无法获取交易批次 {}-{}", 
-          max_retries, archive_canister_id, start, start + length - 1);
-    Err(create_error(&format!("调用归档canister失败，已重试 {} 次: {}", 
-            max_retries, last_error.unwrap())))
+    // 3. 尝试直接解码为Vec<Transaction>
+    debug!("尝试解码为Vec<Transaction>...");
+    if let Ok(transactions) = Decode!(&response, Vec<Transaction>) {
+        let tx_count = transactions.len();
+        debug!("成功解码为Vec<Transaction>，交易数量: {}", tx_count);
+        
+        if tx_count > 0 {
+            let end = start + tx_count as u64 - 1;
+            info!("成功获取到归档交易批次：{}-{}，使用Vec<Transaction>解码，已保存到数据库", start, end);
+        }
+        
+        // 给交易添加索引信息
+        let mut indexed_transactions = Vec::new();
+        for (i, mut tx) in transactions.into_iter().enumerate() {
+            let index = start + i as u64;
+            tx.index = Some(index);
+            indexed_transactions.push(tx);
+        }
+        
+        return Ok(indexed_transactions);
+    }
+    
+    // 所有解码方法都失败，但API调用成功了，重试可能没用
+    debug!("所有解码方法都失败，返回空交易列表");
+    error!("解码错误：归档交易批次 {}-{} 所有解码方式均失败，API调用成功但无法解析响应数据，已跳过此批次", 
+          start, start + length - 1);
+    Ok(Vec::new())
 }
 
 /// 获取主 canister 交易
@@ -199,7 +178,7 @@ pub async fn fetch_ledger_transactions(
     canister_id: &Principal,
     start: u64,
     length: u64,
-) -> Result<(Vec<Transaction>, u64, u64), Box<dyn Error>> {
+) -> Result<(Vec<Transaction>, u64, u64), Box<dyn Error + Send + Sync>> {
     debug!("查询ledger交易: start={}, length={}", start, length);
     
     // 验证参数
@@ -221,109 +200,90 @@ pub async fn fetch_ledger_transactions(
         }
     };
     
-    // 实现重试机制
-    let max_retries = 3;
-    let mut retry_count = 0;
-    let mut last_error = None;
-    
-    while retry_count < max_retries {
-        match agent.query(canister_id, "get_transactions")
-            .with_arg(arg_bytes.clone())
-            .call()
-            .await {
-            Ok(response) => {
-                debug!("收到ledger响应，长度: {} 字节", response.len());
+    let response = match query_with_retry(agent, canister_id, "get_transactions", &arg_bytes, "调用ledger canister").await {
+        Ok(response) => response,
+        Err(e) => {
+            error!("网络错误：调用主账本canister失败，无法获取交易批次 {}-{}: {}", 
+                  start, start + length - 1, e);
+            return Err(e);
+        }
+    };
+    
+    debug!("收到ledger响应，长度: {} 字节", response.len());
+    
+    // 尝试使用LedgerGetTransactionsResult解析
+    match Decode!(&response, LedgerGetTransactionsResult) {
+        Ok(result) => {
+            debug!("成功解码为LedgerGetTransactionsResult");
+            debug!("first_index: {}, log_length: {}, 交易数: {}, 归档交易数: {}", 
+                result.first_index.0, 
+                result.log_length.0,
+                result.transactions.len(),
+                result.archived_transactions.len());
+            
+            let first_index = result.first_index.0.to_u64().unwrap_or(0);
+            let log_length = result.log_length.0.to_u64().unwrap_or(0);
+            let tx_count = result.transactions.len();
+            
+            // 输出精简信息到命令行
+            if tx_count > 0 {
+                let end = first_index + tx_count as u64 - 1;
+                info!("成功获取到主账本交易批次：{}-{}，使用LedgerGetTransactionsResult解码，已保存到数据库", first_index, end);
+            } else {
+                debug!("主账本未返回任何交易");
+            }
+            
+            // 给交易添加索引信息
+            let mut transactions = Vec::new();
+            for (i, mut tx) in result.transactions.into_iter().enumerate() {
+                let index = first_index + i as u64;
+                tx.index = Some(index);
+                transactions.push(tx);
+            }
+            
+            Ok((transactions, first_index, log_length))
+        },
+        Err(e) => {
+            debug!("解析ledger响应失败，尝试备用解码方法: {}", e);
+            
+            // 尝试使用SimpleTransactionRange解析
+            if let Ok(range) = Decode!(&response, SimpleTransactionRange) {
+                debug!("使用SimpleTransactionRange成功解析");
+                let tx_count = range.transactions.len();
                 
-                // 尝试使用LedgerGetTransactionsResult解析
-                match Decode!(&response, LedgerGetTransactionsResult) {
-                    Ok(result) => {
-                        debug!("成功解码为LedgerGetTransactionsResult");
-                        debug!("first_index: {}, log_length: {}, 交易数: {}, 归档交易数: {}", 
-                            result.first_index.0, 
-                            result.log_length.0,
-                            result.transactions.len(),
-                            result.archived_transactions.len());
-                        
-                        let first_index = result.first_index.0.to_u64().unwrap_or(0);
-                        let log_length = result.log_length.0.to_u64().unwrap_or(0);
-                        let tx_count = result.transactions.len();
-                        
-                        // 输出精简信息到命令行
-                        if tx_count > 0 {
-                            let end = first_index + tx_count as u64 - 1;
-                            info!("成功获取到主账本交易批次：{}-{}，使用LedgerGetTransactionsResult解码，已保存到数据库", first_index, end);
-                        } else {
-                            debug!("主账本未返回任何交易");
-                        }
-                        
-                        // 给交易添加索引信息
-                        let mut transactions = Vec::new();
-                        for (i, mut tx) in result.transactions.into_iter().enumerate() {
-                            let index = first_index + i as u64;
-                            tx.index = Some(index);
-                            transactions.push(tx);
-                        }
-                        
-                        return Ok((transactions, first_index, log_length));
-                    },
-                    Err(e) => {
-                        debug!("解析ledger响应失败，尝试备用解码方法: {}", e);
-                        
-                        // 尝试使用SimpleTransactionRange解析
-                        if let Ok(range) = Decode!(&response, SimpleTransactionRange) {
-                            debug!("使用SimpleTransactionRange成功解析");
-                            let tx_count = range.transactions.len();
-                            
-                            if tx_count > 0 {
-                                let end = start + tx_count as u64 - 1;
-                                info!("成功获取到主账本交易批次：{}-{}，使用SimpleTransactionRange解码，已保存到数据库", start, end);
-                                
-                                // 给交易添加索引信息
-                                let mut transactions = Vec::new();
-                                for (i, mut tx) in range.transactions.into_iter().enumerate() {
-                                    let index = start + i as u64;
-                                    tx.index = Some(index);
-                                    transactions.push(tx);
-                                }
-                                
-                                return Ok((transactions, start, start + tx_count as u64));
-                            }
-                            
-                            // 由于SimpleTransactionRange没有first_index信息，假设为start
-                            return Ok((Vec::new(), start, start));
-                        }
-                        
-                        // 如果两种解码方法都失败，但API调用成功，返回空结果
-                        debug!("所有解码方法都失败，返回空交易列表");
-                        error!("解码错误：主账本交易批次 {}-{} 所有解码方式均失败，API调用成功但无法解析响应数据，已跳过此批次", 
-                              start, start + length - 1);
-                        return Ok((Vec::new(), start, start));
+                if tx_count > 0 {
+                    let end = start + tx_count as u64 - 1;
+                    info!("成功获取到主账本交易批次：{}-{}，使用SimpleTransactionRange解码，已保存到数据库", start, end);
+                    
+                    // 给交易添加索引信息
+                    let mut transactions = Vec::new();
+                    for (i, mut tx) in range.transactions.into_iter().enumerate() {
+                        let index = start + i as u64;
+                        tx.index = Some(index);
+                        transactions.push(tx);
                     }
+                    
+                    return Ok((transactions, start, start + tx_count as u64));
                 }
-            },
-            Err(e) => {
-                retry_count += 1;
-                last_error = Some(e);
-                let wait_time = Duration::from_secs(2 * retry_count); // 指数退避
-                warn!("网络错误：调用主账本canister失败 (尝试 {}/{}): {}，等待 {:?} 后重试", 
-                    retry_count, max_retries, last_error.as_ref().unwrap(), wait_time);
-                tokio::time::sleep(wait_time).await;
+                
+                // 由于SimpleTransactionRange没有first_index信息，假设为start
+                return Ok((Vec::new(), start, start));
             }
+            
+            // 如果两种解码方法都失败，但API调用成功，返回空结果
+            debug!("所有解码方法都失败，返回空交易列表");
+            error!("解码错误：主账本交易批次 {}-{} 所有解码方式均失败，API调用成功但无法解析响应数据，已跳过此批次", 
+                  start, start + length - 1);
+            Ok((Vec::new(), start, start))
         }
     }
-    
-    // 如果达到最大重试次数仍然失败
-    error!("网络错误：达到最大重试次数 ({}), 调用主账本canister失败，无法获取交易批次 {}-{}", 
-          max_retries, start, start + length - 1);
-    Err(create_error(&format!("调用ledger canister失败，已重试 {} 次: {}", 
-            max_retries, last_error.unwrap())))
 }
 
 /// 获取区块链上的第一个交易索引
 pub async fn get_first_transaction_index(
     agent: &Agent,
     canister_id: &Principal,
-) -> Result<u64, Box<dyn Error>> {
+) -> Result<u64, Box<dyn Error + Send + Sync>> {
     debug!("尝试获取区块链上的第一个交易索引...");
     
     // 查询第一个交易，主要目的是获取first_index
@@ -340,64 +300,41 @@ pub async fn get_first_transaction_index(
         }
     };
     
-    // 实现重试机制
-    let max_retries = 3;
-    let mut retry_count = 0;
-    let mut last_error: Option<String> = None;
-    
-    while retry_count < max_retries {
-        match agent.query(canister_id, "get_transactions")
-            .with_arg(arg_bytes.clone())
-            .call()
-            .await {
-            Ok(response) => {
-                // 尝试解码响应
-                match Decode!(&response, LedgerGetTransactionsResult) {
-                    Ok(result) => {
-                        let first_index = result.first_index.0.to_u64().unwrap_or(0);
-                        info!("获取到区块链初始索引: {}", first_index);
-                        return Ok(first_index);
-                    },
-                    Err(e) => {
-                        debug!("解析ledger响应失败: {}", e);
-                        
-                        // 尝试使用SimpleTransactionRange解析
-                        if let Ok(_range) = Decode!(&response, SimpleTransactionRange) {
-                            debug!("使用SimpleTransactionRange成功解析");
-                            info!("区块链初始索引默认为0");
-                            // 由于SimpleTransactionRange没有first_index信息，假设为0
-                            return Ok(0);
-                        }
-                        
-                        // 如果所有解码方法都失败，可能需要重试
-                        retry_count += 1;
-                        last_error = Some(format!("无法解析响应: {}", e));
-                    }
-                }
-            },
-            Err(e) => {
-                retry_count += 1;
-                let error_msg = format!("调用canister失败: {}", e);
-                last_error = Some(error_msg.clone());
-                let wait_time = Duration::from_secs(2 * retry_count); // 指数退避
-                warn!("调用canister失败 (尝试 {}/{}): {}，等待 {:?} 后重试", 
-                    retry_count, max_retries, error_msg, wait_time);
-                tokio::time::sleep(wait_time).await;
+    let response = query_with_retry(agent, canister_id, "get_transactions", &arg_bytes, "调用canister").await
+        .inspect_err(|_| warn!("无法获取区块链初始索引，将使用默认值0"))?;
+    
+    // 尝试解码响应
+    match Decode!(&response, LedgerGetTransactionsResult) {
+        Ok(result) => {
+            let first_index = result.first_index.0.to_u64().unwrap_or(0);
+            info!("获取到区块链初始索引: {}", first_index);
+            Ok(first_index)
+        },
+        Err(e) => {
+            debug!("解析ledger响应失败: {}", e);
+            
+            // 尝试使用SimpleTransactionRange解析
+            if let Ok(_range) = Decode!(&response, SimpleTransactionRange) {
+                debug!("使用SimpleTransactionRange成功解析");
+                info!("区块链初始索引默认为0");
+                // 由于SimpleTransactionRange没有first_index信息，假设为0
+                return Ok(0);
             }
+            
+            warn!("无法获取区块链初始索引，将使用默认值0");
+            Err(create_error(&format!("无法解析响应: {}", e)))
         }
     }
-    
-    warn!("无法获取区块链初始索引，将使用默认值0");
-    Err(create_error(&last_error.unwrap_or_else(|| "尝试获取区块链初始索引失败，达到最大重试次数".to_string())))
 }
 
+
 /// 测试归档canister可用性，不记录普通交易日志
 pub async fn test_archive_transactions(
     agent: &Agent,
     archive_canister_id: &Principal,
     start: u64,
     length: u64,
-) -> Result<Vec<Transaction>, Box<dyn Error>> {
+) -> Result<Vec<Transaction>, Box<dyn Error + Send + Sync>> {
     debug!("测试归档canister可用性: start={}, length={}", start, length);
     
     if length == 0 {
@@ -420,96 +357,108 @@ pub async fn test_archive_transactions(
     
     debug!("测试调用归档canister: {}", archive_canister_id);
     
-    // 添加重试逻辑
-    let max_retries = 3;
-    let mut retry_count = 0;
-    let mut last_error = None;
-    
-    while retry_count < max_retries {
-        match agent.query(archive_canister_id, "get_transactions")
-            .with_arg(arg_bytes.clone())
-            .call()
-            .await {
-            Ok(response) => {
-                debug!("收到归档canister测试响应，长度: {} 字节", response.len());
-                
-                // 尝试多种可能的结构解码方式
-                
-                // 1. 首先尝试解码为SimpleTransactionRange（调整顺序，优先尝试）
-                debug!("尝试解码为SimpleTransactionRange...");
-                if let Ok(range) = Decode!(&response, SimpleTransactionRange) {
-                    let tx_count = range.transactions.len();
-                    debug!("测试成功解码为SimpleTransactionRange，交易数量: {}", tx_count);
-                    
-                    // 给交易添加索引信息
-                    let mut indexed_transactions = Vec::new();
-                    for (i, mut tx) in range.transactions.into_iter().enumerate() {
-                        let index = start + i as u64;
-                        tx.index = Some(index);
-                        indexed_transactions.push(tx);
-                    }
-                    
-                    debug!("归档canister测试成功");
-                    return Ok(indexed_transactions);
-                }
-                
-                // 2. 尝试解码为TransactionList(Vec<Transaction>)
-                debug!("尝试解码为TransactionList...");
-                if let Ok(list) = Decode!(&response, TransactionList) {
-                    let tx_count = list.0.len();
-                    debug!("测试成功解码为TransactionList，交易数量: {}", tx_count);
-                    
-                    // 给交易添加索引信息
-                    let mut indexed_transactions = Vec::new();
-                    for (i, mut tx) in list.0.into_iter().enumerate() {
-                        let index = start + i as u64;
-                        tx.index = Some(index);
-                        indexed_transactions.push(tx);
-                    }
-                    
-                    debug!("归档canister测试成功");
-                    return Ok(indexed_transactions);
-                }
-                
-                // 3. 尝试直接解码为Vec<Transaction>
-                debug!("尝试解码为Vec<Transaction>...");
-                if let Ok(transactions) = Decode!(&response, Vec<Transaction>) {
-                    let tx_count = transactions.len();
-                    debug!("测试成功解码为Vec<Transaction>，交易数量: {}", tx_count);
-                    
-                    // 给交易添加索引信息
-                    let mut indexed_transactions = Vec::new();
-                    for (i, mut tx) in transactions.into_iter().enumerate() {
-                        let index = start + i as u64;
-                        tx.index = Some(index);
-                        indexed_transactions.push(tx);
-                    }
-                    
-                    debug!("归档canister测试成功");
-                    return Ok(indexed_transactions);
-                }
-                
-                // 所有解码方法都失败，但API调用成功了，重试可能没用
-                debug!("测试解码失败，返回空交易列表");
-                error!("解码错误：测试归档canister {} 所有解码方式均失败，API调用成功但无法解析响应数据", 
-                      archive_canister_id);
-                return Ok(Vec::new());
-            },
-            Err(e) => {
-                retry_count += 1;
-                last_error = Some(e);
-                let wait_time = Duration::from_secs(2 * retry_count); // 指数退避
-                warn!("网络错误：测试调用归档canister失败 (尝试 {}/{}): {}，等待 {:?} 后重试", 
-                    retry_count, max_retries, last_error.as_ref().unwrap(), wait_time);
-                tokio::time::sleep(wait_time).await;
-            }
+    let response = match query_with_retry(agent, archive_canister_id, "get_transactions", &arg_bytes, "测试调用归档canister").await {
+        Ok(response) => response,
+        Err(e) => {
+            error!("网络错误：测试调用归档canister {} 失败: {}", archive_canister_id, e);
+            return Err(e);
+        }
+    };
+    
+    debug!("收到归档canister测试响应，长度: {} 字节", response.len());
+    
+    // 尝试多种可能的结构解码方式
+    
+    // 1. 首先尝试解码为SimpleTransactionRange（调整顺序，优先尝试）
+    debug!("尝试解码为SimpleTransactionRange...");
+    if let Ok(range) = Decode!(&response, SimpleTransactionRange) {
+        let tx_count = range.transactions.len();
+        debug!("测试成功解码为SimpleTransactionRange，交易数量: {}", tx_count);
+        
+        // 给交易添加索引信息
+        let mut indexed_transactions = Vec::new();
+        for (i, mut tx) in range.transactions.into_iter().enumerate() {
+            let index = start + i as u64;
+            tx.index = Some(index);
+            indexed_transactions.push(tx);
         }
+        
+        debug!("归档canister测试成功");
+        return Ok(indexed_transactions);
     }
     
-    // 如果达到最大重试次数仍然失败
-    error!("网络错误：达到最大重试次数 ({}), 测试调用归档canister {} 失败", 
-          max_retries, archive_canister_id);
-    Err(create_error(&format!("测试调用归档canister失败，已重试 {} 次: {}", 
-            max_retries, last_error.unwrap())))
+    // 2. 尝试解码为TransactionList(Vec<Transaction>)
+    debug!("尝试解码为TransactionList...");
+    if let Ok(list) = Decode!(&response, TransactionList) {
+        let tx_count = list.0.len();
+        debug!("测试成功解码为TransactionList，交易数量: {}", tx_count);
+        
+        // 给交易添加索引信息
+        let mut indexed_transactions = Vec::new();
+        for (i, mut tx) in list.0.into_iter().enumerate() {
+            let index = start + i as u64;
+            tx.index = Some(index);
+            indexed_transactions.push(tx);
+        }
+        
+        debug!("归档canister测试成功");
+        return Ok(indexed_transactions);
+    }
+    
+    // 3. 尝试直接解码为Vec<Transaction>
+    debug!("尝试解码为Vec<Transaction>...");
+    if let Ok(transactions) = Decode!(&response, Vec<Transaction>) {
+        let tx_count = transactions.len();
+        debug!("测试成功解码为Vec<Transaction>，交易数量: {}", tx_count);
+        
+        // 给交易添加索引信息
+        let mut indexed_transactions = Vec::new();
+        for (i, mut tx) in transactions.into_iter().enumerate() {
+            let index = start + i as u64;
+            tx.index = Some(index);
+            indexed_transactions.push(tx);
+        }
+        
+        debug!("归档canister测试成功");
+        return Ok(indexed_transactions);
+    }
+    
+    // 所有解码方法都失败，但API调用成功了，重试可能没用
+    debug!("测试解码失败，返回空交易列表");
+    error!("解码错误：测试归档canister {} 所有解码方式均失败，API调用成功但无法解析响应数据", 
+          archive_canister_id);
+    Ok(Vec::new())
 }
 
+
+/// 获取主账本当前的日志长度（下一笔交易的索引）
+pub async fn get_ledger_log_length(
+    agent: &Agent,
+    canister_id: &Principal,
+) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    let arg = GetTransactionsArg {
+        start: candid::Nat::from(0u64),
+        length: candid::Nat::from(0u64),
+    };
+    let arg_bytes = Encode!(&arg)?;
+    
+    let response = query_with_retry(agent, canister_id, "get_transactions", &arg_bytes, "查询ledger日志长度").await?;
+    let result = Decode!(&response, LedgerGetTransactionsResult)
+        .map_err(|e| create_error(&format!("解析ledger响应失败: {}", e)))?;
+    Ok(result.log_length.0.to_u64().unwrap_or(0))
+}
+
+/// 查询账户当前余额（icrc1_balance_of）
+pub async fn fetch_balance_of(
+    agent: &Agent,
+    canister_id: &Principal,
+    account: &Account,
+) -> Result<candid::Nat, Box<dyn Error + Send + Sync>> {
+    let arg_bytes = Encode!(account)?;
+    
+    let response = query_with_retry(agent, canister_id, "icrc1_balance_of", &arg_bytes,
+        &format!("查询账户 {} 余额", account)).await?;
+    let balance = Decode!(&response, candid::Nat)
+        .map_err(|e| create_error(&format!("解析余额失败: {}", e)))?;
+    Ok(balance)
+}
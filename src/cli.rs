@@ -0,0 +1,272 @@
+/**
+ * 文件描述: 命令行接口定义
+ * 功能概述:
+ * - 使用 clap 解析子命令和全局参数（--config）
+ * - 不带子命令运行时等同于 run，兼容原有的启动方式（包括隐藏的 --reset 参数）
+ * - 支持拆分部署：sync 只执行同步，serve 作为只读 API 实例
+ * - 定义适合脚本和定时任务使用的退出码
+ *
+ * 主要组件:
+ * - Cli结构体: 全局参数和子命令
+ * - Command枚举: run / sync / serve / reset / rebuild / verify / status / migrate / snapshot / backfill
+ * - SnapshotCommand枚举: 快照导出和导入
+ * - BackfillCommand枚举: 分片回填的计划、工作进程、进度、收尾和重试
+ * - IndexRange结构体: verify 使用的交易索引区间（a..b 或 a..=b）
+ * - EXIT_*常量: 进程退出码
+ */
+
+use std::fmt;
+use std::str::FromStr;
+use clap::{Parser, Subcommand};
+use crate::config::DEFAULT_CONFIG_FILE;
+
+/// 执行成功
+pub const EXIT_OK: u8 = 0;
+/// 运行出错（数据库、IC 网络等）
+pub const EXIT_FAILURE: u8 = 1;
+/// 命令行参数错误（clap 解析失败时同样以 2 退出）
+pub const EXIT_USAGE: u8 = 2;
+/// 配置文件缺失或无法解析
+pub const EXIT_CONFIG: u8 = 3;
+/// verify 发现不一致，或 status 发现需要处理的代币
+pub const EXIT_CHECK_FAILED: u8 = 4;
+/// 收到停止信号，命令未执行完
+pub const EXIT_INTERRUPTED: u8 = 130;
+
+/// ICP 代币交易索引服务
+#[derive(Debug, Parser)]
+#[command(name = "index-rs", version, about = "ICP 代币交易索引服务")]
+pub struct Cli {
+    /// 配置文件路径
+    #[arg(long, global = true, default_value = DEFAULT_CONFIG_FILE)]
+    pub config: String,
+
+    /// 兼容旧版本的 `--reset` 启动参数，未指定子命令时等同于 reset
+    #[arg(long, hide = true)]
+    pub reset: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+impl Cli {
+    /// 本次运行的子命令，未指定时为 run（旧版本的 `--reset` 参数对应 reset）
+    pub fn command(&self) -> Command {
+        self.command.clone().unwrap_or(if self.reset {
+            Command::Reset { token: None }
+        } else {
+            Command::Run
+        })
+    }
+}
+
+/// 子命令
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    /// 启动索引服务（默认）：同步所有代币，配置中启用 API 服务器时同时提供查询接口
+    Run,
+    /// 只同步代币，不启动 API 服务器
+    Sync,
+    /// 只读 API 实例：不连接 IC 网络、不执行同步和迁移，从代币注册表读取代币列表，要求配置中启用 API 服务器
+    Serve,
+    /// 清空代币的全部数据并从链上重新同步，完成后退出
+    Reset {
+        /// 只重置指定代币（默认重置所有同步中的代币）
+        #[arg(long)]
+        token: Option<String>,
+    },
+    /// 根据已保存的交易重建账户、余额和总供应量，不从链上重新获取交易
+    Rebuild {
+        /// 只重建指定代币（默认重建所有同步中的代币，已暂停或移除的代币需要显式指定）
+        #[arg(long)]
+        token: Option<String>,
+    },
+    /// 将数据库中指定区间的交易与链上数据逐笔比对，发现不一致时以退出码 4 退出
+    Verify {
+        /// 代币符号
+        #[arg(long)]
+        token: String,
+        /// 交易索引区间，a..b 不含 b，a..=b 含 b
+        #[arg(long)]
+        range: IndexRange,
+    },
+    /// 输出各代币的同步状态；同步中的代币未完成初始同步、存在缺口或重建未完成时以退出码 4 退出
+    Status {
+        /// 只输出指定代币
+        #[arg(long)]
+        token: Option<String>,
+        /// 以 JSON 格式输出
+        #[arg(long)]
+        json: bool,
+    },
+    /// 只执行数据库迁移后退出
+    Migrate,
+    /// 导出或导入代币快照
+    #[command(subcommand)]
+    Snapshot(SnapshotCommand),
+    /// 分片回填：将超大账本的初始同步拆分给多个工作进程并行执行
+    #[command(subcommand)]
+    Backfill(BackfillCommand),
+}
+
+/// 快照子命令
+#[derive(Debug, Clone, Subcommand)]
+pub enum SnapshotCommand {
+    /// 导出代币快照，默认导出到余额已计算的位置
+    Export {
+        /// 代币符号
+        #[arg(long)]
+        token: String,
+        /// 导出截至该交易索引（含）的状态
+        #[arg(long)]
+        at_index: Option<u64>,
+        /// 输出文件路径（默认写入 snapshots/ 目录）
+        #[arg(long)]
+        output: Option<String>,
+    },
+    /// 将快照导入到空数据库
+    Import {
+        /// 快照文件路径
+        #[arg(long)]
+        file: String,
+        /// 只导入指定代币（默认使用快照中记录的代币）
+        #[arg(long)]
+        token: Option<String>,
+    },
+}
+
+/// 分片回填子命令
+#[derive(Debug, Clone, Subcommand)]
+pub enum BackfillCommand {
+    /// 将代币的初始同步区间拆分为工作单元，保存到 backfill_jobs 集合
+    Plan {
+        /// 代币符号
+        #[arg(long)]
+        token: String,
+        /// 每个工作单元包含的交易索引数量
+        #[arg(long, default_value_t = crate::sync::backfill::DEFAULT_UNIT_SIZE)]
+        unit_size: u64,
+    },
+    /// 作为工作进程认领并处理工作单元，所选代币的单元全部完成后退出
+    Work {
+        /// 只处理指定代币（默认处理所有有回填计划的代币）
+        #[arg(long)]
+        token: Option<String>,
+        /// 本进程同时处理的工作单元数量
+        #[arg(long, default_value_t = 1)]
+        concurrency: usize,
+    },
+    /// 输出回填进度；存在超过最大尝试次数的工作单元时以退出码 4 退出
+    Status {
+        /// 只输出指定代币
+        #[arg(long)]
+        token: Option<String>,
+        /// 以 JSON 格式输出
+        #[arg(long)]
+        json: bool,
+    },
+    /// 参与处理剩余的工作单元，全部完成后计算余额并切换到增量同步（同步进程启动时也会自动执行）
+    Finish {
+        /// 代币符号
+        #[arg(long)]
+        token: String,
+    },
+    /// 重置超过最大尝试次数的工作单元，使其可以再次被认领
+    Retry {
+        /// 代币符号
+        #[arg(long)]
+        token: String,
+    },
+}
+
+/// 交易索引区间（含两端）
+#[derive(Debug, Clone, Copy)]
+pub struct IndexRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl FromStr for IndexRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |value: &str| value.trim().parse::<u64>()
+            .map_err(|_| format!("无效的交易索引: {}", value));
+        let (start, end) = if let Some((start, end)) = s.split_once("..=") {
+            (parse(start)?, parse(end)?)
+        } else if let Some((start, end)) = s.split_once("..") {
+            let end = parse(end)?;
+            if end == 0 {
+                return Err(format!("区间为空: {}", s));
+            }
+            (parse(start)?, end - 1)
+        } else {
+            return Err(format!("区间格式应为 a..b 或 a..=b: {}", s));
+        };
+        if start > end {
+            return Err(format!("区间为空: {}", s));
+        }
+        Ok(IndexRange { start, end })
+    }
+}
+
+impl fmt::Display for IndexRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}..={}", self.start, self.end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Result<(u64, u64), String> {
+        s.parse::<IndexRange>().map(|range| (range.start, range.end))
+    }
+
+    #[test]
+    fn exclusive_range_excludes_end() {
+        assert_eq!(parse("100..200"), Ok((100, 199)));
+        assert_eq!(parse("0..1"), Ok((0, 0)));
+    }
+
+    #[test]
+    fn inclusive_range_keeps_end() {
+        assert_eq!(parse("100..=200"), Ok((100, 200)));
+        assert_eq!(parse("5..=5"), Ok((5, 5)));
+        assert_eq!(parse(" 1 ..= 2 "), Ok((1, 2)));
+    }
+
+    #[test]
+    fn empty_and_reversed_ranges_are_rejected() {
+        assert!(parse("5..0").is_err());
+        assert!(parse("0..0").is_err());
+        assert!(parse("5..5").is_err());
+        assert!(parse("10..=9").is_err());
+        assert!(parse("10..3").is_err());
+    }
+
+    #[test]
+    fn malformed_ranges_are_rejected() {
+        assert!(parse("100").is_err());
+        assert!(parse("a..10").is_err());
+        assert!(parse("1..=b").is_err());
+        assert!(parse("-1..10").is_err());
+        assert!(parse("..10").is_err());
+    }
+
+    #[test]
+    fn legacy_reset_flag_maps_to_reset() {
+        let cli = Cli::parse_from(["index-rs", "--reset"]);
+        assert!(matches!(cli.command(), Command::Reset { token: None }));
+        let cli = Cli::parse_from(["index-rs"]);
+        assert!(matches!(cli.command(), Command::Run));
+    }
+
+    #[test]
+    fn display_round_trips() {
+        let range: IndexRange = "3..10".parse().unwrap();
+        assert_eq!(range.to_string(), "3..=9");
+        assert_eq!(parse(&range.to_string()), Ok((3, 9)));
+    }
+}
@@ -0,0 +1,436 @@
+/**
+ * 文件描述: 一次性子命令的实现（reset / rebuild / verify / status / backfill）
+ * 功能概述:
+ * - 按 --token 选择要处理的代币，未指定时处理全部代币
+ * - 调用 sync 和 db 模块完成重置、重建、校验和状态查询
+ * - 返回进程退出码（见 cli 模块的 EXIT_* 常量），便于脚本和定时任务判断结果
+ * - reset 和 rebuild 执行前获取代币的同步租约，其他实例正在同步该代币时跳过
+ *
+ * 主要组件:
+ * - select_tokens函数: 按 --token 选择代币
+ * - acquire_lease / release_lease函数: 为修改数据的命令获取和释放代币的同步租约
+ * - reset函数: 重置代币并从链上重新同步
+ * - rebuild函数: 根据已保存的交易重建派生数据
+ * - verify函数: 比对数据库与链上的交易
+ * - status函数: 输出各代币的同步状态
+ * - backfill_plan / backfill_work / backfill_status / backfill_finish / backfill_retry函数: 分片回填
+ */
+
+use std::error::Error;
+use ic_agent::Agent;
+use log::{info, error, warn};
+use mongodb::bson::{doc, Bson, Document};
+use crate::cli::{IndexRange, EXIT_CHECK_FAILED, EXIT_FAILURE, EXIT_INTERRUPTED, EXIT_OK, EXIT_USAGE};
+use crate::config::parse_canister_id;
+use crate::db::DbConnection;
+use crate::db::leases;
+use crate::db::backfill_jobs::{get_plan, get_progress, retry_failed_units};
+use crate::db::registry::RegistryEntry;
+use crate::models::{TokenConfig, TokenStatus};
+use crate::shutdown;
+use crate::sync::admin::reset_and_sync_all_transactions;
+use crate::sync::rebuild::rebuild_derived_data;
+use crate::sync::verify::verify_range;
+use crate::sync::backfill::{finalize_backfill, plan_backfill, run_backfill_worker};
+
+/// 按 --token 选择代币；未指定时返回 `default`，指定的代币不存在时返回 None
+pub fn select_tokens<'a>(
+    tokens: &'a [TokenConfig],
+    token: Option<&str>,
+    default: Vec<&'a TokenConfig>,
+) -> Option<Vec<&'a TokenConfig>> {
+    match token {
+        Some(symbol) => match tokens.iter().find(|t| t.symbol == symbol) {
+            Some(found) => Some(vec![found]),
+            None => {
+                error!("配置和代币注册表中没有代币 {}", symbol);
+                None
+            },
+        },
+        None => Some(default),
+    }
+}
+
+/// 为修改数据的命令获取代币的同步租约，其他实例持有租约时返回 false
+async fn acquire_lease(db_conn: &DbConnection, token: &TokenConfig) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    if leases::acquire(&db_conn.lease_col, &token.symbol).await? {
+        return Ok(true);
+    }
+    let holder = leases::get_lease(&db_conn.lease_col, &token.symbol).await?
+        .and_then(|lease| lease.get_str("holder").ok().map(|h| h.to_string()))
+        .unwrap_or_else(|| "-".to_string());
+    error!("{}: 实例 {} 正在同步该代币，请先停止该实例后再执行", token.symbol, holder);
+    Ok(false)
+}
+
+/// 释放代币的同步租约；释放失败只记录警告，租约过期后其他实例即可接管，不影响后续代币的处理
+async fn release_lease(db_conn: &DbConnection, token: &TokenConfig) {
+    if let Err(e) = leases::release(&db_conn.lease_col, &token.symbol).await {
+        warn!("{}: 释放同步租约失败，其他实例将在租约过期后接管: {}", token.symbol, e);
+    }
+}
+
+/// 重置代币的全部数据并从链上重新同步
+pub async fn reset(
+    agent: &Agent,
+    db_conn: &DbConnection,
+    targets: &[&TokenConfig],
+) -> Result<u8, Box<dyn Error + Send + Sync>> {
+    let mut failed = 0;
+    for token in targets {
+        if shutdown::is_requested() {
+            break;
+        }
+        // 先校验 canister ID，避免获取租约后出错返回而没有释放租约
+        let canister_id = parse_canister_id(&token.canister_id)?;
+        if !acquire_lease(db_conn, token).await? {
+            failed += 1;
+            continue;
+        }
+        info!("开始代币 {} 的数据库重置和重新同步操作...", token.symbol);
+        match reset_and_sync_all_transactions(agent, &canister_id, db_conn, token).await {
+            Ok(_) => info!("{}: 重置和同步运行成功", token.symbol),
+            Err(e) => {
+                error!("{}: 重置和同步失败: {}", token.symbol, e);
+                failed += 1;
+            },
+        }
+        release_lease(db_conn, token).await;
+    }
+
+    if shutdown::is_requested() {
+        return Ok(EXIT_INTERRUPTED);
+    }
+    Ok(if failed > 0 { EXIT_FAILURE } else { EXIT_OK })
+}
+
+/// 根据已保存的交易重建派生数据
+pub async fn rebuild(
+    db_conn: &DbConnection,
+    targets: &[&TokenConfig],
+) -> Result<u8, Box<dyn Error + Send + Sync>> {
+    let mut incomplete = 0;
+    for token in targets {
+        if shutdown::is_requested() {
+            break;
+        }
+        if !acquire_lease(db_conn, token).await? {
+            incomplete += 1;
+            continue;
+        }
+        let completed = rebuild_derived_data(db_conn, token).await;
+        release_lease(db_conn, token).await;
+        if !completed? {
+            incomplete += 1;
+        }
+    }
+
+    if shutdown::is_requested() {
+        return Ok(EXIT_INTERRUPTED);
+    }
+    Ok(if incomplete > 0 { EXIT_FAILURE } else { EXIT_OK })
+}
+
+/// 比对数据库与链上的交易，结果输出到标准输出
+pub async fn verify(
+    agent: &Agent,
+    db_conn: &DbConnection,
+    token: &TokenConfig,
+    range: IndexRange,
+) -> Result<u8, Box<dyn Error + Send + Sync>> {
+    let report = verify_range(agent, db_conn, token, range.start, range.end).await?;
+
+    println!("{} {}: 已比对 {} 笔交易，缺失 {}，不一致 {}，链上不存在 {}",
+             token.symbol, range, report.checked, report.missing, report.mismatched, report.unexpected);
+    for (label, indices) in [
+        ("缺失", &report.missing_indices),
+        ("不一致", &report.mismatched_indices),
+        ("链上不存在", &report.unexpected_indices),
+    ] {
+        if !indices.is_empty() {
+            let list: Vec<String> = indices.iter().map(|i| i.to_string()).collect();
+            println!("  {}: {}", label, list.join(", "));
+        }
+    }
+
+    if report.interrupted {
+        return Ok(EXIT_INTERRUPTED);
+    }
+    if report.is_consistent() {
+        info!("{}: 区间 {} 的交易与链上一致", token.symbol, range);
+        Ok(EXIT_OK)
+    } else {
+        warn!("{}: 区间 {} 的交易与链上不一致", token.symbol, range);
+        Ok(EXIT_CHECK_FAILED)
+    }
+}
+
+/// 输出各代币的同步状态
+///
+/// 同步中的代币未完成初始同步、存在未回填的缺口或重建未完成时返回 EXIT_CHECK_FAILED。
+pub async fn status(
+    db_conn: &DbConnection,
+    registry: &[RegistryEntry],
+    token: Option<&str>,
+    json: bool,
+) -> Result<u8, Box<dyn Error + Send + Sync>> {
+    let entries: Vec<&RegistryEntry> = registry.iter()
+        .filter(|e| token.map(|t| e.token.symbol == t).unwrap_or(true))
+        .collect();
+    if entries.is_empty() {
+        if let Some(symbol) = token {
+            error!("配置和代币注册表中没有代币 {}", symbol);
+            return Ok(EXIT_USAGE);
+        }
+    }
+
+    let mut reports = Vec::new();
+    let mut needs_attention = false;
+    for entry in entries {
+        let report = token_status(db_conn, entry).await?;
+        if report.get_array("issues").map(|issues| !issues.is_empty()).unwrap_or(false) {
+            needs_attention = true;
+        }
+        reports.push(report);
+    }
+
+    if json {
+        let value = Bson::Array(reports.into_iter().map(Bson::Document).collect()).into_relaxed_extjson();
+        println!("{}", serde_json::to_string_pretty(&value)?);
+    } else {
+        for report in &reports {
+            print_token_status(report);
+        }
+    }
+
+    Ok(if needs_attention { EXIT_CHECK_FAILED } else { EXIT_OK })
+}
+
+/// 单个代币的同步状态和需要处理的问题
+async fn token_status(db_conn: &DbConnection, entry: &RegistryEntry) -> Result<Document, Box<dyn Error + Send + Sync>> {
+    let symbol = &entry.token.symbol;
+    let status = entry.status.as_str();
+    let sync_doc = db_conn.sync_status_col
+        .find_one(doc! { "status_type": "sync_state", "token": symbol }, None)
+        .await?
+        .unwrap_or_default();
+
+    let sync_mode = sync_doc.get_str("sync_mode").ok();
+    let missing = sync_doc.get_document("gap_scan").ok()
+        .and_then(|scan| scan.get_i64("missing_count").ok())
+        .unwrap_or(0);
+    let rebuild_pending = sync_doc.get_document("rebuild").ok()
+        .and_then(|r| r.get_bool("pending").ok())
+        .unwrap_or(false);
+
+    let mut issues: Vec<&str> = Vec::new();
+    if entry.status == TokenStatus::Active {
+        match sync_mode {
+            None => issues.push("尚未同步"),
+            Some("incremental") => {},
+            Some(_) => issues.push("初始同步未完成"),
+        }
+        if missing > 0 {
+            issues.push("存在交易索引缺口");
+        }
+        if rebuild_pending {
+            issues.push("派生数据重建未完成");
+        }
+    }
+
+    let backfill = match get_plan(&db_conn.backfill_col, symbol).await? {
+        Some(plan) if !plan.get_bool("finished").unwrap_or(false) => Some(get_progress(&db_conn.backfill_col, symbol).await?),
+        _ => None,
+    };
+    if let Some(progress) = &backfill {
+        issues.push(if progress.failed > 0 { "分片回填存在失败的工作单元" } else { "分片回填未完成" });
+    }
+    let lease = leases::get_lease(&db_conn.lease_col, symbol).await?;
+    let lease_holder = lease.as_ref().and_then(|l| l.get_str("holder").ok());
+    let lease_expires_at = lease.as_ref()
+        .and_then(|l| l.get_datetime("expires_at").ok())
+        .map(|t| t.timestamp_millis() / 1000);
+
+    Ok(doc! {
+        "token": symbol,
+        "status": status,
+        "lease_holder": lease_holder,
+        "lease_expires_at": lease_expires_at,
+        "sync_mode": sync_mode,
+        "last_synced_index": sync_doc.get_i64("last_synced_index").ok(),
+        "last_balance_calculated_index": sync_doc.get_i64("last_balance_calculated_index").ok(),
+        "updated_at": sync_doc.get_i64("updated_at").ok(),
+        "missing_count": missing,
+        "rebuild_pending": rebuild_pending,
+        "backfill": backfill.map(|progress| progress.to_document()),
+        "issues": issues,
+    })
+}
+
+/// 以文本形式输出单个代币的状态
+fn print_token_status(report: &Document) {
+    let index = |key: &str| report.get_i64(key).map(|i| i.to_string()).unwrap_or_else(|_| "-".to_string());
+    let updated_at = report.get_i64("updated_at").ok()
+        .and_then(|t| chrono::DateTime::from_timestamp(t, 0))
+        .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_else(|| "-".to_string());
+    println!("{}: 状态 {}，同步模式 {}，已同步到 {}，余额计算到 {}，更新于 {}，缺失交易 {}，租约持有者 {}",
+             report.get_str("token").unwrap_or("-"),
+             report.get_str("status").unwrap_or("-"),
+             report.get_str("sync_mode").unwrap_or("-"),
+             index("last_synced_index"),
+             index("last_balance_calculated_index"),
+             updated_at,
+             report.get_i64("missing_count").unwrap_or(0),
+             report.get_str("lease_holder").unwrap_or("-"));
+    if let Ok(issues) = report.get_array("issues") {
+        for issue in issues.iter().filter_map(|i| i.as_str()) {
+            println!("  需要处理: {}", issue);
+        }
+    }
+}
+
+/// 创建分片回填计划
+pub async fn backfill_plan(
+    agent: &Agent,
+    db_conn: &DbConnection,
+    token: &TokenConfig,
+    unit_size: u64,
+) -> Result<u8, Box<dyn Error + Send + Sync>> {
+    if !acquire_lease(db_conn, token).await? {
+        return Ok(EXIT_FAILURE);
+    }
+    let result = plan_backfill(agent, db_conn, token, unit_size).await;
+    release_lease(db_conn, token).await;
+    let units = result?;
+    println!("{}: 已创建 {} 个工作单元，在任意数量的机器上执行 backfill work 开始回填", token.symbol, units);
+    Ok(EXIT_OK)
+}
+
+/// 作为工作进程处理分片回填的工作单元，`concurrency` 个单元同时处理
+pub async fn backfill_work(
+    agent: &Agent,
+    db_conn: &DbConnection,
+    targets: &[&TokenConfig],
+    concurrency: usize,
+) -> Result<u8, Box<dyn Error + Send + Sync>> {
+    let mut tokens = Vec::new();
+    for token in targets {
+        if get_plan(&db_conn.backfill_col, &token.symbol).await?.is_some() {
+            tokens.push((*token).clone());
+        }
+    }
+    if tokens.is_empty() {
+        error!("没有代币的回填计划，请先执行 backfill plan");
+        return Ok(EXIT_USAGE);
+    }
+
+    let mut handles = Vec::new();
+    for n in 0..concurrency.max(1) {
+        let agent = agent.clone();
+        let db_conn = db_conn.clone();
+        let tokens = tokens.clone();
+        let worker_id = format!("{}-{}", leases::instance_id(), n);
+        handles.push(tokio::spawn(async move {
+            let tokens: Vec<&TokenConfig> = tokens.iter().collect();
+            run_backfill_worker(&agent, &db_conn, &tokens, &worker_id).await
+        }));
+    }
+
+    let mut code = EXIT_OK;
+    for handle in handles {
+        match handle.await {
+            Ok(Ok(true)) => {},
+            Ok(Ok(false)) => code = code.max(EXIT_INTERRUPTED),
+            Ok(Err(e)) => {
+                error!("回填工作进程出错退出: {}", e);
+                code = code.max(EXIT_FAILURE);
+            },
+            Err(e) => {
+                error!("回填工作进程异常终止: {}", e);
+                code = code.max(EXIT_FAILURE);
+            },
+        }
+    }
+    if code == EXIT_OK {
+        println!("所有工作单元已完成，同步进程启动时（或执行 backfill finish）将统一计算余额");
+    }
+    Ok(code)
+}
+
+/// 输出分片回填进度；存在超过最大尝试次数的工作单元时返回 EXIT_CHECK_FAILED
+pub async fn backfill_status(
+    db_conn: &DbConnection,
+    targets: &[&TokenConfig],
+    json: bool,
+) -> Result<u8, Box<dyn Error + Send + Sync>> {
+    let mut reports = Vec::new();
+    let mut has_failed = false;
+    for token in targets {
+        let plan = match get_plan(&db_conn.backfill_col, &token.symbol).await? {
+            Some(plan) => plan,
+            None => continue,
+        };
+        let progress = get_progress(&db_conn.backfill_col, &token.symbol).await?;
+        has_failed |= progress.failed > 0;
+        reports.push(doc! {
+            "token": &token.symbol,
+            "start": plan.get_i64("start").unwrap_or(0),
+            "end": plan.get_i64("end").unwrap_or(0),
+            "unit_size": plan.get_i64("unit_size").unwrap_or(0),
+            "finished": plan.get_bool("finished").unwrap_or(false),
+            "progress": progress.to_document(),
+        });
+    }
+
+    if json {
+        let value = Bson::Array(reports.into_iter().map(Bson::Document).collect()).into_relaxed_extjson();
+        println!("{}", serde_json::to_string_pretty(&value)?);
+    } else if reports.is_empty() {
+        println!("没有回填计划");
+    } else {
+        for report in &reports {
+            let progress = report.get_document("progress")?;
+            let count = |key: &str| progress.get_i64(key).unwrap_or(0);
+            println!("{}: 索引 {}..={}，{}，工作单元 {}/{} 已完成，处理中 {}，待处理 {}，失败 {}，已写入 {} 笔交易",
+                     report.get_str("token")?,
+                     report.get_i64("start")?,
+                     report.get_i64("end")?,
+                     if report.get_bool("finished")? { "已完成收尾" } else { "未完成" },
+                     count("done"), count("total"), count("claimed"), count("pending"), count("failed"), count("transactions"));
+        }
+    }
+    Ok(if has_failed { EXIT_CHECK_FAILED } else { EXIT_OK })
+}
+
+/// 参与处理剩余的工作单元，全部完成后计算余额并切换到增量同步
+pub async fn backfill_finish(
+    agent: &Agent,
+    db_conn: &DbConnection,
+    token: &TokenConfig,
+) -> Result<u8, Box<dyn Error + Send + Sync>> {
+    if get_plan(&db_conn.backfill_col, &token.symbol).await?.is_none() {
+        error!("{}: 没有回填计划", token.symbol);
+        return Ok(EXIT_USAGE);
+    }
+    if !acquire_lease(db_conn, token).await? {
+        return Ok(EXIT_FAILURE);
+    }
+    let result = finalize_backfill(Some(agent), db_conn, token).await;
+    release_lease(db_conn, token).await;
+    Ok(if result? { EXIT_OK } else { EXIT_INTERRUPTED })
+}
+
+/// 重置超过最大尝试次数的工作单元
+pub async fn backfill_retry(
+    db_conn: &DbConnection,
+    token: &TokenConfig,
+) -> Result<u8, Box<dyn Error + Send + Sync>> {
+    if get_plan(&db_conn.backfill_col, &token.symbol).await?.is_none() {
+        error!("{}: 没有回填计划", token.symbol);
+        return Ok(EXIT_USAGE);
+    }
+    let count = retry_failed_units(&db_conn.backfill_col, &token.symbol).await?;
+    println!("{}: 已重置 {} 个失败的工作单元", token.symbol, count);
+    Ok(EXIT_OK)
+}
@@ -2,15 +2,13 @@
  * 文件描述: 配置管理模块，负责加载和处理应用配置
  * 功能概述:
  * - 从配置文件加载应用设置
- * - 解析命令行参数
  * - 创建IC网络连接
  * - 获取代币信息
  * 
  * 主要组件:
- * - load_config函数 (第26-61行): 从config.toml文件加载应用配置
- * - parse_args函数 (第63-69行): 解析命令行参数(如--reset)
- * - get_token_decimals函数 (第71-122行): 从IC网络获取代币小数位数
- * - create_agent函数 (第124-138行): 创建IC网络连接代理
+ * - load_config函数 (第26-61行): 从配置文件加载应用配置（启动和配置热更新时调用，路径由 --config 指定）
+ * - get_token_decimals函数 (第71-122行): 从IC网络获取代币小数位数（重试策略见 ic::client）
+ * - create_agent函数 (第124-138行): 创建IC网络连接代理，多个节点之间自动切换（见 ic::endpoints）
  * - parse_canister_id函数 (第140-149行): 解析Canister ID为Principal类型
  */
 
@@ -18,16 +16,23 @@ use std::error::Error;
 use config as config_rs;
 use ic_agent::export::Principal;
 use candid::{Encode, Decode};
+use std::sync::Arc;
 use ic_agent::Agent;
 use log::{info, error, warn};
-use crate::models::{Config as AppConfig, DEFAULT_DECIMALS};
+use crate::models::{Config as AppConfig, IcConfig, DEFAULT_DECIMALS};
+use crate::ic::endpoints::EndpointPool;
+use crate::ic::client::query_with_retry;
+use crate::ic::limiter::configure_limits;
 use crate::utils::create_error;
 
-/// 加载应用配置
-pub async fn load_config() -> Result<AppConfig, Box<dyn Error>> {
+/// 默认配置文件路径
+pub const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+/// 从指定路径加载应用配置
+pub async fn load_config(path: &str) -> Result<AppConfig, Box<dyn Error + Send + Sync>> {
     // 使用TOML配置文件
     let settings = match config_rs::Config::builder()
-        .add_source(config_rs::File::with_name("config").required(false))
+        .add_source(config_rs::File::from(std::path::Path::new(path)).required(false))
         .build() {
         Ok(config) => config,
         Err(e) => {
@@ -37,7 +42,7 @@ pub async fn load_config() -> Result<AppConfig, Box<dyn Error>> {
     
     // 如果没有找到任何配置文件，返回错误
     if settings.get_string("mongodb_url").is_err() {
-        return Err(create_error("未找到配置文件。请创建config.toml"));
+        return Err(create_error(&format!("未找到配置文件 {}。请创建配置文件或通过 --config 指定路径", path)));
     }
     
     let cfg: AppConfig = match settings.try_deserialize() {
@@ -60,20 +65,12 @@ pub async fn load_config() -> Result<AppConfig, Box<dyn Error>> {
     Ok(cfg)
 }
 
-/// 解析命令行参数
-pub async fn parse_args(args: &crate::models::AppArgs) -> Result<(), Box<dyn Error>> {
-    if args.reset {
-        info!("检测到重置参数 --reset");
-    }
-    Ok(())
-}
-
 /// 查询代币小数位数
 pub async fn get_token_decimals(
     agent: &Agent,
     canister_id: &Principal,
     token_symbol: &str,
-) -> Result<u8, Box<dyn Error>> {
+) -> Result<u8, Box<dyn Error + Send + Sync>> {
     info!("{}: 查询代币小数位数...", token_symbol);
     
     // 调用icrc1_decimals方法
@@ -86,49 +83,45 @@ pub async fn get_token_decimals(
         }
     };
     
-    // 添加重试逻辑
-    let max_retries = 3;
-    let mut retry_count = 0;
-    
-    while retry_count < max_retries {
-        match agent.query(canister_id, "icrc1_decimals")
-            .with_arg(arg_bytes.clone())
-            .call()
-            .await {
-            Ok(response) => {
-                match Decode!(&response, u8) {
-                    Ok(decimals) => {
-                        info!("{}: 代币小数位数: {}", token_symbol, decimals);
-                        return Ok(decimals);
-                    },
-                    Err(e) => {
-                        warn!("{}: 解析decimals响应失败: {}, 使用默认值{}", token_symbol, e, DEFAULT_DECIMALS);
-                        return Ok(DEFAULT_DECIMALS);
-                    }
+    match query_with_retry(agent, canister_id, "icrc1_decimals", &arg_bytes, &format!("{}: 查询decimals", token_symbol)).await {
+        Ok(response) => {
+            match Decode!(&response, u8) {
+                Ok(decimals) => {
+                    info!("{}: 代币小数位数: {}", token_symbol, decimals);
+                    Ok(decimals)
+                },
+                Err(e) => {
+                    warn!("{}: 解析decimals响应失败: {}, 使用默认值{}", token_symbol, e, DEFAULT_DECIMALS);
+                    Ok(DEFAULT_DECIMALS)
                 }
-            },
-            Err(e) => {
-                retry_count += 1;
-                let wait_time = std::time::Duration::from_secs(2 * retry_count);
-                warn!("{}: 查询decimals失败 (尝试 {}/{}): {}, 等待 {:?} 后重试", 
-                    token_symbol, retry_count, max_retries, e, wait_time);
-                tokio::time::sleep(wait_time).await;
             }
+        },
+        Err(e) => {
+            warn!("{}: 查询decimals失败，使用默认值{}: {}", token_symbol, DEFAULT_DECIMALS, e);
+            Ok(DEFAULT_DECIMALS)
         }
     }
-    
-    warn!("{}: 查询decimals达到最大重试次数，使用默认值{}", token_symbol, DEFAULT_DECIMALS);
-    Ok(DEFAULT_DECIMALS)
 }
 
 /// 创建IC连接代理
-pub fn create_agent(ic_url: &str) -> Result<Agent, Box<dyn Error>> {
+///
+/// 返回的节点列表是代理的传输层，用于启动健康检查和查询节点状态。
+/// 同时按 [ic] 段配置设置 canister 调用的限流和熔断参数。
+pub fn create_agent(ic_url: &str, ic_config: Option<&IcConfig>) -> Result<(Agent, Arc<EndpointPool>), Box<dyn Error + Send + Sync>> {
+    configure_limits(ic_config);
+    let endpoints = match EndpointPool::new(ic_url, ic_config) {
+        Ok(pool) => Arc::new(pool),
+        Err(e) => {
+            error!("IC网络地址无效: {} - 错误: {}", ic_url, e);
+            return Err(Box::new(e));
+        }
+    };
     match Agent::builder()
-        .with_url(ic_url)
+        .with_arc_transport(endpoints.clone())
         .build() {
         Ok(a) => {
-            info!("IC网络连接创建成功: {}", ic_url);
-            Ok(a)
+            info!("IC网络连接创建成功: {}", endpoints.urls().join(", "));
+            Ok((a, endpoints))
         },
         Err(e) => {
             error!("IC网络连接创建失败: {} - 错误: {}", ic_url, e);
@@ -138,7 +131,7 @@ pub fn create_agent(ic_url: &str) -> Result<Agent, Box<dyn Error>> {
 }
 
 /// 解析Canister ID
-pub fn parse_canister_id(canister_id_text: &str) -> Result<Principal, Box<dyn Error>> {
+pub fn parse_canister_id(canister_id_text: &str) -> Result<Principal, Box<dyn Error + Send + Sync>> {
     match Principal::from_text(canister_id_text) {
         Ok(id) => Ok(id),
         Err(e) => {
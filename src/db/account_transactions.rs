@@ -0,0 +1,296 @@
+/**
+ * 文件描述: 账户-交易关系边集合管理模块
+ * 功能概述:
+ * - 以 (account, index, role) 三元组保存账户与交易的关联，替代账户文档中无上限增长的 transaction_indices 数组
+ * - 按交易索引分页读取账户关联的交易
+ * - 将旧版数组结构原地迁移为边集合
+ *
+ * 主要组件:
+ * - ROLE_* 常量: 账户在交易中的角色 (from / to / spender)
+ * - save_account_transaction_edge函数: 保存一条账户-交易边
+ * - get_account_transaction_indices函数: 按索引分页获取账户的交易索引，支持游标条件（API使用）
+ * - get_account_transaction_indices_after函数: 按索引升序获取某索引之后的一页交易索引（余额计算使用）
+ * - clear_account_transactions函数: 清空边集合
+ * - migrate_account_transaction_indices函数: 将 accounts 集合中的 transaction_indices 数组迁移到边集合
+ */
+
+use std::error::Error;
+use mongodb::{Collection, bson::{doc, Bson, Document}};
+use mongodb::error::ErrorKind;
+use mongodb::options::{FindOptions, InsertManyOptions, UpdateOptions};
+use futures::stream::TryStreamExt;
+use tokio::time::Duration;
+use log::{info, error, warn, debug};
+use crate::models::Transaction;
+use crate::utils::{create_error, get_transaction_account_roles};
+
+/// 账户作为发送方（转账/销毁/授权的 from）
+pub const ROLE_FROM: &str = "from";
+/// 账户作为接收方（转账/铸币的 to）
+pub const ROLE_TO: &str = "to";
+/// 账户作为授权代理（transferFrom/授权销毁的 spender，或 approve 的被授权方）
+pub const ROLE_SPENDER: &str = "spender";
+/// 迁移旧数据时找不到对应交易，无法判断角色
+pub const ROLE_UNKNOWN: &str = "unknown";
+
+/// 迁移时每批处理的交易索引数量
+const MIGRATION_CHUNK_SIZE: usize = 1000;
+/// MongoDB 唯一索引冲突的错误码
+const DUPLICATE_KEY_CODE: i32 = 11000;
+
+/// 保存一条账户-交易边
+pub async fn save_account_transaction_edge(
+    account_tx_col: &Collection<Document>,
+    account: &str,
+    tx_index: u64,
+    role: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let max_retries = 3;
+    let mut retry_count = 0;
+
+    while retry_count < max_retries {
+        // 以三元组为唯一键，重复写入时不产生新文档
+        match account_tx_col.update_one(
+            doc! { "account": account, "index": tx_index as i64, "role": role },
+            doc! { "$setOnInsert": { "account": account, "index": tx_index as i64, "role": role } },
+            UpdateOptions::builder().upsert(true).build()
+        ).await {
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                retry_count += 1;
+                let wait_time = Duration::from_millis(500 * retry_count);
+                warn!("保存账户-交易边失败 (账户: {}, 索引: {}, 角色: {}) (尝试 {}/{}): {}，等待 {:?} 后重试",
+                    account, tx_index, role, retry_count, max_retries, e, wait_time);
+                tokio::time::sleep(wait_time).await;
+            }
+        }
+    }
+
+    Err(create_error(&format!("保存账户-交易边失败 (账户: {}, 索引: {}, 角色: {}), 已重试 {} 次",
+        account, tx_index, role, max_retries)))
+}
+
+/// 分页获取账户关联的交易索引（默认按索引降序）
+///
+/// 同一笔交易中账户可能有多个角色（例如自己转给自己），这里按索引去重后再计算 skip/limit。
+/// `index_range` 为分页游标对应的索引条件（例如 `{"$lt": 100}`），`ascending` 为 true 时按索引升序返回。
+/// 查询走 (account, index, role) 复合索引，只读取到当前页为止。
+pub async fn get_account_transaction_indices(
+    account_tx_col: &Collection<Document>,
+    account: &str,
+    index_range: Option<Document>,
+    ascending: bool,
+    skip: u64,
+    limit: u64,
+) -> Result<Vec<u64>, Box<dyn Error + Send + Sync>> {
+    let options = FindOptions::builder()
+        .sort(doc! { "index": if ascending { 1 } else { -1 } })
+        .projection(doc! { "index": 1, "_id": 0 })
+        .batch_size(((skip + limit) as u32).clamp(1, 1000))
+        .build();
+
+    let mut filter = doc! { "account": account };
+    if let Some(range) = index_range {
+        filter.insert("index", range);
+    }
+    let mut cursor = account_tx_col.find(filter, options).await?;
+
+    let mut indices = Vec::new();
+    let mut last_index: Option<u64> = None;
+    let mut skipped = 0u64;
+
+    while let Some(edge) = cursor.try_next().await? {
+        let index = match edge.get_i64("index") {
+            Ok(i) => i as u64,
+            Err(_) => continue,
+        };
+        // 结果按索引排序，重复索引必然相邻
+        if last_index == Some(index) {
+            continue;
+        }
+        last_index = Some(index);
+
+        if skipped < skip {
+            skipped += 1;
+            continue;
+        }
+
+        indices.push(index);
+        if indices.len() as u64 >= limit {
+            break;
+        }
+    }
+
+    Ok(indices)
+}
+
+/// 按交易索引升序获取账户在 `after` 之后的一页交易索引
+///
+/// 用于余额计算时分页遍历账户的全部交易，`after` 为 None 表示从头开始。
+pub async fn get_account_transaction_indices_after(
+    account_tx_col: &Collection<Document>,
+    account: &str,
+    after: Option<u64>,
+    page_size: i64,
+) -> Result<Vec<i64>, Box<dyn Error + Send + Sync>> {
+    let filter = match after {
+        Some(index) => doc! { "account": account, "index": { "$gt": index as i64 } },
+        None => doc! { "account": account },
+    };
+    let options = FindOptions::builder()
+        .sort(doc! { "index": 1 })
+        .projection(doc! { "index": 1, "_id": 0 })
+        .limit(page_size)
+        .build();
+
+    let edges: Vec<Document> = account_tx_col.find(filter, options).await?.try_collect().await?;
+
+    let mut indices: Vec<i64> = edges.iter()
+        .filter_map(|edge| edge.get_i64("index").ok())
+        .collect();
+    indices.dedup();
+
+    Ok(indices)
+}
+
+/// 清空账户-交易边集合
+pub async fn clear_account_transactions(account_tx_col: &Collection<Document>) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    match account_tx_col.delete_many(doc! {}, None).await {
+        Ok(result) => {
+            info!("已清除 {} 条账户-交易边记录", result.deleted_count);
+            Ok(result.deleted_count)
+        },
+        Err(e) => {
+            error!("清除账户-交易边集合失败: {}", e);
+            Err(create_error(&format!("清除账户-交易边集合失败: {}", e)))
+        }
+    }
+}
+
+/// 批量插入账户-交易边，已存在的边（唯一索引冲突）直接跳过
+///
+/// 使用无序插入，一条边冲突不影响同批其余的边；迁移中断后重新执行时已写入的边全部冲突。
+async fn insert_edges_ignoring_duplicates(
+    account_tx_col: &Collection<Document>,
+    edges: Vec<Document>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if edges.is_empty() {
+        return Ok(());
+    }
+    let options = InsertManyOptions::builder().ordered(false).build();
+    match account_tx_col.insert_many(edges, options).await {
+        Ok(_) => Ok(()),
+        Err(e) => match e.kind.as_ref() {
+            ErrorKind::BulkWrite(failure) if failure.write_concern_error.is_none()
+                && failure.write_errors.iter().flatten().all(|write_error| write_error.code == DUPLICATE_KEY_CODE) => Ok(()),
+            _ => Err(create_error(&format!("批量写入账户-交易边失败: {}", e))),
+        },
+    }
+}
+
+/// 将旧版 accounts 集合中的 transaction_indices 数组迁移到边集合
+///
+/// 角色信息从交易本身推导，每块交易索引的边用一次批量插入写入。每个账户的边全部写入后才会移除其数组字段，
+/// 因此迁移中断后可以直接重新执行。返回迁移的账户数量。
+pub async fn migrate_account_transaction_indices(
+    accounts_col: &Collection<Document>,
+    account_tx_col: &Collection<Document>,
+    tx_col: &Collection<Document>,
+) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    let legacy_filter = doc! { "transaction_indices": { "$exists": true } };
+    let pending = accounts_col.count_documents(legacy_filter.clone(), None).await?;
+    if pending == 0 {
+        debug!("没有需要迁移的账户交易索引数组");
+        return Ok(0);
+    }
+
+    info!("发现 {} 个账户仍使用 transaction_indices 数组，开始迁移到账户-交易边集合...", pending);
+
+    let mut migrated = 0u64;
+    let mut accounts_cursor = accounts_col.find(legacy_filter, None).await?;
+
+    while let Some(account_doc) = accounts_cursor.try_next().await? {
+        let account = match account_doc.get_str("account") {
+            Ok(acc) => acc.to_string(),
+            Err(e) => {
+                error!("迁移时无法获取账户信息: {}", e);
+                continue;
+            }
+        };
+
+        let tx_indices: Vec<i64> = match account_doc.get("transaction_indices") {
+            Some(Bson::Array(arr)) => arr.iter().filter_map(|b| match b {
+                Bson::Int64(i) => Some(*i),
+                Bson::Int32(i) => Some(i64::from(*i)),
+                _ => None,
+            }).collect(),
+            _ => Vec::new(),
+        };
+
+        let mut first_index: Option<i64> = None;
+        let mut last_index: Option<i64> = None;
+
+        for chunk in tx_indices.chunks(MIGRATION_CHUNK_SIZE) {
+            let tx_docs: Vec<Document> = tx_col
+                .find(doc! { "index": { "$in": chunk } }, None)
+                .await?
+                .try_collect()
+                .await?;
+
+            let mut found = std::collections::HashSet::new();
+            let mut edges = Vec::with_capacity(chunk.len());
+            for tx_doc in tx_docs {
+                let tx: Transaction = match mongodb::bson::from_document(tx_doc) {
+                    Ok(tx) => tx,
+                    Err(e) => {
+                        warn!("迁移时反序列化交易失败: {}", e);
+                        continue;
+                    }
+                };
+                let index = tx.index.unwrap_or(0);
+                for (acc, role) in get_transaction_account_roles(&tx) {
+                    if acc == account {
+                        edges.push(doc! { "account": &account, "index": index as i64, "role": role });
+                        found.insert(index as i64);
+                    }
+                }
+            }
+
+            // 交易缺失或账户不匹配时保留关联，角色记为 unknown
+            for index in chunk.iter().filter(|i| !found.contains(i)) {
+                edges.push(doc! { "account": &account, "index": *index, "role": ROLE_UNKNOWN });
+            }
+            insert_edges_ignoring_duplicates(account_tx_col, edges).await?;
+
+            if let Some(min) = chunk.iter().min() {
+                first_index = Some(first_index.map_or(*min, |f| f.min(*min)));
+            }
+            if let Some(max) = chunk.iter().max() {
+                last_index = Some(last_index.map_or(*max, |l| l.max(*max)));
+            }
+        }
+
+        // 边全部写入后再移除旧数组
+        let mut set_doc = Document::new();
+        if let Some(first) = first_index {
+            set_doc.insert("first_tx_index", first);
+        }
+        if let Some(last) = last_index {
+            set_doc.insert("last_tx_index", last);
+        }
+        let update = if set_doc.is_empty() {
+            doc! { "$unset": { "transaction_indices": "" } }
+        } else {
+            doc! { "$unset": { "transaction_indices": "" }, "$set": set_doc }
+        };
+        accounts_col.update_one(doc! { "account": &account }, update, None).await?;
+
+        migrated += 1;
+        if migrated.is_multiple_of(1000) {
+            info!("已迁移 {}/{} 个账户", migrated, pending);
+        }
+    }
+
+    info!("账户交易索引迁移完成，共迁移 {} 个账户", migrated);
+    Ok(migrated)
+}
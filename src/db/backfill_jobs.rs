@@ -0,0 +1,323 @@
+/**
+ * 文件描述: 分片回填任务模块，在 backfill_jobs 集合中保存初始同步的回填计划和工作单元
+ * 功能概述:
+ * - 回填计划: 每个代币一条记录（kind: "plan"），记录回填区间、单元大小和是否已完成
+ * - 工作单元: 计划区间按归档边界和单元大小拆分的子区间（kind: "unit"），状态为 pending / claimed / done
+ * - 工作进程通过带过期时间的认领获取单元，过期时间使用 MongoDB 服务器时间（$$NOW），
+ *   进程崩溃后单元在过期后可被其他工作进程重新认领
+ * - 单元失败时回到 pending 并记录错误，超过最大尝试次数后不再自动认领；
+ *   最后一次尝试时进程崩溃（认领过期且尝试次数已用完）的单元同样视为失败
+ *
+ * 主要组件:
+ * - BackfillUnit结构体: 拆分出的工作单元
+ * - BackfillProgress结构体: 计划的完成情况
+ * - create_plan函数: 保存回填计划和全部工作单元
+ * - get_plan函数: 读取代币的回填计划
+ * - claim_unit函数: 认领一个待处理或认领已过期的工作单元
+ * - renew_unit / complete_unit / fail_unit函数: 续期、完成和释放工作单元
+ * - get_progress函数: 统计计划的完成情况
+ * - finish_plan函数: 标记计划已完成（余额计算完成后调用）
+ * - retry_failed_units函数: 重置超过最大尝试次数的工作单元
+ * - clear_backfill_jobs函数: 删除代币的回填计划和工作单元
+ */
+
+use std::error::Error;
+use chrono::Utc;
+use futures::stream::TryStreamExt;
+use mongodb::Collection;
+use mongodb::bson::{doc, Document};
+use mongodb::options::{FindOneAndUpdateOptions, ReturnDocument};
+use tokio::time::Duration;
+use crate::utils::create_error;
+
+/// 单个工作单元的最大尝试次数，超过后需要通过 backfill retry 重置
+pub const MAX_UNIT_ATTEMPTS: i32 = 5;
+/// 回填计划记录使用的 unit_id
+const PLAN_UNIT_ID: i64 = -1;
+
+/// 超过最大尝试次数的工作单元：失败后回到 pending 的单元，以及最后一次尝试时认领已过期的单元
+fn failed_units_filter() -> Document {
+    doc! {
+        "attempts": { "$gte": MAX_UNIT_ATTEMPTS },
+        "$or": [
+            { "status": "pending" },
+            { "status": "claimed", "$expr": { "$lt": ["$lease_expires_at", "$$NOW"] } },
+        ],
+    }
+}
+
+/// 拆分出的工作单元（含两端）
+#[derive(Debug, Clone)]
+pub struct BackfillUnit {
+    pub unit_id: i64,
+    pub start: u64,
+    pub end: u64,
+    /// archive 或 ledger
+    pub source: &'static str,
+}
+
+/// 回填计划的完成情况
+#[derive(Debug, Default, Clone)]
+pub struct BackfillProgress {
+    pub total: u64,
+    pub pending: u64,
+    pub claimed: u64,
+    pub done: u64,
+    /// 超过最大尝试次数、不再自动认领的单元
+    pub failed: u64,
+    /// 已完成单元写入的交易数量
+    pub transactions: u64,
+}
+
+impl BackfillProgress {
+    /// 所有工作单元是否已完成
+    pub fn is_complete(&self) -> bool {
+        self.done == self.total
+    }
+
+    pub fn to_document(&self) -> Document {
+        doc! {
+            "total": self.total as i64,
+            "pending": self.pending as i64,
+            "claimed": self.claimed as i64,
+            "done": self.done as i64,
+            "failed": self.failed as i64,
+            "transactions": self.transactions as i64,
+        }
+    }
+}
+
+/// 保存回填计划和全部工作单元，代币已有未完成的计划时返回错误
+pub async fn create_plan(
+    backfill_col: &Collection<Document>,
+    token_symbol: &str,
+    start: u64,
+    end: u64,
+    unit_size: u64,
+    units: &[BackfillUnit],
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if let Some(plan) = get_plan(backfill_col, token_symbol).await? {
+        if !plan.get_bool("finished").unwrap_or(false) {
+            return Err(create_error(&format!("{}: 已有未完成的回填计划", token_symbol)));
+        }
+        clear_backfill_jobs(backfill_col, token_symbol).await?;
+    }
+
+    let now = Utc::now().timestamp();
+    let unit_docs: Vec<Document> = units.iter()
+        .map(|unit| doc! {
+            "token": token_symbol,
+            "kind": "unit",
+            "unit_id": unit.unit_id,
+            "start": unit.start as i64,
+            "end": unit.end as i64,
+            "source": unit.source,
+            "status": "pending",
+            "attempts": 0_i32,
+            "created_at": now,
+        })
+        .collect();
+    if !unit_docs.is_empty() {
+        backfill_col.insert_many(unit_docs, None).await?;
+    }
+    // 计划记录最后写入，工作进程只处理已有计划的代币
+    backfill_col.insert_one(doc! {
+        "token": token_symbol,
+        "kind": "plan",
+        "unit_id": PLAN_UNIT_ID,
+        "start": start as i64,
+        "end": end as i64,
+        "unit_size": unit_size as i64,
+        "total_units": units.len() as i64,
+        "finished": false,
+        "created_at": now,
+    }, None).await?;
+    Ok(())
+}
+
+/// 读取代币的回填计划
+pub async fn get_plan(
+    backfill_col: &Collection<Document>,
+    token_symbol: &str,
+) -> Result<Option<Document>, Box<dyn Error + Send + Sync>> {
+    Ok(backfill_col.find_one(doc! { "token": token_symbol, "kind": "plan" }, None).await?)
+}
+
+/// 代币是否有未完成的回填计划
+pub async fn has_unfinished_plan(
+    backfill_col: &Collection<Document>,
+    token_symbol: &str,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    Ok(get_plan(backfill_col, token_symbol).await?
+        .map(|plan| !plan.get_bool("finished").unwrap_or(false))
+        .unwrap_or(false))
+}
+
+/// 认领一个工作单元：优先认领单元号最小的待处理单元，认领已过期的单元同样可以被重新认领
+///
+/// 认领成功时尝试次数加一，返回认领后的单元文档；没有可认领的单元时返回 None。
+pub async fn claim_unit(
+    backfill_col: &Collection<Document>,
+    token_symbol: &str,
+    worker_id: &str,
+    ttl: Duration,
+) -> Result<Option<Document>, Box<dyn Error + Send + Sync>> {
+    let filter = doc! {
+        "token": token_symbol,
+        "kind": "unit",
+        "attempts": { "$lt": MAX_UNIT_ATTEMPTS },
+        "$or": [
+            { "status": "pending" },
+            { "status": "claimed", "$expr": { "$lt": ["$lease_expires_at", "$$NOW"] } },
+        ],
+    };
+    let update = vec![doc! {
+        "$set": {
+            "status": "claimed",
+            "claimed_by": worker_id,
+            "claimed_at": "$$NOW",
+            "lease_expires_at": { "$add": ["$$NOW", ttl.as_millis() as i64] },
+            "attempts": { "$add": [{ "$ifNull": ["$attempts", 0] }, 1] },
+        }
+    }];
+    let options = FindOneAndUpdateOptions::builder()
+        .sort(doc! { "unit_id": 1 })
+        .return_document(ReturnDocument::After)
+        .build();
+    Ok(backfill_col.find_one_and_update(filter, update, options).await?)
+}
+
+/// 为认领的单元续期，单元已被其他工作进程重新认领时返回 false
+pub async fn renew_unit(
+    backfill_col: &Collection<Document>,
+    token_symbol: &str,
+    unit_id: i64,
+    worker_id: &str,
+    ttl: Duration,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    let result = backfill_col.update_one(
+        doc! { "token": token_symbol, "unit_id": unit_id, "status": "claimed", "claimed_by": worker_id },
+        vec![doc! { "$set": { "lease_expires_at": { "$add": ["$$NOW", ttl.as_millis() as i64] } } }],
+        None
+    ).await?;
+    Ok(result.matched_count > 0)
+}
+
+/// 标记单元已完成
+pub async fn complete_unit(
+    backfill_col: &Collection<Document>,
+    token_symbol: &str,
+    unit_id: i64,
+    worker_id: &str,
+    tx_count: u64,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    let result = backfill_col.update_one(
+        doc! { "token": token_symbol, "unit_id": unit_id, "claimed_by": worker_id },
+        doc! {
+            "$set": { "status": "done", "tx_count": tx_count as i64, "completed_at": Utc::now().timestamp() },
+            "$unset": { "lease_expires_at": "", "last_error": "" },
+        },
+        None
+    ).await?;
+    Ok(result.matched_count > 0)
+}
+
+/// 释放单元，记录原因后回到待处理状态
+///
+/// `count_attempt` 为 false 时（例如收到停止信号）本次认领不计入尝试次数。
+pub async fn fail_unit(
+    backfill_col: &Collection<Document>,
+    token_symbol: &str,
+    unit_id: i64,
+    worker_id: &str,
+    error: &str,
+    count_attempt: bool,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut update = doc! {
+        "$set": { "status": "pending", "last_error": error, "failed_at": Utc::now().timestamp() },
+        "$unset": { "claimed_by": "", "lease_expires_at": "" },
+    };
+    if !count_attempt {
+        update.insert("$inc", doc! { "attempts": -1_i32 });
+    }
+    backfill_col.update_one(
+        doc! { "token": token_symbol, "unit_id": unit_id, "status": "claimed", "claimed_by": worker_id },
+        update,
+        None
+    ).await?;
+    Ok(())
+}
+
+/// 统计回填计划的完成情况
+pub async fn get_progress(
+    backfill_col: &Collection<Document>,
+    token_symbol: &str,
+) -> Result<BackfillProgress, Box<dyn Error + Send + Sync>> {
+    let count = |filter: Document| async move {
+        let mut filter = filter;
+        filter.insert("token", token_symbol);
+        filter.insert("kind", "unit");
+        backfill_col.count_documents(filter, None).await
+    };
+    let total = count(doc! {}).await?;
+    let done = count(doc! { "status": "done" }).await?;
+    let failed = count(failed_units_filter()).await?;
+    let failed_pending = count(doc! { "status": "pending", "attempts": { "$gte": MAX_UNIT_ATTEMPTS } }).await?;
+    // 各项分别统计，期间单元状态可能变化，相减时不低于 0
+    let claimed = count(doc! { "status": "claimed" }).await?.saturating_sub(failed.saturating_sub(failed_pending));
+    let pending = count(doc! { "status": "pending" }).await?.saturating_sub(failed_pending);
+
+    let pipeline = vec![
+        doc! { "$match": { "token": token_symbol, "kind": "unit", "status": "done" } },
+        doc! { "$group": { "_id": null, "transactions": { "$sum": "$tx_count" } } },
+    ];
+    let mut cursor = backfill_col.aggregate(pipeline, None).await?;
+    let transactions = match cursor.try_next().await? {
+        Some(result) => result.get_i64("transactions")
+            .or_else(|_| result.get_i32("transactions").map(i64::from))
+            .unwrap_or(0) as u64,
+        None => 0,
+    };
+
+    Ok(BackfillProgress { total, pending, claimed, done, failed, transactions })
+}
+
+/// 标记回填计划已完成
+pub async fn finish_plan(
+    backfill_col: &Collection<Document>,
+    token_symbol: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    backfill_col.update_one(
+        doc! { "token": token_symbol, "kind": "plan" },
+        doc! { "$set": { "finished": true, "finished_at": Utc::now().timestamp() } },
+        None
+    ).await?;
+    Ok(())
+}
+
+/// 重置超过最大尝试次数的工作单元（包括认领已过期的），返回重置的数量
+pub async fn retry_failed_units(
+    backfill_col: &Collection<Document>,
+    token_symbol: &str,
+) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    let mut filter = failed_units_filter();
+    filter.insert("token", token_symbol);
+    filter.insert("kind", "unit");
+    let result = backfill_col.update_many(
+        filter,
+        doc! {
+            "$set": { "status": "pending", "attempts": 0_i32 },
+            "$unset": { "claimed_by": "", "lease_expires_at": "" },
+        },
+        None
+    ).await?;
+    Ok(result.modified_count)
+}
+
+/// 删除代币的回填计划和全部工作单元
+pub async fn clear_backfill_jobs(
+    backfill_col: &Collection<Document>,
+    token_symbol: &str,
+) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    Ok(backfill_col.delete_many(doc! { "token": token_symbol }, None).await?.deleted_count)
+}
@@ -8,12 +8,17 @@
  * 
  * 主要组件:
  * - get_account_balance函数: 获取指定账户的余额
- * - calculate_all_balances函数: 全量计算所有账户余额
+ * - calculate_all_balances函数: 全量计算所有账户余额，按账户更新同步进度（见 sync::progress）
  * - calculate_incremental_balances函数: 增量计算受影响账户的余额
- * - calculate_account_balance函数: 根据交易历史计算单个账户余额
+ * - recalculate_account_balances函数: 按完整交易历史重新计算指定账户的余额
+ * - calculate_account_balance函数: 分页读取账户-交易边，根据交易历史计算单个账户余额
+ * - replay_account_balance函数: 从给定的期初余额开始累计交易
+ * - opening_balance_of / get_opening_balance函数: 读取部分索引时账户的期初余额
+ * - apply_transaction_to_balance函数: 计算单笔交易对账户余额的影响
  * - safe_subtract_balance_with_logging函数: 安全扣减余额并记录异常
- * - log_balance_anomaly函数: 记录余额异常
+ * - log_balance_anomalies函数: 批量记录余额异常
  * - save_account_balance函数: 保存账户余额
+ * - backfill_balance_sort函数: 为旧余额文档补写可排序的 balance_sort 字段
  * - normalize_account_id函数: 规范化账户ID格式
  */
 
@@ -22,15 +27,24 @@ use std::sync::Arc;
 use std::collections::HashMap;
 use tokio::sync::Mutex;
 use mongodb::{Collection};
-use mongodb::bson::{doc, Bson, Document};
-use mongodb::options::FindOptions;
+use mongodb::bson::{doc, Document};
 use tokio::time::Duration;
 use candid::Nat;
 use num_traits::Zero;
 use log::{info, error, warn, debug};
 use crate::models::{Transaction, BalanceAnomaly};
-use crate::utils::{create_error, format_token_amount};
+use crate::utils::{create_error, format_token_amount, to_sortable_amount};
 use crate::db::supply;
+use crate::db::TokenCollections;
+use crate::db::account_transactions::get_account_transaction_indices_after;
+use crate::db::transactions::find_transactions_by_indices;
+use crate::sync::progress::{self, Phase};
+
+/// 余额计算时每页读取的交易索引数量
+const BALANCE_PAGE_SIZE: i64 = 1000;
+
+/// 全量余额计算时每处理多少个账户输出一次进度
+const BALANCE_PROGRESS_INTERVAL: u64 = 1000;
 
 // 全局账户锁映射
 lazy_static::lazy_static! {
@@ -50,7 +64,7 @@ async fn get_account_lock(account: &str) -> Arc<Mutex<()>> {
 pub async fn get_account_balance(
     balances_col: &Collection<Document>,
     account: &str,
-) -> Result<String, Box<dyn Error>> {
+) -> Result<String, Box<dyn Error + Send + Sync>> {
     // 规范化账户格式
     let normalized_account = normalize_account_id(account);
     
@@ -67,7 +81,7 @@ pub async fn get_account_balance(
 }
 
 /// 清空余额集合
-pub async fn clear_balances(balances_col: &Collection<Document>) -> Result<u64, Box<dyn Error>> {
+pub async fn clear_balances(balances_col: &Collection<Document>) -> Result<u64, Box<dyn Error + Send + Sync>> {
     match balances_col.delete_many(doc! {}, None).await {
         Ok(result) => {
             info!("已清除 {} 条余额记录", result.deleted_count);
@@ -85,22 +99,25 @@ pub async fn clear_balances(balances_col: &Collection<Document>) -> Result<u64,
 #[allow(unused_variables)]
 pub async fn calculate_all_balances(
     accounts_col: &Collection<Document>,
+    account_tx_col: &Collection<Document>,
     tx_col: &Collection<Document>,
     balances_col: &Collection<Document>,
     supply_col: &Collection<Document>,
     anomalies_col: &Collection<Document>,
     token_config: &crate::models::TokenConfig,
-) -> Result<(u64, u64), Box<dyn Error>> {
+) -> Result<(u64, u64), Box<dyn Error + Send + Sync>> {
     // 获取代币小数位数，默认为8
     let _token_decimals = token_config.decimals.unwrap_or(8);
-    let _token_symbol = &token_config.symbol;
+    let token_symbol = &token_config.symbol;
     info!("开始计算所有账户余额...");
     
     // 首先清空余额集合
     clear_balances(balances_col).await?;
     
     // 查询所有账户
+    let total_accounts = accounts_col.count_documents(doc! {}, None).await?;
     let mut accounts_cursor = accounts_col.find(doc! {}, None).await?;
+    progress::begin_phase(token_symbol, Phase::Balances, 0, total_accounts.saturating_sub(1));
     
     let mut success_count = 0u64;
     let mut error_count = 0u64;
@@ -108,6 +125,14 @@ pub async fn calculate_all_balances(
     
     // 遍历所有账户
     while accounts_cursor.advance().await? {
+        let processed = success_count + error_count;
+        if processed > 0 {
+            progress::advance(token_symbol, processed - 1);
+        }
+        if processed > 0 && processed.is_multiple_of(BALANCE_PROGRESS_INTERVAL) {
+            info!("余额计算进度: {}/{} 个账户 ({:.1}%)", processed, total_accounts,
+                  processed as f64 * 100.0 / total_accounts.max(1) as f64);
+        }
         let raw_doc = accounts_cursor.current();
         // 转换为Document类型
         let account_doc = Document::try_from(raw_doc.to_owned())?;
@@ -121,35 +146,12 @@ pub async fn calculate_all_balances(
             }
         };
         
-        // 获取该账户的所有交易索引
-        let tx_indices: Vec<i64> = if let Some(indices) = account_doc.get("transaction_indices") {
-            if let Bson::Array(arr) = indices {
-                arr.iter().filter_map(|b| match b {
-                    Bson::Int64(i) => Some(*i),
-                    Bson::Int32(i) => Some(i64::from(*i)),
-                    _ => None,
-                }).collect()
-            } else {
-                error!("账户 {} 的交易索引不是数组格式", account);
-                error_count += 1;
-                continue;
-            }
-        } else {
-            error!("无法获取账户 {} 的交易索引", account);
-            error_count += 1;
-            continue;
-        };
-        
-        if tx_indices.is_empty() {
-            debug!("账户 {} 没有交易记录", account);
-            continue;
-        }
-        
-        // 计算该账户的余额
-        match calculate_account_balance(&account, &tx_indices, tx_col, token_config, anomalies_col).await {
-            Ok((balance, has_anomalies)) => {
+        // 计算该账户的余额，部分索引时从期初余额开始累计
+        let opening_balance = opening_balance_of(&account_doc);
+        match replay_account_balance(&account, account_tx_col, tx_col, token_config, anomalies_col, opening_balance, None).await {
+            Ok((balance, has_anomalies, last_tx_index)) => {
                 // 更新余额记录
-                match save_account_balance(balances_col, &account, &balance).await {
+                match save_account_balance(balances_col, &account, &balance, last_tx_index).await {
                     Ok(_) => {
                         success_count += 1;
                         if has_anomalies {
@@ -172,6 +174,7 @@ pub async fn calculate_all_balances(
     
     info!("全量余额计算完成: 处理 {} 个账户, 失败 {} 个账户, 检测到 {} 个余额异常", 
           success_count, error_count, total_anomalies);
+    progress::advance(token_symbol, (success_count + error_count).max(total_accounts).saturating_sub(1));
 
     // 重新计算并保存总供应量
     supply::recalculate_total_supply(balances_col, supply_col).await?;
@@ -183,13 +186,9 @@ pub async fn calculate_all_balances(
 /// 计算新交易对相关账户余额的影响，而不是重新计算所有账户的余额
 pub async fn calculate_incremental_balances(
     new_transactions: &[Transaction],
-    tx_col: &Collection<Document>,
-    accounts_col: &Collection<Document>,
-    balances_col: &Collection<Document>,
-    supply_col: &Collection<Document>,
-    anomalies_col: &Collection<Document>,
+    collections: &TokenCollections,
     token_config: &crate::models::TokenConfig,
-) -> Result<(u64, u64), Box<dyn Error>> {
+) -> Result<(u64, u64), Box<dyn Error + Send + Sync>> {
     // 获取代币小数位数，默认为8
     let _token_decimals = token_config.decimals.unwrap_or(8);
     let _token_symbol = &token_config.symbol;
@@ -248,14 +247,27 @@ pub async fn calculate_incremental_balances(
     
     debug!("找到 {} 个受影响的账户需要更新余额", affected_accounts.len());
     
+    let (success_count, error_count) = recalculate_account_balances(
+        affected_accounts,
+        collections,
+        token_config
+    ).await?;
+    
+    Ok((success_count, error_count))
+}
+
+/// 按完整交易历史重新计算指定账户的余额，并重新计算总供应量
+pub async fn recalculate_account_balances(
+    accounts: impl IntoIterator<Item = String>,
+    collections: &TokenCollections,
+    token_config: &crate::models::TokenConfig,
+) -> Result<(u64, u64), Box<dyn Error + Send + Sync>> {
     let mut success_count = 0u64;
     let mut error_count = 0u64;
     let mut total_anomalies = 0u64;
     
     // 顺序处理每个受影响的账户，但使用账户锁确保并发安全
-    
-    // 处理每个受影响的账户
-    for account in affected_accounts {
+    for account in accounts {
         // 获取账户锁
         let account_lock = get_account_lock(&account).await;
         
@@ -263,50 +275,18 @@ pub async fn calculate_incremental_balances(
         let _guard = account_lock.lock().await;
         debug!("获取账户 {} 的锁", account);
         
-        // 查询账户交易索引
-        let account_doc = match accounts_col.find_one(doc! { "account": &account }, None).await {
-            Ok(Some(doc)) => doc,
-            Ok(None) => {
-                error!("找不到账户 {} 的记录", account);
-                error_count += 1;
-                continue;
-            },
-            Err(e) => {
-                error!("查询账户 {} 时出错: {}", account, e);
-                error_count += 1;
-                continue;
-            }
-        };
-        
-        // 获取该账户的所有交易索引
-        let tx_indices: Vec<i64> = if let Some(indices) = account_doc.get("transaction_indices") {
-            if let Bson::Array(arr) = indices {
-                arr.iter().filter_map(|b| match b {
-                    Bson::Int64(i) => Some(*i),
-                    Bson::Int32(i) => Some(i64::from(*i)),
-                    _ => None,
-                }).collect()
-            } else {
-                error!("账户 {} 的交易索引不是数组格式", account);
-                error_count += 1;
-                continue;
-            }
-        } else {
-            error!("无法获取账户 {} 的交易索引", account);
-            error_count += 1;
-            continue;
-        };
-        
-        if tx_indices.is_empty() {
-            debug!("账户 {} 没有交易记录", account);
-            continue;
-        }
-        
         // 计算该账户的余额
-        match calculate_account_balance(&account, &tx_indices, tx_col, token_config, anomalies_col).await {
-            Ok((balance, has_anomalies)) => {
+        match calculate_account_balance(
+            &account,
+            &collections.accounts_col,
+            &collections.account_tx_col,
+            &collections.tx_col,
+            token_config,
+            &collections.balance_anomalies_col
+        ).await {
+            Ok((balance, has_anomalies, last_tx_index)) => {
                 // 更新余额记录
-                match save_account_balance(balances_col, &account, &balance).await {
+                match save_account_balance(&collections.balances_col, &account, &balance, last_tx_index).await {
                     Ok(_) => {
                         success_count += 1;
                         if has_anomalies {
@@ -331,277 +311,358 @@ pub async fn calculate_incremental_balances(
           success_count, error_count, total_anomalies);
     
     // 重新计算并保存总供应量
-    supply::recalculate_total_supply(balances_col, supply_col).await?;
+    supply::recalculate_total_supply(&collections.balances_col, &collections.total_supply_col).await?;
    
     Ok((success_count, error_count))
 }
 
+/// 读取账户文档中的期初余额
+///
+/// 部分索引时，起点之前的余额由 sync::partial 写入 accounts 文档的 opening_balance 字段；
+/// 完整索引或尚未写入时为 0。
+pub fn opening_balance_of(account_doc: &Document) -> Nat {
+    account_doc.get_str("opening_balance").ok()
+        .and_then(|b| Nat::parse(b.as_bytes()).ok())
+        .unwrap_or_else(|| Nat::from(0u64))
+}
+
+/// 查询账户的期初余额
+pub async fn get_opening_balance(
+    accounts_col: &Collection<Document>,
+    account: &str,
+) -> Result<Nat, Box<dyn Error + Send + Sync>> {
+    let options = mongodb::options::FindOneOptions::builder()
+        .projection(doc! { "opening_balance": 1 })
+        .build();
+    Ok(accounts_col.find_one(doc! { "account": account }, options).await?
+        .map(|account_doc| opening_balance_of(&account_doc))
+        .unwrap_or_else(|| Nat::from(0u64)))
+}
+
 /// 计算单个账户的余额
+///
+/// 按交易索引升序分页读取账户-交易边，逐页加载交易并累计余额，
+/// 避免一次性把大账户的全部交易索引放进内存或 $in 查询。
 pub async fn calculate_account_balance(
     account: &str,
-    tx_indices: &[i64],
+    accounts_col: &Collection<Document>,
+    account_tx_col: &Collection<Document>,
+    tx_col: &Collection<Document>,
+    token_config: &crate::models::TokenConfig,
+    anomalies_col: &Collection<Document>,
+) -> Result<(Nat, bool, Option<u64>), Box<dyn Error + Send + Sync>> {
+    calculate_account_balance_until(account, accounts_col, account_tx_col, tx_col, token_config, anomalies_col, None).await
+}
+
+/// 计算账户截至某个交易索引（含）的余额
+///
+/// `until_index` 为 None 时计算到最新交易，导出历史快照时用于还原指定索引处的余额。
+pub async fn calculate_account_balance_until(
+    account: &str,
+    accounts_col: &Collection<Document>,
+    account_tx_col: &Collection<Document>,
+    tx_col: &Collection<Document>,
+    token_config: &crate::models::TokenConfig,
+    anomalies_col: &Collection<Document>,
+    until_index: Option<u64>,
+) -> Result<(Nat, bool, Option<u64>), Box<dyn Error + Send + Sync>> {
+    let opening_balance = get_opening_balance(accounts_col, account).await?;
+    replay_account_balance(account, account_tx_col, tx_col, token_config, anomalies_col, opening_balance, until_index).await
+}
+
+/// 从 `start_balance` 开始按交易历史累计账户余额
+pub async fn replay_account_balance(
+    account: &str,
+    account_tx_col: &Collection<Document>,
     tx_col: &Collection<Document>,
     token_config: &crate::models::TokenConfig,
     anomalies_col: &Collection<Document>,
-) -> Result<(Nat, bool), Box<dyn Error>> {
+    start_balance: Nat,
+    until_index: Option<u64>,
+) -> Result<(Nat, bool, Option<u64>), Box<dyn Error + Send + Sync>> {
     // 获取代币小数位数，默认为8
     let _token_decimals = token_config.decimals.unwrap_or(8);
     let _token_symbol = &token_config.symbol;
     // 规范化账户ID
     let normalized_account = normalize_account_id(account);
-    let mut balance = Nat::from(0u64);
+    let mut balance = start_balance;
     let mut processed_count = 0u64;
     let mut has_anomalies = false;
-    
-    // 查询与该账户相关的所有交易
-    let filter = doc! { 
-        "index": { "$in": tx_indices }
-    };
-    
-    let options = FindOptions::builder()
-        .sort(doc! { "index": 1 }) // 按交易索引排序，确保按时间顺序处理
-        .build();
-    
-    let mut tx_cursor = tx_col.find(filter, options).await?;
-    
-    // 遍历处理每一笔交易
-    while tx_cursor.advance().await? {
-        let raw_doc = tx_cursor.current();
-        // 转换为Document类型
-        let tx_doc = Document::try_from(raw_doc.to_owned())?;
+    let mut last_index: Option<u64> = None;
+    
+    loop {
+        // 读取下一页交易索引
+        let mut tx_indices = get_account_transaction_indices_after(
+            account_tx_col, account, last_index, BALANCE_PAGE_SIZE
+        ).await?;
+        if let Some(until) = until_index {
+            tx_indices.retain(|i| *i as u64 <= until);
+        }
+        if tx_indices.is_empty() {
+            break;
+        }
+        last_index = tx_indices.last().map(|i| *i as u64);
         
-        // 反序列化为交易对象 - 使用克隆避免所有权移动
-        let tx: Transaction = match mongodb::bson::from_document(tx_doc.clone()) {
-            Ok(transaction) => transaction,
-            Err(e) => {
-                error!("反序列化交易失败: {}", e);
-                continue;
-            }
-        };
+        // 按交易索引升序读取，确保按时间顺序处理；已归档的交易从冷存储读取
+        let tx_docs = find_transactions_by_indices(tx_col, &tx_indices, true).await?;
         
-        // 获取交易索引，用于记录异常
-        let tx_index = tx.index.unwrap_or(0);
+        // 本页检测到的余额异常，处理完本页后一次写入
+        let mut anomalies = Vec::new();
         
-        // 检查交易状态 - 如果存在status字段且不是"COMPLETED"或"SUCCESS"，则跳过
-        if let Some(status) = tx_doc.get_str("status").ok() {
-            if status != "COMPLETED" && status != "SUCCESS" {
-                let index = tx.index.unwrap_or(0);
-                debug!("跳过未完成的交易 [索引:{}] [状态:{}]", index, status);
+        // 遍历处理每一笔交易
+        for tx_doc in tx_docs {
+            // 反序列化为交易对象 - 使用克隆避免所有权移动
+            let tx: Transaction = match mongodb::bson::from_document(tx_doc.clone()) {
+                Ok(transaction) => transaction,
+                Err(e) => {
+                    error!("反序列化交易失败: {}", e);
+                    continue;
+                }
+            };
+            
+            // 检查交易状态 - 如果存在status字段且不是"COMPLETED"或"SUCCESS"，则跳过
+            if let Ok(status) = tx_doc.get_str("status") {
+                if status != "COMPLETED" && status != "SUCCESS" {
+                    let index = tx.index.unwrap_or(0);
+                    debug!("跳过未完成的交易 [索引:{}] [状态:{}]", index, status);
                 
-                // 记录交易类型以便更好地分析
-                match tx.kind.as_str() {
-                    "transfer" => {
-                        if let Some(ref transfer) = tx.transfer {
-                            debug!("  - 跳过的转账交易: {} -> {} [金额:{}]",
-                                transfer.from, transfer.to, transfer.amount.0);
-                        }
-                    },
-                    "mint" => {
-                        if let Some(ref mint) = tx.mint {
-                            debug!("  - 跳过的铸币交易: 接收方:{} [金额:{}]",
-                                mint.to, mint.amount.0);
-                        }
-                    },
-                    "burn" => {
-                        if let Some(ref burn) = tx.burn {
-                            debug!("  - 跳过的销毁交易: 发送方:{} [金额:{}]",
-                                burn.from, burn.amount.0);
-                        }
-                    },
-                    "approve" => {
-                        if let Some(ref approve) = tx.approve {
-                            debug!("  - 跳过的授权交易: {} 授权给 {} [金额:{}]",
-                                approve.from, approve.spender, approve.amount.0);
+                    // 记录交易类型以便更好地分析
+                    match tx.kind.as_str() {
+                        "transfer" => {
+                            if let Some(ref transfer) = tx.transfer {
+                                debug!("  - 跳过的转账交易: {} -> {} [金额:{}]",
+                                    transfer.from, transfer.to, transfer.amount.0);
+                            }
+                        },
+                        "mint" => {
+                            if let Some(ref mint) = tx.mint {
+                                debug!("  - 跳过的铸币交易: 接收方:{} [金额:{}]",
+                                    mint.to, mint.amount.0);
+                            }
+                        },
+                        "burn" => {
+                            if let Some(ref burn) = tx.burn {
+                                debug!("  - 跳过的销毁交易: 发送方:{} [金额:{}]",
+                                    burn.from, burn.amount.0);
+                            }
+                        },
+                        "approve" => {
+                            if let Some(ref approve) = tx.approve {
+                                debug!("  - 跳过的授权交易: {} 授权给 {} [金额:{}]",
+                                    approve.from, approve.spender, approve.amount.0);
+                            }
+                        },
+                        _ => {
+                            debug!("  - 跳过的未知类型交易: {}", tx.kind);
                         }
-                    },
-                    _ => {
-                        debug!("  - 跳过的未知类型交易: {}", tx.kind);
                     }
+                    continue;
                 }
-                continue;
             }
-        }
         
-        // 检查账户格式是否包含子账户
-        let account_parts: Vec<&str> = normalized_account.split(':').collect();
-        // 添加前导下划线避免未使用变量警告
-        let _principal_id = account_parts[0];
-        let _subaccount_hex = if account_parts.len() > 1 { Some(account_parts[1]) } else { None };
+            // 根据交易类型和账户角色计算余额变化
+            let anomaly = apply_transaction_to_balance(
+                &mut balance,
+                &normalized_account,
+                &tx,
+                &mut anomalies
+            );
+            has_anomalies = has_anomalies || anomaly;
+            
+            processed_count += 1;
+        }
         
-        // 根据交易类型和账户角色计算余额变化
-        match tx.kind.as_str() {
-            "transfer" => {
-                if let Some(ref transfer) = tx.transfer {
-                    let from_account = transfer.from.to_string();
-                    let to_account = transfer.to.to_string();
-                    
-                    // 验证账户匹配，考虑子账户
-                    let is_from = account_match(&from_account, &normalized_account);
-                    let is_to = account_match(&to_account, &normalized_account);
-                    
-                    // 检查是否是transferFrom操作 (当spender字段存在时)
-                    let is_spender = if let Some(ref spender) = transfer.spender {
-                        account_match(&spender.to_string(), &normalized_account)
-                    } else {
-                        false
-                    };
+        if let Err(e) = log_balance_anomalies(anomalies_col, &anomalies).await {
+            warn!("账户 {} 的余额异常记录未保存: {}", normalized_account, e);
+        }
+    }
+    
+    // 余额已包含的最大交易索引，批次提交据此跳过已计入的交易
+    let last_tx_index = last_index;
+    
+    // 使用更精简的日志格式
+    debug!("已完成 {} 余额计算，共 {} 笔交易，余额：{} ({} 代币)", 
+           normalized_account, processed_count, balance.0, format_token_amount(&balance, _token_decimals));
+           
+    if has_anomalies {
+        info!("账户 {} 在余额计算中检测到异常，已记录详细信息", normalized_account);
+    }
+    
+    Ok((balance, has_anomalies, last_tx_index))
+}
+
+/// 将单笔交易对账户余额的影响累加到 `balance` 上
+///
+/// `account` 需为规范化后的账户ID。检测到的余额异常追加到 `anomalies`，由调用方写入异常集合
+/// （批次提交时与余额在同一事务中写入）。返回是否检测到余额异常。
+pub fn apply_transaction_to_balance(
+    balance: &mut Nat,
+    account: &str,
+    tx: &Transaction,
+    anomalies: &mut Vec<BalanceAnomaly>,
+) -> bool {
+    let mut has_anomalies = false;
+    // 获取交易索引，用于记录异常
+    let tx_index = tx.index.unwrap_or(0);
+    
+    match tx.kind.as_str() {
+        "transfer" => {
+            if let Some(ref transfer) = tx.transfer {
+                let from_account = transfer.from.to_string();
+                let to_account = transfer.to.to_string();
+                
+                // 验证账户匹配，考虑子账户
+                let is_from = account_match(&from_account, account);
+                let is_to = account_match(&to_account, account);
+                
+                // 检查是否是transferFrom操作 (当spender字段存在时)
+                let is_spender = if let Some(ref spender) = transfer.spender {
+                    account_match(&spender.to_string(), account)
+                } else {
+                    false
+                };
+                
+                // 如果是发送方，减少余额
+                if is_from {
+                    // 先创建错误消息，避免借用冲突
+                    let error_msg = format!("账户 {} 的余额不足，当前余额: {}, 转账金额: {}", 
+                                          account, balance.0, transfer.amount.0);
+                    // 安全扣减余额，确保不会变成负数
+                    let anomaly = safe_subtract_balance_with_logging(
+                        balance, 
+                        &transfer.amount, 
+                        &error_msg,
+                        account,
+                        tx_index,
+                        "transfer",
+                        anomalies
+                    );
+                    has_anomalies = has_anomalies || anomaly;
                     
-                    // 如果是发送方，减少余额
-                    if is_from {
-                        // 先创建错误消息，避免借用冲突
-                        let error_msg = format!("账户 {} 的余额不足，当前余额: {}, 转账金额: {}", 
-                                              normalized_account, balance.0, transfer.amount.0);
-                        // 安全扣减余额，确保不会变成负数
-                        if let Ok(anomaly) = safe_subtract_balance_with_logging(
-                            &mut balance, 
-                            &transfer.amount, 
-                            &error_msg,
-                            &normalized_account,
-                            tx_index,
-                            "transfer",
-                            anomalies_col
-                        ).await {
+                    // 减去手续费
+                    if let Some(ref fee) = transfer.fee {
+                        if !fee.0.is_zero() {
+                            let fee_error_msg = format!("账户 {} 的余额不足以支付手续费，当前余额: {}, 手续费: {}", 
+                                                     account, balance.0, fee.0);
+                            let anomaly = safe_subtract_balance_with_logging(
+                                balance, 
+                                fee, 
+                                &fee_error_msg,
+                                account,
+                                tx_index,
+                                "transfer_fee",
+                                anomalies
+                            );
                             has_anomalies = has_anomalies || anomaly;
                         }
-                        
-                        // 减去手续费
-                        if let Some(ref fee) = transfer.fee {
-                            if !fee.0.is_zero() {
-                                let fee_error_msg = format!("账户 {} 的余额不足以支付手续费，当前余额: {}, 手续费: {}", 
-                                                         normalized_account, balance.0, fee.0);
-                                if let Ok(anomaly) = safe_subtract_balance_with_logging(
-                                    &mut balance, 
-                                    fee, 
-                                    &fee_error_msg,
-                                    &normalized_account,
-                                    tx_index,
-                                    "transfer_fee",
-                                    anomalies_col
-                                ).await {
-                                    has_anomalies = has_anomalies || anomaly;
-                                }
-                            }
-                        }
-                    }
-                    
-                    // 如果是接收方，增加余额
-                    if is_to {
-                        balance = balance + transfer.amount.clone();
-                    }
-                    
-                    // 如果是spender (转账授权代理)，则不直接影响余额
-                    if is_spender {
-                        debug!("账户 {} 作为授权代理执行了从 {} 到 {} 的转账，金额: {}", 
-                                normalized_account, from_account, to_account, transfer.amount.0);
                     }
                 }
-            },
-            "mint" => {
-                if let Some(ref mint) = tx.mint {
-                    let to_account = mint.to.to_string();
-                    
-                    // 如果是接收方，增加余额
-                    if account_match(&to_account, &normalized_account) {
-                        balance = balance + mint.amount.clone();
-                    }
+                
+                // 如果是接收方，增加余额
+                if is_to {
+                    *balance += transfer.amount.clone();
                 }
-            },
-            "burn" => {
-                if let Some(ref burn) = tx.burn {
-                    let from_account = burn.from.to_string();
-                    
-                    // 检查是否是授权销毁
-                    let is_spender = if let Some(ref spender) = burn.spender {
-                        account_match(&spender.to_string(), &normalized_account)
-                    } else {
-                        false
-                    };
-                    
-                    // 如果是发送方，减少余额
-                    if account_match(&from_account, &normalized_account) {
-                        let error_msg = format!("账户 {} 的余额不足，当前余额: {}, 销毁金额: {}", 
-                                              normalized_account, balance.0, burn.amount.0);
-                        if let Ok(anomaly) = safe_subtract_balance_with_logging(
-                            &mut balance, 
-                            &burn.amount, 
-                            &error_msg,
-                            &normalized_account,
-                            tx_index,
-                            "burn",
-                            anomalies_col
-                        ).await {
-                            has_anomalies = has_anomalies || anomaly;
-                        }
-                    }
-                    
-                    // 记录spender操作
-                    if is_spender {
-                        debug!("账户 {} 作为授权代理执行了从 {} 销毁代币的操作，金额: {}", 
-                                normalized_account, from_account, burn.amount.0);
-                    }
+                
+                // 如果是spender (转账授权代理)，则不直接影响余额
+                if is_spender {
+                    debug!("账户 {} 作为授权代理执行了从 {} 到 {} 的转账，金额: {}", 
+                            account, from_account, to_account, transfer.amount.0);
                 }
-            },
-            "approve" => {
-                // approve操作不直接影响余额，只是授权
-                // 但如果有手续费，需要从发送方扣除
-                if let Some(ref approve) = tx.approve {
-                    let from_account = approve.from.to_string();
-                    
-                    if account_match(&from_account, &normalized_account) {
-                        if let Some(ref fee) = approve.fee {
-                            if !fee.0.is_zero() {
-                                let fee_error_msg = format!("账户 {} 的余额不足以支付授权手续费，当前余额: {}, 手续费: {}", 
-                                                         normalized_account, balance.0, fee.0);
-                                if let Ok(anomaly) = safe_subtract_balance_with_logging(
-                                    &mut balance, 
-                                    fee, 
-                                    &fee_error_msg,
-                                    &normalized_account,
-                                    tx_index,
-                                    "approve_fee",
-                                    anomalies_col
-                                ).await {
-                                    has_anomalies = has_anomalies || anomaly;
-                                }
-                            }
+            }
+        },
+        "mint" => {
+            if let Some(ref mint) = tx.mint {
+                let to_account = mint.to.to_string();
+                
+                // 如果是接收方，增加余额
+                if account_match(&to_account, account) {
+                    *balance += mint.amount.clone();
+                }
+            }
+        },
+        "burn" => {
+            if let Some(ref burn) = tx.burn {
+                let from_account = burn.from.to_string();
+                
+                // 检查是否是授权销毁
+                let is_spender = if let Some(ref spender) = burn.spender {
+                    account_match(&spender.to_string(), account)
+                } else {
+                    false
+                };
+                
+                // 如果是发送方，减少余额
+                if account_match(&from_account, account) {
+                    let error_msg = format!("账户 {} 的余额不足，当前余额: {}, 销毁金额: {}", 
+                                          account, balance.0, burn.amount.0);
+                    let anomaly = safe_subtract_balance_with_logging(
+                        balance, 
+                        &burn.amount, 
+                        &error_msg,
+                        account,
+                        tx_index,
+                        "burn",
+                        anomalies
+                    );
+                    has_anomalies = has_anomalies || anomaly;
+                }
+                
+                // 记录spender操作
+                if is_spender {
+                    debug!("账户 {} 作为授权代理执行了从 {} 销毁代币的操作，金额: {}", 
+                            account, from_account, burn.amount.0);
+                }
+            }
+        },
+        "approve" => {
+            // approve操作不直接影响余额，只是授权
+            // 但如果有手续费，需要从发送方扣除
+            if let Some(ref approve) = tx.approve {
+                let from_account = approve.from.to_string();
+                
+                if account_match(&from_account, account) {
+                    if let Some(ref fee) = approve.fee {
+                        if !fee.0.is_zero() {
+                            let fee_error_msg = format!("账户 {} 的余额不足以支付授权手续费，当前余额: {}, 手续费: {}", 
+                                                     account, balance.0, fee.0);
+                            let anomaly = safe_subtract_balance_with_logging(
+                                balance, 
+                                fee, 
+                                &fee_error_msg,
+                                account,
+                                tx_index,
+                                "approve_fee",
+                                anomalies
+                            );
+                            has_anomalies = has_anomalies || anomaly;
                         }
                     }
                 }
-            },
-            "notify" => {
-                // 处理ICRC-3标准的通知事件
-                debug!("处理通知事件 (索引:{}), 目前通知事件不影响余额", tx.index.unwrap_or(0));
-            },
-            _ => {
-                warn!("未知交易类型: {}, 跳过余额计算 (索引:{})", tx.kind, tx.index.unwrap_or(0));
             }
+        },
+        "notify" => {
+            // 处理ICRC-3标准的通知事件
+            debug!("处理通知事件 (索引:{}), 目前通知事件不影响余额", tx.index.unwrap_or(0));
+        },
+        _ => {
+            warn!("未知交易类型: {}, 跳过余额计算 (索引:{})", tx.kind, tx.index.unwrap_or(0));
         }
-        
-        processed_count += 1;
-    }
-    
-    // 使用更精简的日志格式
-    debug!("已完成 {} 余额计算，共 {} 笔交易，余额：{} ({} 代币)", 
-           normalized_account, processed_count, balance.0, format_token_amount(&balance, _token_decimals));
-           
-    if has_anomalies {
-        info!("账户 {} 在余额计算中检测到异常，已记录详细信息", normalized_account);
     }
     
-    Ok((balance, has_anomalies))
+    has_anomalies
 }
 
 /// 安全减少余额，确保不会变成负数
-/// 如果余额不足，将记录异常情况
-async fn safe_subtract_balance_with_logging(
+/// 如果余额不足，将异常情况追加到 `anomalies`，返回是否检测到异常
+fn safe_subtract_balance_with_logging(
     balance: &mut Nat,
     amount: &Nat,
     warning_msg: &str,
     account: &str,
     tx_index: u64,
     tx_type: &str,
-    anomalies_col: &Collection<Document>
-) -> Result<bool, Box<dyn Error>> {
+    anomalies: &mut Vec<BalanceAnomaly>
+) -> bool {
     let mut anomaly_detected = false;
     
     if *balance >= *amount {
@@ -621,29 +682,30 @@ async fn safe_subtract_balance_with_logging(
             timestamp: chrono::Utc::now().timestamp(),
         };
         
-        // 将异常记录保存到数据库
-        if let Err(e) = log_balance_anomaly(anomalies_col, &anomaly).await {
-            error!("记录余额异常失败: {}", e);
-        } else {
-            anomaly_detected = true;
-        }
+        anomalies.push(anomaly);
+        anomaly_detected = true;
         
         *balance = Nat::from(0u64);
     }
     
-    Ok(anomaly_detected)
+    anomaly_detected
 }
 
-/// 保存余额异常记录到数据库
-async fn log_balance_anomaly(
+/// 保存余额异常记录到数据库，一次写入多条
+pub async fn log_balance_anomalies(
     anomalies_col: &Collection<Document>,
-    anomaly: &BalanceAnomaly
-) -> Result<(), Box<dyn Error>> {
-    let anomaly_doc = mongodb::bson::to_document(anomaly)?;
+    anomalies: &[BalanceAnomaly]
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if anomalies.is_empty() {
+        return Ok(());
+    }
+    let anomaly_docs = anomalies.iter()
+        .map(mongodb::bson::to_document)
+        .collect::<Result<Vec<Document>, _>>()?;
     
-    match anomalies_col.insert_one(anomaly_doc, None).await {
+    match anomalies_col.insert_many(anomaly_docs, None).await {
         Ok(_) => {
-            debug!("已记录账户 {} 的余额异常 (交易索引: {})", anomaly.account, anomaly.tx_index);
+            debug!("已记录 {} 条余额异常", anomalies.len());
             Ok(())
         },
         Err(e) => {
@@ -713,10 +775,21 @@ async fn save_account_balance(
     balances_col: &Collection<Document>,
     account: &str,
     balance: &Nat,
-) -> Result<(), Box<dyn Error>> {
+    last_tx_index: Option<u64>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
     // 规范化账户格式
     let normalized_account = normalize_account_id(account);
     
+    let mut balance_doc = doc! {
+        "account": &normalized_account,
+        "balance": balance.0.to_string(),
+        "balance_sort": to_sortable_amount(balance),
+        "last_updated": chrono::Utc::now().timestamp(),
+    };
+    if let Some(index) = last_tx_index {
+        balance_doc.insert("last_tx_index", index as i64);
+    }
+    
     // 设置重试逻辑
     let max_retries = 3;
     let mut retry_count = 0;
@@ -725,13 +798,7 @@ async fn save_account_balance(
         // 更新余额
         match balances_col.update_one(
             doc! { "account": &normalized_account },
-            doc! {
-                "$set": {
-                    "account": &normalized_account,
-                    "balance": balance.0.to_string(),
-                    "last_updated": (chrono::Utc::now().timestamp() as i64),
-                }
-            },
+            doc! { "$set": balance_doc.clone() },
             mongodb::options::UpdateOptions::builder().upsert(true).build()
         ).await {
             Ok(_) => {
@@ -754,6 +821,36 @@ async fn save_account_balance(
     Err(create_error(&format!("更新账户 {} 余额失败，已重试 {} 次", normalized_account, max_retries)))
 }
 
+/// 为缺少 balance_sort 的旧余额文档补写可排序余额字段，返回更新的文档数
+pub async fn backfill_balance_sort(balances_col: &Collection<Document>) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    let mut cursor = balances_col.find(doc! { "balance_sort": { "$exists": false } }, None).await?;
+    let mut updated = 0u64;
+    
+    while cursor.advance().await? {
+        let balance_doc = Document::try_from(cursor.current().to_owned())?;
+        let (account, balance) = match (balance_doc.get_str("account"), balance_doc.get_str("balance")) {
+            (Ok(account), Ok(balance)) => (account.to_string(), balance.to_string()),
+            _ => continue,
+        };
+        let balance = match Nat::parse(balance.as_bytes()) {
+            Ok(balance) => balance,
+            Err(e) => {
+                warn!("账户 {} 的余额 {} 无法解析: {}", account, balance, e);
+                continue;
+            }
+        };
+        
+        balances_col.update_one(
+            doc! { "account": &account },
+            doc! { "$set": { "balance_sort": to_sortable_amount(&balance) } },
+            None
+        ).await?;
+        updated += 1;
+    }
+    
+    Ok(updated)
+}
+
 /// 规范化账户ID，去除全0子账户
 pub fn normalize_account_id(account: &str) -> String {
     // 拆分账户字符串，检查principal和子账户
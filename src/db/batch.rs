@@ -0,0 +1,634 @@
+/**
+ * 文件描述: 批次提交模块，保证一批交易及其派生数据与同步游标一致落库
+ * 功能概述:
+ * - 副本集/分片集群: 在一个会话事务中写入交易、账户-交易边、余额增量、总供应量和同步游标，
+ *   并在同一事务中更新本实例的租约记录：租约已过期或被其他实例接管时放弃提交，接管与提交同时发生时事务因写冲突重试
+ * - 单机MongoDB（不支持事务）: 按固定顺序逐步写入，每一步都可重复执行，同步游标最后写入
+ *
+ * 主要组件:
+ * - commit_batch函数: 批次提交入口，根据连接能力选择事务或顺序写入
+ * - commit_pending_balances函数: 补算余额计算进度落后的交易区间，与批次提交一样以租约记录为界
+ * - commit_batch_in_transaction函数: 事务模式
+ * - commit_batch_sequential函数: 单机回退模式
+ * - compute_balance_updates函数: 根据当前余额和批次交易计算余额增量和余额异常
+ * - balance_updates_command函数: 用一条 update 命令批量写入批次涉及的所有账户余额
+ * - batch_write_commands函数: 事务模式下用 update 命令批量写入交易、账户登记和账户-交易边
+ *
+ * 余额文档中的 last_tx_index 记录该余额已包含的最大交易索引，索引不大于它的交易不会重复计入，
+ * 因此单机模式下进程在任意一步中断后，从同步游标重放同一批次不会重复记账；总供应量文档的
+ * last_tx_index 同理记录已计入的批次末尾索引。
+ */
+
+use std::error::Error;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use mongodb::{Client, ClientSession, Collection};
+use mongodb::bson::{doc, to_document, Document};
+use mongodb::error::{TRANSIENT_TRANSACTION_ERROR, UNKNOWN_TRANSACTION_COMMIT_RESULT};
+use mongodb::options::UpdateOptions;
+use futures::stream::TryStreamExt;
+use candid::Nat;
+use chrono::Utc;
+use log::{info, warn, debug, error};
+use crate::db::{DbConnection, TokenCollections};
+use crate::db::accounts::save_account_transaction;
+use crate::db::balances::{apply_transaction_to_balance, normalize_account_id};
+use crate::db::leases;
+use crate::db::transactions::add_sortable_amount_fields;
+use crate::models::{BalanceAnomaly, Transaction};
+use crate::utils::{create_error, get_transaction_account_roles, to_sortable_amount};
+
+/// 事务遇到临时错误时的最大重试次数
+const MAX_TRANSACTION_RETRIES: u32 = 3;
+
+/// 单个账户的余额变化
+struct BalanceUpdate {
+    account: String,
+    old_balance: Nat,
+    new_balance: Nat,
+    last_tx_index: u64,
+}
+
+/// 提交一批已按索引排序的交易
+///
+/// `apply_balances` 为 true 时同时把交易计入余额和总供应量，并推进 last_balance_calculated_index；
+/// 为 false 时只写交易、账户关系和同步游标（初始同步之后统一计算余额）。
+pub async fn commit_batch(
+    db_conn: &DbConnection,
+    collections: &TokenCollections,
+    token_symbol: &str,
+    transactions: &[Transaction],
+    apply_balances: bool,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (last_index, last_timestamp) = match transactions.iter()
+        .filter_map(|tx| tx.index.map(|index| (index, tx.timestamp)))
+        .max_by_key(|(index, _)| *index)
+    {
+        Some(last) => last,
+        None => return Ok(()),
+    };
+
+    if !db_conn.supports_transactions {
+        return commit_batch_sequential(
+            db_conn, collections, token_symbol, transactions, apply_balances, last_index, last_timestamp
+        ).await;
+    }
+
+    retry_fenced_transaction(token_symbol, last_index, || commit_batch_in_transaction(
+        db_conn, collections, token_symbol, transactions, apply_balances, last_index, last_timestamp
+    )).await
+}
+
+/// 把已同步但尚未计入余额的交易计入余额和总供应量，并把 last_balance_calculated_index 推进到 `last_index`
+///
+/// 用于余额计算进度落后于同步游标时补算（见 sync::worker）。余额写入方式与 commit_batch 相同：
+/// 事务模式下在同一事务中更新租约记录，本实例不再持有租约时放弃提交；单机模式下写入前检查本地租约状态。
+pub async fn commit_pending_balances(
+    db_conn: &DbConnection,
+    collections: &TokenCollections,
+    token_symbol: &str,
+    transactions: &[Transaction],
+    last_index: u64,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if !db_conn.supports_transactions {
+        if !leases::is_held(token_symbol) {
+            return Err(create_error(&format!(
+                "{}: 本实例不再持有同步租约，余额补算 (索引至 {}) 未提交", token_symbol, last_index
+            )));
+        }
+        apply_balances_sequential(db_conn, collections, transactions, last_index).await?;
+        db_conn.sync_status_col.update_one(
+            doc! { "status_type": "sync_state", "token": token_symbol },
+            balance_progress_update_doc(last_index),
+            UpdateOptions::builder().upsert(true).build()
+        ).await?;
+        return Ok(());
+    }
+
+    retry_fenced_transaction(token_symbol, last_index, || pending_balances_in_transaction(
+        db_conn, collections, token_symbol, transactions, last_index
+    )).await
+}
+
+/// 执行带租约校验的事务，遇到临时错误时重试；事务返回 false（不再持有租约）时返回错误
+async fn retry_fenced_transaction<F, Fut>(
+    token_symbol: &str,
+    last_index: u64,
+    mut run: F,
+) -> Result<(), Box<dyn Error + Send + Sync>>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = mongodb::error::Result<bool>>,
+{
+    let mut retry_count = 0;
+    loop {
+        match run().await {
+            Ok(true) => {
+                debug!("{}: 事务提交成功，索引至 {}", token_symbol, last_index);
+                return Ok(());
+            },
+            Ok(false) => {
+                return Err(create_error(&format!(
+                    "{}: 本实例不再持有同步租约，写入 (索引至 {}) 未提交", token_symbol, last_index
+                )));
+            },
+            Err(e) if e.contains_label(TRANSIENT_TRANSACTION_ERROR) && retry_count < MAX_TRANSACTION_RETRIES => {
+                retry_count += 1;
+                let wait_time = tokio::time::Duration::from_millis(500 * retry_count as u64);
+                warn!("{}: 事务遇到临时错误 (尝试 {}/{}): {}，等待 {:?} 后重试",
+                    token_symbol, retry_count, MAX_TRANSACTION_RETRIES, e, wait_time);
+                tokio::time::sleep(wait_time).await;
+            },
+            Err(e) => {
+                return Err(create_error(&format!("{}: 事务提交失败 (索引至 {}): {}", token_symbol, last_index, e)));
+            }
+        }
+    }
+}
+
+/// 在事务中更新本实例的租约记录，租约已过期或被其他实例接管时返回 false；未启用租约时直接返回 true
+///
+/// 其他实例接管租约会修改同一条记录，两者只有一个能成功。
+async fn fence_lease(
+    db_conn: &DbConnection,
+    token_symbol: &str,
+    session: &mut ClientSession,
+) -> mongodb::error::Result<bool> {
+    let filter = match leases::held_lease_filter(token_symbol) {
+        Some(filter) => filter,
+        None => return Ok(true),
+    };
+    let fenced = db_conn.lease_col.update_one_with_session(
+        filter,
+        vec![doc! { "$set": { "committed_at": "$$NOW" } }],
+        None,
+        session
+    ).await?;
+    Ok(fenced.matched_count > 0)
+}
+
+/// 事务模式：所有写入在同一个事务中提交，本实例不再持有租约时放弃事务并返回 false
+async fn commit_batch_in_transaction(
+    db_conn: &DbConnection,
+    collections: &TokenCollections,
+    token_symbol: &str,
+    transactions: &[Transaction],
+    apply_balances: bool,
+    last_index: u64,
+    last_timestamp: u64,
+) -> mongodb::error::Result<bool> {
+    let client = &db_conn.client;
+    let mut session = client.start_session(None).await?;
+    session.start_transaction(None).await?;
+
+    if !fence_lease(db_conn, token_symbol, &mut session).await? {
+        session.abort_transaction().await?;
+        return Ok(false);
+    }
+
+    // 交易、账户登记和账户-交易边各用一条 update 命令写入整个批次，事务内的往返次数不随批次大小增长
+    let database = client.database(&collections.tx_col.namespace().db);
+    for command in batch_write_commands(collections, transactions)? {
+        let reply = database.run_command_with_session(command, None, &mut session).await?;
+        check_write_errors(&reply)?;
+    }
+
+    if apply_balances {
+        apply_balances_in_session(db_conn, collections, transactions, last_index, &mut session).await?;
+    }
+
+    db_conn.sync_status_col.update_one_with_session(
+        doc! { "status_type": "sync_state", "token": token_symbol },
+        sync_cursor_update_doc(token_symbol, last_index, last_timestamp, apply_balances),
+        UpdateOptions::builder().upsert(true).build(),
+        &mut session
+    ).await?;
+
+    commit_with_retry(&mut session).await?;
+    Ok(true)
+}
+
+/// 事务模式：余额补算，只写余额、余额异常、总供应量和余额计算进度
+async fn pending_balances_in_transaction(
+    db_conn: &DbConnection,
+    collections: &TokenCollections,
+    token_symbol: &str,
+    transactions: &[Transaction],
+    last_index: u64,
+) -> mongodb::error::Result<bool> {
+    let mut session = db_conn.client.start_session(None).await?;
+    session.start_transaction(None).await?;
+
+    if !fence_lease(db_conn, token_symbol, &mut session).await? {
+        session.abort_transaction().await?;
+        return Ok(false);
+    }
+
+    apply_balances_in_session(db_conn, collections, transactions, last_index, &mut session).await?;
+
+    db_conn.sync_status_col.update_one_with_session(
+        doc! { "status_type": "sync_state", "token": token_symbol },
+        balance_progress_update_doc(last_index),
+        UpdateOptions::builder().upsert(true).build(),
+        &mut session
+    ).await?;
+
+    commit_with_retry(&mut session).await?;
+    Ok(true)
+}
+
+/// 在事务中把交易计入余额和总供应量，余额异常一并写入
+async fn apply_balances_in_session(
+    db_conn: &DbConnection,
+    collections: &TokenCollections,
+    transactions: &[Transaction],
+    last_index: u64,
+    session: &mut ClientSession,
+) -> mongodb::error::Result<()> {
+    let accounts = affected_accounts(transactions);
+    let mut current = HashMap::new();
+    let mut cursor = collections.balances_col.find_with_session(
+        doc! { "account": { "$in": &accounts } }, None, session
+    ).await?;
+    while let Some(balance_doc) = cursor.next(session).await {
+        let balance_doc = balance_doc?;
+        if let Some((account, state)) = parse_balance_doc(&balance_doc) {
+            current.insert(account, state);
+        }
+    }
+    drop(cursor);
+
+    // 余额异常与余额在同一事务中写入，事务回滚重试时不会重复记录
+    let (updates, anomalies) = compute_balance_updates(&current, transactions);
+    if !anomalies.is_empty() {
+        collections.balance_anomalies_col.insert_many_with_session(anomaly_documents(&anomalies)?, None, session).await?;
+    }
+    if !updates.is_empty() {
+        let database = db_conn.client.database(&collections.balances_col.namespace().db);
+        let reply = database.run_command_with_session(
+            balance_updates_command(&collections.balances_col, &updates), None, session
+        ).await?;
+        check_write_errors(&reply)?;
+    }
+
+    let supply_doc = collections.total_supply_col
+        .find_one_with_session(doc! { "id": "total_supply" }, None, session)
+        .await?;
+    let old_supply = supply_doc
+        .and_then(|d| d.get_str("value").ok().and_then(|v| Nat::parse(v.as_bytes()).ok()))
+        .unwrap_or_else(|| Nat::from(0u64));
+    let new_supply = apply_supply_delta(old_supply, &updates);
+    collections.total_supply_col.update_one_with_session(
+        doc! { "id": "total_supply" },
+        doc! { "$set": { "id": "total_supply", "value": new_supply.to_string(), "last_tx_index": last_index as i64 } },
+        UpdateOptions::builder().upsert(true).build(),
+        session
+    ).await?;
+
+    Ok(())
+}
+
+/// 提交事务，提交结果未知时按驱动建议重试提交
+async fn commit_with_retry(session: &mut ClientSession) -> mongodb::error::Result<()> {
+    let mut retry_count = 0;
+    loop {
+        match session.commit_transaction().await {
+            Ok(()) => return Ok(()),
+            Err(e) if e.contains_label(UNKNOWN_TRANSACTION_COMMIT_RESULT) && retry_count < MAX_TRANSACTION_RETRIES => {
+                retry_count += 1;
+                warn!("事务提交结果未知 (尝试 {}/{}): {}，重新提交", retry_count, MAX_TRANSACTION_RETRIES, e);
+            },
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// 单机回退模式：按 交易 -> 账户关系 -> 总供应量 -> 余额 -> 同步游标 的顺序写入
+///
+/// 每一步都是幂等的，游标最后写入；中途失败时游标停在上一批次，下次从游标重放本批次。
+/// 总供应量与事务模式一样按余额增量调整，不再每批次遍历整个余额集合。
+async fn commit_batch_sequential(
+    db_conn: &DbConnection,
+    collections: &TokenCollections,
+    token_symbol: &str,
+    transactions: &[Transaction],
+    apply_balances: bool,
+    last_index: u64,
+    last_timestamp: u64,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let upsert = UpdateOptions::builder().upsert(true).build();
+
+    for tx in transactions {
+        crate::db::transactions::save_transaction(&collections.tx_col, tx).await?;
+        let index = tx.index.unwrap_or(0);
+        for (account, role) in get_transaction_account_roles(tx) {
+            save_account_transaction(&collections.accounts_col, &collections.account_tx_col, &account, index, role).await?;
+        }
+    }
+
+    if apply_balances {
+        apply_balances_sequential(db_conn, collections, transactions, last_index).await?;
+    }
+
+    db_conn.sync_status_col.update_one(
+        doc! { "status_type": "sync_state", "token": token_symbol },
+        sync_cursor_update_doc(token_symbol, last_index, last_timestamp, apply_balances),
+        upsert
+    ).await?;
+
+    debug!("{}: 批次顺序提交完成，同步游标: {}", token_symbol, last_index);
+    Ok(())
+}
+
+/// 单机回退模式：把交易计入余额和总供应量
+///
+/// 总供应量先于余额写入，并记录已计入的批次末尾索引：余额写到一半中断时，
+/// 重放只会为尚未写入的账户重新计算增量，而总供应量按索引判断已经包含整个批次。
+async fn apply_balances_sequential(
+    db_conn: &DbConnection,
+    collections: &TokenCollections,
+    transactions: &[Transaction],
+    last_index: u64,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let upsert = UpdateOptions::builder().upsert(true).build();
+    let accounts = affected_accounts(transactions);
+    let balance_docs: Vec<Document> = collections.balances_col
+        .find(doc! { "account": { "$in": &accounts } }, None)
+        .await?
+        .try_collect()
+        .await?;
+    let current: HashMap<String, (Nat, Option<u64>)> = balance_docs.iter()
+        .filter_map(parse_balance_doc)
+        .collect();
+
+    // 余额写入前中断时重放会再次检测到同样的异常，按 (账户, 交易索引, 交易类型) upsert，不重复记录
+    let (updates, anomalies) = compute_balance_updates(&current, transactions);
+    let database = db_conn.client.database(&collections.balances_col.namespace().db);
+    if !anomalies.is_empty() {
+        let reply = database.run_command(anomaly_upsert_command(&collections.balance_anomalies_col, &anomalies)?, None).await?;
+        check_write_errors(&reply)?;
+    }
+
+    // 总供应量先于余额写入，并记录已计入的批次末尾索引：余额写到一半中断时，
+    // 重放只会为尚未写入的账户重新计算增量，而总供应量按索引判断已经包含整个批次
+    let supply_doc = collections.total_supply_col.find_one(doc! { "id": "total_supply" }, None).await?;
+    let supply_applied = supply_doc.as_ref()
+        .and_then(|d| d.get_i64("last_tx_index").ok())
+        .is_some_and(|applied| applied >= last_index as i64);
+    if !supply_applied {
+        let old_supply = supply_doc
+            .and_then(|d| d.get_str("value").ok().and_then(|v| Nat::parse(v.as_bytes()).ok()))
+            .unwrap_or_else(|| Nat::from(0u64));
+        let new_supply = apply_supply_delta(old_supply, &updates);
+        collections.total_supply_col.update_one(
+            doc! { "id": "total_supply" },
+            doc! { "$set": { "id": "total_supply", "value": new_supply.to_string(), "last_tx_index": last_index as i64 } },
+            upsert.clone()
+        ).await?;
+    }
+
+    if !updates.is_empty() {
+        let reply = database.run_command(balance_updates_command(&collections.balances_col, &updates), None).await?;
+        check_write_errors(&reply)?;
+    }
+
+    Ok(())
+}
+
+/// 批次中涉及的所有账户（规范化后去重）
+fn affected_accounts(transactions: &[Transaction]) -> Vec<String> {
+    let mut accounts: Vec<String> = transactions.iter()
+        .flat_map(get_transaction_account_roles)
+        .map(|(account, _)| normalize_account_id(&account))
+        .collect();
+    accounts.sort();
+    accounts.dedup();
+    accounts
+}
+
+/// 解析余额文档，返回 (账户, (余额, 已计入的最大交易索引))
+fn parse_balance_doc(balance_doc: &Document) -> Option<(String, (Nat, Option<u64>))> {
+    let account = balance_doc.get_str("account").ok()?.to_string();
+    let balance = balance_doc.get_str("balance").ok()
+        .and_then(|b| Nat::parse(b.as_bytes()).ok())
+        .unwrap_or_else(|| Nat::from(0u64));
+    let last_tx_index = balance_doc.get_i64("last_tx_index").ok().map(|i| i as u64);
+    Some((account, (balance, last_tx_index)))
+}
+
+/// 在当前余额基础上依次计入批次交易，返回余额变化和检测到的余额异常
+///
+/// 同一笔交易中账户有多个角色时只计入一次；索引不大于余额 last_tx_index 的交易视为已计入。
+/// 不访问数据库，异常记录由调用方与余额一起写入。
+fn compute_balance_updates(
+    current: &HashMap<String, (Nat, Option<u64>)>,
+    transactions: &[Transaction],
+) -> (Vec<BalanceUpdate>, Vec<BalanceAnomaly>) {
+    let mut updates: HashMap<String, BalanceUpdate> = HashMap::new();
+    let mut anomalies = Vec::new();
+
+    for tx in transactions {
+        let index = tx.index.unwrap_or(0);
+        let mut accounts: Vec<String> = get_transaction_account_roles(tx).into_iter()
+            .map(|(account, _)| normalize_account_id(&account))
+            .collect();
+        accounts.sort();
+        accounts.dedup();
+
+        for account in accounts {
+            let (old_balance, last_applied) = current.get(&account)
+                .cloned()
+                .unwrap_or_else(|| (Nat::from(0u64), None));
+            if last_applied.is_some_and(|last| index <= last) {
+                continue;
+            }
+
+            let update = updates.entry(account.clone()).or_insert_with(|| BalanceUpdate {
+                account: account.clone(),
+                old_balance: old_balance.clone(),
+                new_balance: old_balance,
+                last_tx_index: index,
+            });
+            apply_transaction_to_balance(&mut update.new_balance, &account, tx, &mut anomalies);
+            update.last_tx_index = index;
+        }
+    }
+
+    (updates.into_values().collect(), anomalies)
+}
+
+/// 批量 upsert 的 update 命令，每条语句为 (查询条件, 更新文档)
+fn upsert_command(col: &Collection<Document>, statements: Vec<(Document, Document)>) -> Document {
+    let statements: Vec<Document> = statements.into_iter()
+        .map(|(query, update)| doc! { "q": query, "u": update, "upsert": true })
+        .collect();
+    doc! { "update": col.name(), "updates": statements, "ordered": true }
+}
+
+/// 批量更新余额的 update 命令，每个账户一条语句，一次往返写入整个批次的余额
+fn balance_updates_command(balances_col: &Collection<Document>, updates: &[BalanceUpdate]) -> Document {
+    upsert_command(balances_col, updates.iter()
+        .map(|update| (doc! { "account": &update.account }, balance_update_doc(update)))
+        .collect())
+}
+
+/// 批次交易、账户登记和账户-交易边的 update 命令（事务模式使用），没有内容的命令不生成
+///
+/// 账户登记按账户合并为一条语句，$min/$max 取批次内的首末交易索引；边使用 $setOnInsert，重复提交不会产生重复记录。
+fn batch_write_commands(
+    collections: &TokenCollections,
+    transactions: &[Transaction],
+) -> mongodb::error::Result<Vec<Document>> {
+    let mut tx_statements = Vec::with_capacity(transactions.len());
+    let mut account_ranges: BTreeMap<String, (i64, i64)> = BTreeMap::new();
+    let mut edge_statements = Vec::new();
+
+    for tx in transactions {
+        let index = tx.index.unwrap_or(0) as i64;
+        let mut tx_doc = to_document(tx)?;
+        add_sortable_amount_fields(&mut tx_doc, tx);
+        tx_statements.push((doc! { "index": index }, doc! { "$set": tx_doc }));
+
+        for (account, role) in get_transaction_account_roles(tx) {
+            let range = account_ranges.entry(account.clone()).or_insert((index, index));
+            range.0 = range.0.min(index);
+            range.1 = range.1.max(index);
+            edge_statements.push((
+                doc! { "account": &account, "index": index, "role": role },
+                doc! { "$setOnInsert": { "account": &account, "index": index, "role": role } },
+            ));
+        }
+    }
+
+    let account_statements: Vec<(Document, Document)> = account_ranges.into_iter()
+        .map(|(account, (first, last))| (
+            doc! { "account": &account },
+            doc! {
+                "$set": { "account": &account },
+                "$min": { "first_tx_index": first },
+                "$max": { "last_tx_index": last }
+            },
+        ))
+        .collect();
+
+    Ok([
+        (&collections.tx_col, tx_statements),
+        (&collections.accounts_col, account_statements),
+        (&collections.account_tx_col, edge_statements),
+    ].into_iter()
+        .filter(|(_, statements)| !statements.is_empty())
+        .map(|(col, statements)| upsert_command(col, statements))
+        .collect())
+}
+
+/// update 命令的单条语句失败时不会返回错误，而是在回复的 writeErrors 中列出
+fn check_write_errors(reply: &Document) -> mongodb::error::Result<()> {
+    match reply.get_array("writeErrors") {
+        Ok(errors) if !errors.is_empty() => {
+            error!("批量写入失败: {:?}", errors);
+            Err(mongodb::error::Error::custom(format!("批量写入失败: {} 条语句出错", errors.len())))
+        },
+        _ => Ok(()),
+    }
+}
+
+/// 余额异常记录转换为文档
+fn anomaly_documents(anomalies: &[BalanceAnomaly]) -> mongodb::error::Result<Vec<Document>> {
+    anomalies.iter()
+        .map(|anomaly| to_document(anomaly).map_err(Into::into))
+        .collect()
+}
+
+/// 按 (账户, 交易索引, 交易类型) upsert 余额异常的 update 命令（单机模式使用），已记录的异常保持不变
+fn anomaly_upsert_command(anomalies_col: &Collection<Document>, anomalies: &[BalanceAnomaly]) -> mongodb::error::Result<Document> {
+    let statements = anomalies.iter()
+        .zip(anomaly_documents(anomalies)?)
+        .map(|(anomaly, anomaly_doc)| (
+            doc! { "account": &anomaly.account, "tx_index": anomaly.tx_index as i64, "tx_type": &anomaly.tx_type },
+            doc! { "$setOnInsert": anomaly_doc },
+        ))
+        .collect();
+    Ok(upsert_command(anomalies_col, statements))
+}
+
+/// 余额更新文档
+fn balance_update_doc(update: &BalanceUpdate) -> Document {
+    doc! {
+        "$set": {
+            "account": &update.account,
+            "balance": update.new_balance.0.to_string(),
+            "balance_sort": to_sortable_amount(&update.new_balance),
+            "last_tx_index": update.last_tx_index as i64,
+            "last_updated": Utc::now().timestamp(),
+        }
+    }
+}
+
+/// 按余额变化调整总供应量
+fn apply_supply_delta(old_supply: Nat, updates: &[BalanceUpdate]) -> Nat {
+    let mut increased = old_supply;
+    let mut decreased = Nat::from(0u64);
+    for update in updates {
+        increased += update.new_balance.clone();
+        decreased += update.old_balance.clone();
+    }
+    if increased >= decreased {
+        increased - decreased
+    } else {
+        warn!("总供应量增量计算结果为负，已置为0，建议重新计算余额");
+        Nat::from(0u64)
+    }
+}
+
+/// 同步游标更新文档
+///
+/// 不计入余额的批次（初始同步，或余额计算进度落后时）不修改同步模式：初始同步期间保持全量模式，
+/// 中断后重新执行初始同步，由 prepare_token_sync 在计算完余额后切换到增量模式。
+fn sync_cursor_update_doc(token_symbol: &str, last_index: u64, last_timestamp: u64, apply_balances: bool) -> Document {
+    let mut set_doc = doc! {
+        "token": token_symbol,
+        "last_synced_index": last_index as i64,
+        "last_synced_timestamp": last_timestamp as i64,
+        "updated_at": Utc::now().timestamp(),
+    };
+    if apply_balances {
+        set_doc.insert("sync_mode", "incremental");
+        set_doc.insert("last_balance_calculated_index", last_index as i64);
+        doc! { "$set": set_doc }
+    } else {
+        doc! { "$set": set_doc, "$setOnInsert": { "sync_mode": "full" } }
+    }
+}
+
+/// 余额计算进度更新文档
+fn balance_progress_update_doc(last_index: u64) -> Document {
+    doc! {
+        "$set": {
+            "last_balance_calculated_index": last_index as i64,
+            "updated_at": Utc::now().timestamp(),
+        }
+    }
+}
+
+/// 检测当前MongoDB部署是否支持多文档事务
+///
+/// 副本集成员会返回 setName，mongos 返回 msg = "isdbgrid"；单机部署两者都没有。
+pub async fn detect_transaction_support(client: &Client) -> bool {
+    match client.database("admin").run_command(doc! { "hello": 1 }, None).await {
+        Ok(reply) => {
+            let is_replica_set = reply.get_str("setName").is_ok();
+            let is_mongos = reply.get_str("msg").map(|m| m == "isdbgrid").unwrap_or(false);
+            if is_replica_set || is_mongos {
+                info!("MongoDB 支持多文档事务，批次将以事务方式提交");
+                true
+            } else {
+                warn!("MongoDB 为单机部署，不支持事务，批次将按顺序写入并最后更新同步游标");
+                false
+            }
+        },
+        Err(e) => {
+            warn!("检测MongoDB部署类型失败: {}，按不支持事务处理", e);
+            false
+        }
+    }
+}
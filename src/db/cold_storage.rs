@@ -0,0 +1,480 @@
+/**
+ * 文件描述: 交易冷存储模块，将超过保留期的交易从MongoDB迁移到本地压缩分段文件
+ * 功能概述:
+ * - 按代币配置的保留期，把旧交易按固定数量打包为 gzip 压缩的 BSON 分段文件
+ * - 每个代币目录维护一个 manifest.json，记录各分段覆盖的索引和时间范围
+ * - 查询交易时，MongoDB 中不存在的索引自动从分段文件读取
+ *
+ * 主要组件:
+ * - ColdStorage结构体: 单个代币的冷存储目录及其分段清单
+ * - register_cold_storage / cold_storage_for函数: 按交易集合名称登记和查找冷存储，
+ *   读取路径据此透明回退，无需额外传参
+ * - ColdStorage::reload_if_changed方法: manifest.json 修改后重新加载分段清单（只读 API 实例定期调用，执行保留策略前也会调用）
+ * - run_retention函数: 执行一次保留策略，归档并删除过期交易
+ * - clear_cold_storage函数: 重置时删除代币的全部分段文件
+ *
+ * 目录结构: {cold_storage_dir}/{代币符号小写}/manifest.json 与 seg_{起始索引}_{结束索引}.bson.gz
+ *
+ * 只归档索引不超过 last_balance_calculated_index 的交易：余额和总供应量在批次提交时已经计入，
+ * 之后不再依赖被归档的交易文档；全量重算余额时读取路径同样会回退到分段文件。
+ *
+ * 文件读写使用 tokio::fs，分段的压缩和解压放到阻塞线程池执行，不占用异步运行时的工作线程。
+ */
+
+use std::error::Error;
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+use mongodb::Collection;
+use mongodb::bson::{doc, Document};
+use mongodb::options::FindOptions;
+use futures::stream::TryStreamExt;
+use tokio::io::AsyncWriteExt;
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use log::{info, warn, debug};
+use crate::db::sync_status::get_sync_status;
+use crate::models::{RetentionConfig, TokenConfig};
+use crate::utils::create_error;
+
+/// 默认冷存储根目录
+pub const DEFAULT_COLD_STORAGE_DIR: &str = "cold_storage";
+/// 默认每个分段包含的交易数量
+const DEFAULT_SEGMENT_SIZE: u64 = 10000;
+/// 单次执行保留策略最多写入的分段数，避免长时间阻塞同步
+const MAX_SEGMENTS_PER_RUN: usize = 10;
+/// manifest 格式版本
+const MANIFEST_VERSION: u32 = 1;
+/// 每天的纳秒数（交易时间戳为纳秒）
+const NANOS_PER_DAY: u64 = 86_400 * 1_000_000_000;
+
+lazy_static::lazy_static! {
+    /// 交易集合名称 -> 冷存储
+    static ref COLD_STORES: RwLock<HashMap<String, Arc<ColdStorage>>> = RwLock::new(HashMap::new());
+}
+
+/// 分段文件信息
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SegmentInfo {
+    pub file: String,
+    pub start_index: u64,
+    pub end_index: u64,
+    pub count: u64,
+    pub min_timestamp: u64,
+    pub max_timestamp: u64,
+    /// MongoDB 中对应的文档是否已删除
+    pub pruned: bool,
+    pub created_at: i64,
+}
+
+/// 分段清单
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub token: String,
+    pub segments: Vec<SegmentInfo>,
+}
+
+/// 单个代币的冷存储
+pub struct ColdStorage {
+    dir: PathBuf,
+    token_symbol: String,
+    manifest: RwLock<Manifest>,
+    /// 最近一次读取的 manifest.json 修改时间，只读实例据此判断是否需要重新加载
+    manifest_modified: Mutex<Option<SystemTime>>,
+    /// 最近读取的一个分段，余额重算等顺序读取场景下避免重复解压
+    cache: Mutex<Option<(String, Arc<Vec<Document>>)>>,
+}
+
+impl ColdStorage {
+    /// 打开代币的冷存储目录，manifest 不存在时视为空
+    async fn open(dir: PathBuf, token_symbol: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let (manifest, modified) = read_manifest(&dir, token_symbol).await?;
+        Ok(ColdStorage {
+            dir,
+            token_symbol: token_symbol.to_string(),
+            manifest: RwLock::new(manifest),
+            manifest_modified: Mutex::new(modified),
+            cache: Mutex::new(None),
+        })
+    }
+
+    /// manifest.json 的修改时间变化时重新加载分段清单，返回是否重新加载
+    ///
+    /// 只读 API 实例不执行保留策略，同步进程归档新分段或重置冷存储后需要重新读取清单，
+    /// 否则已从 MongoDB 删除的交易在只读实例上查询不到。热备实例接管租约后同样需要重新读取。
+    pub async fn reload_if_changed(&self) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let modified = manifest_modified_at(&self.dir).await?;
+        if modified == *self.manifest_modified.lock().unwrap() {
+            return Ok(false);
+        }
+        let (manifest, modified) = read_manifest(&self.dir, &self.token_symbol).await?;
+        *self.manifest.write().unwrap() = manifest;
+        *self.manifest_modified.lock().unwrap() = modified;
+        *self.cache.lock().unwrap() = None;
+        Ok(true)
+    }
+
+    /// 已归档的最大交易索引
+    pub fn archived_until(&self) -> Option<u64> {
+        self.manifest.read().unwrap().segments.last().map(|s| s.end_index)
+    }
+
+    /// 已归档的最小交易索引
+    pub fn archived_from(&self) -> Option<u64> {
+        self.manifest.read().unwrap().segments.first().map(|s| s.start_index)
+    }
+
+    /// 已归档且已从 MongoDB 删除的交易数量
+    pub fn archived_count(&self) -> u64 {
+        self.manifest.read().unwrap().segments.iter().filter(|s| s.pruned).map(|s| s.count).sum()
+    }
+
+    /// 与索引区间 [start, end] 有交集的分段
+    fn segments_in_range(&self, start: u64, end: u64) -> Vec<SegmentInfo> {
+        self.manifest.read().unwrap().segments.iter()
+            .filter(|s| s.start_index <= end && s.end_index >= start)
+            .cloned()
+            .collect()
+    }
+
+    /// 第一笔时间戳不早于 `time_ns` 的归档交易索引，按分段的时间范围定位后只读取一个分段
+    pub async fn index_at_time(&self, time_ns: u64) -> Result<Option<u64>, Box<dyn Error + Send + Sync>> {
+        let segment = self.manifest.read().unwrap().segments.iter()
+            .find(|s| s.max_timestamp >= time_ns)
+            .cloned();
+        let Some(segment) = segment else {
+            return Ok(None);
+        };
+        let docs = self.load_segment(&segment).await?;
+        Ok(docs.iter()
+            .filter(|d| d.get_i64("timestamp").is_ok_and(|t| t as u64 >= time_ns))
+            .filter_map(|d| d.get_i64("index").ok())
+            .min()
+            .map(|index| index as u64))
+    }
+
+    /// 读取单笔归档交易
+    pub async fn get_transaction(&self, index: u64) -> Result<Option<Document>, Box<dyn Error + Send + Sync>> {
+        for segment in self.segments_in_range(index, index) {
+            let docs = self.load_segment(&segment).await?;
+            if let Some(found) = docs.iter().find(|d| d.get_i64("index").ok() == Some(index as i64)) {
+                return Ok(Some(found.clone()));
+            }
+        }
+        Ok(None)
+    }
+
+    /// 读取索引区间内的归档交易，按索引升序
+    pub async fn get_range(&self, start: u64, end: u64) -> Result<Vec<Document>, Box<dyn Error + Send + Sync>> {
+        let mut result = Vec::new();
+        for segment in self.segments_in_range(start, end) {
+            let docs = self.load_segment(&segment).await?;
+            result.extend(docs.iter()
+                .filter(|d| d.get_i64("index").map(|i| i >= start as i64 && i <= end as i64).unwrap_or(false))
+                .cloned());
+        }
+        Ok(result)
+    }
+
+    /// 按索引列表读取归档交易
+    pub async fn get_by_indices(&self, indices: &[i64]) -> Result<Vec<Document>, Box<dyn Error + Send + Sync>> {
+        let (min, max) = match (indices.iter().min(), indices.iter().max()) {
+            (Some(min), Some(max)) => (*min as u64, *max as u64),
+            _ => return Ok(Vec::new()),
+        };
+        let wanted: std::collections::HashSet<i64> = indices.iter().copied().collect();
+        let mut result = Vec::new();
+        for segment in self.segments_in_range(min, max) {
+            if !wanted.iter().any(|i| *i as u64 >= segment.start_index && *i as u64 <= segment.end_index) {
+                continue;
+            }
+            let docs = self.load_segment(&segment).await?;
+            result.extend(docs.iter()
+                .filter(|d| d.get_i64("index").map(|i| wanted.contains(&i)).unwrap_or(false))
+                .cloned());
+        }
+        Ok(result)
+    }
+
+    /// 解压并解析分段文件
+    async fn load_segment(&self, segment: &SegmentInfo) -> Result<Arc<Vec<Document>>, Box<dyn Error + Send + Sync>> {
+        if let Some((file, docs)) = self.cache.lock().unwrap().as_ref() {
+            if *file == segment.file {
+                return Ok(docs.clone());
+            }
+        }
+
+        let compressed = tokio::fs::read(self.dir.join(&segment.file)).await?;
+        let segment_info = segment.clone();
+        let docs = tokio::task::spawn_blocking(move || decode_segment(&compressed, &segment_info)).await??;
+
+        let docs = Arc::new(docs);
+        *self.cache.lock().unwrap() = Some((segment.file.clone(), docs.clone()));
+        Ok(docs)
+    }
+
+    /// 写入一个分段文件并追加到 manifest
+    async fn write_segment(&self, docs: &[Document]) -> Result<SegmentInfo, Box<dyn Error + Send + Sync>> {
+        let indices: Vec<u64> = docs.iter().filter_map(|d| d.get_i64("index").ok()).map(|i| i as u64).collect();
+        let timestamps: Vec<u64> = docs.iter().filter_map(|d| d.get_i64("timestamp").ok()).map(|t| t as u64).collect();
+        let (start_index, end_index) = match (indices.iter().min(), indices.iter().max()) {
+            (Some(min), Some(max)) => (*min, *max),
+            _ => return Err(create_error("分段中没有有效的交易索引")),
+        };
+
+        let to_encode = docs.to_vec();
+        let compressed = tokio::task::spawn_blocking(move || encode_segment(to_encode)).await??;
+
+        let segment = SegmentInfo {
+            file: format!("seg_{:020}_{:020}.bson.gz", start_index, end_index),
+            start_index,
+            end_index,
+            count: docs.len() as u64,
+            min_timestamp: timestamps.iter().copied().min().unwrap_or(0),
+            max_timestamp: timestamps.iter().copied().max().unwrap_or(0),
+            pruned: false,
+            created_at: chrono::Utc::now().timestamp(),
+        };
+
+        write_file_atomically(&self.dir.join(&segment.file), &compressed).await?;
+
+        let manifest = {
+            let mut manifest = self.manifest.write().unwrap();
+            manifest.segments.retain(|s| s.file != segment.file);
+            manifest.segments.push(segment.clone());
+            manifest.segments.sort_by_key(|s| s.start_index);
+            manifest.clone()
+        };
+        self.save_manifest(&manifest).await?;
+
+        Ok(segment)
+    }
+
+    /// 标记分段对应的 MongoDB 文档已删除
+    async fn mark_pruned(&self, file: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let manifest = {
+            let mut manifest = self.manifest.write().unwrap();
+            for segment in manifest.segments.iter_mut().filter(|s| s.file == file) {
+                segment.pruned = true;
+            }
+            manifest.clone()
+        };
+        self.save_manifest(&manifest).await
+    }
+
+    async fn save_manifest(&self, manifest: &Manifest) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let content = serde_json::to_vec_pretty(manifest)?;
+        write_file_atomically(&self.dir.join("manifest.json"), &content).await
+    }
+}
+
+/// manifest.json 的修改时间，文件不存在时返回 None
+async fn manifest_modified_at(dir: &Path) -> Result<Option<SystemTime>, Box<dyn Error + Send + Sync>> {
+    match tokio::fs::metadata(dir.join("manifest.json")).await {
+        Ok(metadata) => Ok(Some(metadata.modified()?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(Box::new(e)),
+    }
+}
+
+/// 读取代币的分段清单及其修改时间，manifest 不存在时视为空
+async fn read_manifest(dir: &Path, token_symbol: &str) -> Result<(Manifest, Option<SystemTime>), Box<dyn Error + Send + Sync>> {
+    let Some(modified) = manifest_modified_at(dir).await? else {
+        return Ok((Manifest { version: MANIFEST_VERSION, token: token_symbol.to_string(), segments: Vec::new() }, None));
+    };
+    let content = tokio::fs::read_to_string(dir.join("manifest.json")).await?;
+    let manifest: Manifest = serde_json::from_str(&content)?;
+    if manifest.version > MANIFEST_VERSION {
+        return Err(create_error(&format!(
+            "{}: 冷存储清单版本 {} 高于程序支持的版本 {}", token_symbol, manifest.version, MANIFEST_VERSION
+        )));
+    }
+    Ok((manifest, Some(modified)))
+}
+
+/// 从连续存放的 BSON 流中读取下一个文档的原始字节，流结束时返回 None
+///
+/// 每个 BSON 文档以4字节小端长度开头，长度包含自身。
+pub fn read_bson_bytes<R: Read>(reader: &mut R) -> Result<Option<Vec<u8>>, Box<dyn Error + Send + Sync>> {
+    let mut len_bytes = [0u8; 4];
+    match reader.read_exact(&mut len_bytes) {
+        Ok(()) => {},
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(Box::new(e)),
+    }
+    let len = i32::from_le_bytes(len_bytes);
+    if len < 5 {
+        return Err(create_error(&format!("无效的BSON文档长度: {}", len)));
+    }
+    let mut buf = vec![0u8; len as usize];
+    buf[..4].copy_from_slice(&len_bytes);
+    reader.read_exact(&mut buf[4..])?;
+    Ok(Some(buf))
+}
+
+/// 解压分段文件并逐个解析其中的交易文档（CPU 密集，在阻塞线程池中执行）
+fn decode_segment(compressed: &[u8], segment: &SegmentInfo) -> Result<Vec<Document>, Box<dyn Error + Send + Sync>> {
+    let mut decoder = GzDecoder::new(compressed);
+    let mut docs = Vec::with_capacity(segment.count as usize);
+    while let Some(bytes) = read_bson_bytes(&mut decoder)
+        .map_err(|e| create_error(&format!("分段文件 {} 已损坏: {}", segment.file, e)))?
+    {
+        docs.push(Document::from_reader(bytes.as_slice())?);
+    }
+    Ok(docs)
+}
+
+/// 去掉 `_id` 后把交易文档压缩为分段内容（CPU 密集，在阻塞线程池中执行）
+fn encode_segment(docs: Vec<Document>) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    for mut tx_doc in docs {
+        tx_doc.remove("_id");
+        tx_doc.to_writer(&mut encoder)?;
+    }
+    Ok(encoder.finish()?)
+}
+
+/// 先写临时文件并落盘，再重命名覆盖目标文件
+async fn write_file_atomically(path: &Path, content: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let tmp_path = path.with_extension("tmp");
+    let mut file = tokio::fs::File::create(&tmp_path).await?;
+    file.write_all(content).await?;
+    file.sync_all().await?;
+    tokio::fs::rename(&tmp_path, path).await?;
+    Ok(())
+}
+
+/// 代币的冷存储目录
+fn token_dir(base_dir: &str, token_symbol: &str) -> PathBuf {
+    Path::new(base_dir).join(token_symbol.to_lowercase())
+}
+
+/// 登记代币的冷存储，之后该交易集合的读取会自动回退到分段文件
+pub async fn register_cold_storage(
+    tx_col: &Collection<Document>,
+    base_dir: &str,
+    token_symbol: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let storage = ColdStorage::open(token_dir(base_dir, token_symbol), token_symbol).await?;
+    if let Some(until) = storage.archived_until() {
+        info!("{}: 冷存储已归档至交易索引 {}", token_symbol, until);
+    }
+    COLD_STORES.write().unwrap().insert(tx_col.name().to_string(), Arc::new(storage));
+    Ok(())
+}
+
+/// 查找交易集合对应的冷存储
+pub fn cold_storage_for(tx_col: &Collection<Document>) -> Option<Arc<ColdStorage>> {
+    COLD_STORES.read().unwrap().get(tx_col.name()).cloned()
+}
+
+/// 执行一次保留策略，返回本次归档的交易数量
+///
+/// 只归档完整的分段（segment_size 笔交易），且分段内全部交易都早于保留期、
+/// 索引不超过余额已计算到的位置。分段写入 manifest 后才删除 MongoDB 中的文档，
+/// 删除中断时下次执行会先补删。
+pub async fn run_retention(
+    tx_col: &Collection<Document>,
+    sync_status_col: &Collection<Document>,
+    token_config: &TokenConfig,
+    retention: &RetentionConfig,
+) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    let token_symbol = &token_config.symbol;
+    let storage = match cold_storage_for(tx_col) {
+        Some(storage) => storage,
+        None => return Err(create_error(&format!("{}: 未登记冷存储", token_symbol))),
+    };
+    tokio::fs::create_dir_all(&storage.dir).await?;
+
+    // 热备实例接管租约前，分段可能由上一个持有者归档；先重新读取清单，
+    // 否则写入新分段时会用过期的清单覆盖 manifest.json，丢失对方归档的分段
+    if storage.reload_if_changed().await? {
+        info!("{}: 冷存储清单已由其他实例更新，已重新加载", token_symbol);
+    }
+
+    // 补删上次未完成删除的分段
+    let unpruned: Vec<SegmentInfo> = storage.manifest.read().unwrap().segments.iter()
+        .filter(|s| !s.pruned)
+        .cloned()
+        .collect();
+    for segment in unpruned {
+        prune_segment(&storage, tx_col, &segment).await?;
+    }
+
+    let balance_index = match get_sync_status(sync_status_col, token_symbol).await?
+        .and_then(|status| status.last_balance_calculated_index)
+    {
+        Some(index) => index,
+        None => return Ok(0),
+    };
+    let now_ns = chrono::Utc::now().timestamp_nanos_opt().unwrap_or(0).max(0) as u64;
+    let cutoff = now_ns.saturating_sub(retention.keep_days * NANOS_PER_DAY);
+    let segment_size = retention.segment_size.unwrap_or(DEFAULT_SEGMENT_SIZE).max(1);
+
+    let mut archived = 0u64;
+    for _ in 0..MAX_SEGMENTS_PER_RUN {
+        let filter = match storage.archived_until() {
+            Some(until) => doc! { "index": { "$gt": until as i64, "$lte": balance_index as i64 } },
+            None => doc! { "index": { "$lte": balance_index as i64 } },
+        };
+        let options = FindOptions::builder()
+            .sort(doc! { "index": 1 })
+            .limit(segment_size as i64)
+            .build();
+        let docs: Vec<Document> = tx_col.find(filter, options).await?.try_collect().await?;
+
+        if (docs.len() as u64) < segment_size {
+            debug!("{}: 可归档交易不足一个分段 ({} < {})", token_symbol, docs.len(), segment_size);
+            break;
+        }
+        let newest = docs.iter().filter_map(|d| d.get_i64("timestamp").ok()).max().unwrap_or(i64::MAX);
+        if newest as u64 >= cutoff {
+            debug!("{}: 下一分段包含保留期内的交易，停止归档", token_symbol);
+            break;
+        }
+
+        let segment = storage.write_segment(&docs).await?;
+        info!("{}: 已归档交易 {}-{} ({} 笔) 到 {}",
+            token_symbol, segment.start_index, segment.end_index, segment.count, segment.file);
+        prune_segment(&storage, tx_col, &segment).await?;
+        archived += segment.count;
+    }
+
+    Ok(archived)
+}
+
+/// 删除分段中已归档的 MongoDB 文档
+///
+/// 按分段内实际包含的索引删除，之后补回到 MongoDB 的同区间交易（例如缺口回填）不受影响。
+async fn prune_segment(
+    storage: &ColdStorage,
+    tx_col: &Collection<Document>,
+    segment: &SegmentInfo,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let docs = storage.load_segment(segment).await?;
+    let indices: Vec<i64> = docs.iter().filter_map(|d| d.get_i64("index").ok()).collect();
+    let result = tx_col.delete_many(doc! { "index": { "$in": &indices } }, None).await?;
+    debug!("分段 {} 已从MongoDB删除 {} 笔交易", segment.file, result.deleted_count);
+    storage.mark_pruned(&segment.file).await
+}
+
+/// 删除代币的全部冷存储分段（重置时使用）
+pub async fn clear_cold_storage(tx_col: &Collection<Document>, token_symbol: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let storage = match cold_storage_for(tx_col) {
+        Some(storage) => storage,
+        None => return Ok(()),
+    };
+    if tokio::fs::try_exists(&storage.dir).await? {
+        tokio::fs::remove_dir_all(&storage.dir).await?;
+        warn!("{}: 已删除冷存储目录 {}", token_symbol, storage.dir.display());
+    }
+    storage.manifest.write().unwrap().segments.clear();
+    *storage.manifest_modified.lock().unwrap() = None;
+    *storage.cache.lock().unwrap() = None;
+    Ok(())
+}
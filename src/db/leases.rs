@@ -0,0 +1,286 @@
+/**
+ * 文件描述: 同步租约模块，多个实例共享同一个数据库时保证每个代币同一时刻只有一个实例在同步
+ * 功能概述:
+ * - 在 sync_leases 集合中为每个代币保存一条租约记录（持有者、获取时间、续期时间、过期时间）
+ * - 获取和续期使用 MongoDB 服务器时间（$$NOW），不依赖各实例的本地时钟
+ * - 持有者定期续期；超过有效期未续期的租约可以被其他实例接管，实现热备切换
+ * - 本地记录已持有的租约及其最晚有效时间，续期失败或被接管后同步任务在下一批次前停止写入
+ * - 正常停止时释放租约，热备实例无需等待过期即可接管
+ *
+ * 主要组件:
+ * - configure函数: 按配置设置实例标识、有效期和续期间隔
+ * - acquire函数: 获取或续期代币的租约
+ * - is_held函数: 本实例当前是否持有代币的租约（同步批次之间检查）
+ * - held_lease_filter函数: 批次事务中校验租约的查询条件
+ * - release函数: 释放代币的租约
+ * - get_lease函数: 读取代币的租约记录（status 命令使用）
+ * - spawn_heartbeat函数: 定期为已持有的租约续期
+ * - status_document函数: /api/status 中的租约状态
+ *
+ * 未启用租约时 acquire 和 is_held 总是返回 true，行为与单实例部署相同。
+ */
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Mutex, RwLock};
+use std::time::Instant;
+use mongodb::Collection;
+use mongodb::bson::{doc, Document};
+use mongodb::error::{ErrorKind, WriteFailure};
+use mongodb::options::UpdateOptions;
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
+use log::{info, warn, debug};
+use crate::models::LeaseConfig;
+
+/// 默认租约有效期（秒）
+const DEFAULT_LEASE_TTL_SECS: u64 = 30;
+/// 默认续期间隔（秒）
+const DEFAULT_RENEW_INTERVAL_SECS: u64 = 10;
+/// MongoDB 唯一索引冲突的错误码
+const DUPLICATE_KEY_CODE: i32 = 11000;
+
+/// 租约设置
+#[derive(Debug, Clone)]
+struct LeaseSettings {
+    enabled: bool,
+    instance_id: String,
+    ttl: Duration,
+    renew_interval: Duration,
+}
+
+impl Default for LeaseSettings {
+    fn default() -> Self {
+        LeaseSettings {
+            enabled: true,
+            instance_id: default_instance_id(),
+            ttl: Duration::from_secs(DEFAULT_LEASE_TTL_SECS),
+            renew_interval: Duration::from_secs(DEFAULT_RENEW_INTERVAL_SECS),
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    /// 本进程的租约设置，启动时由 configure 设置
+    static ref SETTINGS: RwLock<LeaseSettings> = RwLock::new(LeaseSettings::default());
+    /// 本实例持有的租约，值为按本地时钟计算的最晚有效时间
+    static ref HELD: Mutex<HashMap<String, Instant>> = Mutex::new(HashMap::new());
+}
+
+/// 默认实例标识：主机名-进程号
+fn default_instance_id() -> String {
+    let hostname = std::env::var("HOSTNAME").ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "index-rs".to_string());
+    format!("{}-{}", hostname, std::process::id())
+}
+
+/// 按配置设置租约参数，启动时调用一次
+pub fn configure(config: Option<&LeaseConfig>) {
+    let mut settings = LeaseSettings::default();
+    if let Some(config) = config {
+        settings.enabled = config.enabled.unwrap_or(true);
+        if let Some(instance_id) = config.instance_id.as_ref().filter(|id| !id.trim().is_empty()) {
+            settings.instance_id = instance_id.trim().to_string();
+        }
+        settings.ttl = Duration::from_secs(config.ttl_secs.unwrap_or(DEFAULT_LEASE_TTL_SECS).max(1));
+        settings.renew_interval = Duration::from_secs(config.renew_interval_secs.unwrap_or(DEFAULT_RENEW_INTERVAL_SECS).max(1));
+    }
+    if settings.renew_interval >= settings.ttl {
+        let renew_interval = (settings.ttl / 3).max(Duration::from_millis(500));
+        warn!("租约续期间隔 ({:?}) 不小于有效期 ({:?})，改为 {:?}", settings.renew_interval, settings.ttl, renew_interval);
+        settings.renew_interval = renew_interval;
+    }
+
+    if settings.enabled {
+        info!("同步租约: 实例 {}，有效期 {:?}，续期间隔 {:?}", settings.instance_id, settings.ttl, settings.renew_interval);
+    } else {
+        warn!("同步租约已关闭，请确保只有一个实例同步该数据库");
+    }
+    *SETTINGS.write().unwrap() = settings;
+}
+
+fn settings() -> LeaseSettings {
+    SETTINGS.read().unwrap().clone()
+}
+
+/// 本实例的标识（分片回填的工作进程同样使用）
+pub fn instance_id() -> String {
+    settings().instance_id
+}
+
+/// 续期间隔，热备实例按该间隔尝试接管
+pub fn renew_interval() -> Duration {
+    settings().renew_interval
+}
+
+/// 是否为唯一索引冲突（另一个实例同时插入了同一代币的租约）
+fn is_duplicate_key(e: &mongodb::error::Error) -> bool {
+    match e.kind.as_ref() {
+        ErrorKind::Write(WriteFailure::WriteError(write_error)) => write_error.code == DUPLICATE_KEY_CODE,
+        ErrorKind::Command(command_error) => command_error.code == DUPLICATE_KEY_CODE,
+        _ => false,
+    }
+}
+
+/// 获取或续期代币的租约，返回本实例是否持有租约
+///
+/// 租约不存在、已过期或本来就由本实例持有时获取成功；另一个实例持有未过期的租约时返回 false。
+pub async fn acquire(
+    lease_col: &Collection<Document>,
+    token_symbol: &str,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    let settings = settings();
+    if !settings.enabled {
+        return Ok(true);
+    }
+    let ttl_ms = settings.ttl.as_millis() as i64;
+    let sent_at = Instant::now();
+
+    let filter = doc! {
+        "token": token_symbol,
+        "$or": [
+            { "holder": &settings.instance_id },
+            { "$expr": { "$lt": ["$expires_at", "$$NOW"] } },
+        ],
+    };
+    let update = vec![doc! {
+        "$set": {
+            "token": token_symbol,
+            "acquired_at": {
+                "$cond": [{ "$eq": ["$holder", &settings.instance_id] }, "$acquired_at", "$$NOW"]
+            },
+            "holder": &settings.instance_id,
+            "renewed_at": "$$NOW",
+            "expires_at": { "$add": ["$$NOW", ttl_ms] },
+        }
+    }];
+    let previous = get_lease(lease_col, token_symbol).await?;
+    let options = UpdateOptions::builder().upsert(true).build();
+    match lease_col.update_one(filter, update, options).await {
+        Ok(_) => {},
+        Err(e) if is_duplicate_key(&e) => return Ok(false),
+        Err(e) => return Err(e.into()),
+    }
+
+    let newly_held = HELD.lock().unwrap().insert(token_symbol.to_string(), sent_at + settings.ttl).is_none();
+    if newly_held {
+        match previous.as_ref().and_then(|lease| lease.get_str("holder").ok()) {
+            Some(holder) if holder != settings.instance_id => {
+                warn!("{}: 实例 {} 的租约已过期，由本实例接管同步", token_symbol, holder);
+            },
+            _ => info!("{}: 已获取同步租约", token_symbol),
+        }
+    }
+    Ok(true)
+}
+
+/// 本实例当前是否持有代币的租约
+///
+/// 只检查本地记录：续期失败超过有效期或被其他实例接管后返回 false，同步任务应停止写入。
+pub fn is_held(token_symbol: &str) -> bool {
+    if !settings().enabled {
+        return true;
+    }
+    HELD.lock().unwrap().get(token_symbol).is_some_and(|deadline| *deadline > Instant::now())
+}
+
+/// 本实例持有且尚未过期的租约记录的查询条件，批次事务用它校验租约；未启用租约时返回 None
+pub fn held_lease_filter(token_symbol: &str) -> Option<Document> {
+    let settings = settings();
+    if !settings.enabled {
+        return None;
+    }
+    Some(doc! {
+        "token": token_symbol,
+        "holder": settings.instance_id,
+        "$expr": { "$gt": ["$expires_at", "$$NOW"] },
+    })
+}
+
+/// 释放代币的租约，只删除由本实例持有的记录
+pub async fn release(
+    lease_col: &Collection<Document>,
+    token_symbol: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let settings = settings();
+    if !settings.enabled || HELD.lock().unwrap().remove(token_symbol).is_none() {
+        return Ok(());
+    }
+    lease_col.delete_one(doc! { "token": token_symbol, "holder": &settings.instance_id }, None).await?;
+    info!("{}: 已释放同步租约", token_symbol);
+    Ok(())
+}
+
+/// 读取代币的租约记录
+pub async fn get_lease(
+    lease_col: &Collection<Document>,
+    token_symbol: &str,
+) -> Result<Option<Document>, Box<dyn Error + Send + Sync>> {
+    Ok(lease_col.find_one(doc! { "token": token_symbol }, None).await?)
+}
+
+/// 为已持有的租约续期
+///
+/// 租约已被其他实例接管时立即放弃；续期请求失败时保留本地记录，超过有效期后 is_held 自然返回 false。
+async fn renew_all(lease_col: &Collection<Document>) {
+    let settings = settings();
+    let tokens: Vec<String> = HELD.lock().unwrap().keys().cloned().collect();
+    for token_symbol in tokens {
+        let ttl_ms = settings.ttl.as_millis() as i64;
+        let sent_at = Instant::now();
+        let result = lease_col.update_one(
+            doc! { "token": &token_symbol, "holder": &settings.instance_id },
+            vec![doc! { "$set": { "renewed_at": "$$NOW", "expires_at": { "$add": ["$$NOW", ttl_ms] } } }],
+            None
+        ).await;
+        match result {
+            Ok(result) if result.matched_count == 0 => {
+                HELD.lock().unwrap().remove(&token_symbol);
+                warn!("{}: 同步租约已被其他实例接管，本实例停止同步该代币", token_symbol);
+            },
+            Ok(_) => {
+                if let Some(deadline) = HELD.lock().unwrap().get_mut(&token_symbol) {
+                    *deadline = sent_at + settings.ttl;
+                }
+                debug!("{}: 同步租约已续期", token_symbol);
+            },
+            Err(e) => warn!("{}: 同步租约续期失败: {}", token_symbol, e),
+        }
+    }
+}
+
+/// 定期为本实例持有的租约续期
+///
+/// 服务停止期间仍然续期，同步任务写入停止检查点后释放租约。
+pub fn spawn_heartbeat(lease_col: Collection<Document>) -> Option<JoinHandle<()>> {
+    if !settings().enabled {
+        return None;
+    }
+    Some(tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(renew_interval()).await;
+            renew_all(&lease_col).await;
+        }
+    }))
+}
+
+/// /api/status 中的租约状态
+pub fn status_document() -> Document {
+    let settings = settings();
+    let now = Instant::now();
+    let mut held: Vec<String> = HELD.lock().unwrap().iter()
+        .filter(|(_, deadline)| **deadline > now)
+        .map(|(token, _)| token.clone())
+        .collect();
+    held.sort();
+    doc! {
+        "enabled": settings.enabled,
+        "instance_id": settings.instance_id,
+        "ttl_secs": settings.ttl.as_secs() as i64,
+        "renew_interval_secs": settings.renew_interval.as_secs_f64(),
+        "held_tokens": held,
+    }
+}
@@ -3,19 +3,24 @@
  * 功能概述:
  * - 初始化MongoDB连接
  * - 创建和管理代币相关的集合
- * - 创建数据库索引
+ * - 数据库结构版本管理与迁移（见 schema 模块）
  * - 提供数据库访问限流和并发控制
+ * - 检测部署是否支持事务，供批次提交使用（见 batch 模块）
+ * - 旧交易的冷存储归档与透明读取（见 cold_storage 模块）
+ * - 代币索引状态快照的导出与导入（见 snapshot 模块）
+ * - 代币注册表的持久化（见 registry 模块）
+ * - 多实例共享数据库时的同步租约（见 leases 模块）
+ * - 分片回填的计划和工作单元（见 backfill_jobs 模块）
  * 
  * 主要组件:
- * - DbConnection结构体: 包含数据库连接和各代币集合
+ * - DbConnection结构体: 包含数据库连接和各代币集合，代币集合可在运行时增删
  * - TokenCollections结构体: 单个代币的所有相关集合
- * - init_db函数: 初始化数据库连接，创建各代币集合
- * - create_indexes函数: 创建数据库索引以优化查询性能
+ * - init_db函数: 初始化数据库连接（代币集合由 add_token_collections 创建）
  * - with_db_semaphore函数: 限制数据库并发操作数量的工具函数
  */
 
 use std::error::Error;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use std::collections::HashMap;
 use mongodb::{Client, Collection, Database};
 use mongodb::bson::Document;
@@ -26,31 +31,76 @@ use crate::models::TokenConfig;
 
 pub mod transactions;
 pub mod accounts;
+pub mod account_transactions;
 pub mod balances;
 pub mod sync_status;
 pub mod supply;
+pub mod schema;
+pub mod batch;
+pub mod cold_storage;
+pub mod snapshot;
+pub mod registry;
+pub mod leases;
+pub mod backfill_jobs;
 
 #[derive(Clone)]
 /// 数据库连接信息
 pub struct DbConnection {
-    #[allow(dead_code)]
+    pub client: Client,
     pub db: Database,
-    pub collections: HashMap<String, TokenCollections>,
+    /// 各代币的集合，运行时注册或移除代币时更新
+    collections: Arc<RwLock<HashMap<String, TokenCollections>>>,
     pub sync_status_col: Collection<Document>,
+    pub schema_version_col: Collection<Document>,
+    pub registry_col: Collection<Document>,
+    pub lease_col: Collection<Document>,
+    pub backfill_col: Collection<Document>,
     #[allow(dead_code)]
     pub db_semaphore: Arc<Semaphore>,
+    /// 部署是否支持多文档事务（副本集或分片集群）
+    pub supports_transactions: bool,
 }
 
 impl DbConnection {
     /// 获取指定代币的交易集合
     #[allow(dead_code)]
     pub fn get_transactions_collection(&self, token_symbol: &str) -> Collection<Document> {
-        if let Some(collections) = self.collections.get(token_symbol) {
-            collections.tx_col.clone()
+        if let Some(collections) = self.token_collections(token_symbol) {
+            collections.tx_col
         } else {
             panic!("未找到代币 {} 的集合", token_symbol)
         }
     }
+
+    /// 获取指定代币的集合
+    pub fn token_collections(&self, token_symbol: &str) -> Option<TokenCollections> {
+        self.collections.read().unwrap().get(token_symbol).cloned()
+    }
+
+    /// 已创建集合的代币符号，按字母顺序排列
+    pub fn token_symbols(&self) -> Vec<String> {
+        let mut symbols: Vec<String> = self.collections.read().unwrap().keys().cloned().collect();
+        symbols.sort();
+        symbols
+    }
+
+    /// 为代币创建集合句柄，已存在时直接返回
+    ///
+    /// MongoDB 在首次写入时创建集合，索引由结构迁移创建（见 schema 模块）。
+    pub fn add_token_collections(&self, token: &TokenConfig) -> TokenCollections {
+        let mut collections = self.collections.write().unwrap();
+        collections.entry(token.symbol.clone())
+            .or_insert_with(|| {
+                info!("为代币 {} ({}) 创建集合", token.name, token.symbol);
+                TokenCollections::new(&self.db, &token.symbol)
+            })
+            .clone()
+    }
+
+    /// 移除代币的集合句柄（不删除数据）
+    pub fn remove_token_collections(&self, token_symbol: &str) -> Option<TokenCollections> {
+        self.collections.write().unwrap().remove(token_symbol)
+    }
 }
 
 #[derive(Clone)]
@@ -60,13 +110,45 @@ pub struct TokenCollections {
     pub symbol: String,
     pub tx_col: Collection<Document>,
     pub accounts_col: Collection<Document>,
+    pub account_tx_col: Collection<Document>,
     pub balances_col: Collection<Document>,
     pub total_supply_col: Collection<Document>,
     pub balance_anomalies_col: Collection<Document>,
 }
 
+impl TokenCollections {
+    /// 按代币符号的小写形式作为集合名前缀
+    fn new(db: &Database, token_symbol: &str) -> Self {
+        let prefix = token_symbol.to_lowercase();
+        TokenCollections {
+            symbol: token_symbol.to_string(),
+            tx_col: db.collection(&format!("{}_transactions", prefix)),
+            accounts_col: db.collection(&format!("{}_accounts", prefix)),
+            account_tx_col: db.collection(&format!("{}_account_transactions", prefix)),
+            balances_col: db.collection(&format!("{}_balances", prefix)),
+            total_supply_col: db.collection(&format!("{}_total_supply", prefix)),
+            balance_anomalies_col: db.collection(&format!("{}_balance_anomalies", prefix)),
+        }
+    }
+
+    /// 删除该代币的全部集合
+    pub async fn drop_all(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        for col in [
+            &self.tx_col,
+            &self.accounts_col,
+            &self.account_tx_col,
+            &self.balances_col,
+            &self.total_supply_col,
+            &self.balance_anomalies_col,
+        ] {
+            col.drop(None).await?;
+        }
+        Ok(())
+    }
+}
+
 /// 初始化MongoDB连接
-pub async fn init_db(mongodb_url: &str, database_name: &str, tokens: &[TokenConfig]) -> Result<DbConnection, Box<dyn Error>> {
+pub async fn init_db(mongodb_url: &str, database_name: &str) -> Result<DbConnection, Box<dyn Error + Send + Sync>> {
     info!("初始化MongoDB连接: {}", mongodb_url);
     
     let options = ClientOptions::parse_with_resolver_config(mongodb_url, ResolverConfig::cloudflare()).await?;
@@ -80,116 +162,39 @@ pub async fn init_db(mongodb_url: &str, database_name: &str, tokens: &[TokenConf
     
     info!("已连接到MongoDB");
     
+    let supports_transactions = batch::detect_transaction_support(&mongo_client).await;
+    
     let db = mongo_client.database(database_name);
     let sync_status_col: Collection<Document> = db.collection("sync_status");
+    let schema_version_col: Collection<Document> = db.collection("schema_version");
+    let registry_col: Collection<Document> = db.collection("token_registry");
+    let lease_col: Collection<Document> = db.collection("sync_leases");
+    let backfill_col: Collection<Document> = db.collection("backfill_jobs");
     let db_semaphore = Arc::new(Semaphore::new(30));
     
-    // 为每个代币创建集合
-    let mut collections = HashMap::new();
-    for token in tokens {
-        info!("为代币 {} ({}) 创建集合", token.name, token.symbol);
-        
-        let prefix = token.symbol.to_lowercase();
-        let tx_col: Collection<Document> = db.collection(&format!("{}_transactions", prefix));
-        let accounts_col: Collection<Document> = db.collection(&format!("{}_accounts", prefix));
-        let balances_col: Collection<Document> = db.collection(&format!("{}_balances", prefix));
-        let total_supply_col: Collection<Document> = db.collection(&format!("{}_total_supply", prefix));
-        let balance_anomalies_col: Collection<Document> = db.collection(&format!("{}_balance_anomalies", prefix));
-        
-        let token_collections = TokenCollections {
-            symbol: token.symbol.clone(),
-            tx_col,
-            accounts_col,
-            balances_col,
-            total_supply_col,
-            balance_anomalies_col,
-        };
-        
-        collections.insert(token.symbol.clone(), token_collections);
-    }
-    
     Ok(DbConnection {
+        client: mongo_client,
         db,
-        collections,
+        collections: Arc::new(RwLock::new(HashMap::new())),
         sync_status_col,
+        schema_version_col,
+        registry_col,
+        lease_col,
+        backfill_col,
         db_semaphore,
+        supports_transactions,
     })
 }
 
-/// 创建数据库索引
-pub async fn create_indexes(conn: &DbConnection) -> Result<(), Box<dyn Error>> {
-    info!("创建或确认数据库索引...");
-    
-    // 为每个代币创建索引
-    for (symbol, collections) in &conn.collections {
-        info!("为代币 {} 创建索引", symbol);
-        
-        // 交易索引
-        match collections.tx_col.create_index(
-            mongodb::IndexModel::builder()
-                .keys(mongodb::bson::doc! { "index": 1 })
-                .options(mongodb::options::IndexOptions::builder().unique(true).build())
-                .build(),
-            None
-        ).await {
-            Ok(_) => info!("{}: 交易索引创建成功", symbol),
-            Err(e) => error!("{}: 交易索引创建失败: {}", symbol, e)
-        }
-        
-        // 账户索引
-        match collections.accounts_col.create_index(
-            mongodb::IndexModel::builder()
-                .keys(mongodb::bson::doc! { "account": 1 })
-                .build(),
-            None
-        ).await {
-            Ok(_) => info!("{}: 账户索引创建成功", symbol),
-            Err(e) => error!("{}: 账户索引创建失败: {}", symbol, e)
-        }
-        
-        // 余额索引
-        match collections.balances_col.create_index(
-            mongodb::IndexModel::builder()
-                .keys(mongodb::bson::doc! { "account": 1 })
-                .options(mongodb::options::IndexOptions::builder().unique(true).build())
-                .build(),
-            None
-        ).await {
-            Ok(_) => info!("{}: 余额索引创建成功", symbol),
-            Err(e) => error!("{}: 余额索引创建失败: {}", symbol, e)
-        }
-    }
-    
-    // 同步状态索引
-    // 在创建新索引前清除旧的索引，避免重复键错误
-    match conn.sync_status_col.drop_indexes(None).await {
-        Ok(_) => info!("同步状态集合旧索引已清除"),
-        Err(e) => error!("同步状态集合清除旧索引失败: {}", e)
-    }
-    
-    match conn.sync_status_col.create_index(
-        mongodb::IndexModel::builder()
-            .keys(mongodb::bson::doc! { "status_type": 1, "token": 1 })
-            .options(mongodb::options::IndexOptions::builder().unique(true).build())
-            .build(),
-        None
-    ).await {
-        Ok(_) => info!("同步状态索引创建成功"),
-        Err(e) => error!("同步状态索引创建失败: {}", e)
-    }
-    
-    Ok(())
-}
-
 /// 辅助函数：使用信号量限制并发数，并在释放信号量前执行异步操作
 #[allow(dead_code)]
 pub async fn with_db_semaphore<F, T>(
     semaphore: Arc<Semaphore>,
     operation_name: &str,
     func: F
-) -> Result<T, Box<dyn Error>>
+) -> Result<T, Box<dyn Error + Send + Sync>>
 where
-    F: FnOnce() -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<T, Box<dyn Error>>> + Send>>,
+    F: FnOnce() -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<T, Box<dyn Error + Send + Sync>>> + Send>>,
 {
     // 尝试获取信号量许可
     let permit = match semaphore.acquire().await {
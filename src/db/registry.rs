@@ -0,0 +1,202 @@
+/**
+ * 文件描述: 代币注册表模块，在 MongoDB 中持久化需要索引的代币列表
+ * 功能概述:
+ * - 保存每个代币的配置、状态（同步中/已暂停/已移除）和来源（config.toml/管理接口）
+ * - 启动时将 config.toml 中的代币与注册表合并，得到本次运行需要管理的代币
+ * - 管理接口增删、暂停、恢复代币时更新注册表，重启后保持一致
+ *
+ * 主要组件:
+ * - RegistryEntry结构体: 注册表中的一个代币
+ * - merge_config_tokens函数: 合并 config.toml 中的代币与注册表
+ * - read_registry_tokens函数: 只读 API 实例读取代币列表，不写入注册表
+ * - get_registry_entry函数: 读取单个代币的注册表记录
+ * - save_registry_entry函数: 新增或更新注册表记录
+ * - set_registry_status函数: 更新代币状态
+ * - delete_registry_entry函数: 删除注册表记录
+ *
+ * config.toml 中的代币以配置文件为准，注册表只保留其状态；
+ * 通过管理接口添加的代币只存在于注册表中。
+ */
+
+use std::error::Error;
+use futures::stream::TryStreamExt;
+use mongodb::Collection;
+use mongodb::bson::{self, doc, Document};
+use mongodb::options::{FindOptions, UpdateOptions};
+use chrono::Utc;
+use log::{info, warn};
+use crate::models::{TokenConfig, TokenSource, TokenStatus};
+
+/// 注册表中的一个代币
+#[derive(Debug, Clone)]
+pub struct RegistryEntry {
+    pub token: TokenConfig,
+    pub status: TokenStatus,
+    pub source: TokenSource,
+}
+
+impl RegistryEntry {
+    /// 转换为 API 返回的文档
+    pub fn to_document(&self) -> Document {
+        let config = bson::to_document(&self.token).unwrap_or_default();
+        doc! {
+            "symbol": &self.token.symbol,
+            "status": self.status.as_str(),
+            "source": source_str(self.source),
+            "config": config,
+        }
+    }
+}
+
+fn source_str(source: TokenSource) -> &'static str {
+    match source {
+        TokenSource::Config => "config",
+        TokenSource::Api => "api",
+    }
+}
+
+/// 从注册表文档解析代币，格式不正确时返回 None
+fn parse_entry(entry_doc: &Document) -> Option<RegistryEntry> {
+    let config = entry_doc.get_document("config").ok()?;
+    let token: TokenConfig = bson::from_document(config.clone()).ok()?;
+    let status = bson::from_bson(entry_doc.get("status")?.clone()).ok()?;
+    let source = bson::from_bson(entry_doc.get("source")?.clone()).ok()?;
+    Some(RegistryEntry { token, status, source })
+}
+
+/// 读取注册表中的全部代币，按创建时间排序
+pub async fn load_registry(
+    registry_col: &Collection<Document>,
+) -> Result<Vec<RegistryEntry>, Box<dyn Error + Send + Sync>> {
+    let options = FindOptions::builder().sort(doc! { "created_at": 1 }).build();
+    let docs: Vec<Document> = registry_col.find(None, options).await?.try_collect().await?;
+
+    let mut entries = Vec::new();
+    for entry_doc in &docs {
+        match parse_entry(entry_doc) {
+            Some(entry) => entries.push(entry),
+            None => warn!("忽略格式不正确的代币注册表记录: {:?}", entry_doc.get("symbol")),
+        }
+    }
+    Ok(entries)
+}
+
+/// 读取单个代币的注册表记录
+pub async fn get_registry_entry(
+    registry_col: &Collection<Document>,
+    symbol: &str,
+) -> Result<Option<RegistryEntry>, Box<dyn Error + Send + Sync>> {
+    let entry_doc = registry_col.find_one(doc! { "symbol": symbol }, None).await?;
+    Ok(entry_doc.as_ref().and_then(parse_entry))
+}
+
+/// 新增或更新注册表记录
+pub async fn save_registry_entry(
+    registry_col: &Collection<Document>,
+    entry: &RegistryEntry,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let now = Utc::now().timestamp();
+    registry_col.update_one(
+        doc! { "symbol": &entry.token.symbol },
+        doc! {
+            "$set": {
+                "status": entry.status.as_str(),
+                "source": source_str(entry.source),
+                "config": bson::to_document(&entry.token)?,
+                "updated_at": now,
+            },
+            "$setOnInsert": { "created_at": now },
+        },
+        UpdateOptions::builder().upsert(true).build()
+    ).await?;
+    Ok(())
+}
+
+/// 更新代币状态
+pub async fn set_registry_status(
+    registry_col: &Collection<Document>,
+    symbol: &str,
+    status: TokenStatus,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    registry_col.update_one(
+        doc! { "symbol": symbol },
+        doc! { "$set": { "status": status.as_str(), "updated_at": Utc::now().timestamp() } },
+        None
+    ).await?;
+    Ok(())
+}
+
+/// 删除注册表记录
+pub async fn delete_registry_entry(
+    registry_col: &Collection<Document>,
+    symbol: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    registry_col.delete_one(doc! { "symbol": symbol }, None).await?;
+    Ok(())
+}
+
+/// 读取需要提供查询的代币（不含已移除的代币），不写入注册表（只读 API 实例使用）
+///
+/// 注册表为空时说明同步进程尚未启动过，使用 config.toml 中的代币。
+pub async fn read_registry_tokens(
+    registry_col: &Collection<Document>,
+    config_tokens: &[TokenConfig],
+) -> Result<Vec<RegistryEntry>, Box<dyn Error + Send + Sync>> {
+    let entries: Vec<RegistryEntry> = load_registry(registry_col).await?
+        .into_iter()
+        .filter(|e| e.status != TokenStatus::Removed)
+        .collect();
+    if !entries.is_empty() {
+        return Ok(entries);
+    }
+    Ok(config_tokens.iter()
+        .map(|token| RegistryEntry { token: token.clone(), status: TokenStatus::Active, source: TokenSource::Config })
+        .collect())
+}
+
+/// 合并 config.toml 中的代币与注册表，返回本次运行需要管理的代币（不含已移除的代币）
+///
+/// config.toml 中的代币排在前面，配置以文件为准，状态沿用注册表记录；
+/// 之后是通过管理接口添加的代币，按添加时间排序。
+pub async fn merge_config_tokens(
+    registry_col: &Collection<Document>,
+    config_tokens: &[TokenConfig],
+) -> Result<Vec<RegistryEntry>, Box<dyn Error + Send + Sync>> {
+    let stored = load_registry(registry_col).await?;
+    let mut merged = Vec::new();
+
+    for token in config_tokens {
+        let existing = stored.iter().find(|e| e.token.symbol == token.symbol);
+        let entry = RegistryEntry {
+            token: token.clone(),
+            status: existing.map_or(TokenStatus::Active, |e| e.status),
+            source: TokenSource::Config,
+        };
+        save_registry_entry(registry_col, &entry).await?;
+        match entry.status {
+            TokenStatus::Removed => info!("{}: 已通过管理接口移除，忽略 config.toml 中的配置", token.symbol),
+            TokenStatus::Paused => {
+                info!("{}: 已暂停同步", token.symbol);
+                merged.push(entry);
+            },
+            TokenStatus::Active => merged.push(entry),
+        }
+    }
+
+    for entry in stored {
+        if entry.status == TokenStatus::Removed
+            || config_tokens.iter().any(|t| t.symbol == entry.token.symbol) {
+            continue;
+        }
+        if entry.source == TokenSource::Config {
+            // 已从 config.toml 中删除的代币，不再自动同步
+            info!("{}: 已不在 config.toml 中，从注册表移除", entry.token.symbol);
+            delete_registry_entry(registry_col, &entry.token.symbol).await?;
+            continue;
+        }
+        info!("{}: 从代币注册表加载 (状态: {})", entry.token.symbol, entry.status.as_str());
+        merged.push(entry);
+    }
+
+    Ok(merged)
+}
@@ -0,0 +1,409 @@
+/**
+ * 文件描述: 数据库结构版本管理与迁移模块
+ * 功能概述:
+ * - 在 schema_version 集合中记录全局和各代币集合的结构版本
+ * - 按顺序执行迁移（索引变更、字段重命名、数据回填），中断后可从未完成的迁移继续
+ * - 数据库版本高于程序支持的版本时拒绝启动同步
+ *
+ * 主要组件:
+ * - GLOBAL_MIGRATIONS / TOKEN_MIGRATIONS: 有序迁移列表，版本号从1开始连续递增
+ * - run_migrations函数: 将全局及所有代币的结构升级到最新版本
+ * - migrate_token函数: 将单个代币的结构升级到最新版本（运行时注册代币时使用）
+ * - check_schema_versions函数: 只检查版本，不执行迁移（关闭自动迁移时使用）
+ *
+ * 新增迁移时在对应列表末尾追加一项，并在 apply_global_migration / apply_token_migration 中实现。
+ * 每个迁移都必须可以重复执行：进程在迁移中途退出后，下次启动会重新执行该迁移。
+ */
+
+use std::error::Error;
+use mongodb::{Collection, IndexModel};
+use mongodb::bson::{doc, Document};
+use mongodb::options::{IndexOptions, UpdateOptions};
+use chrono::Utc;
+use log::{info, error, warn};
+use crate::db::{DbConnection, TokenCollections};
+use crate::db::account_transactions::migrate_account_transaction_indices;
+use crate::db::transactions::backfill_sortable_amounts;
+use crate::db::balances::backfill_balance_sort;
+use crate::utils::create_error;
+
+/// 全局集合（sync_status 等）使用的版本记录标识
+const GLOBAL_SCOPE: &str = "global";
+
+/// 一项迁移的描述
+pub struct Migration {
+    pub version: i64,
+    pub description: &'static str,
+}
+
+/// 全局集合的迁移列表
+pub const GLOBAL_MIGRATIONS: &[Migration] = &[
+    Migration { version: 1, description: "同步状态唯一索引 (status_type, token)" },
+    Migration { version: 2, description: "代币注册表唯一索引 (symbol)" },
+    Migration { version: 3, description: "同步租约唯一索引 (token)" },
+    Migration { version: 4, description: "分片回填任务索引 (token, unit_id) / (token, kind, status)" },
+];
+
+/// 每个代币集合的迁移列表
+pub const TOKEN_MIGRATIONS: &[Migration] = &[
+    Migration { version: 1, description: "交易、账户、余额基础索引" },
+    Migration { version: 2, description: "账户-交易边唯一索引 (account, index, role)" },
+    Migration { version: 3, description: "accounts.transaction_indices 数组迁移到账户-交易边集合" },
+    Migration { version: 4, description: "补写可排序金额字段 amount_sort / fee_sort / balance_sort" },
+    Migration { version: 5, description: "可排序金额字段索引" },
+    Migration { version: 6, description: "交易类型索引 (kind, index)，高级搜索按类型过滤时使用" },
+    Migration { version: 7, description: "交易时间索引 (timestamp, index)，按时间查找交易索引和按时间过滤时使用" },
+];
+
+/// 程序支持的全局结构版本
+pub fn current_global_version() -> i64 {
+    GLOBAL_MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+/// 程序支持的代币结构版本
+pub fn current_token_version() -> i64 {
+    TOKEN_MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+/// 读取指定范围的结构版本，没有记录时返回0
+pub async fn get_schema_version(
+    schema_col: &Collection<Document>,
+    scope: &str,
+) -> Result<i64, Box<dyn Error + Send + Sync>> {
+    match schema_col.find_one(doc! { "scope": scope }, None).await? {
+        Some(version_doc) => Ok(version_doc.get_i64("version").unwrap_or(0)),
+        None => Ok(0),
+    }
+}
+
+/// 标记迁移开始，记录正在迁移的目标版本
+async fn mark_migration_started(
+    schema_col: &Collection<Document>,
+    scope: &str,
+    migration: &Migration,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    schema_col.update_one(
+        doc! { "scope": scope },
+        doc! {
+            "$set": {
+                "migrating_to": migration.version,
+                "migration_description": migration.description,
+                "migration_started_at": Utc::now().timestamp(),
+            },
+            "$setOnInsert": { "version": 0_i64 },
+        },
+        UpdateOptions::builder().upsert(true).build()
+    ).await?;
+    Ok(())
+}
+
+/// 标记迁移完成，更新版本号并清除进行中标记
+async fn mark_migration_finished(
+    schema_col: &Collection<Document>,
+    scope: &str,
+    version: i64,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    schema_col.update_one(
+        doc! { "scope": scope },
+        doc! {
+            "$set": { "version": version, "updated_at": Utc::now().timestamp() },
+            "$unset": { "migrating_to": "", "migration_description": "", "migration_started_at": "" },
+        },
+        None
+    ).await?;
+    Ok(())
+}
+
+/// 直接设置某个范围的结构版本
+///
+/// 导入快照时按快照记录的版本登记，随后由 run_migrations 补齐之后的迁移。
+pub async fn set_schema_version(
+    schema_col: &Collection<Document>,
+    scope: &str,
+    version: i64,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    schema_col.update_one(
+        doc! { "scope": scope },
+        doc! { "$set": { "scope": scope, "version": version, "updated_at": Utc::now().timestamp() } },
+        UpdateOptions::builder().upsert(true).build()
+    ).await?;
+    Ok(())
+}
+
+/// 检查数据库版本不高于程序支持的版本，返回数据库当前版本
+async fn ensure_not_newer(
+    schema_col: &Collection<Document>,
+    scope: &str,
+    supported: i64,
+) -> Result<i64, Box<dyn Error + Send + Sync>> {
+    let version = get_schema_version(schema_col, scope).await?;
+    if version > supported {
+        error!("{}: 数据库结构版本 {} 高于程序支持的版本 {}", scope, version, supported);
+        return Err(create_error(&format!(
+            "{}: 数据库结构版本 {} 高于程序支持的版本 {}，请升级程序后再运行",
+            scope, version, supported
+        )));
+    }
+    Ok(version)
+}
+
+/// 将全局及所有代币的数据库结构升级到最新版本
+pub async fn run_migrations(conn: &DbConnection) -> Result<(), Box<dyn Error + Send + Sync>> {
+    info!("检查数据库结构版本...");
+    let schema_col = &conn.schema_version_col;
+
+    // 先检查所有范围，避免部分迁移后才发现版本不兼容
+    ensure_not_newer(schema_col, GLOBAL_SCOPE, current_global_version()).await?;
+    let symbols = conn.token_symbols();
+    for symbol in &symbols {
+        ensure_not_newer(schema_col, symbol, current_token_version()).await?;
+    }
+
+    ensure_schema_version_index(schema_col).await?;
+
+    let version = get_schema_version(schema_col, GLOBAL_SCOPE).await?;
+    for migration in GLOBAL_MIGRATIONS.iter().filter(|m| m.version > version) {
+        info!("{}: 执行迁移 v{}: {}", GLOBAL_SCOPE, migration.version, migration.description);
+        mark_migration_started(schema_col, GLOBAL_SCOPE, migration).await?;
+        if let Err(e) = apply_global_migration(conn, migration.version).await {
+            error!("{}: 迁移 v{} 失败: {}", GLOBAL_SCOPE, migration.version, e);
+            return Err(e);
+        }
+        mark_migration_finished(schema_col, GLOBAL_SCOPE, migration.version).await?;
+    }
+
+    for symbol in &symbols {
+        if let Some(collections) = conn.token_collections(symbol) {
+            migrate_token(conn, symbol, &collections).await?;
+        }
+    }
+
+    info!("数据库结构已是最新版本 (全局 v{}, 代币 v{})", current_global_version(), current_token_version());
+    Ok(())
+}
+
+/// 将单个代币的集合结构升级到最新版本
+pub async fn migrate_token(
+    conn: &DbConnection,
+    symbol: &str,
+    collections: &TokenCollections,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let schema_col = &conn.schema_version_col;
+    let version = ensure_not_newer(schema_col, symbol, current_token_version()).await?;
+    for migration in TOKEN_MIGRATIONS.iter().filter(|m| m.version > version) {
+        info!("{}: 执行迁移 v{}: {}", symbol, migration.version, migration.description);
+        mark_migration_started(schema_col, symbol, migration).await?;
+        if let Err(e) = apply_token_migration(symbol, collections, migration.version).await {
+            error!("{}: 迁移 v{} 失败: {}", symbol, migration.version, e);
+            return Err(e);
+        }
+        mark_migration_finished(schema_col, symbol, migration.version).await?;
+    }
+    Ok(())
+}
+
+/// 删除某个范围的结构版本记录（删除代币数据时使用）
+pub async fn delete_schema_version(
+    schema_col: &Collection<Document>,
+    scope: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    schema_col.delete_one(doc! { "scope": scope }, None).await?;
+    Ok(())
+}
+
+/// 只检查版本，不执行迁移
+///
+/// 数据库版本落后时返回错误，提示使用 migrate 命令升级；高于程序版本时同样拒绝运行。
+pub async fn check_schema_versions(conn: &DbConnection) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let schema_col = &conn.schema_version_col;
+    let mut pending = Vec::new();
+
+    let version = ensure_not_newer(schema_col, GLOBAL_SCOPE, current_global_version()).await?;
+    if version < current_global_version() {
+        pending.push(format!("{} v{} -> v{}", GLOBAL_SCOPE, version, current_global_version()));
+    }
+    for symbol in conn.token_symbols() {
+        let version = ensure_not_newer(schema_col, &symbol, current_token_version()).await?;
+        if version < current_token_version() {
+            pending.push(format!("{} v{} -> v{}", symbol, version, current_token_version()));
+        }
+    }
+
+    if !pending.is_empty() {
+        warn!("存在未执行的数据库迁移: {}", pending.join(", "));
+        return Err(create_error(&format!(
+            "数据库结构需要迁移 ({})，请执行 migrate 命令或开启 auto_migrate",
+            pending.join(", ")
+        )));
+    }
+
+    Ok(())
+}
+
+/// schema_version 集合自身的索引
+async fn ensure_schema_version_index(schema_col: &Collection<Document>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    schema_col.create_index(
+        IndexModel::builder()
+            .keys(doc! { "scope": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build(),
+        None
+    ).await?;
+    Ok(())
+}
+
+/// 执行一项全局迁移
+async fn apply_global_migration(conn: &DbConnection, version: i64) -> Result<(), Box<dyn Error + Send + Sync>> {
+    match version {
+        1 => {
+            // 旧版本每次启动都会重建同步状态索引，先清除遗留索引，避免与唯一索引冲突；集合不存在时删除失败不影响迁移
+            match conn.sync_status_col.drop_indexes(None).await {
+                Ok(_) => info!("同步状态集合旧索引已清除"),
+                Err(e) => warn!("同步状态集合清除旧索引失败: {}", e),
+            }
+            conn.sync_status_col.create_index(
+                IndexModel::builder()
+                    .keys(doc! { "status_type": 1, "token": 1 })
+                    .options(IndexOptions::builder().unique(true).build())
+                    .build(),
+                None
+            ).await?;
+            info!("同步状态索引创建成功");
+        },
+        2 => {
+            conn.registry_col.create_index(
+                IndexModel::builder()
+                    .keys(doc! { "symbol": 1 })
+                    .options(IndexOptions::builder().unique(true).build())
+                    .build(),
+                None
+            ).await?;
+            info!("代币注册表索引创建成功");
+        },
+        3 => {
+            conn.lease_col.create_index(
+                IndexModel::builder()
+                    .keys(doc! { "token": 1 })
+                    .options(IndexOptions::builder().unique(true).build())
+                    .build(),
+                None
+            ).await?;
+            info!("同步租约索引创建成功");
+        },
+        4 => {
+            conn.backfill_col.create_index(
+                IndexModel::builder()
+                    .keys(doc! { "token": 1, "unit_id": 1 })
+                    .options(IndexOptions::builder().unique(true).build())
+                    .build(),
+                None
+            ).await?;
+            conn.backfill_col.create_index(
+                IndexModel::builder()
+                    .keys(doc! { "token": 1, "kind": 1, "status": 1 })
+                    .build(),
+                None
+            ).await?;
+            info!("分片回填任务索引创建成功");
+        },
+        _ => return Err(create_error(&format!("未实现的全局迁移版本: {}", version))),
+    }
+    Ok(())
+}
+
+/// 执行一项代币集合迁移
+async fn apply_token_migration(
+    symbol: &str,
+    collections: &TokenCollections,
+    version: i64,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    match version {
+        1 => {
+            collections.tx_col.create_index(
+                IndexModel::builder()
+                    .keys(doc! { "index": 1 })
+                    .options(IndexOptions::builder().unique(true).build())
+                    .build(),
+                None
+            ).await?;
+            collections.accounts_col.create_index(
+                IndexModel::builder()
+                    .keys(doc! { "account": 1 })
+                    .build(),
+                None
+            ).await?;
+            collections.balances_col.create_index(
+                IndexModel::builder()
+                    .keys(doc! { "account": 1 })
+                    .options(IndexOptions::builder().unique(true).build())
+                    .build(),
+                None
+            ).await?;
+            info!("{}: 基础索引创建成功", symbol);
+        },
+        2 => {
+            collections.account_tx_col.create_index(
+                IndexModel::builder()
+                    .keys(doc! { "account": 1, "index": 1, "role": 1 })
+                    .options(IndexOptions::builder().unique(true).build())
+                    .build(),
+                None
+            ).await?;
+            info!("{}: 账户-交易边索引创建成功", symbol);
+        },
+        3 => {
+            let migrated = migrate_account_transaction_indices(
+                &collections.accounts_col,
+                &collections.account_tx_col,
+                &collections.tx_col
+            ).await?;
+            info!("{}: 已将 {} 个账户的交易索引数组迁移到边集合", symbol, migrated);
+        },
+        4 => {
+            let tx_count = backfill_sortable_amounts(&collections.tx_col).await?;
+            let balance_count = backfill_balance_sort(&collections.balances_col).await?;
+            info!("{}: 已补写 {} 笔交易和 {} 条余额的可排序金额", symbol, tx_count, balance_count);
+        },
+        5 => {
+            collections.tx_col.create_index(
+                IndexModel::builder()
+                    .keys(doc! { "amount_sort": 1 })
+                    .build(),
+                None
+            ).await?;
+            collections.tx_col.create_index(
+                IndexModel::builder()
+                    .keys(doc! { "fee_sort": 1 })
+                    .build(),
+                None
+            ).await?;
+            collections.balances_col.create_index(
+                IndexModel::builder()
+                    .keys(doc! { "balance_sort": -1 })
+                    .build(),
+                None
+            ).await?;
+            info!("{}: 可排序金额索引创建成功", symbol);
+        },
+        6 => {
+            collections.tx_col.create_index(
+                IndexModel::builder()
+                    .keys(doc! { "kind": 1, "index": -1 })
+                    .build(),
+                None
+            ).await?;
+            info!("{}: 交易类型索引创建成功", symbol);
+        },
+        7 => {
+            collections.tx_col.create_index(
+                IndexModel::builder()
+                    .keys(doc! { "timestamp": 1, "index": 1 })
+                    .build(),
+                None
+            ).await?;
+            info!("{}: 交易时间索引创建成功", symbol);
+        },
+        _ => return Err(create_error(&format!("{}: 未实现的代币迁移版本: {}", symbol, version))),
+    }
+    Ok(())
+}
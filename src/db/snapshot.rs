@@ -0,0 +1,560 @@
+/**
+ * 文件描述: 代币索引状态快照的导出与导入
+ * 功能概述:
+ * - 将单个代币截至某个交易索引的交易、账户登记、账户-交易边、余额、总供应量和同步游标导出为一个文件
+ * - 将快照导入到空数据库，导入后增量同步从快照的同步游标继续
+ *
+ * 主要组件:
+ * - export_snapshot函数: 导出快照，默认导出到余额已计算的位置
+ * - import_snapshot函数: 校验并导入快照
+ *
+ * 文件格式（gzip 压缩的连续 BSON 文档）:
+ * - 头部: { format, format_version, token, name, canister_id, decimals, at_index, schema_version, created_at }
+ * - 记录: { s: 分区名, d: 文档 }，分区依次为 tx / account / edge / balance / supply / cursor
+ * - 尾部: { trailer: true, counts: {...}, sha256 }，sha256 覆盖头部和全部记录的未压缩字节
+ *
+ * 导出读取的是正在运行的数据库：交易和边按索引截断，余额文档中 last_tx_index 超过快照索引的账户
+ * 会按截断后的交易重新计算，因此导出时同步服务可以继续运行。
+ *
+ * gzip 压缩、解压和快照文件读写放到阻塞线程池执行，不占用异步运行时的工作线程。
+ */
+
+use std::error::Error;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use mongodb::Collection;
+use mongodb::bson::{doc, Document};
+use mongodb::options::{FindOneOptions, FindOptions, UpdateOptions};
+use futures::stream::TryStreamExt;
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use sha2::{Digest, Sha256};
+use candid::Nat;
+use chrono::Utc;
+use log::{info, warn};
+use tokio::sync::mpsc;
+use crate::db::{DbConnection, TokenCollections};
+use crate::db::balances::calculate_account_balance_until;
+use crate::db::cold_storage::{cold_storage_for, read_bson_bytes};
+use crate::db::schema::{current_token_version, delete_schema_version, get_schema_version, run_migrations, set_schema_version};
+use crate::db::sync_status::{get_partial_start, get_sync_status};
+use crate::db::transactions::{get_earliest_transaction_index, get_transaction_documents_by_index_range};
+use crate::models::TokenConfig;
+use crate::utils::{create_error, to_sortable_amount};
+
+/// 快照文件格式标识
+const SNAPSHOT_FORMAT: &str = "index-rs-snapshot";
+/// 快照文件格式版本
+const SNAPSHOT_FORMAT_VERSION: i32 = 1;
+/// 导出时每次读取的交易索引跨度
+const EXPORT_CHUNK_SIZE: u64 = 5000;
+/// 导入时每批插入的文档数量
+const IMPORT_BATCH_SIZE: usize = 1000;
+/// 默认快照目录
+const DEFAULT_SNAPSHOT_DIR: &str = "snapshots";
+/// 导出时未压缩数据累计到该大小后交给阻塞线程池压缩写入
+const WRITE_BUFFER_SIZE: usize = 4 * 1024 * 1024;
+
+const SECTION_TX: &str = "tx";
+const SECTION_ACCOUNT: &str = "account";
+const SECTION_EDGE: &str = "edge";
+const SECTION_BALANCE: &str = "balance";
+const SECTION_SUPPLY: &str = "supply";
+const SECTION_CURSOR: &str = "cursor";
+
+/// 写快照文件，同时累计校验和与各分区计数
+///
+/// 记录先写入内存缓冲，累计到 WRITE_BUFFER_SIZE 后在阻塞线程池中压缩写入文件。
+struct SnapshotWriter {
+    /// 压缩写入进行中时为 None
+    encoder: Option<GzEncoder<BufWriter<File>>>,
+    buffer: Vec<u8>,
+    hasher: Sha256,
+    counts: BTreeMap<&'static str, i64>,
+}
+
+impl SnapshotWriter {
+    async fn create(path: &Path) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let file = tokio::fs::File::create(path).await?.into_std().await;
+        Ok(SnapshotWriter {
+            encoder: Some(GzEncoder::new(BufWriter::new(file), Compression::default())),
+            buffer: Vec::with_capacity(WRITE_BUFFER_SIZE),
+            hasher: Sha256::new(),
+            counts: BTreeMap::new(),
+        })
+    }
+
+    async fn write_raw(&mut self, document: &Document) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let start = self.buffer.len();
+        document.to_writer(&mut self.buffer)?;
+        self.hasher.update(&self.buffer[start..]);
+        if self.buffer.len() >= WRITE_BUFFER_SIZE {
+            self.flush_buffer().await?;
+        }
+        Ok(())
+    }
+
+    async fn write_record(&mut self, section: &'static str, mut record: Document) -> Result<(), Box<dyn Error + Send + Sync>> {
+        record.remove("_id");
+        self.write_raw(&doc! { "s": section, "d": record }).await?;
+        *self.counts.entry(section).or_insert(0) += 1;
+        Ok(())
+    }
+
+    /// 在阻塞线程池中压缩并写入缓冲的数据
+    async fn flush_buffer(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut encoder = self.encoder.take().ok_or_else(|| create_error("快照文件写入失败，不能继续写入"))?;
+        let bytes = std::mem::replace(&mut self.buffer, Vec::with_capacity(WRITE_BUFFER_SIZE));
+        let encoder = tokio::task::spawn_blocking(move || {
+            encoder.write_all(&bytes)?;
+            Ok::<_, std::io::Error>(encoder)
+        }).await??;
+        self.encoder = Some(encoder);
+        Ok(())
+    }
+
+    /// 写入尾部并落盘
+    async fn finish(mut self) -> Result<BTreeMap<&'static str, i64>, Box<dyn Error + Send + Sync>> {
+        let checksum = hex::encode(self.hasher.finalize_reset());
+        let mut counts_doc = Document::new();
+        for (section, count) in &self.counts {
+            counts_doc.insert(*section, *count);
+        }
+        let trailer = doc! { "trailer": true, "counts": counts_doc, "sha256": checksum };
+        trailer.to_writer(&mut self.buffer)?;
+        self.flush_buffer().await?;
+
+        let encoder = self.encoder.take().ok_or_else(|| create_error("快照文件写入失败，不能继续写入"))?;
+        tokio::task::spawn_blocking(move || {
+            let mut writer = encoder.finish()?;
+            writer.flush()?;
+            writer.get_ref().sync_all()
+        }).await??;
+        Ok(self.counts)
+    }
+}
+
+/// 导出代币快照，返回快照文件路径
+///
+/// `at_index` 为 None 时导出到余额已计算的位置，指定时不能超过该位置。
+pub async fn export_snapshot(
+    db_conn: &DbConnection,
+    token_config: &TokenConfig,
+    at_index: Option<u64>,
+    output: Option<&str>,
+) -> Result<PathBuf, Box<dyn Error + Send + Sync>> {
+    let token_symbol = &token_config.symbol;
+    let collections = token_collections(db_conn, token_symbol)?;
+
+    let status = match get_sync_status(&db_conn.sync_status_col, token_symbol).await? {
+        Some(status) => status,
+        None => return Err(create_error(&format!("{}: 没有同步状态，无法导出快照", token_symbol))),
+    };
+    let max_index = match status.last_balance_calculated_index {
+        Some(calculated) => calculated.min(status.last_synced_index),
+        None => return Err(create_error(&format!("{}: 尚未计算过余额，无法导出快照", token_symbol))),
+    };
+    let at_index = at_index.unwrap_or(max_index);
+    if at_index > max_index {
+        return Err(create_error(&format!(
+            "{}: 快照索引 {} 超过余额已计算的位置 {}", token_symbol, at_index, max_index
+        )));
+    }
+    let schema_version = get_schema_version(&db_conn.schema_version_col, token_symbol).await?;
+
+    let path = match output {
+        Some(output) => PathBuf::from(output),
+        None => Path::new(DEFAULT_SNAPSHOT_DIR).join(format!(
+            "{}_{}_{}.snapshot.gz",
+            token_symbol.to_lowercase(), at_index, Utc::now().format("%Y%m%d%H%M%S")
+        )),
+    };
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let tmp_path = path.with_extension("tmp");
+
+    info!("{}: 开始导出截至交易索引 {} 的快照到 {}", token_symbol, at_index, path.display());
+    let mut writer = SnapshotWriter::create(&tmp_path).await?;
+    writer.write_raw(&doc! {
+        "format": SNAPSHOT_FORMAT,
+        "format_version": SNAPSHOT_FORMAT_VERSION,
+        "token": token_symbol.as_str(),
+        "name": token_config.name.as_str(),
+        "canister_id": token_config.canister_id.as_str(),
+        "decimals": token_config.decimals.map(|d| d as i32),
+        "at_index": at_index as i64,
+        "schema_version": schema_version,
+        "created_at": Utc::now().timestamp(),
+    }).await?;
+
+    // 交易（包含冷存储中已归档的交易）
+    let mut last_timestamp = 0i64;
+    if let Some(first_index) = get_earliest_transaction_index(&collections.tx_col).await? {
+        let mut start = first_index;
+        while start <= at_index {
+            let end = (start + EXPORT_CHUNK_SIZE - 1).min(at_index);
+            for tx_doc in get_transaction_documents_by_index_range(&collections.tx_col, start, end).await? {
+                if let Ok(timestamp) = tx_doc.get_i64("timestamp") {
+                    last_timestamp = timestamp;
+                }
+                writer.write_record(SECTION_TX, tx_doc).await?;
+            }
+            start = end + 1;
+        }
+    }
+    info!("{}: 已导出 {} 笔交易", token_symbol, writer.counts.get(SECTION_TX).unwrap_or(&0));
+
+    // 账户登记，截断到快照索引
+    let mut cursor = collections.accounts_col.find(doc! {}, None).await?;
+    while let Some(mut account_doc) = cursor.try_next().await? {
+        if account_doc.get_i64("first_tx_index").map(|i| i as u64 > at_index).unwrap_or(false) {
+            continue;
+        }
+        if account_doc.get_i64("last_tx_index").map(|i| i as u64 > at_index).unwrap_or(false) {
+            let account = account_doc.get_str("account").unwrap_or_default().to_string();
+            match last_edge_index_until(&collections.account_tx_col, &account, at_index).await? {
+                Some(index) => { account_doc.insert("last_tx_index", index as i64); },
+                None => continue,
+            }
+        }
+        writer.write_record(SECTION_ACCOUNT, account_doc).await?;
+    }
+
+    // 账户-交易边
+    let options = FindOptions::builder().sort(doc! { "index": 1 }).build();
+    let mut cursor = collections.account_tx_col
+        .find(doc! { "index": { "$lte": at_index as i64 } }, options)
+        .await?;
+    while let Some(edge_doc) = cursor.try_next().await? {
+        writer.write_record(SECTION_EDGE, edge_doc).await?;
+    }
+
+    // 余额，last_tx_index 超过快照索引的账户按截断后的交易重新计算
+    let mut total_supply = Nat::from(0u64);
+    let mut recalculated = 0u64;
+    let mut cursor = collections.balances_col.find(doc! {}, None).await?;
+    while let Some(mut balance_doc) = cursor.try_next().await? {
+        let account = balance_doc.get_str("account").unwrap_or_default().to_string();
+        let last_tx_index = balance_doc.get_i64("last_tx_index").ok().map(|i| i as u64);
+        let balance = match last_tx_index {
+            Some(index) if index <= at_index => {
+                Nat::parse(balance_doc.get_str("balance").unwrap_or("0").as_bytes()).unwrap_or_default()
+            },
+            _ => {
+                let (balance, _has_anomalies, last_tx_index) = calculate_account_balance_until(
+                    &account,
+                    &collections.accounts_col,
+                    &collections.account_tx_col,
+                    &collections.tx_col,
+                    token_config,
+                    &collections.balance_anomalies_col,
+                    Some(at_index),
+                ).await?;
+                recalculated += 1;
+                let last_tx_index = match last_tx_index {
+                    Some(index) => index,
+                    // 账户在快照索引之后才出现
+                    None => continue,
+                };
+                balance_doc.insert("balance", balance.to_string());
+                balance_doc.insert("balance_sort", to_sortable_amount(&balance));
+                balance_doc.insert("last_tx_index", last_tx_index as i64);
+                balance
+            }
+        };
+        total_supply += balance;
+        writer.write_record(SECTION_BALANCE, balance_doc).await?;
+    }
+    if recalculated > 0 {
+        info!("{}: 已按快照索引重新计算 {} 个账户的余额", token_symbol, recalculated);
+    }
+
+    writer.write_record(SECTION_SUPPLY, doc! { "id": "total_supply", "value": total_supply.to_string() }).await?;
+    let mut cursor_doc = doc! {
+        "last_synced_index": at_index as i64,
+        "last_synced_timestamp": last_timestamp,
+        "last_balance_calculated_index": at_index as i64,
+    };
+    if let Some(start) = get_partial_start(&db_conn.sync_status_col, token_symbol).await? {
+        cursor_doc.insert("partial_start", start.to_document());
+    }
+    writer.write_record(SECTION_CURSOR, cursor_doc).await?;
+
+    let counts = writer.finish().await?;
+    tokio::fs::rename(&tmp_path, &path).await?;
+    info!("{}: 快照导出完成: {} ({:?})", token_symbol, path.display(), counts);
+    Ok(path)
+}
+
+/// 导入快照到空数据库
+///
+/// 快照中的代币必须在配置中存在，且目标集合和同步状态均为空。
+/// 导入完成后补齐快照结构版本之后的迁移，重新启动即可从快照的同步游标继续增量同步。
+pub async fn import_snapshot(
+    db_conn: &DbConnection,
+    tokens: &[TokenConfig],
+    file: &str,
+    token_override: Option<&str>,
+) -> Result<String, Box<dyn Error + Send + Sync>> {
+    // 先完整校验一遍，避免导入到一半才发现文件损坏
+    let path = file.to_string();
+    let header = tokio::task::spawn_blocking(move || verify_snapshot(&path)).await??;
+    let format_version = header.get_i32("format_version").unwrap_or(0);
+    if format_version > SNAPSHOT_FORMAT_VERSION {
+        return Err(create_error(&format!(
+            "快照格式版本 {} 高于程序支持的版本 {}", format_version, SNAPSHOT_FORMAT_VERSION
+        )));
+    }
+    let snapshot_version = header.get_i64("schema_version").unwrap_or(0);
+    if snapshot_version > current_token_version() {
+        return Err(create_error(&format!(
+            "快照结构版本 {} 高于程序支持的版本 {}，请升级程序后再导入", snapshot_version, current_token_version()
+        )));
+    }
+
+    let snapshot_token = header.get_str("token").unwrap_or_default();
+    let token_symbol = token_override.unwrap_or(snapshot_token).to_string();
+    let token_config = match tokens.iter().find(|t| t.symbol == token_symbol) {
+        Some(token) => token,
+        None => return Err(create_error(&format!("配置中没有代币 {}，请先添加代币配置", token_symbol))),
+    };
+    if header.get_str("canister_id").ok() != Some(token_config.canister_id.as_str()) {
+        warn!("{}: 快照的 canister_id ({}) 与配置 ({}) 不一致",
+            token_symbol, header.get_str("canister_id").unwrap_or_default(), token_config.canister_id);
+    }
+    let collections = token_collections(db_conn, &token_symbol)?;
+    ensure_empty(db_conn, &collections, &token_symbol).await?;
+
+    let at_index = header.get_i64("at_index").unwrap_or(0);
+    info!("{}: 开始导入快照 {} (交易索引 {}, 结构版本 v{})", token_symbol, file, at_index, snapshot_version);
+
+    // 同步游标最后写入：游标存在即表示导入完整；中途失败时清除已写入的数据，可以直接重新导入
+    let counts = match import_records(db_conn, &collections, &token_symbol, file, snapshot_version).await {
+        Ok(result) => result,
+        Err(e) => {
+            warn!("{}: 快照导入失败，清除已导入的部分数据: {}", token_symbol, e);
+            if let Err(cleanup_error) = discard_partial_import(db_conn, &collections, &token_symbol).await {
+                return Err(create_error(&format!(
+                    "{}: 快照导入失败: {}；清除已导入的部分数据也失败: {}，请手动清空该代币的集合后重新导入",
+                    token_symbol, e, cleanup_error
+                )));
+            }
+            return Err(e);
+        }
+    };
+    // 执行快照结构版本之后的迁移，失败时下次启动会再次执行
+    run_migrations(db_conn).await?;
+
+    info!("{}: 快照导入完成 {:?}，重新启动后将从交易索引 {} 之后继续同步", token_symbol, counts, at_index);
+    Ok(token_symbol)
+}
+
+/// 写入快照中的全部记录，返回各分区数量
+///
+/// 总供应量、结构版本和同步游标在全部批次写入成功后才写入，同步游标最后写入。
+async fn import_records(
+    db_conn: &DbConnection,
+    collections: &TokenCollections,
+    token_symbol: &str,
+    file: &str,
+    snapshot_version: i64,
+) -> Result<BTreeMap<String, u64>, Box<dyn Error + Send + Sync>> {
+    let mut records = spawn_record_reader(file);
+    let mut pending: BTreeMap<String, Vec<Document>> = BTreeMap::new();
+    let mut counts: BTreeMap<String, u64> = BTreeMap::new();
+    let mut supply = None;
+    let mut cursor = None;
+    while let Some(record) = records.recv().await {
+        let record = record?;
+        if record.get_bool("trailer").unwrap_or(false) {
+            break;
+        }
+        let section = record.get_str("s")?.to_string();
+        let data = record.get_document("d")?.clone();
+
+        if section == SECTION_SUPPLY {
+            supply = Some(data);
+        } else if section == SECTION_CURSOR {
+            cursor = Some(data);
+        } else {
+            let batch = pending.entry(section.clone()).or_default();
+            batch.push(data);
+            if batch.len() >= IMPORT_BATCH_SIZE {
+                let docs = std::mem::take(batch);
+                insert_section(collections, &section, docs).await?;
+            }
+        }
+        *counts.entry(section).or_insert(0) += 1;
+    }
+    for (section, docs) in pending {
+        if !docs.is_empty() {
+            insert_section(collections, &section, docs).await?;
+        }
+    }
+    let mut cursor = cursor.ok_or_else(|| create_error("快照中缺少同步游标"))?;
+
+    if let Some(supply) = supply {
+        collections.total_supply_col.update_one(
+            doc! { "id": "total_supply" },
+            doc! { "$set": supply },
+            UpdateOptions::builder().upsert(true).build()
+        ).await?;
+    }
+    // 按快照的结构版本登记，之后的迁移在写入同步游标后执行
+    set_schema_version(&db_conn.schema_version_col, token_symbol, snapshot_version).await?;
+
+    cursor.insert("status_type", "sync_state");
+    cursor.insert("token", token_symbol);
+    cursor.insert("sync_mode", "incremental");
+    cursor.insert("updated_at", Utc::now().timestamp());
+    db_conn.sync_status_col.insert_one(cursor, None).await?;
+    Ok(counts)
+}
+
+/// 在阻塞线程池中解压快照，逐条发送头部之后的记录，读到尾部为止
+fn spawn_record_reader(file: &str) -> mpsc::Receiver<Result<Document, Box<dyn Error + Send + Sync>>> {
+    let (sender, receiver) = mpsc::channel(IMPORT_BATCH_SIZE);
+    let file = file.to_string();
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = read_records(&file, &sender) {
+            let _ = sender.blocking_send(Err(e));
+        }
+    });
+    receiver
+}
+
+/// 读取快照记录并发送，接收端关闭（导入失败）时停止
+fn read_records(
+    file: &str,
+    sender: &mpsc::Sender<Result<Document, Box<dyn Error + Send + Sync>>>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut decoder = GzDecoder::new(BufReader::new(File::open(file)?));
+    read_bson_bytes(&mut decoder)?; // 头部已校验
+    while let Some(bytes) = read_bson_bytes(&mut decoder)? {
+        let record = Document::from_reader(bytes.as_slice())?;
+        let trailer = record.get_bool("trailer").unwrap_or(false);
+        if sender.blocking_send(Ok(record)).is_err() || trailer {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// 清除导入失败时已写入的数据（保留集合和索引），使数据库回到可以重新导入的状态
+async fn discard_partial_import(
+    db_conn: &DbConnection,
+    collections: &TokenCollections,
+    token_symbol: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    for col in [
+        &collections.tx_col,
+        &collections.accounts_col,
+        &collections.account_tx_col,
+        &collections.balances_col,
+        &collections.total_supply_col,
+    ] {
+        col.delete_many(doc! {}, None).await?;
+    }
+    delete_schema_version(&db_conn.schema_version_col, token_symbol).await?;
+    Ok(())
+}
+
+/// 校验快照格式和校验和，返回头部
+fn verify_snapshot(file: &str) -> Result<Document, Box<dyn Error + Send + Sync>> {
+    let mut decoder = GzDecoder::new(BufReader::new(File::open(file)?));
+    let mut hasher = Sha256::new();
+
+    let header_bytes = match read_bson_bytes(&mut decoder)? {
+        Some(bytes) => bytes,
+        None => return Err(create_error("快照文件为空")),
+    };
+    let header = Document::from_reader(header_bytes.as_slice())?;
+    if header.get_str("format").ok() != Some(SNAPSHOT_FORMAT) {
+        return Err(create_error(&format!("{} 不是有效的快照文件", file)));
+    }
+    hasher.update(&header_bytes);
+
+    while let Some(bytes) = read_bson_bytes(&mut decoder)? {
+        let record = Document::from_reader(bytes.as_slice())?;
+        if record.get_bool("trailer").unwrap_or(false) {
+            let expected = record.get_str("sha256").unwrap_or_default();
+            let actual = hex::encode(hasher.finalize());
+            if expected != actual {
+                return Err(create_error(&format!("快照校验和不匹配 (记录 {}, 实际 {})", expected, actual)));
+            }
+            return Ok(header);
+        }
+        hasher.update(&bytes);
+    }
+
+    Err(create_error("快照文件不完整：缺少尾部校验信息"))
+}
+
+/// 导入前确认代币的集合和同步状态为空
+async fn ensure_empty(
+    db_conn: &DbConnection,
+    collections: &TokenCollections,
+    token_symbol: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let checks = [
+        (&collections.tx_col, "交易"),
+        (&collections.accounts_col, "账户"),
+        (&collections.account_tx_col, "账户-交易边"),
+        (&collections.balances_col, "余额"),
+    ];
+    for (col, label) in checks {
+        if col.estimated_document_count(None).await? > 0 {
+            return Err(create_error(&format!(
+                "{}: {}集合不为空，快照只能导入到空数据库", token_symbol, label
+            )));
+        }
+    }
+    if get_sync_status(&db_conn.sync_status_col, token_symbol).await?.is_some() {
+        return Err(create_error(&format!("{}: 已存在同步状态，快照只能导入到空数据库", token_symbol)));
+    }
+    if cold_storage_for(&collections.tx_col).and_then(|s| s.archived_until()).is_some() {
+        return Err(create_error(&format!("{}: 冷存储中已有归档交易，快照只能导入到空数据库", token_symbol)));
+    }
+    Ok(())
+}
+
+async fn insert_section(
+    collections: &TokenCollections,
+    section: &str,
+    docs: Vec<Document>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let col = match section {
+        SECTION_TX => &collections.tx_col,
+        SECTION_ACCOUNT => &collections.accounts_col,
+        SECTION_EDGE => &collections.account_tx_col,
+        SECTION_BALANCE => &collections.balances_col,
+        _ => {
+            warn!("忽略未知的快照分区: {}", section);
+            return Ok(());
+        }
+    };
+    col.insert_many(docs, None).await?;
+    Ok(())
+}
+
+fn token_collections(db_conn: &DbConnection, token_symbol: &str) -> Result<TokenCollections, Box<dyn Error + Send + Sync>> {
+    db_conn.token_collections(token_symbol)
+        .ok_or_else(|| create_error(&format!("没有找到代币 {} 的集合", token_symbol)))
+}
+
+/// 账户在指定索引（含）之前的最后一条边
+async fn last_edge_index_until(
+    account_tx_col: &Collection<Document>,
+    account: &str,
+    at_index: u64,
+) -> Result<Option<u64>, Box<dyn Error + Send + Sync>> {
+    let options = FindOneOptions::builder().sort(doc! { "index": -1 }).build();
+    let edge = account_tx_col
+        .find_one(doc! { "account": account, "index": { "$lte": at_index as i64 } }, options)
+        .await?;
+    Ok(edge.and_then(|e| e.get_i64("index").ok()).map(|i| i as u64))
+}
@@ -21,7 +21,7 @@ use futures::stream::TryStreamExt;
 pub async fn recalculate_total_supply(
     balances_col: &Collection<Document>,
     supply_col: &Collection<Document>,
-) -> Result<Nat, Box<dyn Error>> {
+) -> Result<Nat, Box<dyn Error + Send + Sync>> {
     let mut total = Nat::from(0u64);
 
     // 遍历余额集合求和
@@ -43,14 +43,14 @@ pub async fn recalculate_total_supply(
         )
         .await?;
 
-    info!("已重新计算并更新总供应量: {}", total.to_string());
+    info!("已重新计算并更新总供应量: {}", total);
     Ok(total)
 }
 
 /// 获取当前存储的总供应量
 pub async fn get_stored_total_supply(
     supply_col: &Collection<Document>,
-) -> Result<Option<String>, Box<dyn Error>> {
+) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
     if let Some(doc) = supply_col.find_one(doc! { "id": "total_supply" }, None).await? {
         if let Ok(value) = doc.get_str("value") {
             return Ok(Some(value.to_string()));
@@ -12,12 +12,17 @@
  * - set_incremental_mode函数: 设置为增量同步模式
  * - set_full_sync_mode函数: 设置为全量同步模式
  * - clear_token_sync_status函数: 清除指定代币的同步状态
- * - clear_sync_status函数: 清除所有代币的同步状态
+ * - update_scheduler_status函数: 记录同步任务的调度模式和下次轮询时间
+ * - save_gap_scan / get_gap_scan函数: 保存和读取交易索引缺口扫描结果
+ * - set_partial_start / get_partial_start函数: 保存和读取部分索引的起点
+ * - mark_clean_shutdown / take_clean_shutdown函数: 记录和读取同步任务的正常停止标记
+ * - set_rebuild_pending / is_rebuild_pending函数: 记录和读取未完成的派生数据重建
+ * - update_progress / get_progress函数: 保存和读取同步阶段、吞吐量和预计剩余时间（见 sync::progress）
  */
 
 use std::error::Error;
 use mongodb::{Collection};
-use mongodb::bson::{Document, doc};
+use mongodb::bson::{Bson, Document, doc};
 use tokio::time::Duration;
 use chrono::Utc;
 use log::{info, error, warn};
@@ -30,16 +35,29 @@ pub struct SyncStatus {
     pub token: String, // 代币标识符
     pub last_synced_index: u64,
     pub last_synced_timestamp: u64,
-    pub last_balance_calculated_index: u64,
+    pub last_balance_calculated_index: Option<u64>, // 尚未计算过余额时为 None
     pub updated_at: i64,
     pub sync_mode: String, // "full" 或 "incremental"
 }
 
+impl SyncStatus {
+    /// 尚未计入余额的第一笔交易索引，余额计算进度已追平同步游标时返回 None
+    ///
+    /// 从未计算过余额时从索引 0 开始（通常是创世铸币交易）。
+    pub fn pending_balance_start(&self) -> Option<u64> {
+        match self.last_balance_calculated_index {
+            Some(calculated) if calculated >= self.last_synced_index => None,
+            Some(calculated) => Some(calculated + 1),
+            None => Some(0),
+        }
+    }
+}
+
 /// 获取指定代币的最新同步状态
 pub async fn get_sync_status(
     sync_status_col: &Collection<Document>,
     token_symbol: &str
-) -> Result<Option<SyncStatus>, Box<dyn Error>> {
+) -> Result<Option<SyncStatus>, Box<dyn Error + Send + Sync>> {
     if let Some(doc) = sync_status_col
         .find_one(doc! { "status_type": "sync_state", "token": token_symbol }, None)
         .await?
@@ -50,7 +68,8 @@ pub async fn get_sync_status(
             .unwrap_or(0) as u64;
         let updated_at = doc.get_i64("updated_at").unwrap_or(0);
         let last_balance_calculated_index = doc.get_i64("last_balance_calculated_index")
-            .unwrap_or(0) as u64;
+            .ok()
+            .map(|index| index as u64);
         let sync_mode = doc.get_str("sync_mode")
             .unwrap_or("incremental")
             .to_string();
@@ -75,7 +94,7 @@ pub async fn update_sync_status(
     last_synced_index: u64,
     last_synced_timestamp: u64,
     sync_mode: &str
-) -> Result<(), Box<dyn Error>> {
+) -> Result<(), Box<dyn Error + Send + Sync>> {
     // 设置重试逻辑
     let max_retries = 3;
     let mut retry_count = 0;
@@ -122,7 +141,7 @@ pub async fn set_incremental_mode(
     token_symbol: &str,
     last_synced_index: u64,
     last_synced_timestamp: u64
-) -> Result<(), Box<dyn Error>> {
+) -> Result<(), Box<dyn Error + Send + Sync>> {
     info!("{}: 设置为增量同步模式，最新索引: {}", token_symbol, last_synced_index);
     update_sync_status(sync_status_col, token_symbol, last_synced_index, last_synced_timestamp, "incremental").await
 }
@@ -131,17 +150,16 @@ pub async fn set_incremental_mode(
 pub async fn set_full_sync_mode(
     sync_status_col: &Collection<Document>,
     token_symbol: &str
-) -> Result<(), Box<dyn Error>> {
+) -> Result<(), Box<dyn Error + Send + Sync>> {
     info!("{}: 设置为全量同步模式", token_symbol);
     update_sync_status(sync_status_col, token_symbol, 0, 0, "full").await
 }
 
 /// 清除指定代币的同步状态
-#[allow(dead_code)]
 pub async fn clear_token_sync_status(
     sync_status_col: &Collection<Document>,
     token_symbol: &str
-) -> Result<(), Box<dyn Error>> {
+) -> Result<(), Box<dyn Error + Send + Sync>> {
     match sync_status_col.delete_many(doc! { "token": token_symbol }, None).await {
         Ok(result) => {
             info!("{}: 已清除 {} 条同步状态记录", token_symbol, result.deleted_count);
@@ -154,28 +172,12 @@ pub async fn clear_token_sync_status(
     }
 }
 
-/// 清除所有代币的同步状态
-pub async fn clear_sync_status(
-    sync_status_col: &Collection<Document>
-) -> Result<(), Box<dyn Error>> {
-    match sync_status_col.delete_many(doc! {}, None).await {
-        Ok(result) => {
-            info!("已清除所有代币的 {} 条同步状态记录", result.deleted_count);
-            Ok(())
-        },
-        Err(e) => {
-            error!("清除所有同步状态记录失败: {}", e);
-            Err(create_error(&format!("清除所有同步状态记录失败: {}", e)))
-        }
-    }
-}
-
 /// 更新余额已计算到的最新交易索引
 pub async fn update_balance_calculated_index(
     sync_status_col: &Collection<Document>,
     token_symbol: &str,
     last_balance_calculated_index: u64,
-) -> Result<(), Box<dyn Error>> {
+) -> Result<(), Box<dyn Error + Send + Sync>> {
     let now = Utc::now().timestamp();
 
     match sync_status_col.update_one(
@@ -199,3 +201,326 @@ pub async fn update_balance_calculated_index(
     }
 }
 
+
+/// 更新同步任务的调度状态（sync_status 文档的 scheduler 字段）
+///
+/// 只更新已有的同步状态记录，不会为尚未同步的代币创建记录。
+pub async fn update_scheduler_status(
+    sync_status_col: &Collection<Document>,
+    token_symbol: &str,
+    scheduler: Document,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    sync_status_col.update_one(
+        doc! { "status_type": "sync_state", "token": token_symbol },
+        doc! { "$set": { "scheduler": scheduler } },
+        None
+    ).await?;
+    Ok(())
+}
+
+/// 交易索引缺口记录
+#[derive(Debug, Clone)]
+pub struct GapRecord {
+    pub start: u64,
+    pub end: u64,
+    /// 回填来源: ledger、archive 或 mixed（跨越归档和主账本）
+    pub source: String,
+    pub detected_at: i64,
+    /// 已尝试回填的次数
+    pub attempts: u32,
+    pub last_error: Option<String>,
+}
+
+impl GapRecord {
+    pub fn count(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    fn to_document(&self) -> Document {
+        doc! {
+            "start": self.start as i64,
+            "end": self.end as i64,
+            "count": self.count() as i64,
+            "source": &self.source,
+            "detected_at": self.detected_at,
+            "attempts": self.attempts as i64,
+            "last_error": self.last_error.clone(),
+        }
+    }
+
+    fn from_document(gap_doc: &Document) -> Option<Self> {
+        Some(GapRecord {
+            start: gap_doc.get_i64("start").ok()? as u64,
+            end: gap_doc.get_i64("end").ok()? as u64,
+            source: gap_doc.get_str("source").unwrap_or("ledger").to_string(),
+            detected_at: gap_doc.get_i64("detected_at").unwrap_or(0),
+            attempts: gap_doc.get_i64("attempts").unwrap_or(0) as u32,
+            last_error: gap_doc.get_str("last_error").ok().map(|s| s.to_string()),
+        })
+    }
+}
+
+/// 最近一次缺口扫描的结果
+#[derive(Debug, Clone)]
+pub struct GapScan {
+    pub scanned_at: i64,
+    pub from_index: u64,
+    pub to_index: u64,
+    pub gaps: Vec<GapRecord>,
+}
+
+/// 保存缺口扫描结果（sync_status 文档的 gap_scan 字段）
+pub async fn save_gap_scan(
+    sync_status_col: &Collection<Document>,
+    token_symbol: &str,
+    scan: &GapScan,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let gaps: Vec<Document> = scan.gaps.iter().map(|g| g.to_document()).collect();
+    let missing: u64 = scan.gaps.iter().map(|g| g.count()).sum();
+    sync_status_col.update_one(
+        doc! { "status_type": "sync_state", "token": token_symbol },
+        doc! {
+            "$set": {
+                "gap_scan": {
+                    "scanned_at": scan.scanned_at,
+                    "from_index": scan.from_index as i64,
+                    "to_index": scan.to_index as i64,
+                    "missing_count": missing as i64,
+                    "gaps": gaps,
+                }
+            }
+        },
+        None
+    ).await?;
+    Ok(())
+}
+
+/// 读取最近一次缺口扫描结果
+pub async fn get_gap_scan(
+    sync_status_col: &Collection<Document>,
+    token_symbol: &str,
+) -> Result<Option<GapScan>, Box<dyn Error + Send + Sync>> {
+    let status_doc = sync_status_col
+        .find_one(doc! { "status_type": "sync_state", "token": token_symbol }, None)
+        .await?;
+    let scan_doc = match status_doc.as_ref().and_then(|d| d.get_document("gap_scan").ok()) {
+        Some(scan_doc) => scan_doc,
+        None => return Ok(None),
+    };
+
+    let gaps = scan_doc.get_array("gaps")
+        .map(|gaps| gaps.iter()
+            .filter_map(|g| g.as_document())
+            .filter_map(GapRecord::from_document)
+            .collect())
+        .unwrap_or_default();
+
+    Ok(Some(GapScan {
+        scanned_at: scan_doc.get_i64("scanned_at").unwrap_or(0),
+        from_index: scan_doc.get_i64("from_index").unwrap_or(0) as u64,
+        to_index: scan_doc.get_i64("to_index").unwrap_or(0) as u64,
+        gaps,
+    }))
+}
+
+/// 部分索引的起点，此前的交易没有被索引
+#[derive(Debug, Clone)]
+pub struct PartialStart {
+    pub index: u64,
+    /// 起始交易的时间戳（纳秒）
+    pub timestamp: u64,
+}
+
+impl PartialStart {
+    /// API 响应中的 partial_since 字段
+    pub fn to_document(&self) -> Document {
+        doc! {
+            "index": self.index as i64,
+            "timestamp": self.timestamp as i64,
+        }
+    }
+}
+
+/// 保存部分索引的起点（sync_status 文档的 partial_start 字段）
+///
+/// 初始同步前写入，此时同步状态可能还不存在，因此使用 upsert。
+pub async fn set_partial_start(
+    sync_status_col: &Collection<Document>,
+    token_symbol: &str,
+    start: &PartialStart,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    sync_status_col.update_one(
+        doc! { "status_type": "sync_state", "token": token_symbol },
+        doc! {
+            "$set": { "partial_start": start.to_document() },
+            "$setOnInsert": {
+                "last_synced_index": 0i64,
+                "last_synced_timestamp": 0i64,
+                "sync_mode": "full",
+                "updated_at": Utc::now().timestamp(),
+            }
+        },
+        mongodb::options::UpdateOptions::builder().upsert(true).build()
+    ).await?;
+    info!("{}: 部分索引起点: 交易索引 {}", token_symbol, start.index);
+    Ok(())
+}
+
+/// 读取部分索引的起点，完整索引时返回 None
+pub async fn get_partial_start(
+    sync_status_col: &Collection<Document>,
+    token_symbol: &str,
+) -> Result<Option<PartialStart>, Box<dyn Error + Send + Sync>> {
+    let status_doc = sync_status_col
+        .find_one(doc! { "status_type": "sync_state", "token": token_symbol }, None)
+        .await?;
+    Ok(status_doc
+        .as_ref()
+        .and_then(|d| d.get_document("partial_start").ok())
+        .and_then(|start| Some(PartialStart {
+            index: start.get_i64("index").ok()? as u64,
+            timestamp: start.get_i64("timestamp").unwrap_or(0) as u64,
+        })))
+}
+
+/// 记录代币同步任务已正常停止（sync_status 文档的 shutdown 字段）
+///
+/// 只在最后一个批次提交完成、余额进度写入之后调用，下次启动时据此跳过一致性推测。
+pub async fn mark_clean_shutdown(
+    sync_status_col: &Collection<Document>,
+    token_symbol: &str,
+    last_synced_index: u64,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    sync_status_col.update_one(
+        doc! { "status_type": "sync_state", "token": token_symbol },
+        doc! {
+            "$set": {
+                "shutdown": {
+                    "clean": true,
+                    "at": Utc::now().timestamp(),
+                    "last_synced_index": last_synced_index as i64,
+                }
+            }
+        },
+        None
+    ).await?;
+    info!("{}: 已记录正常停止，同步游标: {}", token_symbol, last_synced_index);
+    Ok(())
+}
+
+/// 读取并清除正常停止标记，上次运行正常停止时返回 true
+///
+/// 标记只使用一次，本次运行如果异常退出，下次启动时会重新校验同步状态。
+pub async fn take_clean_shutdown(
+    sync_status_col: &Collection<Document>,
+    token_symbol: &str,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    let previous = sync_status_col.find_one_and_update(
+        doc! { "status_type": "sync_state", "token": token_symbol },
+        doc! { "$unset": { "shutdown": "" } },
+        None
+    ).await?;
+    Ok(previous
+        .as_ref()
+        .and_then(|d| d.get_document("shutdown").ok())
+        .and_then(|s| s.get_bool("clean").ok())
+        .unwrap_or(false))
+}
+
+/// 设置或清除派生数据重建标记（sync_status 文档的 rebuild 字段）
+///
+/// 重建开始前写入，全部完成后清除；启动时发现标记说明上次重建被中断，需要重新执行。
+pub async fn set_rebuild_pending(
+    sync_status_col: &Collection<Document>,
+    token_symbol: &str,
+    pending: bool,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let update = if pending {
+        doc! { "$set": { "rebuild": { "pending": true, "started_at": Utc::now().timestamp() } } }
+    } else {
+        doc! { "$unset": { "rebuild": "" } }
+    };
+    sync_status_col.update_one(
+        doc! { "status_type": "sync_state", "token": token_symbol },
+        update,
+        None
+    ).await?;
+    Ok(())
+}
+
+/// 是否有未完成的派生数据重建
+pub async fn is_rebuild_pending(
+    sync_status_col: &Collection<Document>,
+    token_symbol: &str,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    let status_doc = sync_status_col
+        .find_one(doc! { "status_type": "sync_state", "token": token_symbol }, None)
+        .await?;
+    Ok(status_doc
+        .as_ref()
+        .and_then(|d| d.get_document("rebuild").ok())
+        .and_then(|r| r.get_bool("pending").ok())
+        .unwrap_or(false))
+}
+
+/// 同步进度记录的 status_type
+///
+/// 初始同步的归档阶段 sync_state 文档可能还不存在，进度单独保存，避免提前创建同步游标。
+const PROGRESS_STATUS_TYPE: &str = "sync_progress";
+
+/// 保存同步进度（sync_progress 文档的 progress 字段）
+pub async fn update_progress(
+    sync_status_col: &Collection<Document>,
+    token_symbol: &str,
+    progress: Document,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    sync_status_col.update_one(
+        doc! { "status_type": PROGRESS_STATUS_TYPE, "token": token_symbol },
+        doc! { "$set": { "progress": progress, "updated_at": Utc::now().timestamp() } },
+        mongodb::options::UpdateOptions::builder().upsert(true).build()
+    ).await?;
+    Ok(())
+}
+
+/// 读取同步进度，尚未记录时返回 None
+pub async fn get_progress(
+    sync_status_col: &Collection<Document>,
+    token_symbol: &str,
+) -> Result<Option<Document>, Box<dyn Error + Send + Sync>> {
+    let status_doc = sync_status_col
+        .find_one(doc! { "status_type": PROGRESS_STATUS_TYPE, "token": token_symbol }, None)
+        .await?;
+    Ok(status_doc.and_then(|mut d| match d.remove("progress") {
+        Some(Bson::Document(progress)) => Some(progress),
+        _ => None,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(last_synced_index: u64, last_balance_calculated_index: Option<u64>) -> SyncStatus {
+        SyncStatus {
+            token: "TEST".to_string(),
+            last_synced_index,
+            last_synced_timestamp: 0,
+            last_balance_calculated_index,
+            updated_at: 0,
+            sync_mode: "incremental".to_string(),
+        }
+    }
+
+    #[test]
+    fn pending_balances_start_at_genesis_when_never_calculated() {
+        assert_eq!(status(10, None).pending_balance_start(), Some(0));
+        assert_eq!(status(0, None).pending_balance_start(), Some(0));
+    }
+
+    #[test]
+    fn pending_balances_start_after_the_calculated_index() {
+        assert_eq!(status(10, Some(0)).pending_balance_start(), Some(1));
+        assert_eq!(status(10, Some(7)).pending_balance_start(), Some(8));
+        assert_eq!(status(10, Some(10)).pending_balance_start(), None);
+    }
+}
@@ -7,7 +7,12 @@
  * 
  * 主要组件:
  * - save_transaction函数: 将交易保存到数据库，支持重试机制
- * - get_latest_transaction_index函数: 查询数据库中最新的交易索引
+ * - add_sortable_amount_fields函数: 为交易文档附加可排序的 amount_sort / fee_sort 字段
+ * - backfill_sortable_amounts函数: 为旧交易文档补写 amount_sort / fee_sort 字段
+ * - get_latest_transaction_index / get_earliest_transaction_index函数: 查询最新 / 最早的交易索引（含冷存储）
+ * - find_transaction_by_index / find_transactions_by_indices函数: 按索引读取交易，
+ *   MongoDB 中不存在时回退到冷存储分段文件
+ * - find_index_at_time函数: 查找第一笔时间戳不早于指定时间的交易索引（含冷存储）
  * - clear_transactions函数: 清空交易集合中的所有记录
  */
 
@@ -16,8 +21,10 @@ use mongodb::{Collection, bson::{doc, to_bson}};
 use mongodb::bson::Document;
 use log::{info, error, warn};
 use tokio::time::Duration;
+use futures::stream::TryStreamExt;
 use crate::models::Transaction;
-use crate::utils::create_error;
+use crate::db::cold_storage::cold_storage_for;
+use crate::utils::{create_error, to_sortable_amount, get_transaction_amount, get_transaction_fee};
 use mongodb::options::FindOptions;
 use std::convert::TryFrom;
 
@@ -25,7 +32,7 @@ use std::convert::TryFrom;
 pub async fn save_transaction(
     tx_col: &Collection<Document>,
     tx: &Transaction,
-) -> Result<(), Box<dyn Error>> {
+) -> Result<(), Box<dyn Error + Send + Sync>> {
     let index = tx.index.unwrap_or(0);
     
     // 尝试将交易转换为BSON格式
@@ -37,7 +44,7 @@ pub async fn save_transaction(
         }
     };
     
-    let doc = match tx_bson.as_document() {
+    let mut doc = match tx_bson.as_document() {
         Some(doc) => doc.clone(),
         None => {
             error!("无法将BSON转换为Document，索引: {}", index);
@@ -45,6 +52,8 @@ pub async fn save_transaction(
         }
     };
     
+    add_sortable_amount_fields(&mut doc, tx);
+    
     // 设置重试逻辑
     let max_retries = 3;
     let mut retry_count = 0;
@@ -70,25 +79,89 @@ pub async fn save_transaction(
     Err(create_error(&format!("保存交易(索引:{})失败，已重试 {} 次", index, max_retries)))
 }
 
+/// 为交易文档附加可排序的金额字段
+///
+/// amount/fee 原字段保持 candid Nat 字符串格式不变，另存定宽补零的 amount_sort / fee_sort 用于范围查询。
+pub fn add_sortable_amount_fields(doc: &mut Document, tx: &Transaction) {
+    if let Some(amount) = get_transaction_amount(tx) {
+        doc.insert("amount_sort", to_sortable_amount(amount));
+    }
+    if let Some(fee) = get_transaction_fee(tx) {
+        doc.insert("fee_sort", to_sortable_amount(fee));
+    }
+}
+
+/// 为缺少 amount_sort 的旧交易文档补写可排序金额字段，返回更新的文档数
+///
+/// 没有金额的交易（如通知事件）不写入该字段，重新执行时会再次被扫描但不会更新。
+pub async fn backfill_sortable_amounts(tx_col: &Collection<Document>) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    let mut cursor = tx_col.find(doc! { "amount_sort": { "$exists": false } }, None).await?;
+    let mut updated = 0u64;
+    
+    while let Some(tx_doc) = cursor.try_next().await? {
+        let index = match tx_doc.get_i64("index") {
+            Ok(index) => index,
+            Err(_) => continue,
+        };
+        let tx: Transaction = match mongodb::bson::from_document(tx_doc) {
+            Ok(tx) => tx,
+            Err(e) => {
+                warn!("补写可排序金额时反序列化交易失败 (索引: {}): {}", index, e);
+                continue;
+            }
+        };
+        
+        let mut fields = Document::new();
+        add_sortable_amount_fields(&mut fields, &tx);
+        if fields.is_empty() {
+            continue;
+        }
+        tx_col.update_one(doc! { "index": index }, doc! { "$set": fields }, None).await?;
+        
+        updated += 1;
+        if updated.is_multiple_of(10000) {
+            info!("已补写 {} 笔交易的可排序金额", updated);
+        }
+    }
+    
+    Ok(updated)
+}
+
 /// 获取最新的交易索引
 pub async fn get_latest_transaction_index(
     tx_col: &Collection<Document>,
-) -> Result<Option<u64>, Box<dyn Error>> {
+) -> Result<Option<u64>, Box<dyn Error + Send + Sync>> {
     let options = mongodb::options::FindOneOptions::builder()
         .sort(doc! { "index": -1 })
         .build();
     
+    let mut latest = None;
     if let Some(doc) = tx_col.find_one(doc! {}, options).await? {
-        if let Some(index) = doc.get_i64("index").ok() {
-            return Ok(Some(index as u64));
+        if let Ok(index) = doc.get_i64("index") {
+            latest = Some(index as u64);
         }
     }
     
-    Ok(None)
+    // 交易全部归档后集合可能为空，以冷存储的归档进度为准
+    let archived = cold_storage_for(tx_col).and_then(|storage| storage.archived_until());
+    Ok(latest.max(archived))
+}
+
+/// 最早的交易索引（含冷存储）
+pub async fn get_earliest_transaction_index(tx_col: &Collection<Document>) -> Result<Option<u64>, Box<dyn Error + Send + Sync>> {
+    let options = mongodb::options::FindOneOptions::builder().sort(doc! { "index": 1 }).build();
+    let in_db = tx_col.find_one(doc! {}, options).await?
+        .and_then(|d| d.get_i64("index").ok())
+        .map(|i| i as u64);
+    let archived = cold_storage_for(tx_col).and_then(|s| s.archived_from());
+    Ok(match (in_db, archived) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    })
 }
 
 /// 清空交易集合
-pub async fn clear_transactions(tx_col: &Collection<Document>) -> Result<u64, Box<dyn Error>> {
+pub async fn clear_transactions(tx_col: &Collection<Document>) -> Result<u64, Box<dyn Error + Send + Sync>> {
     match tx_col.delete_many(doc! {}, None).await {
         Ok(result) => {
             info!("已清除 {} 条交易记录", result.deleted_count);
@@ -106,7 +179,29 @@ pub async fn get_transactions_by_index_range(
     tx_col: &Collection<Document>,
     start_index: u64,
     end_index: u64,
-) -> Result<Vec<Transaction>, Box<dyn Error>> {
+) -> Result<Vec<Transaction>, Box<dyn Error + Send + Sync>> {
+    let docs = get_transaction_documents_by_index_range(tx_col, start_index, end_index).await?;
+
+    let mut result = Vec::new();
+    for doc in docs {
+        match mongodb::bson::from_document::<Transaction>(doc) {
+            Ok(tx) => result.push(tx),
+            Err(e) => {
+                warn!("反序列化交易失败: {}", e);
+                continue;
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+/// 根据索引范围获取原始交易文档（含冷存储中的交易），按索引升序
+pub async fn get_transaction_documents_by_index_range(
+    tx_col: &Collection<Document>,
+    start_index: u64,
+    end_index: u64,
+) -> Result<Vec<Document>, Box<dyn Error + Send + Sync>> {
     if start_index > end_index {
         return Ok(Vec::new());
     }
@@ -122,19 +217,102 @@ pub async fn get_transactions_by_index_range(
         .build();
 
     let mut cursor = tx_col.find(filter, options).await?;
-    let mut result = Vec::new();
-
+    let mut docs = Vec::new();
     while cursor.advance().await? {
-        let raw_doc = cursor.current();
-        let doc = Document::try_from(raw_doc.to_owned())?;
-        match mongodb::bson::from_document::<Transaction>(doc) {
-            Ok(tx) => result.push(tx),
-            Err(e) => {
-                warn!("反序列化交易失败: {}", e);
-                continue;
+        docs.push(Document::try_from(cursor.current().to_owned())?);
+    }
+
+    // 区间起点已归档时，补上冷存储中的交易
+    if let Some(storage) = cold_storage_for(tx_col) {
+        if let Some(until) = storage.archived_until() {
+            if start_index <= until {
+                let present: std::collections::HashSet<i64> = docs.iter()
+                    .filter_map(|d| d.get_i64("index").ok())
+                    .collect();
+                let archived = storage.get_range(start_index, end_index.min(until)).await?;
+                docs.extend(archived.into_iter()
+                    .filter(|d| d.get_i64("index").map(|i| !present.contains(&i)).unwrap_or(false)));
+                docs.sort_by_key(|d| d.get_i64("index").unwrap_or(0));
             }
         }
     }
 
-    Ok(result)
+    Ok(docs)
+}
+
+/// 按索引读取单笔交易文档，MongoDB 中不存在时回退到冷存储
+pub async fn find_transaction_by_index(
+    tx_col: &Collection<Document>,
+    index: u64,
+) -> Result<Option<Document>, Box<dyn Error + Send + Sync>> {
+    if let Some(tx_doc) = tx_col.find_one(doc! { "index": index as i64 }, None).await? {
+        return Ok(Some(tx_doc));
+    }
+    match cold_storage_for(tx_col) {
+        Some(storage) => storage.get_transaction(index).await,
+        None => Ok(None),
+    }
+}
+
+/// 查找第一笔时间戳不早于 `time_ns`（纳秒）的交易索引，没有这样的交易时返回 None
+///
+/// 账本交易的时间戳随索引单调不减，因此按 (timestamp, index) 索引取第一条即可。
+/// 冷存储中的交易更早，先按分段清单的时间范围查找；部分索引起点之前的交易不在查找范围内。
+pub async fn find_index_at_time(
+    tx_col: &Collection<Document>,
+    time_ns: u64,
+) -> Result<Option<u64>, Box<dyn Error + Send + Sync>> {
+    if let Some(storage) = cold_storage_for(tx_col) {
+        if let Some(index) = storage.index_at_time(time_ns).await? {
+            return Ok(Some(index));
+        }
+    }
+    let time_ns = i64::try_from(time_ns).unwrap_or(i64::MAX);
+    let options = mongodb::options::FindOneOptions::builder()
+        .sort(doc! { "timestamp": 1, "index": 1 })
+        .projection(doc! { "index": 1, "_id": 0 })
+        .build();
+    let found = tx_col.find_one(doc! { "timestamp": { "$gte": time_ns } }, options).await?;
+    Ok(found.and_then(|d| d.get_i64("index").ok()).map(|index| index as u64))
+}
+
+/// 按索引列表读取交易文档，结果按索引排序
+///
+/// MongoDB 中缺少的索引会从冷存储分段文件中查找。
+pub async fn find_transactions_by_indices(
+    tx_col: &Collection<Document>,
+    indices: &[i64],
+    ascending: bool,
+) -> Result<Vec<Document>, Box<dyn Error + Send + Sync>> {
+    if indices.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let options = FindOptions::builder()
+        .sort(doc! { "index": if ascending { 1 } else { -1 } })
+        .build();
+    let mut docs: Vec<Document> = tx_col
+        .find(doc! { "index": { "$in": indices } }, options)
+        .await?
+        .try_collect()
+        .await?;
+
+    if docs.len() < indices.len() {
+        if let Some(storage) = cold_storage_for(tx_col) {
+            let present: std::collections::HashSet<i64> = docs.iter()
+                .filter_map(|d| d.get_i64("index").ok())
+                .collect();
+            let missing: Vec<i64> = indices.iter().copied().filter(|i| !present.contains(i)).collect();
+            let archived = storage.get_by_indices(&missing).await?;
+            if !archived.is_empty() {
+                docs.extend(archived);
+                docs.sort_by_key(|d| d.get_i64("index").unwrap_or(0));
+                if !ascending {
+                    docs.reverse();
+                }
+            }
+        }
+    }
+
+    Ok(docs)
 }
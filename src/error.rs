@@ -33,6 +33,12 @@ pub enum ApiError {
     /// 序列化/反序列化错误
     #[allow(dead_code)]
     SerializationError(String),
+    /// 缺少或无效的管理令牌
+    Unauthorized(String),
+    /// 管理接口未启用
+    Forbidden(String),
+    /// 当前实例不支持该操作（只读 API 实例）
+    Conflict(String),
 }
 
 impl fmt::Display for ApiError {
@@ -44,6 +50,9 @@ impl fmt::Display for ApiError {
             ApiError::TokenError(msg) => write!(f, "代币错误: {}", msg),
             ApiError::Internal(msg) => write!(f, "内部服务器错误: {}", msg),
             ApiError::SerializationError(msg) => write!(f, "序列化错误: {}", msg),
+            ApiError::Unauthorized(msg) => write!(f, "未授权: {}", msg),
+            ApiError::Forbidden(msg) => write!(f, "禁止访问: {}", msg),
+            ApiError::Conflict(msg) => write!(f, "操作冲突: {}", msg),
         }
     }
 }
@@ -70,9 +79,14 @@ pub async fn handle_rejection(err: warp::Rejection) -> Result<impl warp::Reply,
             ApiError::TokenError(_) => (StatusCode::BAD_REQUEST, e.to_string()),
             ApiError::Internal(_) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
             ApiError::SerializationError(_) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+            ApiError::Unauthorized(_) => (StatusCode::UNAUTHORIZED, e.to_string()),
+            ApiError::Forbidden(_) => (StatusCode::FORBIDDEN, e.to_string()),
+            ApiError::Conflict(_) => (StatusCode::CONFLICT, e.to_string()),
         }
     } else if let Some(e) = err.find::<warp::filters::body::BodyDeserializeError>() {
         (StatusCode::BAD_REQUEST, format!("无效的请求数据: {}", e))
+    } else if let Some(e) = err.find::<warp::reject::InvalidQuery>() {
+        (StatusCode::BAD_REQUEST, format!("无效的查询参数: {}", e))
     } else {
         (StatusCode::INTERNAL_SERVER_ERROR, "内部服务器错误".to_string())
     };
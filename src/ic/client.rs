@@ -0,0 +1,92 @@
+/**
+ * 文件描述: IC 调用客户端，同步代码通过它调用 canister 的查询方法
+ * 功能概述:
+ * - 调用前检查熔断状态并等待限流令牌，调用后记录结果（见 limiter 模块）
+ * - 统一的重试策略: 最多尝试 3 次，按指数退避并加入随机抖动；canister 已熔断时不再重试
+ *
+ * 主要组件:
+ * - query函数: 单次查询调用
+ * - query_with_retry函数: 带重试的查询调用
+ * - circuit_retry_after函数: 判断错误是否由熔断引起，返回距离允许下一次调用的时间
+ */
+
+use std::error::Error;
+use ic_agent::Agent;
+use ic_agent::export::Principal;
+use rand::Rng;
+use tokio::time::Duration;
+use log::warn;
+use crate::ic::limiter::{self, CircuitOpenError};
+use crate::utils::create_error;
+
+/// 最大尝试次数
+const MAX_ATTEMPTS: u32 = 3;
+/// 重试退避基数，第 n 次重试前等待 基数 * 2^(n-1)，再加上不超过基数的随机抖动
+const RETRY_BACKOFF_BASE: Duration = Duration::from_millis(500);
+
+/// 调用 canister 的查询方法
+///
+/// canister 已熔断时立即返回 `CircuitOpenError`，不会发出请求。
+pub async fn query(
+    agent: &Agent,
+    canister_id: &Principal,
+    method: &str,
+    arg: &[u8],
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    limiter::acquire(canister_id).await?;
+    match agent.query(canister_id, method)
+        .with_arg(arg.to_vec())
+        .call()
+        .await {
+        Ok(response) => {
+            limiter::record_success(canister_id);
+            Ok(response)
+        },
+        Err(e) => {
+            limiter::record_failure(canister_id, &e.to_string());
+            Err(Box::new(e))
+        }
+    }
+}
+
+/// 带重试的查询调用
+///
+/// `description` 用于日志和错误信息，如 "调用归档canister"。canister 已熔断时直接返回熔断错误。
+pub async fn query_with_retry(
+    agent: &Agent,
+    canister_id: &Principal,
+    method: &str,
+    arg: &[u8],
+    description: &str,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut last_error = None;
+    for attempt in 1..=MAX_ATTEMPTS {
+        match query(agent, canister_id, method, arg).await {
+            Ok(response) => return Ok(response),
+            Err(e) if circuit_retry_after(e.as_ref()).is_some() => return Err(e),
+            Err(e) => {
+                if attempt < MAX_ATTEMPTS {
+                    let wait_time = retry_backoff(attempt);
+                    warn!("{}失败 (尝试 {}/{}): {}，等待 {:?} 后重试",
+                        description, attempt, MAX_ATTEMPTS, e, wait_time);
+                    tokio::time::sleep(wait_time).await;
+                }
+                last_error = Some(e);
+            }
+        }
+    }
+    Err(create_error(&format!("{}失败，已重试 {} 次: {}",
+        description, MAX_ATTEMPTS, last_error.map(|e| e.to_string()).unwrap_or_default())))
+}
+
+/// 错误由熔断引起时返回距离允许下一次调用的时间
+pub fn circuit_retry_after(error: &(dyn Error + Send + Sync + 'static)) -> Option<Duration> {
+    error.downcast_ref::<CircuitOpenError>().map(|e| e.retry_after)
+}
+
+/// 第 `attempt` 次失败后的等待时间
+fn retry_backoff(attempt: u32) -> Duration {
+    let base_ms = RETRY_BACKOFF_BASE.as_millis() as u64;
+    let jitter_ms = rand::thread_rng().gen_range(0..=base_ms);
+    Duration::from_millis(base_ms * 2u64.pow(attempt - 1) + jitter_ms)
+}
@@ -0,0 +1,565 @@
+/**
+ * 文件描述: IC 网络多节点传输层，为 ic_agent::Agent 提供节点健康检查和自动切换
+ * 功能概述:
+ * - 节点列表由 ic_url 和 [ic] 段的 endpoints 组成，ic_url 排在最前
+ * - 记录每个节点的请求延迟（指数移动平均）、请求数、失败数和最近一次错误
+ * - 连接失败、超时、5xx 和 429 响应计为节点失败并切换到下一个节点重试；
+ *   连续失败达到上限的节点标记为不健康，只在没有健康节点时才会被使用
+ * - 后台定期请求各节点的 /api/v2/status，不健康的节点恢复后重新加入
+ * - 可选：查询请求在健康节点之间轮询，更新调用始终优先使用排在最前的健康节点
+ * - 节点地址可以是任意 HTTP 地址，测试时可以用本地 HTTP 服务代替真实节点
+ *
+ * 主要组件:
+ * - EndpointPool结构体: 节点列表与健康状态，实现 ic_agent 的 Transport 接口
+ * - spawn_health_checks方法: 启动后台健康检查任务
+ * - status_document方法: 节点状态，供状态接口返回
+ */
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+use ic_agent::{AgentError, RequestId};
+use ic_agent::agent::Transport;
+use ic_agent::agent::http_transport::ReqwestTransport;
+use ic_agent::export::Principal;
+use mongodb::bson::{doc, Document};
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, MissedTickBehavior};
+use chrono::Utc;
+use log::{info, warn, debug};
+use crate::models::IcConfig;
+
+/// 默认单次请求超时（秒）
+const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 30;
+/// 默认健康检查间隔（秒）
+const DEFAULT_HEALTH_CHECK_INTERVAL_SECS: u64 = 30;
+/// 默认连续失败上限
+const DEFAULT_MAX_CONSECUTIVE_FAILURES: u32 = 3;
+/// 延迟指数移动平均中最新一次请求的权重
+const LATENCY_EWMA_ALPHA: f64 = 0.2;
+
+type TransportFuture<'a, V> = Pin<Box<dyn Future<Output = Result<V, AgentError>> + Send + 'a>>;
+
+/// 节点指标定义: 名称、类型、说明和取值函数
+type EndpointMetric = (&'static str, &'static str, &'static str, fn(&EndpointHealth) -> Option<String>);
+
+/// 节点健康状态
+#[derive(Debug, Clone)]
+struct EndpointHealth {
+    healthy: bool,
+    consecutive_failures: u32,
+    /// 请求延迟的指数移动平均（毫秒）
+    latency_ms: Option<f64>,
+    /// 同步请求数和失败数，不含健康检查
+    requests: u64,
+    failures: u64,
+    last_error: Option<String>,
+    last_success_at: Option<i64>,
+    last_failure_at: Option<i64>,
+    last_checked_at: Option<i64>,
+}
+
+impl Default for EndpointHealth {
+    fn default() -> Self {
+        EndpointHealth {
+            healthy: true,
+            consecutive_failures: 0,
+            latency_ms: None,
+            requests: 0,
+            failures: 0,
+            last_error: None,
+            last_success_at: None,
+            last_failure_at: None,
+            last_checked_at: None,
+        }
+    }
+}
+
+/// 单个 IC 节点
+struct Endpoint {
+    url: String,
+    transport: ReqwestTransport,
+    health: Mutex<EndpointHealth>,
+}
+
+impl Endpoint {
+    fn is_healthy(&self) -> bool {
+        self.health.lock().unwrap().healthy
+    }
+
+    /// 记录一次成功的请求，节点从不健康恢复时返回 true
+    fn record_success(&self, latency: Duration, probe: bool) -> bool {
+        let mut health = self.health.lock().unwrap();
+        let latency_ms = latency.as_secs_f64() * 1000.0;
+        health.latency_ms = Some(match health.latency_ms {
+            Some(average) => average + LATENCY_EWMA_ALPHA * (latency_ms - average),
+            None => latency_ms,
+        });
+        health.consecutive_failures = 0;
+        health.last_success_at = Some(Utc::now().timestamp());
+        if probe {
+            health.last_checked_at = health.last_success_at;
+        } else {
+            health.requests += 1;
+        }
+        let recovered = !health.healthy;
+        health.healthy = true;
+        recovered
+    }
+
+    /// 记录一次失败的请求，节点因此被标记为不健康时返回 true
+    fn record_failure(&self, error: &AgentError, max_failures: u32, probe: bool) -> bool {
+        let mut health = self.health.lock().unwrap();
+        health.consecutive_failures += 1;
+        health.last_error = Some(error.to_string());
+        health.last_failure_at = Some(Utc::now().timestamp());
+        if probe {
+            health.last_checked_at = health.last_failure_at;
+        } else {
+            health.requests += 1;
+            health.failures += 1;
+        }
+        let became_unhealthy = health.healthy && health.consecutive_failures >= max_failures;
+        if became_unhealthy {
+            health.healthy = false;
+        }
+        became_unhealthy
+    }
+}
+
+/// 节点故障导致的错误可以换一个节点重试；其他错误（如请求被拒绝）换节点也不会成功
+fn is_endpoint_failure(error: &AgentError) -> bool {
+    match error {
+        AgentError::TransportError(_) | AgentError::TimeoutWaitingForResponse() => true,
+        AgentError::HttpError(payload) => payload.status >= 500 || payload.status == 429,
+        _ => false,
+    }
+}
+
+/// IC 节点列表与健康状态，作为 Agent 的传输层使用
+pub struct EndpointPool {
+    endpoints: Vec<Endpoint>,
+    query_round_robin: bool,
+    /// 轮询查询时下一次使用的起始节点
+    next_query: AtomicUsize,
+    request_timeout: Duration,
+    health_check_interval: Duration,
+    max_consecutive_failures: u32,
+}
+
+impl EndpointPool {
+    /// 根据 ic_url 和 [ic] 段配置创建节点列表，重复的地址只保留一个
+    pub fn new(ic_url: &str, config: Option<&IcConfig>) -> Result<Self, AgentError> {
+        let mut urls = vec![ic_url.to_string()];
+        for url in config.and_then(|c| c.endpoints.as_ref()).into_iter().flatten() {
+            if !urls.contains(url) {
+                urls.push(url.clone());
+            }
+        }
+
+        let mut endpoints = Vec::with_capacity(urls.len());
+        for url in urls {
+            endpoints.push(Endpoint {
+                transport: ReqwestTransport::create(url.clone())?,
+                url,
+                health: Mutex::new(EndpointHealth::default()),
+            });
+        }
+
+        Ok(EndpointPool {
+            endpoints,
+            query_round_robin: config.and_then(|c| c.query_round_robin).unwrap_or(false),
+            next_query: AtomicUsize::new(0),
+            request_timeout: Duration::from_secs(
+                config.and_then(|c| c.request_timeout_secs).unwrap_or(DEFAULT_REQUEST_TIMEOUT_SECS)),
+            health_check_interval: Duration::from_secs(
+                config.and_then(|c| c.health_check_interval_secs).unwrap_or(DEFAULT_HEALTH_CHECK_INTERVAL_SECS)),
+            max_consecutive_failures: config.and_then(|c| c.max_consecutive_failures)
+                .unwrap_or(DEFAULT_MAX_CONSECUTIVE_FAILURES)
+                .max(1),
+        })
+    }
+
+    /// 节点地址列表，按优先级排列
+    pub fn urls(&self) -> Vec<String> {
+        self.endpoints.iter().map(|e| e.url.clone()).collect()
+    }
+
+    /// 本次请求依次尝试的节点：健康节点在前，不健康节点作为最后的备选
+    ///
+    /// `rotate` 为 true 时起始节点依次后移，使请求均匀分布到各个健康节点。
+    fn candidates(&self, rotate: bool) -> Vec<usize> {
+        let count = self.endpoints.len();
+        let start = if rotate {
+            self.next_query.fetch_add(1, Ordering::Relaxed) % count
+        } else {
+            0
+        };
+        let (healthy, unhealthy): (Vec<usize>, Vec<usize>) = (0..count)
+            .map(|i| (start + i) % count)
+            .partition(|&i| self.endpoints[i].is_healthy());
+        healthy.into_iter().chain(unhealthy).collect()
+    }
+
+    /// 按节点顺序发送请求，节点故障时切换到下一个节点，每个节点最多尝试一次
+    async fn execute<'a, V, F>(&'a self, rotate: bool, request: F) -> Result<V, AgentError>
+    where
+        F: Fn(&'a ReqwestTransport) -> TransportFuture<'a, V>,
+    {
+        let mut last_error = None;
+        for index in self.candidates(rotate) {
+            let endpoint = &self.endpoints[index];
+            let started = Instant::now();
+            let result = match tokio::time::timeout(self.request_timeout, request(&endpoint.transport)).await {
+                Ok(result) => result,
+                Err(_) => Err(AgentError::TimeoutWaitingForResponse()),
+            };
+            match result {
+                Err(e) if is_endpoint_failure(&e) => {
+                    if endpoint.record_failure(&e, self.max_consecutive_failures, false) {
+                        warn!("IC 节点 {} 连续失败 {} 次，标记为不健康", endpoint.url, self.max_consecutive_failures);
+                    }
+                    if self.endpoints.len() > 1 {
+                        warn!("IC 节点 {} 请求失败，切换到下一个节点: {}", endpoint.url, e);
+                    }
+                    last_error = Some(e);
+                },
+                result => {
+                    // 节点正常响应，即使请求本身被拒绝也说明节点可用
+                    if endpoint.record_success(started.elapsed(), false) {
+                        info!("IC 节点 {} 已恢复", endpoint.url);
+                    }
+                    return result;
+                },
+            }
+        }
+        Err(last_error.unwrap_or_else(|| AgentError::TransportError("没有可用的 IC 节点".into())))
+    }
+
+    /// 启动后台健康检查任务，定期请求每个节点的 status 接口
+    pub fn spawn_health_checks(self: &Arc<Self>) -> JoinHandle<()> {
+        let pool = self.clone();
+        tokio::spawn(async move {
+            info!("已启动 IC 节点健康检查，共 {} 个节点，间隔 {:?}", pool.endpoints.len(), pool.health_check_interval);
+            let mut interval = tokio::time::interval(pool.health_check_interval);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+            loop {
+                interval.tick().await;
+                pool.check_health().await;
+            }
+        })
+    }
+
+    /// 对所有节点执行一次健康检查
+    async fn check_health(&self) {
+        let checks = self.endpoints.iter().map(|endpoint| async move {
+            let started = Instant::now();
+            let result = match tokio::time::timeout(self.request_timeout, endpoint.transport.status()).await {
+                Ok(result) => result,
+                Err(_) => Err(AgentError::TimeoutWaitingForResponse()),
+            };
+            match result {
+                Ok(_) => {
+                    debug!("IC 节点 {} 健康检查成功，用时 {:?}", endpoint.url, started.elapsed());
+                    if endpoint.record_success(started.elapsed(), true) {
+                        info!("IC 节点 {} 健康检查成功，重新启用", endpoint.url);
+                    }
+                },
+                Err(e) => {
+                    debug!("IC 节点 {} 健康检查失败: {}", endpoint.url, e);
+                    if endpoint.record_failure(&e, self.max_consecutive_failures, true) {
+                        warn!("IC 节点 {} 健康检查连续失败 {} 次，标记为不健康: {}",
+                            endpoint.url, self.max_consecutive_failures, e);
+                    }
+                },
+            }
+        });
+        futures::future::join_all(checks).await;
+    }
+
+    /// 节点状态，供状态接口返回
+    pub fn status_document(&self) -> Document {
+        let endpoints: Vec<Document> = self.endpoints.iter().enumerate()
+            .map(|(index, endpoint)| {
+                let health = endpoint.health.lock().unwrap().clone();
+                doc! {
+                    "url": &endpoint.url,
+                    "primary": index == 0,
+                    "healthy": health.healthy,
+                    "consecutive_failures": health.consecutive_failures as i64,
+                    "latency_ms": health.latency_ms.map(|ms| (ms * 10.0).round() / 10.0),
+                    "requests": health.requests as i64,
+                    "failures": health.failures as i64,
+                    "last_error": health.last_error,
+                    "last_success_at": health.last_success_at,
+                    "last_failure_at": health.last_failure_at,
+                    "last_checked_at": health.last_checked_at,
+                }
+            })
+            .collect();
+        let healthy_count = self.endpoints.iter().filter(|e| e.is_healthy()).count();
+        doc! {
+            "query_mode": if self.query_round_robin { "round_robin" } else { "failover" },
+            "healthy_count": healthy_count as i64,
+            "endpoints": endpoints,
+        }
+    }
+
+    /// Prometheus 文本格式的节点指标
+    pub fn render_metrics(&self) -> String {
+        let healths: Vec<(&str, EndpointHealth)> = self.endpoints.iter()
+            .map(|endpoint| (endpoint.url.as_str(), endpoint.health.lock().unwrap().clone()))
+            .collect();
+        let metrics: [EndpointMetric; 4] = [
+            ("index_ic_endpoint_healthy", "gauge", "IC 节点是否健康: 1 健康, 0 不健康", |h| Some((h.healthy as u8).to_string())),
+            ("index_ic_endpoint_latency_ms", "gauge", "IC 节点平均延迟（毫秒）", |h| h.latency_ms.map(|ms| ms.to_string())),
+            ("index_ic_endpoint_requests_total", "counter", "发往 IC 节点的请求次数", |h| Some(h.requests.to_string())),
+            ("index_ic_endpoint_failures_total", "counter", "发往 IC 节点失败的请求次数", |h| Some(h.failures.to_string())),
+        ];
+        let mut out = String::new();
+        for (name, kind, help, value) in metrics {
+            out.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n", name, help, name, kind));
+            for (url, health) in &healths {
+                if let Some(value) = value(health) {
+                    out.push_str(&format!("{}{{endpoint=\"{}\"}} {}\n", name, url, value));
+                }
+            }
+        }
+        out
+    }
+}
+
+impl Transport for EndpointPool {
+    fn call(
+        &self,
+        effective_canister_id: Principal,
+        envelope: Vec<u8>,
+        request_id: RequestId,
+    ) -> TransportFuture<'_, ()> {
+        Box::pin(self.execute(false, move |transport| {
+            transport.call(effective_canister_id, envelope.clone(), request_id)
+        }))
+    }
+
+    fn read_state(
+        &self,
+        effective_canister_id: Principal,
+        envelope: Vec<u8>,
+    ) -> TransportFuture<'_, Vec<u8>> {
+        Box::pin(self.execute(false, move |transport| {
+            transport.read_state(effective_canister_id, envelope.clone())
+        }))
+    }
+
+    fn read_subnet_state(&self, subnet_id: Principal, envelope: Vec<u8>) -> TransportFuture<'_, Vec<u8>> {
+        Box::pin(self.execute(false, move |transport| {
+            transport.read_subnet_state(subnet_id, envelope.clone())
+        }))
+    }
+
+    fn query(&self, effective_canister_id: Principal, envelope: Vec<u8>) -> TransportFuture<'_, Vec<u8>> {
+        Box::pin(self.execute(self.query_round_robin, move |transport| {
+            transport.query(effective_canister_id, envelope.clone())
+        }))
+    }
+
+    fn status(&self) -> TransportFuture<'_, Vec<u8>> {
+        Box::pin(self.execute(false, |transport| transport.status()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use warp::Filter;
+    use warp::http::StatusCode;
+
+    /// 本地 HTTP 节点的响应方式
+    #[derive(Clone, Copy)]
+    enum Behavior {
+        Ok,
+        Status(u16),
+        /// 不响应，直到请求超时
+        Hang,
+    }
+
+    /// 代替 IC 节点的本地 HTTP 服务，记录收到请求的节点编号
+    struct StandIn {
+        url: String,
+        behavior: Arc<Mutex<Behavior>>,
+    }
+
+    impl StandIn {
+        async fn start(id: usize, behavior: Behavior, log: Arc<Mutex<Vec<usize>>>) -> Self {
+            let behavior = Arc::new(Mutex::new(behavior));
+            let current = behavior.clone();
+            let route = warp::any().and_then(move || {
+                let behavior = *current.lock().unwrap();
+                let log = log.clone();
+                async move {
+                    log.lock().unwrap().push(id);
+                    let status = match behavior {
+                        Behavior::Ok => StatusCode::OK,
+                        Behavior::Status(code) => StatusCode::from_u16(code).unwrap(),
+                        Behavior::Hang => {
+                            tokio::time::sleep(Duration::from_secs(30)).await;
+                            StatusCode::OK
+                        },
+                    };
+                    Ok::<_, warp::Rejection>(warp::reply::with_status(Vec::<u8>::new(), status))
+                }
+            });
+            let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+            tokio::spawn(server);
+            StandIn { url: format!("http://{}", addr), behavior }
+        }
+
+        fn set(&self, behavior: Behavior) {
+            *self.behavior.lock().unwrap() = behavior;
+        }
+    }
+
+    /// 启动一组本地节点，返回节点、请求记录和节点池
+    async fn setup(behaviors: &[Behavior], round_robin: bool, max_failures: u32) -> (Vec<StandIn>, Arc<Mutex<Vec<usize>>>, EndpointPool) {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut stand_ins = Vec::new();
+        for (id, behavior) in behaviors.iter().enumerate() {
+            stand_ins.push(StandIn::start(id, *behavior, log.clone()).await);
+        }
+        let config = IcConfig {
+            endpoints: Some(stand_ins.iter().skip(1).map(|s| s.url.clone()).collect()),
+            query_round_robin: Some(round_robin),
+            request_timeout_secs: Some(1),
+            health_check_interval_secs: None,
+            max_consecutive_failures: Some(max_failures),
+            max_requests_per_sec: None,
+            max_canister_requests_per_sec: None,
+            burst: None,
+            circuit_failure_threshold: None,
+            circuit_open_secs: None,
+        };
+        let pool = EndpointPool::new(&stand_ins[0].url, Some(&config)).unwrap();
+        (stand_ins, log, pool)
+    }
+
+    async fn query(pool: &EndpointPool) -> Result<Vec<u8>, AgentError> {
+        pool.query(Principal::anonymous(), Vec::new()).await
+    }
+
+    fn take_log(log: &Arc<Mutex<Vec<usize>>>) -> Vec<usize> {
+        std::mem::take(&mut *log.lock().unwrap())
+    }
+
+    #[tokio::test]
+    async fn fails_over_on_5xx_and_429() {
+        let (_stand_ins, log, pool) = setup(&[Behavior::Status(500), Behavior::Status(429), Behavior::Ok], false, 3).await;
+
+        assert!(query(&pool).await.is_ok());
+        assert_eq!(take_log(&log), vec![0, 1, 2]);
+        let status = pool.status_document();
+        let endpoints = status.get_array("endpoints").unwrap();
+        let failures: Vec<i64> = endpoints.iter()
+            .map(|e| e.as_document().unwrap().get_i64("failures").unwrap())
+            .collect();
+        assert_eq!(failures, vec![1, 1, 0]);
+    }
+
+    #[tokio::test]
+    async fn fails_over_on_timeout() {
+        let (_stand_ins, log, pool) = setup(&[Behavior::Hang, Behavior::Ok], false, 3).await;
+
+        assert!(query(&pool).await.is_ok());
+        assert_eq!(take_log(&log), vec![0, 1]);
+        assert_eq!(pool.endpoints[0].health.lock().unwrap().consecutive_failures, 1);
+    }
+
+    #[tokio::test]
+    async fn does_not_fail_over_on_client_errors() {
+        let (_stand_ins, log, pool) = setup(&[Behavior::Status(400), Behavior::Ok], false, 3).await;
+
+        assert!(matches!(query(&pool).await, Err(AgentError::HttpError(payload)) if payload.status == 400));
+        assert_eq!(take_log(&log), vec![0]);
+        assert!(pool.endpoints[0].is_healthy());
+    }
+
+    #[tokio::test]
+    async fn marks_unhealthy_after_threshold_and_recovers_through_health_check() {
+        let (stand_ins, log, pool) = setup(&[Behavior::Status(503), Behavior::Ok], false, 2).await;
+
+        assert!(query(&pool).await.is_ok());
+        assert!(pool.endpoints[0].is_healthy());
+        assert!(query(&pool).await.is_ok());
+        assert!(!pool.endpoints[0].is_healthy());
+        assert_eq!(take_log(&log), vec![0, 1, 0, 1]);
+
+        // 不健康的节点不再优先使用
+        assert!(query(&pool).await.is_ok());
+        assert_eq!(take_log(&log), vec![1]);
+
+        // 节点仍然故障时健康检查不会恢复它
+        pool.check_health().await;
+        assert!(!pool.endpoints[0].is_healthy());
+        take_log(&log);
+
+        stand_ins[0].set(Behavior::Ok);
+        pool.check_health().await;
+        assert!(pool.endpoints[0].is_healthy());
+        take_log(&log);
+
+        assert!(query(&pool).await.is_ok());
+        assert_eq!(take_log(&log), vec![0]);
+    }
+
+    #[tokio::test]
+    async fn uses_unhealthy_endpoints_as_last_resort() {
+        let (stand_ins, log, pool) = setup(&[Behavior::Status(503), Behavior::Status(503)], false, 1).await;
+
+        assert!(query(&pool).await.is_err());
+        assert!(!pool.endpoints[0].is_healthy() && !pool.endpoints[1].is_healthy());
+        take_log(&log);
+
+        stand_ins[1].set(Behavior::Ok);
+        assert!(query(&pool).await.is_ok());
+        assert_eq!(take_log(&log), vec![0, 1]);
+        assert!(pool.endpoints[1].is_healthy());
+    }
+
+    #[tokio::test]
+    async fn round_robins_queries_across_healthy_endpoints() {
+        let (_stand_ins, log, pool) = setup(&[Behavior::Ok, Behavior::Ok, Behavior::Ok], true, 3).await;
+
+        for _ in 0..6 {
+            assert!(query(&pool).await.is_ok());
+        }
+        assert_eq!(take_log(&log), vec![0, 1, 2, 0, 1, 2]);
+
+        // 更新调用不轮询，始终使用排在最前的健康节点
+        for _ in 0..2 {
+            assert!(pool.read_state(Principal::anonymous(), Vec::new()).await.is_ok());
+        }
+        assert_eq!(take_log(&log), vec![0, 0]);
+
+        // 不健康的节点不参与轮询
+        let error = AgentError::TimeoutWaitingForResponse();
+        pool.endpoints[1].record_failure(&error, 1, true);
+        for _ in 0..6 {
+            assert!(query(&pool).await.is_ok());
+        }
+        let hits = take_log(&log);
+        assert_eq!(hits.len(), 6);
+        assert!(!hits.contains(&1));
+        assert!(hits.contains(&0) && hits.contains(&2));
+    }
+
+    #[tokio::test]
+    async fn failover_mode_prefers_primary() {
+        let (_stand_ins, log, pool) = setup(&[Behavior::Ok, Behavior::Ok], false, 3).await;
+
+        for _ in 0..3 {
+            assert!(query(&pool).await.is_ok());
+        }
+        assert_eq!(take_log(&log), vec![0, 0, 0]);
+    }
+}
@@ -0,0 +1,513 @@
+/**
+ * 文件描述: IC 调用限流与熔断模块，所有代币的同步任务共享
+ * 功能概述:
+ * - 全局令牌桶限制每秒发往 IC 网络的调用数，每个 canister 另有独立的令牌桶
+ * - 每个 canister 一个熔断器：连续失败达到上限后熔断，熔断期间的调用立即失败；
+ *   熔断时间结束后放行一次试探调用，成功则恢复，失败则重新熔断
+ * - 统计每个 canister 的调用数、失败数、限流等待和熔断拒绝次数，供状态接口和 /metrics 返回
+ *
+ * 主要组件:
+ * - configure_limits函数: 按 [ic] 段配置设置限流和熔断参数
+ * - acquire函数: 检查熔断状态并等待限流令牌
+ * - record_success / record_failure函数: 记录调用结果，更新熔断器
+ * - CircuitOpenError结构体: canister 已熔断时返回的错误
+ * - status_document函数: 限流和熔断状态
+ * - render_metrics函数: Prometheus 文本格式的指标
+ */
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Instant;
+use ic_agent::export::Principal;
+use mongodb::bson::{doc, Document};
+use tokio::time::Duration;
+use chrono::Utc;
+use log::{info, warn};
+use crate::models::IcConfig;
+
+/// 默认熔断阈值（连续失败次数）
+const DEFAULT_CIRCUIT_FAILURE_THRESHOLD: u32 = 5;
+/// 默认熔断时间（秒）
+const DEFAULT_CIRCUIT_OPEN_SECS: u64 = 30;
+/// 试探调用正在进行时，其他调用的等待提示时间
+const HALF_OPEN_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// 限流与熔断参数
+#[derive(Debug, Clone)]
+struct LimitSettings {
+    /// 全局每秒调用数，None 表示不限制
+    global_rate: Option<f64>,
+    /// 每个 canister 每秒调用数，None 表示不限制
+    canister_rate: Option<f64>,
+    /// 令牌桶容量（允许的突发调用数），默认等于每秒调用数
+    burst: Option<f64>,
+    failure_threshold: u32,
+    open_duration: Duration,
+}
+
+impl Default for LimitSettings {
+    fn default() -> Self {
+        LimitSettings {
+            global_rate: None,
+            canister_rate: None,
+            burst: None,
+            failure_threshold: DEFAULT_CIRCUIT_FAILURE_THRESHOLD,
+            open_duration: Duration::from_secs(DEFAULT_CIRCUIT_OPEN_SECS),
+        }
+    }
+}
+
+/// 令牌桶
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64, burst: Option<f64>) -> Self {
+        let capacity = burst.unwrap_or(rate).max(1.0);
+        TokenBucket { rate, capacity, tokens: capacity, updated: Instant::now() }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated = now;
+    }
+
+    /// 距离下一个令牌可用还需等待的时间，令牌可用时返回零
+    fn wait_time(&mut self, now: Instant) -> Duration {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((1.0 - self.tokens) / self.rate)
+        }
+    }
+
+    fn take(&mut self) {
+        self.tokens -= 1.0;
+    }
+}
+
+/// 熔断器状态
+#[derive(Debug, Clone, Copy)]
+enum CircuitState {
+    Closed,
+    Open { until: Instant },
+    /// 熔断时间已过，等待试探调用的结果；`trial_started` 为正在进行的试探调用的开始时间
+    HalfOpen { trial_started: Option<Instant> },
+}
+
+impl CircuitState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CircuitState::Closed => "closed",
+            CircuitState::Open { .. } => "open",
+            CircuitState::HalfOpen { .. } => "half_open",
+        }
+    }
+
+    /// Prometheus 指标中的状态值: 0 正常，1 试探中，2 熔断
+    fn metric_value(&self) -> u8 {
+        match self {
+            CircuitState::Closed => 0,
+            CircuitState::HalfOpen { .. } => 1,
+            CircuitState::Open { .. } => 2,
+        }
+    }
+}
+
+/// 单个 canister 的限流、熔断状态和调用统计
+#[derive(Debug)]
+struct CanisterState {
+    bucket: Option<TokenBucket>,
+    circuit: CircuitState,
+    consecutive_failures: u32,
+    opened_count: u64,
+    opened_at: Option<i64>,
+    requests: u64,
+    failures: u64,
+    /// 因熔断被拒绝的调用数
+    rejected: u64,
+    /// 因限流等待过的调用数和累计等待时间
+    throttled: u64,
+    throttle_wait: Duration,
+    last_error: Option<String>,
+}
+
+impl CanisterState {
+    fn new(settings: &LimitSettings) -> Self {
+        CanisterState {
+            bucket: settings.canister_rate.map(|rate| TokenBucket::new(rate, settings.burst)),
+            circuit: CircuitState::Closed,
+            consecutive_failures: 0,
+            opened_count: 0,
+            opened_at: None,
+            requests: 0,
+            failures: 0,
+            rejected: 0,
+            throttled: 0,
+            throttle_wait: Duration::ZERO,
+            last_error: None,
+        }
+    }
+}
+
+/// 全部 canister 共享的限流器
+#[derive(Debug)]
+struct Limiter {
+    settings: LimitSettings,
+    global: Option<TokenBucket>,
+    canisters: HashMap<Principal, CanisterState>,
+}
+
+impl Limiter {
+    fn new(settings: LimitSettings) -> Self {
+        Limiter {
+            global: settings.global_rate.map(|rate| TokenBucket::new(rate, settings.burst)),
+            settings,
+            canisters: HashMap::new(),
+        }
+    }
+
+    fn canister(&mut self, canister_id: &Principal) -> &mut CanisterState {
+        let settings = &self.settings;
+        self.canisters.entry(*canister_id).or_insert_with(|| CanisterState::new(settings))
+    }
+
+    /// 检查熔断状态，熔断时返回错误；熔断时间已过时转为试探状态
+    fn check_circuit(&mut self, canister_id: &Principal, now: Instant) -> Result<(), CircuitOpenError> {
+        let open_duration = self.settings.open_duration;
+        let state = self.canister(canister_id);
+        let retry_after = match state.circuit {
+            CircuitState::Closed => return Ok(()),
+            CircuitState::Open { until } if now < until => until - now,
+            CircuitState::Open { .. } => {
+                state.circuit = CircuitState::HalfOpen { trial_started: None };
+                return Ok(());
+            },
+            // 试探调用超过熔断时间仍未返回结果（例如调用被取消），允许新的试探调用
+            CircuitState::HalfOpen { trial_started: Some(started) } if now.duration_since(started) < open_duration => {
+                HALF_OPEN_RETRY_AFTER
+            },
+            CircuitState::HalfOpen { .. } => return Ok(()),
+        };
+        state.rejected += 1;
+        Err(CircuitOpenError { canister_id: *canister_id, retry_after })
+    }
+
+    /// 全局令牌桶和 canister 令牌桶都有令牌时取走令牌并返回零，否则返回需要等待的时间
+    fn try_take(&mut self, canister_id: &Principal, now: Instant) -> Duration {
+        let global_wait = self.global.as_mut().map_or(Duration::ZERO, |bucket| bucket.wait_time(now));
+        let state = self.canister(canister_id);
+        let canister_wait = state.bucket.as_mut().map_or(Duration::ZERO, |bucket| bucket.wait_time(now));
+        let wait = global_wait.max(canister_wait);
+        if !wait.is_zero() {
+            return wait;
+        }
+
+        if let Some(bucket) = state.bucket.as_mut() {
+            bucket.take();
+        }
+        if let CircuitState::HalfOpen { trial_started } = &mut state.circuit {
+            *trial_started = Some(now);
+        }
+        if let Some(bucket) = self.global.as_mut() {
+            bucket.take();
+        }
+        Duration::ZERO
+    }
+
+    /// 记录一次成功的调用，试探调用成功时恢复
+    fn record_success(&mut self, canister_id: &Principal) {
+        let state = self.canister(canister_id);
+        state.requests += 1;
+        state.consecutive_failures = 0;
+        if !matches!(state.circuit, CircuitState::Closed) {
+            info!("canister {} 调用恢复正常，解除熔断", canister_id);
+            state.circuit = CircuitState::Closed;
+        }
+    }
+
+    /// 记录一次失败的调用，连续失败达到阈值或试探调用失败时熔断
+    fn record_failure(&mut self, canister_id: &Principal, error: &str, now: Instant) {
+        let threshold = self.settings.failure_threshold;
+        let open_duration = self.settings.open_duration;
+        let state = self.canister(canister_id);
+        state.requests += 1;
+        state.failures += 1;
+        state.consecutive_failures += 1;
+        state.last_error = Some(error.to_string());
+
+        let open = match state.circuit {
+            CircuitState::Closed => state.consecutive_failures >= threshold,
+            CircuitState::HalfOpen { .. } => true,
+            CircuitState::Open { .. } => false,
+        };
+        if open {
+            state.circuit = CircuitState::Open { until: now + open_duration };
+            state.opened_count += 1;
+            state.opened_at = Some(Utc::now().timestamp());
+            warn!("canister {} 连续失败 {} 次，熔断 {:?}: {}", canister_id, state.consecutive_failures, open_duration, error);
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    /// 全局限流器，所有代币的同步任务共享
+    static ref LIMITER: Mutex<Limiter> = Mutex::new(Limiter::new(LimitSettings::default()));
+}
+
+/// canister 已熔断时返回的错误
+#[derive(Debug, Clone)]
+pub struct CircuitOpenError {
+    pub canister_id: Principal,
+    /// 距离允许下一次调用的时间
+    pub retry_after: Duration,
+}
+
+impl fmt::Display for CircuitOpenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "canister {} 连续调用失败已熔断，{:.1} 秒后重试", self.canister_id, self.retry_after.as_secs_f64())
+    }
+}
+
+impl std::error::Error for CircuitOpenError {}
+
+/// 按 [ic] 段配置设置限流和熔断参数，已有的统计数据清空
+pub fn configure_limits(config: Option<&IcConfig>) {
+    let positive = |rate: Option<f64>| rate.filter(|r| *r > 0.0);
+    let settings = LimitSettings {
+        global_rate: positive(config.and_then(|c| c.max_requests_per_sec)),
+        canister_rate: positive(config.and_then(|c| c.max_canister_requests_per_sec)),
+        burst: positive(config.and_then(|c| c.burst)),
+        failure_threshold: config.and_then(|c| c.circuit_failure_threshold)
+            .unwrap_or(DEFAULT_CIRCUIT_FAILURE_THRESHOLD)
+            .max(1),
+        open_duration: Duration::from_secs(config.and_then(|c| c.circuit_open_secs).unwrap_or(DEFAULT_CIRCUIT_OPEN_SECS)),
+    };
+    info!("IC 调用限流: 全局 {}，每个 canister {}；连续失败 {} 次熔断 {:?}",
+        settings.global_rate.map_or("不限制".to_string(), |r| format!("{} 次/秒", r)),
+        settings.canister_rate.map_or("不限制".to_string(), |r| format!("{} 次/秒", r)),
+        settings.failure_threshold,
+        settings.open_duration);
+    *LIMITER.lock().unwrap() = Limiter::new(settings);
+}
+
+/// 调用 canister 前检查熔断状态并等待限流令牌
+pub async fn acquire(canister_id: &Principal) -> Result<(), CircuitOpenError> {
+    let started = Instant::now();
+    let mut throttled = false;
+    loop {
+        let wait = {
+            let mut limiter = LIMITER.lock().unwrap();
+            let now = Instant::now();
+            limiter.check_circuit(canister_id, now)?;
+            let wait = limiter.try_take(canister_id, now);
+            if wait.is_zero() {
+                if throttled {
+                    let state = limiter.canister(canister_id);
+                    state.throttled += 1;
+                    state.throttle_wait += started.elapsed();
+                }
+                return Ok(());
+            }
+            wait
+        };
+        throttled = true;
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// 记录一次成功的调用，试探调用成功时恢复
+pub fn record_success(canister_id: &Principal) {
+    LIMITER.lock().unwrap().record_success(canister_id);
+}
+
+/// 记录一次失败的调用，连续失败达到阈值或试探调用失败时熔断
+pub fn record_failure(canister_id: &Principal, error: &str) {
+    LIMITER.lock().unwrap().record_failure(canister_id, error, Instant::now());
+}
+
+/// 限流和熔断状态，供状态接口返回
+pub fn status_document() -> Document {
+    let mut limiter = LIMITER.lock().unwrap();
+    let now = Instant::now();
+    let global_available = limiter.global.as_mut().map(|bucket| {
+        bucket.refill(now);
+        (bucket.tokens * 10.0).floor() / 10.0
+    });
+    let settings = limiter.settings.clone();
+
+    let mut canisters: Vec<Document> = limiter.canisters.iter()
+        .map(|(canister_id, state)| {
+            let open_remaining_secs = match state.circuit {
+                CircuitState::Open { until } => Some(until.saturating_duration_since(now).as_secs() as i64),
+                _ => None,
+            };
+            doc! {
+                "canister_id": canister_id.to_text(),
+                "circuit": state.circuit.as_str(),
+                "open_remaining_secs": open_remaining_secs,
+                "consecutive_failures": state.consecutive_failures as i64,
+                "opened_count": state.opened_count as i64,
+                "opened_at": state.opened_at,
+                "requests": state.requests as i64,
+                "failures": state.failures as i64,
+                "rejected": state.rejected as i64,
+                "throttled": state.throttled as i64,
+                "throttle_wait_ms": state.throttle_wait.as_millis() as i64,
+                "last_error": state.last_error.clone(),
+            }
+        })
+        .collect();
+    canisters.sort_by(|a, b| a.get_str("canister_id").ok().cmp(&b.get_str("canister_id").ok()));
+
+    doc! {
+        "max_requests_per_sec": settings.global_rate,
+        "max_canister_requests_per_sec": settings.canister_rate,
+        "burst": settings.burst,
+        "global_available_tokens": global_available,
+        "circuit_failure_threshold": settings.failure_threshold as i64,
+        "circuit_open_secs": settings.open_duration.as_secs() as i64,
+        "canisters": canisters,
+    }
+}
+
+/// canister 指标定义: 名称、类型、说明和取值函数
+type CanisterMetric = (&'static str, &'static str, &'static str, fn(&CanisterState) -> String);
+
+/// Prometheus 文本格式的限流和熔断指标
+pub fn render_metrics() -> String {
+    let mut limiter = LIMITER.lock().unwrap();
+    let now = Instant::now();
+    let mut out = String::new();
+
+    if let Some(bucket) = limiter.global.as_mut() {
+        bucket.refill(now);
+        out.push_str("# HELP index_ic_global_available_tokens 全局令牌桶中可用的令牌数\n");
+        out.push_str("# TYPE index_ic_global_available_tokens gauge\n");
+        out.push_str(&format!("index_ic_global_available_tokens {}\n", bucket.tokens));
+    }
+
+    let mut canisters: Vec<(&Principal, &CanisterState)> = limiter.canisters.iter().collect();
+    canisters.sort_by_key(|(canister_id, _)| canister_id.to_text());
+    let metrics: [CanisterMetric; 7] = [
+        ("index_ic_canister_requests_total", "counter", "canister 调用次数", |s| s.requests.to_string()),
+        ("index_ic_canister_failures_total", "counter", "canister 调用失败次数", |s| s.failures.to_string()),
+        ("index_ic_canister_rejected_total", "counter", "因熔断被拒绝的调用次数", |s| s.rejected.to_string()),
+        ("index_ic_canister_throttled_total", "counter", "因限流等待过的调用次数", |s| s.throttled.to_string()),
+        ("index_ic_canister_throttle_wait_seconds_total", "counter", "因限流累计等待的时间", |s| s.throttle_wait.as_secs_f64().to_string()),
+        ("index_ic_canister_circuit_opened_total", "counter", "熔断次数", |s| s.opened_count.to_string()),
+        ("index_ic_canister_circuit_state", "gauge", "熔断器状态: 0 正常, 1 试探中, 2 熔断", |s| s.circuit.metric_value().to_string()),
+    ];
+    for (name, kind, help, value) in metrics {
+        out.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n", name, help, name, kind));
+        for (canister_id, state) in &canisters {
+            out.push_str(&format!("{}{{canister=\"{}\"}} {}\n", name, canister_id, value(state)));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(failure_threshold: u32) -> Limiter {
+        Limiter::new(LimitSettings {
+            failure_threshold,
+            open_duration: Duration::from_secs(30),
+            ..LimitSettings::default()
+        })
+    }
+
+    fn canister() -> Principal {
+        Principal::from_slice(&[1, 2, 3])
+    }
+
+    fn assert_close(actual: Duration, expected: Duration) {
+        let diff = actual.as_secs_f64() - expected.as_secs_f64();
+        assert!(diff.abs() < 1e-6, "{:?} 与 {:?} 不一致", actual, expected);
+    }
+
+    #[test]
+    fn token_bucket_waits_until_next_token() {
+        let mut bucket = TokenBucket::new(10.0, None);
+        let now = bucket.updated;
+        for _ in 0..10 {
+            assert_eq!(bucket.wait_time(now), Duration::ZERO);
+            bucket.take();
+        }
+        assert_close(bucket.wait_time(now), Duration::from_millis(100));
+        assert_close(bucket.wait_time(now + Duration::from_millis(40)), Duration::from_millis(60));
+        assert_eq!(bucket.wait_time(now + Duration::from_millis(100)), Duration::ZERO);
+        // 空闲再久也不超过容量
+        assert_eq!(bucket.wait_time(now + Duration::from_secs(60)), Duration::ZERO);
+        assert!((bucket.tokens - bucket.capacity).abs() < 1e-9);
+    }
+
+    #[test]
+    fn circuit_opens_after_threshold() {
+        let mut limiter = limiter(3);
+        let id = canister();
+        let now = Instant::now();
+        for _ in 0..2 {
+            limiter.record_failure(&id, "timeout", now);
+            assert!(limiter.check_circuit(&id, now).is_ok());
+        }
+        limiter.record_failure(&id, "timeout", now);
+        let error = limiter.check_circuit(&id, now + Duration::from_secs(10)).unwrap_err();
+        assert_eq!(error.retry_after, Duration::from_secs(20));
+        assert_eq!(limiter.canister(&id).opened_count, 1);
+        assert_eq!(limiter.canister(&id).rejected, 1);
+    }
+
+    #[test]
+    fn half_open_allows_exactly_one_trial() {
+        let mut limiter = limiter(1);
+        let id = canister();
+        let now = Instant::now();
+        limiter.record_failure(&id, "timeout", now);
+
+        let after_open = now + Duration::from_secs(30);
+        assert!(limiter.check_circuit(&id, after_open).is_ok());
+        assert!(matches!(limiter.canister(&id).circuit, CircuitState::HalfOpen { trial_started: None }));
+        assert_eq!(limiter.try_take(&id, after_open), Duration::ZERO);
+
+        let error = limiter.check_circuit(&id, after_open + Duration::from_millis(10)).unwrap_err();
+        assert_eq!(error.retry_after, HALF_OPEN_RETRY_AFTER);
+
+        // 试探调用失败后重新熔断
+        limiter.record_failure(&id, "timeout", after_open);
+        assert!(matches!(limiter.canister(&id).circuit, CircuitState::Open { .. }));
+        assert!(limiter.check_circuit(&id, after_open + Duration::from_secs(1)).is_err());
+    }
+
+    #[test]
+    fn record_success_closes_circuit() {
+        let mut limiter = limiter(2);
+        let id = canister();
+        let now = Instant::now();
+        limiter.record_failure(&id, "timeout", now);
+        limiter.record_failure(&id, "timeout", now);
+
+        let after_open = now + Duration::from_secs(30);
+        assert!(limiter.check_circuit(&id, after_open).is_ok());
+        limiter.try_take(&id, after_open);
+        limiter.record_success(&id);
+
+        assert!(matches!(limiter.canister(&id).circuit, CircuitState::Closed));
+        assert_eq!(limiter.canister(&id).consecutive_failures, 0);
+        assert!(limiter.check_circuit(&id, after_open).is_ok());
+        assert!(limiter.check_circuit(&id, after_open).is_ok());
+        // 恢复后重新按阈值计数
+        limiter.record_failure(&id, "timeout", after_open);
+        assert!(limiter.check_circuit(&id, after_open).is_ok());
+    }
+}
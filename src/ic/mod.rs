@@ -0,0 +1,14 @@
+/**
+ * 文件描述: IC 网络客户端模块入口，位于同步代码与 ic_agent::Agent 之间
+ * 功能概述:
+ * - 导出 IC 网络客户端相关子模块
+ *
+ * 主要组件:
+ * - endpoints模块: 多节点传输层，负责节点健康检查、自动切换和查询轮询
+ * - limiter模块: 全局和每个 canister 的调用限流，以及每个 canister 的熔断器
+ * - client模块: 经过限流和熔断的 canister 调用，以及统一的重试策略
+ */
+
+pub mod endpoints;
+pub mod limiter;
+pub mod client;
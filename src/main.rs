@@ -1,3 +1,6 @@
+// 各源文件头部使用 /** */ 块注释作为文件说明
+#![allow(clippy::empty_line_after_doc_comments)]
+
 /**
  * 文件描述: 主程序入口文件，负责初始化并运行区块链索引服务
  * 功能概述: 
@@ -9,14 +12,19 @@
  * - 启动API服务器
  * 
  * 主要组件:
- * - main函数 (第60-108行): 程序入口点，设置日志系统和错误处理
- * - setup_logger函数 (第110-234行): 配置日志系统，设置日志输出到文件和控制台
- * - run_application函数 (第236-647行): 主应用逻辑实现，包括:
- *   - 初始化数据库和IC连接 (第173-182行)
- *   - 根据命令行参数判断是否执行重置同步 (第185-254行)
- *   - 判断各代币是否需要初始同步 (第257-342行)
- *   - 启动API服务器 (第345-367行)
- *   - 执行定时增量同步循环 (第370-647行)
+ * - main函数: 程序入口点，解析命令行（见 cli），设置日志系统，将执行结果转换为退出码
+ * - setup_logger函数: 配置日志系统，设置日志输出到文件和控制台
+ * - build_log_config函数: 根据配置生成日志配置，配置热更新时同样使用
+ * - run_application函数: 主应用逻辑实现，包括:
+ *   - 初始化数据库和IC连接
+ *   - 按子命令分派：status / migrate / snapshot / rebuild / reset / verify / backfill 执行完成后退出 (见 commands)
+ *   - 启动同步租约的定期续期，各代币的租约由同步任务获取，未持有租约的代币作为热备 (见 db::leases)
+ *   - 启动同步进度的定期写入，serve 子命令不启动 (见 sync::progress)
+ *   - 启动API服务器，sync 子命令不启动
+ *   - 为每个代币启动受监督的同步任务，初始同步在各自的任务中执行 (见 sync::worker)，由代币管理器统一启停 (见 sync::manager)
+ *   - 启动配置热更新，监视 --config 指定的配置文件和 SIGHUP (见 reload)
+ *   - 收到 SIGTERM / SIGINT 时优雅停止：同步任务写入检查点，API 服务器在超时前处理完进行中的请求 (见 shutdown)
+ * - run_read_replica函数: serve 子命令的只读 API 实例，不连接 IC 网络、不执行同步
  */
 
 #[allow(unused_variables)]
@@ -25,7 +33,6 @@
 #[allow(non_camel_case_types)]
 #[allow(type_alias_bounds)]
 #[allow(dead_code)]
-
 mod models;
 mod utils;
 mod config;
@@ -35,44 +42,63 @@ mod sync;
 mod api;
 mod api_server;
 mod error;
+mod ic;
+mod reload;
+mod shutdown;
+mod cli;
+mod commands;
+mod search;
+mod pagination;
+mod stream;
 
 use std::error::Error;
-use std::collections::HashMap;
+use std::process::ExitCode;
+use clap::Parser;
+use std::sync::{Arc, RwLock};
 use std::fs;
-use tokio;
 use tokio::time::Duration;
-use log::{info, error, warn, debug, LevelFilter};
-use crate::db::balances;
+use log::{info, error, warn, LevelFilter};
 use log4rs::append::console::{ConsoleAppender, Target};
 use log4rs::append::file::FileAppender;
 use log4rs::encode::pattern::PatternEncoder;
 use log4rs::config::{Appender, Config as LogConfig, Root};
 use log4rs::filter::threshold::ThresholdFilter;
-use crate::config::{load_config, parse_args, parse_canister_id, create_agent, get_token_decimals};
-use crate::db::{init_db, create_indexes};
-use crate::sync::{sync_ledger_transactions, sync_archive_transactions};
-use crate::sync::admin::reset_and_sync_all_transactions;
-use crate::db::balances::calculate_incremental_balances;
-use crate::db::sync_status::{get_sync_status, set_incremental_mode, update_balance_calculated_index};
-use crate::db::transactions::{get_latest_transaction_index, get_transactions_by_index_range};
-use chrono;
+use crate::cli::{BackfillCommand, Cli, Command, SnapshotCommand};
+use crate::config::{load_config, parse_canister_id, create_agent};
+use crate::db::init_db;
+use crate::db::leases;
+use crate::db::schema::{run_migrations, check_schema_versions};
+use crate::db::cold_storage::{register_cold_storage, DEFAULT_COLD_STORAGE_DIR};
+use crate::db::snapshot::{export_snapshot, import_snapshot};
+use crate::sync::manager::TokenManager;
+use crate::db::registry::{merge_config_tokens, read_registry_tokens};
+
+/// 优雅停止时等待 API 服务器处理完进行中请求的最长时间
+const API_DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
+async fn main() -> ExitCode {
+    // 解析命令行参数，参数错误时 clap 输出用法并以退出码 2 退出
+    let cli = Cli::parse();
+
     // 读取配置文件（不使用日志记录）
-    let cfg = match load_config().await {
+    let cfg = match load_config(&cli.config).await {
         Ok(config) => config,
         Err(e) => {
             eprintln!("配置加载失败: {}", e);
-            return Err(e);
+            return ExitCode::from(cli::EXIT_CONFIG);
         }
     };
     
     // 初始化日志系统
-    if let Err(e) = setup_logger(&cfg) {
-        eprintln!("警告: 无法设置日志系统: {}", e);
-        // 继续执行，但日志会输出到标准错误
-    }
+    let log_handle = match setup_logger(&cfg) {
+        Ok(handle) => Some(handle),
+        Err(e) => {
+            eprintln!("警告: 无法设置日志系统: {}", e);
+            // 继续执行，但日志会输出到标准错误
+            None
+        }
+    };
     
     info!("=======================================");
     info!("==============  服务启动  ==============");
@@ -81,40 +107,60 @@ async fn main() -> Result<(), Box<dyn Error>> {
     info!("正在启动区块链索引服务...");
     
     // 设置全局错误捕获
-    let result = run_application(cfg).await;
+    let result = run_application(cli, cfg, log_handle).await;
     
     // 处理顶层错误
-    if let Err(e) = &result {
-        error!("程序执行过程中发生错误: {}", e);
-        let error_details = format!("{:?}", e);
-        error!("详细错误信息: {}", error_details);
-        
-        if error_details.contains("mongodb") || error_details.contains("connection") {
-            error!("可能是数据库连接问题，请检查MongoDB服务是否正常运行以及连接配置是否正确");
-        } else if error_details.contains("canister") || error_details.contains("agent") || error_details.contains("ic") {
-            error!("可能是IC网络连接问题，请检查网络连接以及canister ID配置是否正确");
-        } else if error_details.contains("permission") || error_details.contains("access") {
-            error!("可能是文件或资源访问权限问题，请检查程序运行权限");
+    match result {
+        Ok(code) => ExitCode::from(code),
+        Err(e) => {
+            error!("程序执行过程中发生错误: {}", e);
+            let error_details = format!("{:?}", e);
+            error!("详细错误信息: {}", error_details);
+            
+            if error_details.contains("mongodb") || error_details.contains("connection") {
+                error!("可能是数据库连接问题，请检查MongoDB服务是否正常运行以及连接配置是否正确");
+            } else if error_details.contains("canister") || error_details.contains("agent") || error_details.contains("ic") {
+                error!("可能是IC网络连接问题，请检查网络连接以及canister ID配置是否正确");
+            } else if error_details.contains("permission") || error_details.contains("access") {
+                error!("可能是文件或资源访问权限问题，请检查程序运行权限");
+            }
+            
+            error!("建议尝试以下恢复步骤:");
+            error!("1. 检查配置文件中的参数设置");
+            error!("2. 确认网络连接状态");
+            error!("3. 验证数据库服务是否可用");
+            error!("4. 使用 reset 子命令重新执行完全同步");
+            ExitCode::from(cli::EXIT_FAILURE)
         }
-        
-        error!("建议尝试以下恢复步骤:");
-        error!("1. 检查配置文件中的参数设置");
-        error!("2. 确认网络连接状态");
-        error!("3. 验证数据库服务是否可用");
-        error!("4. 使用 --reset 参数重新启动尝试完全同步");
+    }
+}
+
+/// 根据配置设置日志系统，返回的句柄用于配置热更新时替换日志配置
+fn setup_logger(cfg: &models::Config) -> Result<log4rs::Handle, Box<dyn Error + Send + Sync>> {
+    if cfg.log.is_none() {
+        eprintln!("未找到日志配置，使用默认配置");
+    }
+    let log_config = build_log_config(cfg)?;
+    
+    // 初始化日志系统
+    let handle = log4rs::init_config(log_config)?;
+    
+    match &cfg.log {
+        None => eprintln!("日志系统已初始化，使用默认配置"),
+        Some(log_cfg) if log_cfg.file_enabled => eprintln!("日志系统已初始化，日志文件：{}", log_cfg.file),
+        Some(_) => eprintln!("日志系统已初始化，使用控制台输出"),
     }
     
-    result
+    Ok(handle)
 }
 
-/// 根据配置设置日志系统
-fn setup_logger(cfg: &models::Config) -> Result<(), Box<dyn Error>> {
+/// 根据配置构建日志配置（启动和配置热更新时使用）
+fn build_log_config(cfg: &models::Config) -> Result<LogConfig, Box<dyn Error + Send + Sync>> {
     // 获取日志配置
     let log_cfg = match &cfg.log {
         Some(log_config) => log_config,
         None => {
             // 没有日志配置，创建默认文件日志
-            eprintln!("未找到日志配置，使用默认配置");
             // 确保日志目录存在
             let log_dir = std::path::Path::new("logs");
             if !log_dir.exists() {
@@ -132,9 +178,7 @@ fn setup_logger(cfg: &models::Config) -> Result<(), Box<dyn Error>> {
                 .appender(Appender::builder().build("file", Box::new(file)))
                 .build(Root::builder().appender("file").build(LevelFilter::Info))?;
                 
-            log4rs::init_config(config)?;
-            eprintln!("日志系统已初始化，使用默认配置");
-            return Ok(());
+            return Ok(config);
         }
     };
     
@@ -221,300 +265,200 @@ fn setup_logger(cfg: &models::Config) -> Result<(), Box<dyn Error>> {
     let log_config = config_builder
         .build(root_builder.build(log_level))?;
     
-    // 初始化日志系统
-    log4rs::init_config(log_config)?;
-    
-    if log_cfg.file_enabled {
-        eprintln!("日志系统已初始化，日志文件：{}", log_cfg.file);
-    } else {
-        eprintln!("日志系统已初始化，使用控制台输出");
-    }
-    
-    Ok(())
+    Ok(log_config)
 }
 
-// 将主要应用逻辑移到独立函数，便于错误处理
-async fn run_application(cfg: models::Config) -> Result<(), Box<dyn Error>> {
-    info!("启动索引服务...");
+// 将主要应用逻辑移到独立函数，便于错误处理；返回进程退出码
+async fn run_application(cli: Cli, cfg: models::Config, log_handle: Option<log4rs::Handle>) -> Result<u8, Box<dyn Error + Send + Sync>> {
+    let command = cli.command();
+    info!("执行命令: {:?}（配置文件: {}）", command, cli.config);
     
-    // 获取命令行参数
-    let args = models::AppArgs { reset: std::env::args().any(|arg| arg == "--reset") };
-    let _ = parse_args(&args).await?;
-    let reset_mode = args.reset;
+    // 监听 SIGTERM / SIGINT，同步流程在批次之间检查停止信号
+    shutdown::listen_for_signals();
     
     // 初始化 MongoDB
-    let db_conn = init_db(&cfg.mongodb_url, &cfg.database, &cfg.tokens).await?;
+    let db_conn = init_db(&cfg.mongodb_url, &cfg.database).await?;
+
+    // 同步租约：多个实例共享数据库时每个代币只由一个实例同步；
+    // 只有会获取租约的命令才在后台定期续期，只读命令不启动心跳
+    leases::configure(cfg.lease.as_ref());
+    if matches!(command, Command::Run | Command::Sync | Command::Reset { .. } | Command::Rebuild { .. } | Command::Backfill(_)) {
+        leases::spawn_heartbeat(db_conn.lease_col.clone());
+    }
+
+    // 同步进度（阶段、吞吐量、预计剩余时间）定期写入 sync_status，只读 API 实例从数据库读取
+    if !matches!(command, Command::Serve) {
+        sync::progress::spawn_writer(db_conn.sync_status_col.clone());
+    }
+
+    // 合并配置文件与代币注册表（包含通过管理接口添加的代币），为每个代币创建集合；
+    // 只读 API 实例不写入注册表，由同步进程负责合并
+    let registry = match command {
+        Command::Serve => read_registry_tokens(&db_conn.registry_col, &cfg.tokens).await?,
+        _ => merge_config_tokens(&db_conn.registry_col, &cfg.tokens).await?,
+    };
+    for entry in &registry {
+        db_conn.add_token_collections(&entry.token);
+    }
+    let tokens: Vec<models::TokenConfig> = registry.iter().map(|e| e.token.clone()).collect();
+    let active_tokens: Vec<models::TokenConfig> = registry.iter()
+        .filter(|e| e.status == models::TokenStatus::Active)
+        .map(|e| e.token.clone())
+        .collect();
     
-    // 初始化IC Agent
-    let agent = create_agent(&cfg.ic_url)?;
+    // 输出同步状态后退出，不需要 IC Agent，也不执行迁移
+    if let Command::Status { token, json } = &command {
+        return commands::status(&db_conn, &registry, token.as_deref(), *json).await;
+    }
+    if let Command::Backfill(BackfillCommand::Status { token, json }) = &command {
+        return match commands::select_tokens(&tokens, token.as_deref(), tokens.iter().collect()) {
+            Some(targets) => commands::backfill_status(&db_conn, &targets, *json).await,
+            None => Ok(cli::EXIT_USAGE),
+        };
+    }
 
     // 获取并验证所有代币的canister ID和小数位数
-    for token in &cfg.tokens {
+    for token in &tokens {
         // 解析Canister ID
         info!("{}: 解析canister ID: {}", token.symbol, token.canister_id);
         let _canister_id = parse_canister_id(&token.canister_id)?;
         
         // 获取代币小数位数 (如果未在配置中指定)
-        if token.decimals.is_none() {
+        match token.decimals {
+            Some(decimals) => info!("{}: 使用配置文件中指定的代币小数位: {}", token.symbol, decimals),
             // 仅显示信息，不执行查询，查询将在实际同步时进行
-            info!("{}: 配置中未指定代币小数位，将在同步时从canister查询", token.symbol);
-        } else {
-            info!("{}: 使用配置文件中指定的代币小数位: {}", token.symbol, token.decimals.unwrap());
+            None => info!("{}: 配置中未指定代币小数位，将在同步时从canister查询", token.symbol),
         }
     }
 
-    // 创建索引以提高查询性能
-    create_indexes(&db_conn).await?;
+    // 数据库结构迁移（索引、字段变更、数据回填）
+    if let Command::Migrate = command {
+        run_migrations(&db_conn).await?;
+        info!("数据库迁移完成");
+        return Ok(cli::EXIT_OK);
+    }
+    // 只读 API 实例从不执行迁移，只检查数据库结构版本
+    if cfg.auto_migrate.unwrap_or(true) && !matches!(command, Command::Serve) {
+        run_migrations(&db_conn).await?;
+    } else {
+        check_schema_versions(&db_conn).await?;
+    }
 
-    // 如果是重置模式，执行完整的数据库重置和重新同步
-    if reset_mode && !cfg.tokens.is_empty() {
-        info!("开始执行数据库重置和重新同步操作...");
-        // 单例模式：只有一个代币时，直接重置和同步
-        let first_token = &cfg.tokens[0];
-        // 解析canister ID
-        let canister_id = parse_canister_id(&first_token.canister_id)?;
-        reset_and_sync_all_transactions(&agent, &canister_id, &db_conn, first_token).await?;
-        info!("数据库重置和重新同步成功完成！");
-        return Ok(());
+    // 登记各代币的冷存储，已归档的交易在查询时从分段文件读取
+    let cold_storage_dir = cfg.cold_storage_dir.clone().unwrap_or_else(|| DEFAULT_COLD_STORAGE_DIR.to_string());
+    for token in &tokens {
+        if let Some(collections) = db_conn.token_collections(&token.symbol) {
+            register_cold_storage(&collections.tx_col, &cold_storage_dir, &token.symbol).await?;
+        }
     }
-    
-    // 根据重置模式确定同步策略
-    if reset_mode {
-        info!("重置模式已启用，将对所有代币进行全量同步");
-        // 重置模式下，对所有代币进行全量同步
-        for token in &cfg.tokens {
-            info!("开始代币 {} 的数据库重置和重新同步操作...", token.symbol);
-            
-            // 获取该代币的集合
-            let _collections = match db_conn.collections.get(&token.symbol) {
-                Some(cols) => cols,
-                None => {
-                    error!("没有找到代币 {} 的集合", token.symbol);
-                    continue;
-                }
+
+    match &command {
+        // 快照导出/导入，完成后退出
+        Command::Snapshot(SnapshotCommand::Export { token, at_index, output }) => {
+            let token_config = match commands::select_tokens(&tokens, Some(token), Vec::new()) {
+                Some(targets) => targets[0],
+                None => return Ok(cli::EXIT_USAGE),
             };
-            
-            // 从canister_id获取 Principal
-            let canister_id = match parse_canister_id(&token.canister_id) {
-                Ok(id) => id,
-                Err(e) => {
-                    error!("{}: 解析canister ID失败: {}", token.symbol, e);
-                    continue;
-                }
+            let path = export_snapshot(&db_conn, token_config, *at_index, output.as_deref()).await?;
+            info!("快照已导出: {}", path.display());
+            return Ok(cli::EXIT_OK);
+        },
+        Command::Snapshot(SnapshotCommand::Import { file, token }) => {
+            let token_symbol = import_snapshot(&db_conn, &tokens, file, token.as_deref()).await?;
+            info!("{}: 快照已导入，正常启动即可继续增量同步", token_symbol);
+            return Ok(cli::EXIT_OK);
+        },
+        // 根据已保存的交易重建派生数据，不从链上重新获取交易，完成后退出
+        Command::Rebuild { token } => {
+            return match commands::select_tokens(&tokens, token.as_deref(), active_tokens.iter().collect()) {
+                Some(targets) => commands::rebuild(&db_conn, &targets).await,
+                None => Ok(cli::EXIT_USAGE),
             };
-            
-            // 调用reset_and_sync_all_transactions函数同步该代币的所有交易
-            match reset_and_sync_all_transactions(&agent, &canister_id, &db_conn, &token).await {
-                Ok(_) => {
-                    info!("{}: 重置和同步运行成功", token.symbol);
-                },
-                Err(e) => {
-                    error!("{}: 重置和同步失败: {}", token.symbol, e);
-                }
-            }
-        }
-        return Ok(());
-    }
-    
-    // 判断每个代币是否需要初始同步
-    let mut tokens_sync_status = HashMap::new();
-    for token in &cfg.tokens {
-        let sync_status = get_sync_status(&db_conn.sync_status_col, &token.symbol).await;
-        let needs_initial_sync = match &sync_status {
-            Ok(Some(status)) => {
-                if status.sync_mode == "incremental" && status.last_synced_index > 0 {
-                    info!("{}: 检测到有效的同步状态，上次同步索引：{}，上次同步时间：{}，将继续增量同步", 
-                          token.symbol, status.last_synced_index, 
-                          chrono::DateTime::from_timestamp(status.updated_at, 0)
-                             .unwrap_or_else(|| chrono::Utc::now())
-                             .format("%Y-%m-%d %H:%M:%S"));
-                    false
-                } else {
-                    info!("{}: 同步状态无效或为全量模式，需要进行初始同步", token.symbol);
-                    true
-                }
-            },
-            _ => {
-                info!("{}: 未找到同步状态记录，将进行初始同步", token.symbol);
-                true
-            }
-        };
-        tokens_sync_status.insert(token.symbol.clone(), (sync_status, needs_initial_sync));
+        },
+        Command::Serve if !cfg.api_server.as_ref().map(|api| api.enabled).unwrap_or(false) => {
+            error!("serve 需要在配置文件中启用 [api_server]");
+            return Ok(cli::EXIT_USAGE);
+        },
+        // 只读 API 实例：不连接 IC 网络，不执行同步
+        Command::Serve => {
+            return run_read_replica(&cli, &cfg, db_conn, cold_storage_dir, registry, log_handle).await;
+        },
+        _ => {},
     }
     
-    // 为需要初始同步的代币执行全量同步流程
-    for token in &cfg.tokens {
-        let (sync_status, needs_initial_sync) = match tokens_sync_status.get(&token.symbol) {
-            Some(status) => status,
-            None => {
-                error!("{}: 缺失同步状态信息", token.symbol);
-                continue;
-            }
-        };
-        
-        // 获取该代币的集合
-        let collections = match db_conn.collections.get(&token.symbol) {
-            Some(cols) => cols,
-            None => {
-                error!("{}: 没有找到代币的集合", token.symbol);
-                continue;
-            }
-        };
-        
-        // 解析Canister ID
-        let canister_id = parse_canister_id(&token.canister_id)?;
-        
-        // 获取代币小数位数
-        let _token_decimals = match token.decimals {
-            Some(decimals) => decimals,
-            None => {
-                match get_token_decimals(&agent, &canister_id, &token.symbol).await {
-                    Ok(decimals) => decimals,
-                    Err(e) => {
-                        error!("{}: 获取代币小数位失败: {}", token.symbol, e);
-                        continue;
-                    }
-                }
-            }
-        };
-        
-        if *needs_initial_sync {
-            // 正常模式：先同步所有交易，再统一计算余额
-            info!("{}: 阶段1：同步所有交易数据...", token.symbol);
-            
-            // 先同步归档数据
-            let _archives_result = sync_archive_transactions(
-                &agent,
-                &canister_id,
-                &collections.tx_col,
-                &collections.accounts_col,
-                &collections.balances_col,
-                &collections.total_supply_col,
-                _token_decimals,
-                false // 不计算余额
-            ).await?;
-            
-            // 同步主账本数据
-            info!("{}: 开始同步ledger交易...", token.symbol);
-            let ledger_txs = if let Ok(txs) = sync_ledger_transactions(
-                &agent,
-                &canister_id,
-                &collections.tx_col,
-                &collections.accounts_col,
-                &db_conn.sync_status_col,
-                &collections.total_supply_col,
-                &token,
-                false // 不计算余额
-            ).await {
-                txs
-            } else {
-                error!("{}: 同步ledger交易时发生错误，继续执行后续逻辑", token.symbol);
-                Vec::new()
+    // 初始化IC Agent，配置了多个节点时在节点之间自动切换
+    let (agent, ic_endpoints) = create_agent(&cfg.ic_url, cfg.ic.as_ref())?;
+    ic_endpoints.spawn_health_checks();
+
+    match &command {
+        // 清空代币数据并从链上重新同步，完成后退出
+        Command::Reset { token } => {
+            return match commands::select_tokens(&tokens, token.as_deref(), active_tokens.iter().collect()) {
+                Some(targets) => commands::reset(&agent, &db_conn, &targets).await,
+                None => Ok(cli::EXIT_USAGE),
             };
-            
-            // 阶段2：根据账户交易记录计算余额
-            info!("{}: 阶段2：根据账户交易记录统一计算余额...", token.symbol);
-            // 调用余额计算函数，传递代币配置
-            if let Err(e) = balances::calculate_all_balances(
-                &collections.accounts_col,
-                &collections.tx_col,
-                &collections.balances_col,
-                &collections.total_supply_col,
-                &collections.balance_anomalies_col,
-                &token
-            ).await {
-                error!("{}: 计算余额时出错: {}", token.symbol, e);
-            }
-            
-            // 全量余额计算完成后，记录余额计算进度
-            if let Some(last_tx) = ledger_txs.last() {
-                if let Some(index) = last_tx.index {
-                    if let Err(e) = update_balance_calculated_index(&db_conn.sync_status_col, &token.symbol, index).await {
-                        warn!("{}: 记录余额计算进度失败: {}", token.symbol, e);
-                    }
-                }
-            }
-            
-            // 设置增量同步模式
-            if !ledger_txs.is_empty() {
-                if let Some(last_tx) = ledger_txs.last() {
-                    if let Some(index) = last_tx.index {
-                        info!("{}: 设置增量同步起点为最后一笔交易索引: {}", token.symbol, index);
-                        set_incremental_mode(
-                            &db_conn.sync_status_col,
-                            &token.symbol,
-                            index,
-                            last_tx.timestamp
-                        ).await?;
-                    }
-                }
-            }
-            
-            info!("{}: 初始同步和余额计算完成", token.symbol);
-            info!("============================================");
-        } else if let Ok(Some(status)) = sync_status {
-            // 检查是否需要验证同步状态的完整性
-            info!("{}: 从断点继续同步，验证同步状态的完整性...", token.symbol);
-            
-            // 检查数据库中最新交易索引与同步状态是否一致
-            match get_latest_transaction_index(&collections.tx_col).await {
-                Ok(Some(db_latest_index)) => {
-                    if db_latest_index < status.last_synced_index {
-                        warn!("{}: 数据库最新交易索引 ({}) 小于同步状态记录的索引 ({}), 可能有数据丢失", 
-                             token.symbol, db_latest_index, status.last_synced_index);
-                        info!("{}: 将从数据库最新索引开始重新同步...", token.symbol);
-                        
-                        // 更新同步状态为数据库的最新索引
-                        if let Err(e) = set_incremental_mode(
-                            &db_conn.sync_status_col,
-                            &token.symbol,
-                            db_latest_index,
-                            status.last_synced_timestamp
-                        ).await {
-                            error!("{}: 更新同步状态失败: {}", token.symbol, e);
-                        }
-                    } else if db_latest_index > status.last_synced_index {
-                        info!("{}: 数据库最新交易索引 ({}) 大于同步状态记录的索引 ({}), 将更新同步状态", 
-                              token.symbol, db_latest_index, status.last_synced_index);
-                        
-                        // 更新同步状态为数据库的最新索引
-                        if let Err(e) = set_incremental_mode(
-                            &db_conn.sync_status_col,
-                            &token.symbol,
-                            db_latest_index,
-                            status.last_synced_timestamp
-                        ).await {
-                            error!("{}: 更新同步状态失败: {}", token.symbol, e);
-                        }
-                    } else {
-                        info!("{}: 同步状态与数据库记录一致，索引: {}", token.symbol, db_latest_index);
-                    }
+        },
+        // 比对数据库与链上的交易，完成后退出
+        Command::Verify { token, range } => {
+            return match commands::select_tokens(&tokens, Some(token), Vec::new()) {
+                Some(targets) => commands::verify(&agent, &db_conn, targets[0], *range).await,
+                None => Ok(cli::EXIT_USAGE),
+            };
+        },
+        // 分片回填：创建计划、作为工作进程处理工作单元、收尾和重试，完成后退出
+        Command::Backfill(backfill) => {
+            return match backfill {
+                BackfillCommand::Plan { token, unit_size } => match commands::select_tokens(&tokens, Some(token), Vec::new()) {
+                    Some(targets) => commands::backfill_plan(&agent, &db_conn, targets[0], *unit_size).await,
+                    None => Ok(cli::EXIT_USAGE),
                 },
-                _ => {
-                    warn!("{}: 无法获取数据库最新交易索引，将使用同步状态记录的索引", token.symbol);
-                }
-            }
-            
-            info!("{}: 跳过初始同步，直接进入增量同步模式", token.symbol);
-        }
+                BackfillCommand::Work { token, concurrency } => match commands::select_tokens(&tokens, token.as_deref(), active_tokens.iter().collect()) {
+                    Some(targets) => commands::backfill_work(&agent, &db_conn, &targets, *concurrency).await,
+                    None => Ok(cli::EXIT_USAGE),
+                },
+                BackfillCommand::Finish { token } => match commands::select_tokens(&tokens, Some(token), Vec::new()) {
+                    Some(targets) => commands::backfill_finish(&agent, &db_conn, targets[0]).await,
+                    None => Ok(cli::EXIT_USAGE),
+                },
+                BackfillCommand::Retry { token } => match commands::select_tokens(&tokens, Some(token), Vec::new()) {
+                    Some(targets) => commands::backfill_retry(&db_conn, targets[0]).await,
+                    None => Ok(cli::EXIT_USAGE),
+                },
+                // 已在连接 IC 网络之前处理
+                BackfillCommand::Status { .. } => Ok(cli::EXIT_OK),
+            };
+        },
+        _ => {},
     }
     
-    // 启动API服务器（如果配置中启用）
-    if let Some(api_config) = &cfg.api_server {
+    // 代币管理器持有全部代币的同步任务，管理接口通过它在运行时增删、暂停和恢复代币
+    let manager = Arc::new(TokenManager::new(agent.clone(), db_conn.clone(), cold_storage_dir, registry));
+
+    // API服务器配置在运行时可由配置热更新替换
+    let api_settings: Option<api_server::SharedApiConfig> = cfg.api_server.clone()
+        .map(|api_config| Arc::new(RwLock::new(api_config)));
+
+    // 启动API服务器（如果配置中启用；sync 只执行同步，不启动 API 服务器）
+    let mut api_handle = None;
+    if let Command::Sync = command {
+        info!("sync 模式不启动API服务器");
+    } else if let (Some(api_config), Some(settings)) = (&cfg.api_server, &api_settings) {
         if api_config.enabled {
             info!("配置中启用了API服务器，即将启动...");
             // 克隆数据库连接和端口到新的变量，避免借用 cfg
             let db_conn_clone = db_conn.clone();
             let port = api_config.port;
-            let tokens_clone = cfg.tokens.clone();
+            let manager_clone = manager.clone();
+            let settings = settings.clone();
+            let ic_endpoints = Some(ic_endpoints.clone());
 
             // 创建异步任务启动API服务器
-            tokio::spawn(async move {
-                let api_server = api_server::ApiServer::new(db_conn_clone, tokens_clone);
+            api_handle = Some(tokio::spawn(async move {
+                let api_server = api_server::ApiServer::new(db_conn_clone, manager_clone, settings, ic_endpoints);
                 if let Err(e) = api_server.start(port).await {
                     log::error!("API服务器启动失败: {}", e);
                 }
-            });
+            }));
 
             info!("API服务器已在后台启动，端口: {}", port);
         } else {
@@ -524,180 +468,106 @@ async fn run_application(cfg: models::Config) -> Result<(), Box<dyn Error>> {
         info!("未找到API服务器配置，不会启动API服务");
     }
     
-    // 定时增量同步
-    info!("开始实时监控多代币的新交易");
-    let mut consecutive_errors = HashMap::new();
-    let max_consecutive_errors = 5;
-    let token_rotation_delay = Duration::from_secs(1); // 不同代币同步间隔
-    
-    // 当没有代币时直接返回
-    if cfg.tokens.is_empty() {
-        error!("没有配置代币，结束同步");
-        return Ok(());
-    }
-    
-    // 初始化每个代币的错误计数
-    for token in &cfg.tokens {
-        consecutive_errors.insert(token.symbol.clone(), 0);
+    // 每个代币在独立的受监督任务中执行初始同步和增量同步，共享IC Agent和数据库连接池
+    if active_tokens.is_empty() {
+        warn!("没有需要同步的代币");
+    } else {
+        info!("开始实时监控多代币的新交易");
     }
-    
-    // 创建代币列表循环器
-    let tokens_cycle = std::iter::repeat(cfg.tokens.clone()).flatten();
-    let mut token_iter = tokens_cycle.enumerate();
-    
-    loop {
-        // 获取当前要同步的代币
-        let (index, token) = token_iter.next().unwrap();
-        
-        // 如果不是第一个代币，等待1秒再同步
-        if index > 0 {
-            tokio::time::sleep(token_rotation_delay).await;
-        }
-        
-        // 开始信息 - 使用更整洁的格式
-        info!("============================================");
-        info!("🚀 开始增量同步代币: {}", token.symbol);
-        
-        debug!("{}: 执行定时增量同步...", token.symbol);
-        
-        // 获取该代币的集合
-        let collections = match db_conn.collections.get(&token.symbol) {
-            Some(cols) => cols,
-            None => {
-                error!("{}: 没有找到代币的集合", token.symbol);
-                continue;
-            }
-        };
-        
-        // 解析Canister ID
-        let canister_id = match parse_canister_id(&token.canister_id) {
-            Ok(id) => id,
-            Err(e) => {
-                error!("{}: 解析canister ID失败: {}", token.symbol, e);
-                continue;
-            }
-        };
-        
-        // 获取代币小数位数
-        let _token_decimals = match token.decimals {
-            Some(decimals) => decimals,
+    manager.start_active_workers().await;
+
+    // 配置热更新：日志、API 服务器配置和代币列表的修改无需重启即可生效
+    reload::spawn_config_watcher(reload::ConfigReloader::new(cli.config.clone(), cfg.clone(), log_handle, manager.clone(), api_settings));
+
+    // API 服务器运行时可以随时添加代币，只在未启用 API 服务器时等待同步任务全部结束；
+    // API 服务器意外停止（例如端口绑定失败）时同样优雅停止同步任务，但以失败退出码退出
+    let service_stopped = async {
+        match api_handle.as_mut() {
+            Some(handle) => {
+                let _ = handle.await;
+                warn!("API服务器已停止");
+                true
+            },
             None => {
-                match get_token_decimals(&agent, &canister_id, &token.symbol).await {
-                    Ok(decimals) => decimals,
-                    Err(e) => {
-                        error!("{}: 获取代币小数位失败: {}", token.symbol, e);
-                        continue;
-                    }
-                }
-            }
-        };
-        
-        // 访问或初始化该代币的连续错误计数
-        let error_count = consecutive_errors.entry(token.symbol.clone()).or_insert(0);
-        
-        // 在进行增量同步前，检查是否存在尚未计算余额的已同步交易
-        if let Ok(Some(status)) = get_sync_status(&db_conn.sync_status_col, &token.symbol).await {
-            if status.last_balance_calculated_index < status.last_synced_index {
-                let pending_start = status.last_balance_calculated_index + 1;
-                let pending_end = status.last_synced_index;
-                info!("{}: 发现未计算余额的交易区间 [{}-{}]，开始补算...", token.symbol, pending_start, pending_end);
+                manager.wait_idle().await;
+                warn!("所有代币的同步任务均已停止");
+                false
+            },
+        }
+    };
+    let api_failed = tokio::select! {
+        api_failed = service_stopped => api_failed,
+        _ = shutdown::requested() => false,
+    };
 
-                match get_transactions_by_index_range(&collections.tx_col, pending_start, pending_end).await {
-                    Ok(pending_txs) if !pending_txs.is_empty() => {
-                        match calculate_incremental_balances(
-                            &pending_txs,
-                            &collections.tx_col,
-                            &collections.accounts_col,
-                            &collections.balances_col,
-                            &collections.total_supply_col,
-                            &collections.balance_anomalies_col,
-                            &token
-                        ).await {
-                            Ok((_s, _e)) => {
-                                if let Some(max_idx) = pending_txs.iter().filter_map(|tx| tx.index).max() {
-                                    if let Err(e) = update_balance_calculated_index(&db_conn.sync_status_col, &token.symbol, max_idx).await {
-                                        warn!("{}: 更新余额计算进度失败: {}", token.symbol, e);
-                                    }
-                                }
-                                info!("{}: 补算余额完成", token.symbol);
-                            },
-                            Err(e) => {
-                                error!("{}: 补算余额时发生错误: {}", token.symbol, e);
-                            }
-                        }
-                    },
-                    Ok(_) => {
-                        debug!("{}: 未找到需要补算余额的交易", token.symbol);
-                    },
-                    Err(e) => {
-                        error!("{}: 查询待补算交易失败: {}", token.symbol, e);
-                    }
-                }
-            }
+    // 优雅停止：同步任务在当前批次提交后退出并写入检查点，API 服务器不再接受新连接
+    shutdown::request();
+    manager.shutdown().await;
+    if let Some(handle) = api_handle {
+        if !handle.is_finished() && tokio::time::timeout(API_DRAIN_TIMEOUT, handle).await.is_err() {
+            warn!("API服务器在 {:?} 内未处理完进行中的请求，强制退出", API_DRAIN_TIMEOUT);
         }
-        
-        // 增量同步交易数据
-        match sync_ledger_transactions(
-            &agent,
-            &canister_id,
-            &collections.tx_col,
-            &collections.accounts_col,
-            &db_conn.sync_status_col,
-            &collections.total_supply_col,
-            &token,
-            false // 增量同步时不再实时计算余额
-        ).await {
-            Ok(new_transactions) => {
-                let tx_count = new_transactions.len();
-                // 同步完成后，只计算新交易相关账户的余额
-                if !new_transactions.is_empty() {
-                    info!("{}: 增量同步获取到 {} 笔新交易，计算相关账户余额...", token.symbol, tx_count);
-                    match calculate_incremental_balances(
-                        &new_transactions,
-                        &collections.tx_col,
-                        &collections.accounts_col,
-                        &collections.balances_col,
-                        &collections.total_supply_col,
-                        &collections.balance_anomalies_col,
-                        &token
-                    ).await {
-                        Ok((success, error)) => {
-                            info!("{}: 增量余额计算完成: 更新了 {} 个账户, 失败 {} 个账户", token.symbol, success, error);
-                            *error_count = 0; // 重置错误计数
+    }
+    info!("服务已停止");
+    Ok(if api_failed { cli::EXIT_FAILURE } else { cli::EXIT_OK })
+}
 
-                            // 余额计算成功后，更新余额计算进度
-                            if let Some(max_idx) = new_transactions.iter().filter_map(|tx| tx.index).max() {
-                                if let Err(e) = update_balance_calculated_index(&db_conn.sync_status_col, &token.symbol, max_idx).await {
-                                    warn!("{}: 更新余额计算进度失败: {}", token.symbol, e);
-                                }
-                            }
-                        },
-                        Err(e) => {
-                            error!("{}: 增量计算余额时出错: {}", token.symbol, e);
-                            *error_count += 1;
-                        }
-                    }
-                } else {
-                    debug!("{}: 没有获取到新交易，跳过余额计算", token.symbol);
-                    *error_count = 0; // 重置错误计数
-                }
-                
-                // 结束信息
-                info!("🏁 代币 {} 增量同步完成，本次同步 {} 笔新交易", token.symbol, tx_count);
-                info!("============================================");
-            },
-            Err(e) => {
-                *error_count += 1;
-                error!("{}: 定时增量同步出错 ({}/{}): {}", token.symbol, error_count, max_consecutive_errors, e);
-                
-                if *error_count >= max_consecutive_errors {
-                    error!("{}: 连续错误次数达到上限 ({}), 对该代币等待更长时间后继续...", token.symbol, max_consecutive_errors);
-                    // 发生多次连续错误时，等待更长时间再重试，但继续处理其他代币
-                    *error_count = 0; // 重置计数
-                }
-                
-                info!("============================================");
-            }
+/// 只读 API 实例：不创建 IC Agent，不执行同步和迁移，只提供查询接口
+///
+/// 代币列表和冷存储清单定期从代币注册表和共享的冷存储目录刷新，管理接口的修改操作需要在同步进程上执行。
+async fn run_read_replica(
+    cli: &Cli,
+    cfg: &models::Config,
+    db_conn: db::DbConnection,
+    cold_storage_dir: String,
+    registry: Vec<db::registry::RegistryEntry>,
+    log_handle: Option<log4rs::Handle>,
+) -> Result<u8, Box<dyn Error + Send + Sync>> {
+    let api_config = match &cfg.api_server {
+        Some(api_config) => api_config.clone(),
+        None => return Ok(cli::EXIT_USAGE),
+    };
+    info!("以只读 API 实例运行，提供 {} 个代币的查询", registry.len());
+
+    // 归档的交易只存在于同步进程写入的分段文件中，只读实例必须能访问同一个冷存储目录
+    let retained: Vec<&str> = registry.iter()
+        .filter(|e| e.token.retention.is_some())
+        .map(|e| e.token.symbol.as_str())
+        .collect();
+    if !retained.is_empty() && !tokio::fs::try_exists(&cold_storage_dir).await? {
+        error!("代币 {} 启用了冷存储保留策略，但冷存储目录 {} 不存在；只读 API 实例需要挂载同步进程的 cold_storage_dir",
+            retained.join(", "), cold_storage_dir);
+        return Ok(cli::EXIT_USAGE);
+    }
+
+    let manager = Arc::new(TokenManager::read_only(db_conn.clone(), cold_storage_dir, registry));
+    manager.spawn_registry_refresh();
+
+    let port = api_config.port;
+    let settings: api_server::SharedApiConfig = Arc::new(RwLock::new(api_config));
+    let api_server = api_server::ApiServer::new(db_conn, manager.clone(), settings.clone(), None);
+    let mut api_handle = tokio::spawn(async move {
+        if let Err(e) = api_server.start(port).await {
+            log::error!("API服务器启动失败: {}", e);
         }
+    });
+    info!("API服务器已在后台启动，端口: {}", port);
+
+    // 日志和 API 服务器配置仍可热更新，代币列表以注册表为准
+    reload::spawn_config_watcher(reload::ConfigReloader::new(cli.config.clone(), cfg.clone(), log_handle, manager, Some(settings)));
+
+    tokio::select! {
+        _ = &mut api_handle => {
+            warn!("API服务器已停止");
+            return Ok(cli::EXIT_FAILURE);
+        },
+        _ = shutdown::requested() => {},
+    }
+
+    shutdown::request();
+    if !api_handle.is_finished() && tokio::time::timeout(API_DRAIN_TIMEOUT, api_handle).await.is_err() {
+        warn!("API服务器在 {:?} 内未处理完进行中的请求，强制退出", API_DRAIN_TIMEOUT);
     }
+    info!("服务已停止");
+    Ok(cli::EXIT_OK)
 }
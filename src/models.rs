@@ -207,7 +207,7 @@ pub struct BalanceRecord {
 }
 
 // 日志配置结构体
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, PartialEq)]
 #[allow(dead_code)]
 pub struct LogConfig {
     pub level: String,            // 日志级别: error, warn, info, debug, trace
@@ -224,28 +224,27 @@ pub struct Config {
     pub mongodb_url: String,
     pub database: String,
     pub ic_url: String,
+    pub ic: Option<IcConfig>,      // IC 网络多节点配置（可选）
     pub tokens: Vec<TokenConfig>,  // 多代币配置
     pub log: Option<LogConfig>,    // 日志配置
     pub api_server: Option<ApiServerConfig>, // API服务器配置
+    pub auto_migrate: Option<bool>, // 启动时是否自动执行数据库迁移，默认开启
+    pub cold_storage_dir: Option<String>, // 冷存储分段文件根目录，默认 cold_storage
+    pub lease: Option<LeaseConfig>, // 多实例共享数据库时的同步租约配置（可选）
 }
 
 // API服务器配置结构体
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, PartialEq)]
 pub struct ApiServerConfig {
     pub enabled: bool,       // 是否启用API服务器
     pub port: u16,           // API服务器监听端口
-    #[allow(dead_code)]
     pub cors_enabled: bool,  // 是否启用CORS
-}
-
-// 命令行参数结构体
-#[derive(Debug, Clone)]
-pub struct AppArgs {
-    pub reset: bool,
+    pub admin_token: Option<String>, // 管理接口的 Bearer 令牌，未设置时管理接口不可用
+    pub max_page_size: Option<i64>, // 列表类接口单次返回的最大条数（可选，默认不额外限制）
 }
 
 /// 代币配置结构体
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct TokenConfig {
     /// 代币标识符，用于数据库中区分不同代币
     pub symbol: String,
@@ -255,6 +254,123 @@ pub struct TokenConfig {
     pub canister_id: String,
     /// 代币小数位数
     pub decimals: Option<u8>,
+    /// 部分索引的起始交易索引，设置后初始同步跳过此前的交易
+    pub start_index: Option<u64>,
+    /// 部分索引的起始时间（RFC 3339，如 "2024-05-01T00:00:00Z"），按时间定位起始交易索引；
+    /// 与 start_index 同时设置时以 start_index 为准
+    pub start_time: Option<String>,
+    /// 交易保留策略，未配置时不归档
+    pub retention: Option<RetentionConfig>,
+    /// 跟随链上最新交易时的轮询间隔（秒），默认3
+    pub poll_interval_secs: Option<u64>,
+    /// 没有新交易时轮询间隔逐步加倍的上限（秒），默认60
+    pub max_poll_interval_secs: Option<u64>,
+    /// 同步出错后的退避基数（秒），连续出错时按指数增长并加入随机抖动，默认5
+    pub error_backoff_secs: Option<u64>,
+    /// 错误退避上限（秒），默认300
+    pub max_error_backoff_secs: Option<u64>,
+    /// 同步任务退出或崩溃后的重启策略，默认 always
+    pub restart_policy: Option<RestartPolicy>,
+    /// 重启前等待时间（秒），默认10
+    pub restart_delay_secs: Option<u64>,
+    /// 最大连续重启次数，未设置时不限制；任务稳定运行 10 分钟以上后重新计数
+    pub max_restarts: Option<u32>,
+    /// 交易索引缺口扫描间隔（秒），默认3600
+    pub gap_scan_interval_secs: Option<u64>,
+}
+
+/// 代币在注册表中的状态
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenStatus {
+    /// 正常同步
+    Active,
+    /// 暂停同步，数据仍可查询
+    Paused,
+    /// 已移除（只用于记录从 config.toml 加载、通过管理接口移除的代币，避免重启后重新加入）
+    Removed,
+}
+
+impl TokenStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenStatus::Active => "active",
+            TokenStatus::Paused => "paused",
+            TokenStatus::Removed => "removed",
+        }
+    }
+}
+
+/// 代币的来源
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenSource {
+    /// config.toml
+    Config,
+    /// 管理接口
+    Api,
+}
+
+/// 同步任务重启策略
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RestartPolicy {
+    /// 任务退出后总是重启
+    #[default]
+    Always,
+    /// 仅在任务出错或崩溃时重启
+    OnFailure,
+    /// 不重启
+    Never,
+}
+
+/// 交易保留策略配置
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RetentionConfig {
+    /// MongoDB 中保留最近多少天的交易，更早的交易归档到冷存储
+    pub keep_days: u64,
+    /// 每个分段文件包含的交易数量，默认10000
+    pub segment_size: Option<u64>,
+}
+
+/// IC 网络多节点配置
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct IcConfig {
+    /// 备用节点地址，与 ic_url 一起组成节点列表，ic_url 排在最前
+    pub endpoints: Option<Vec<String>>,
+    /// 查询请求是否在健康节点之间轮询，默认 false（始终使用排在最前的健康节点）
+    pub query_round_robin: Option<bool>,
+    /// 单次请求超时（秒），超时后切换到下一个节点，默认30
+    pub request_timeout_secs: Option<u64>,
+    /// 节点健康检查间隔（秒），默认30
+    pub health_check_interval_secs: Option<u64>,
+    /// 连续失败多少次后将节点标记为不健康，默认3
+    pub max_consecutive_failures: Option<u32>,
+    /// 全局每秒最多发出的 canister 调用数，未设置时不限制
+    pub max_requests_per_sec: Option<f64>,
+    /// 每个 canister 每秒最多的调用数，未设置时不限制
+    pub max_canister_requests_per_sec: Option<f64>,
+    /// 令牌桶容量（允许的突发调用数），默认等于每秒调用数
+    pub burst: Option<f64>,
+    /// 单个 canister 连续失败多少次后熔断，默认5
+    pub circuit_failure_threshold: Option<u32>,
+    /// 熔断时间（秒），之后放行一次试探调用，默认30
+    pub circuit_open_secs: Option<u64>,
+}
+
+/// 同步租约配置
+///
+/// 多个实例连接同一个数据库时，每个代币同一时刻只有持有租约的实例执行同步，其余实例作为热备。
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct LeaseConfig {
+    /// 是否启用租约，默认 true
+    pub enabled: Option<bool>,
+    /// 实例标识，默认使用主机名和进程号；固定后重启的实例可以立即取回自己的租约
+    pub instance_id: Option<String>,
+    /// 租约有效期（秒），持有者超过该时间未续期时由其他实例接管，默认30
+    pub ttl_secs: Option<u64>,
+    /// 续期和热备实例尝试接管的间隔（秒），默认10，必须小于 ttl_secs
+    pub renew_interval_secs: Option<u64>,
 }
 
 /// 余额异常记录
@@ -0,0 +1,222 @@
+/**
+ * 文件描述: 游标分页模块，为列表接口提供基于排序键的分页（keyset pagination）
+ * 功能概述:
+ * - 游标记录上一页第一条或最后一条记录的排序键（交易索引、账户或金额 + 交易索引），
+ *   下一页从该键之后继续查询，深度翻页不需要跳过前面的记录，翻页期间新增的交易也不会导致重复或遗漏
+ * - 游标对客户端不透明（JSON 的十六进制编码），包含所属列表（scope），在其他接口或排序方式下使用时返回 400
+ * - 每次多查询一条记录判断是否还有下一页，响应中返回 next / prev 游标和链接
+ * - skip 分页保留用于兼容，不能与游标同时使用
+ *
+ * 主要组件:
+ * - Direction枚举: 游标方向（向后翻页 next / 向前翻页 prev）
+ * - Cursor结构体: 游标内容，encode / decode 编解码
+ * - keyset_filter函数: 根据排序字段和游标键生成查询条件
+ * - query_sort函数: 按游标方向得到实际的查询排序（向前翻页时反转）
+ * - PageInfo结构体: 响应中的分页信息
+ * - paginate函数: 根据多查询的一条记录生成当前页和分页信息
+ */
+
+use std::error::Error;
+use mongodb::bson::{doc, Bson, Document};
+use serde::{Deserialize, Serialize};
+use crate::utils::create_error;
+
+/// 游标方向
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    /// 查询排序键之后的记录
+    #[serde(rename = "n")]
+    Next,
+    /// 查询排序键之前的记录
+    #[serde(rename = "p")]
+    Prev,
+}
+
+/// 游标内容
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cursor {
+    /// 所属列表，例如 latest、accounts、search:amount_desc
+    #[serde(rename = "s")]
+    pub scope: String,
+    #[serde(rename = "d")]
+    pub direction: Direction,
+    /// 交易索引
+    #[serde(rename = "i", default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<i64>,
+    /// 字符串排序键：账户，或可排序金额
+    #[serde(rename = "k", default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+}
+
+impl Cursor {
+    pub fn new(scope: &str, direction: Direction, index: Option<i64>, key: Option<String>) -> Self {
+        Cursor { scope: scope.to_string(), direction, index, key }
+    }
+
+    /// 编码为不透明的游标字符串
+    pub fn encode(&self) -> String {
+        hex::encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    /// 解码游标字符串，并检查游标是否属于当前列表
+    pub fn decode(token: &str, scope: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let cursor: Cursor = hex::decode(token.trim()).ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or_else(|| create_error("无效的分页游标"))?;
+        if cursor.scope != scope {
+            return Err(create_error("分页游标不属于当前列表或排序方式，请从第一页重新查询"));
+        }
+        Ok(cursor)
+    }
+
+    /// 游标中与排序字段对应的键值
+    fn values(&self, fields: &[(&str, i32)]) -> Result<Vec<Bson>, Box<dyn Error + Send + Sync>> {
+        fields.iter()
+            .map(|(field, _)| match *field {
+                "index" => self.index.map(Bson::Int64),
+                _ => self.key.clone().map(Bson::String),
+            }.ok_or_else(|| create_error("无效的分页游标")))
+            .collect()
+    }
+}
+
+/// 解析请求中的游标，同时指定 skip 时返回错误
+pub fn parse_cursor(
+    cursor: Option<&str>,
+    skip: Option<i64>,
+    scope: &str,
+) -> Result<Option<Cursor>, Box<dyn Error + Send + Sync>> {
+    match cursor.filter(|c| !c.is_empty()) {
+        Some(_) if skip.unwrap_or(0) > 0 => Err(create_error("cursor 和 skip 不能同时使用")),
+        Some(token) => Cursor::decode(token, scope).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// 根据排序字段（字段名, 1 或 -1）和游标生成查询条件：
+/// 向后翻页查询排在游标之后的记录，向前翻页查询排在游标之前的记录
+pub fn keyset_filter(fields: &[(&str, i32)], cursor: &Cursor) -> Result<Document, Box<dyn Error + Send + Sync>> {
+    let values = cursor.values(fields)?;
+    let mut branches = Vec::with_capacity(fields.len());
+    for (position, (field, order)) in fields.iter().enumerate() {
+        let ascending = (*order > 0) == (cursor.direction == Direction::Next);
+        let op = if ascending { "$gt" } else { "$lt" };
+        let mut branch = Document::new();
+        for ((prefix_field, _), value) in fields.iter().zip(values.iter()).take(position) {
+            branch.insert(*prefix_field, value.clone());
+        }
+        branch.insert(*field, doc! { op: values[position].clone() });
+        branches.push(branch);
+    }
+    Ok(match branches.len() {
+        1 => branches.remove(0),
+        _ => doc! { "$or": branches },
+    })
+}
+
+/// 实际的查询排序：向前翻页时反转排序方向，结果在 paginate 中恢复原顺序
+pub fn query_sort(fields: &[(&str, i32)], cursor: Option<&Cursor>) -> Document {
+    let reverse = cursor.is_some_and(|c| c.direction == Direction::Prev);
+    let mut sort = Document::new();
+    for (field, order) in fields {
+        sort.insert(*field, if reverse { -order } else { *order });
+    }
+    sort
+}
+
+/// 响应中的分页信息
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PageInfo {
+    pub limit: i64,
+    /// 下一页游标，没有更多记录时为 null
+    pub next_cursor: Option<String>,
+    /// 上一页游标，当前为第一页时为 null
+    pub prev_cursor: Option<String>,
+    /// 下一页链接（GET 接口）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
+    /// 上一页链接（GET 接口）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prev: Option<String>,
+}
+
+impl PageInfo {
+    /// 生成 next / prev 链接，`base` 为不含 cursor 和 skip 的请求路径和查询参数
+    pub fn with_links(mut self, base: &str) -> Self {
+        let separator = if base.contains('?') { '&' } else { '?' };
+        self.next = self.next_cursor.as_ref().map(|cursor| format!("{}{}cursor={}", base, separator, cursor));
+        self.prev = self.prev_cursor.as_ref().map(|cursor| format!("{}{}cursor={}", base, separator, cursor));
+        self
+    }
+}
+
+/// 根据查询结果生成当前页和分页信息
+///
+/// `items` 按 query_sort 的顺序查询，最多 limit + 1 条；`key_of` 返回记录的 (交易索引, 字符串排序键)。
+/// `skipped` 表示使用了 skip 分页（此时同样存在上一页，但只能通过 skip 返回）。
+pub fn paginate<T>(
+    mut items: Vec<T>,
+    limit: i64,
+    cursor: Option<&Cursor>,
+    skipped: bool,
+    scope: &str,
+    key_of: impl Fn(&T) -> (Option<i64>, Option<String>),
+) -> (Vec<T>, PageInfo) {
+    let backward = cursor.is_some_and(|c| c.direction == Direction::Prev);
+    let has_extra = items.len() as i64 > limit;
+    items.truncate(limit.max(0) as usize);
+    if backward {
+        items.reverse();
+    }
+
+    let has_next = if backward { true } else { has_extra };
+    let has_prev = if backward { has_extra } else { cursor.is_some() || skipped };
+    let make_cursor = |item: &T, direction: Direction| {
+        let (index, key) = key_of(item);
+        Cursor::new(scope, direction, index, key).encode()
+    };
+
+    let info = PageInfo {
+        limit,
+        next_cursor: items.last().filter(|_| has_next).map(|item| make_cursor(item, Direction::Next)),
+        prev_cursor: items.first().filter(|_| has_prev).map(|item| make_cursor(item, Direction::Prev)),
+        next: None,
+        prev: None,
+    };
+    (items, info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_round_trips_within_its_scope() {
+        let cursor = Cursor::new("search:amount_desc", Direction::Prev, Some(42), Some("0007".to_string()));
+        assert_eq!(Cursor::decode(&cursor.encode(), "search:amount_desc").unwrap(), cursor);
+    }
+
+    #[test]
+    fn cursor_from_another_list_is_rejected() {
+        let token = Cursor::new("latest", Direction::Next, Some(10), None).encode();
+        assert!(Cursor::decode(&token, "accounts").is_err());
+        assert!(Cursor::decode(&token, "search:amount_desc").is_err());
+        assert!(Cursor::decode("not-a-cursor", "latest").is_err());
+        assert!(parse_cursor(Some(&token), Some(20), "latest").is_err());
+    }
+
+    #[test]
+    fn keyset_filter_on_single_field() {
+        let next = Cursor::new("latest", Direction::Next, Some(100), None);
+        assert_eq!(keyset_filter(&[("index", -1)], &next).unwrap(), doc! { "index": { "$lt": 100i64 } });
+
+        let prev = Cursor::new("latest", Direction::Prev, Some(100), None);
+        assert_eq!(keyset_filter(&[("index", -1)], &prev).unwrap(), doc! { "index": { "$gt": 100i64 } });
+    }
+
+    #[test]
+    fn keyset_filter_requires_cursor_keys_for_every_field() {
+        let cursor = Cursor::new("search:amount_desc", Direction::Next, Some(7), None);
+        assert!(keyset_filter(&[("amount_sort", -1), ("index", -1)], &cursor).is_err());
+    }
+}
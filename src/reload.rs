@@ -0,0 +1,234 @@
+/**
+ * 文件描述: 配置热更新模块，在不重启服务的情况下应用配置文件（默认 config.toml，可通过 --config 指定）的修改
+ * 功能概述:
+ * - 定期检查配置文件的修改时间，收到 SIGHUP 信号时立即重新加载
+ * - 运行时生效的修改: 日志级别与输出、API 服务器的 CORS 开关 / 分页上限 / 管理令牌、
+ *   代币配置（轮询间隔、退避、重启策略等）、新增和删除的代币
+ * - 拒绝不安全的修改（MongoDB 地址、数据库名），保留原值并记录错误日志
+ * - 需要重启才能生效的修改（IC 网络地址和节点配置、冷存储目录、租约配置、API 服务器端口和开关等）只记录警告
+ *
+ * 主要组件:
+ * - ConfigReloader结构体: 持有当前生效的配置，比较并应用新配置
+ * - spawn_config_watcher函数: 启动监视配置文件和 SIGHUP 信号的后台任务
+ */
+
+use std::sync::Arc;
+use std::time::SystemTime;
+use log::{info, warn, error};
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
+use crate::api_server::SharedApiConfig;
+use crate::config::load_config;
+use crate::models::Config;
+use crate::sync::manager::TokenManager;
+
+/// 检查配置文件修改时间的间隔
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// 检测到修改后等待编辑器写完文件的时间
+const SETTLE_DELAY: Duration = Duration::from_millis(500);
+
+/// 配置热更新器
+pub struct ConfigReloader {
+    /// 配置文件路径
+    config_path: String,
+    /// 当前生效的配置
+    current: Config,
+    log_handle: Option<log4rs::Handle>,
+    manager: Arc<TokenManager>,
+    api_settings: Option<SharedApiConfig>,
+}
+
+impl ConfigReloader {
+    pub fn new(
+        config_path: String,
+        current: Config,
+        log_handle: Option<log4rs::Handle>,
+        manager: Arc<TokenManager>,
+        api_settings: Option<SharedApiConfig>,
+    ) -> Self {
+        ConfigReloader { config_path, current, log_handle, manager, api_settings }
+    }
+
+    /// 重新加载配置文件并应用可以在运行时生效的修改
+    ///
+    /// 配置文件无法解析时保持当前配置不变。
+    pub async fn reload(&mut self) {
+        let mut updated = match load_config(&self.config_path).await {
+            Ok(cfg) => cfg,
+            Err(e) => {
+                error!("重新加载配置失败，继续使用当前配置: {}", e);
+                return;
+            }
+        };
+
+        self.reject_unsafe_changes(&mut updated);
+        self.keep_restart_only_settings(&mut updated);
+
+        if updated.log != self.current.log {
+            self.apply_log_config(&updated);
+        }
+        if updated.api_server != self.current.api_server {
+            self.apply_api_config(&updated);
+        }
+        if updated.tokens != self.current.tokens {
+            info!("代币配置已变化，开始应用...");
+            self.manager.apply_config_tokens(&updated.tokens).await;
+        }
+
+        self.current = updated;
+        info!("配置已重新加载");
+    }
+
+    /// 数据库连接不能在运行时切换，保留原值
+    fn reject_unsafe_changes(&self, updated: &mut Config) {
+        if updated.mongodb_url != self.current.mongodb_url {
+            error!("拒绝修改 mongodb_url：运行中的服务不能切换数据库连接，已忽略该项修改，重启服务后生效");
+            updated.mongodb_url = self.current.mongodb_url.clone();
+        }
+        if updated.database != self.current.database {
+            error!("拒绝修改 database ({} -> {})：运行中的服务不能切换数据库，已忽略该项修改，重启服务后生效",
+                self.current.database, updated.database);
+            updated.database = self.current.database.clone();
+        }
+    }
+
+    /// 只在启动时读取的配置项，修改后需要重启才能生效，本次运行保留原值
+    fn keep_restart_only_settings(&self, updated: &mut Config) {
+        if updated.ic_url != self.current.ic_url {
+            warn!("ic_url 的修改需要重启服务后生效");
+            updated.ic_url = self.current.ic_url.clone();
+        }
+        if updated.ic != self.current.ic {
+            warn!("[ic] 节点配置的修改需要重启服务后生效");
+            updated.ic = self.current.ic.clone();
+        }
+        if updated.cold_storage_dir != self.current.cold_storage_dir {
+            warn!("cold_storage_dir 的修改需要重启服务后生效");
+            updated.cold_storage_dir = self.current.cold_storage_dir.clone();
+        }
+        if updated.lease != self.current.lease {
+            warn!("[lease] 租约配置的修改需要重启服务后生效");
+            updated.lease = self.current.lease.clone();
+        }
+        if updated.auto_migrate != self.current.auto_migrate {
+            warn!("auto_migrate 的修改需要重启服务后生效");
+            updated.auto_migrate = self.current.auto_migrate;
+        }
+        match (&self.current.api_server, &mut updated.api_server) {
+            (Some(current), Some(api)) => {
+                if api.enabled != current.enabled || api.port != current.port {
+                    warn!("api_server.enabled / api_server.port 的修改需要重启服务后生效");
+                    api.enabled = current.enabled;
+                    api.port = current.port;
+                }
+            },
+            (None, None) => {},
+            _ => {
+                warn!("添加或删除 [api_server] 配置需要重启服务后生效");
+                updated.api_server = self.current.api_server.clone();
+            },
+        }
+    }
+
+    /// 替换日志配置
+    fn apply_log_config(&self, updated: &Config) {
+        let handle = match &self.log_handle {
+            Some(handle) => handle,
+            None => {
+                warn!("日志系统未初始化，日志配置的修改需要重启服务后生效");
+                return;
+            }
+        };
+        match crate::build_log_config(updated) {
+            Ok(log_config) => {
+                handle.set_config(log_config);
+                info!("已应用新的日志配置");
+            },
+            Err(e) => error!("日志配置无效，继续使用当前日志配置: {}", e),
+        }
+    }
+
+    /// 更新 API 服务器配置，之后的请求立即使用新配置
+    fn apply_api_config(&self, updated: &Config) {
+        if let (Some(settings), Some(api)) = (&self.api_settings, &updated.api_server) {
+            *settings.write().unwrap() = api.clone();
+            info!("已应用新的 API 服务器配置 (CORS: {}, 分页上限: {:?}, 管理接口: {})",
+                api.cors_enabled,
+                api.max_page_size,
+                if api.admin_token.is_some() { "启用" } else { "禁用" });
+        }
+    }
+}
+
+/// 配置文件的修改时间，文件不存在或无法读取时返回 None
+fn config_modified_time(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// 启动配置监视任务：配置文件修改或收到 SIGHUP 时重新加载
+pub fn spawn_config_watcher(mut reloader: ConfigReloader) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut hangup = Hangup::new();
+        let path = reloader.config_path.clone();
+        let mut last_modified = config_modified_time(&path);
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        info!("已启动配置热更新，监视 {}（也可发送 SIGHUP 立即重新加载）", path);
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    let modified = config_modified_time(&path);
+                    if modified == last_modified {
+                        continue;
+                    }
+                    tokio::time::sleep(SETTLE_DELAY).await;
+                    last_modified = config_modified_time(&path);
+                    info!("检测到配置文件 {} 已修改，重新加载配置...", path);
+                },
+                _ = hangup.recv() => {
+                    last_modified = config_modified_time(&path);
+                    info!("收到 SIGHUP 信号，重新加载配置...");
+                },
+            }
+            reloader.reload().await;
+        }
+    })
+}
+
+/// SIGHUP 信号监听，非 Unix 平台上永不触发
+struct Hangup {
+    #[cfg(unix)]
+    signal: Option<tokio::signal::unix::Signal>,
+}
+
+impl Hangup {
+    fn new() -> Self {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+            let signal = match signal(SignalKind::hangup()) {
+                Ok(signal) => Some(signal),
+                Err(e) => {
+                    warn!("无法监听 SIGHUP 信号，只通过文件修改时间检测配置变化: {}", e);
+                    None
+                }
+            };
+            Hangup { signal }
+        }
+        #[cfg(not(unix))]
+        {
+            Hangup {}
+        }
+    }
+
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        if let Some(signal) = &mut self.signal {
+            if signal.recv().await.is_some() {
+                return;
+            }
+            self.signal = None;
+        }
+        std::future::pending::<()>().await
+    }
+}
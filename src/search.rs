@@ -0,0 +1,818 @@
+/**
+ * 文件描述: 高级搜索模块，将类型化的搜索请求校验并转换为安全的数据库查询
+ * 功能概述:
+ * - 搜索请求只支持固定的条件（交易类型、账户、金额、时间、备注、索引区间），不接受原始 MongoDB 查询
+ * - 条件在执行前校验，无效的账户、金额、备注或区间返回 400
+ * - 不含账户条件时直接按 index 或 amount_sort 索引查询交易集合；按索引排序时已归档的区间从冷存储分块读取，
+ *   按金额排序时结果不含已归档的交易，响应中标记 partial
+ * - 含账户条件时沿账户-交易边集合的 (account, index, role) 索引按交易索引顺序分块读取，
+ *   多个账户条件取交集，再按其余条件过滤交易；单次搜索最多扫描 MAX_SCANNED_INDICES 个交易索引。
+ *   交易通过 find_transactions_by_indices 读取（包含冷存储中已归档的交易），其余条件在内存中判断
+ * - 支持游标分页（见 pagination）：按索引排序时以交易索引为游标，按金额排序时以 (amount_sort, index) 为游标，
+ *   游标与排序方式绑定
+ *
+ * 主要组件:
+ * - SearchRequest结构体: POST /api/search 的请求体
+ * - SearchSort枚举: 排序方式
+ * - TimeValue枚举: 时间条件（纳秒时间戳或 RFC 3339）
+ * - SearchPlan结构体: 校验后的查询计划
+ * - SearchPlan::build函数: 校验请求并生成查询计划
+ * - TransactionMatcher结构体: 交易条件的内存判断，用于含账户条件的搜索
+ * - execute函数: 执行查询计划
+ */
+
+use std::collections::HashSet;
+use std::error::Error;
+use futures::stream::TryStreamExt;
+use mongodb::Collection;
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::options::FindOptions;
+use serde::{Deserialize, Serialize};
+use log::debug;
+use crate::api;
+use crate::db::TokenCollections;
+use crate::db::account_transactions::{ROLE_FROM, ROLE_SPENDER, ROLE_TO};
+use crate::db::cold_storage::cold_storage_for;
+use crate::db::transactions::{find_transactions_by_indices, get_transaction_documents_by_index_range};
+use crate::models::Transaction;
+use crate::pagination::{self, Cursor, Direction, PageInfo};
+use crate::utils::{create_error, get_transaction_amount, parse_account, parse_time, to_sortable_amount};
+
+/// 支持的交易类型
+pub(crate) const TRANSACTION_KINDS: &[&str] = &["transfer", "mint", "burn", "approve"];
+/// 默认返回条数
+const DEFAULT_LIMIT: i64 = 50;
+/// 含账户条件时每次从边集合读取的交易索引数量
+const ACCOUNT_CHUNK_SIZE: usize = 500;
+/// 含账户条件或读取冷存储时单次搜索最多扫描的交易索引数量，超过后返回已找到的结果并标记 truncated
+const MAX_SCANNED_INDICES: usize = 50_000;
+/// 不含账户条件时每次从冷存储读取的交易索引数量
+const ARCHIVE_CHUNK_SIZE: u64 = 1000;
+
+/// 高级搜索请求体，所有字段均为可选，多个条件同时满足
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SearchRequest {
+    /// 代币符号，默认使用查询参数中的 token 或配置的第一个代币
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    /// 交易类型: transfer、mint、burn、approve
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kinds: Option<Vec<String>>,
+    /// 以任意角色参与交易的账户
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+    /// 发送方（转账、销毁、授权的 from）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    /// 接收方（转账、铸币的 to）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+    /// 授权代理（transferFrom、授权销毁的 spender，或 approve 的被授权方）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spender: Option<String>,
+    /// 最小金额（含），代币单位
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_amount: Option<String>,
+    /// 最大金额（含），代币单位
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_amount: Option<String>,
+    /// 起始时间（含），纳秒时间戳或 RFC 3339 字符串
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from_time: Option<TimeValue>,
+    /// 结束时间（含），纳秒时间戳或 RFC 3339 字符串
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to_time: Option<TimeValue>,
+    /// 备注，十六进制
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+    /// 备注，UTF-8 文本（与 memo 二选一）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo_text: Option<String>,
+    /// 起始交易索引（含）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_index: Option<u64>,
+    /// 结束交易索引（含）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_index: Option<u64>,
+    /// 排序方式: index_desc（默认）、index_asc、amount_desc、amount_asc
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort: Option<String>,
+    /// 返回结果的最大条目数，默认 50
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<i64>,
+    /// 跳过的条目数，默认 0（保留用于兼容，建议使用 cursor）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skip: Option<i64>,
+    /// 分页游标，取自上一次响应的 page.next_cursor / page.prev_cursor（不能与 skip 同时使用）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+}
+
+/// 时间条件：纳秒时间戳（数字）或 RFC 3339 字符串
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum TimeValue {
+    Nanos(u64),
+    Text(String),
+}
+
+impl TimeValue {
+    fn to_nanos(&self, field: &str) -> Result<u64, Box<dyn Error + Send + Sync>> {
+        match self {
+            TimeValue::Nanos(nanos) => Ok(*nanos),
+            TimeValue::Text(text) => parse_time(text).map_err(|e| create_error(&format!("{} {}", field, e))),
+        }
+    }
+}
+
+/// 排序方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchSort {
+    IndexDesc,
+    IndexAsc,
+    AmountDesc,
+    AmountAsc,
+}
+
+impl SearchSort {
+    fn parse(value: Option<&str>) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        match value {
+            None | Some("index_desc") => Ok(SearchSort::IndexDesc),
+            Some("index_asc") => Ok(SearchSort::IndexAsc),
+            Some("amount_desc") => Ok(SearchSort::AmountDesc),
+            Some("amount_asc") => Ok(SearchSort::AmountAsc),
+            Some(other) => Err(create_error(&format!(
+                "不支持的排序方式: {}，可选值: index_desc, index_asc, amount_desc, amount_asc", other
+            ))),
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            SearchSort::IndexDesc => "index_desc",
+            SearchSort::IndexAsc => "index_asc",
+            SearchSort::AmountDesc => "amount_desc",
+            SearchSort::AmountAsc => "amount_asc",
+        }
+    }
+
+    /// 交易集合上的排序字段，金额相同时按索引降序，保证排序稳定
+    fn fields(self) -> &'static [(&'static str, i32)] {
+        match self {
+            SearchSort::IndexDesc => &[("index", -1)],
+            SearchSort::IndexAsc => &[("index", 1)],
+            SearchSort::AmountDesc => &[("amount_sort", -1), ("index", -1)],
+            SearchSort::AmountAsc => &[("amount_sort", 1), ("index", -1)],
+        }
+    }
+
+    /// 游标所属列表，不同排序方式的游标不能混用
+    fn scope(self) -> String {
+        format!("search:{}", self.as_str())
+    }
+
+    fn is_by_index(self) -> bool {
+        matches!(self, SearchSort::IndexDesc | SearchSort::IndexAsc)
+    }
+}
+
+/// 账户条件：账户和角色，角色为 None 时匹配任意角色
+#[derive(Debug, Clone)]
+struct AccountCondition {
+    account: String,
+    role: Option<&'static str>,
+}
+
+impl AccountCondition {
+    /// 边集合上的过滤条件
+    fn edge_filter(&self, index_range: &Option<Document>) -> Document {
+        let mut filter = doc! { "account": &self.account };
+        if let Some(role) = self.role {
+            filter.insert("role", role);
+        }
+        if let Some(range) = index_range {
+            filter.insert("index", range.clone());
+        }
+        filter
+    }
+}
+
+/// 校验后的查询计划
+#[derive(Debug, Clone)]
+pub struct SearchPlan {
+    /// 交易集合上的过滤条件（不含账户条件）
+    filter: Document,
+    /// 与 filter 相同的条件，在内存中判断（含账户条件时使用）
+    matcher: TransactionMatcher,
+    /// 交易索引区间条件，账户条件同样使用
+    index_range: Option<Document>,
+    /// 时间区间（纳秒），含账户条件时转换为交易索引区间后用于边集合
+    time_range: (Option<u64>, Option<u64>),
+    accounts: Vec<AccountCondition>,
+    sort: SearchSort,
+    cursor: Option<Cursor>,
+    pub limit: i64,
+    pub skip: i64,
+}
+
+/// 搜索结果
+#[derive(Debug)]
+pub struct SearchResult {
+    pub transactions: Vec<Transaction>,
+    /// 达到扫描上限，可能还有更多匹配的交易
+    pub truncated: bool,
+    /// 按金额排序时查询区间包含冷存储中已归档的交易，结果不含这些交易
+    pub partial: bool,
+    pub page: PageInfo,
+}
+
+impl SearchPlan {
+    /// 校验搜索请求并生成查询计划，`decimals` 用于将金额换算为最小单位
+    pub fn build(request: &SearchRequest, decimals: u8) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut conditions: Vec<Document> = Vec::new();
+        let mut matcher = TransactionMatcher::default();
+
+        if let Some(kinds) = &request.kinds {
+            if kinds.is_empty() {
+                return Err(create_error("kinds 不能为空数组"));
+            }
+            if let Some(kind) = kinds.iter().find(|kind| !TRANSACTION_KINDS.contains(&kind.as_str())) {
+                return Err(create_error(&format!("不支持的交易类型: {}，可选值: {}", kind, TRANSACTION_KINDS.join(", "))));
+            }
+            conditions.push(doc! { "kind": { "$in": kinds.clone() } });
+            matcher.kinds = Some(kinds.clone());
+        }
+
+        if let Some(amount_filter) = api::build_amount_filter(request.min_amount.as_deref(), request.max_amount.as_deref(), decimals)? {
+            if let Ok(range) = amount_filter.get_document("amount_sort") {
+                matcher.min_amount = range.get_str("$gte").ok().map(str::to_string);
+                matcher.max_amount = range.get_str("$lte").ok().map(str::to_string);
+            }
+            conditions.push(amount_filter);
+        }
+
+        let from_time = request.from_time.as_ref().map(|t| t.to_nanos("from_time")).transpose()?;
+        let to_time = request.to_time.as_ref().map(|t| t.to_nanos("to_time")).transpose()?;
+        if let (Some(from_time), Some(to_time)) = (from_time, to_time) {
+            if from_time > to_time {
+                return Err(create_error(&format!("from_time ({}) 不能大于 to_time ({})", from_time, to_time)));
+            }
+        }
+        let mut time_range = Document::new();
+        if let Some(from_time) = from_time {
+            time_range.insert("$gte", to_i64(from_time, "from_time")?);
+        }
+        if let Some(to_time) = to_time {
+            time_range.insert("$lte", to_i64(to_time, "to_time")?);
+        }
+        if !time_range.is_empty() {
+            conditions.push(doc! { "timestamp": time_range });
+        }
+        matcher.time_range = (from_time, to_time);
+
+        if let Some(memo) = parse_memo(request.memo.as_deref(), request.memo_text.as_deref())? {
+            // 备注按字节数组保存在各交易类型的子文档中
+            matcher.memo = Some(memo.clone());
+            let memo = Bson::Array(memo.into_iter().map(|b| Bson::Int32(b as i32)).collect());
+            conditions.push(doc! { "$or": TRANSACTION_KINDS.iter()
+                .map(|kind| doc! { format!("{}.memo", kind): memo.clone() })
+                .collect::<Vec<Document>>() });
+        }
+
+        if let (Some(start), Some(end)) = (request.start_index, request.end_index) {
+            if start > end {
+                return Err(create_error(&format!("start_index ({}) 不能大于 end_index ({})", start, end)));
+            }
+        }
+        let mut range = Document::new();
+        if let Some(start) = request.start_index {
+            range.insert("$gte", to_i64(start, "start_index")?);
+        }
+        if let Some(end) = request.end_index {
+            range.insert("$lte", to_i64(end, "end_index")?);
+        }
+        let index_range = if range.is_empty() { None } else { Some(range) };
+        if let Some(range) = &index_range {
+            conditions.push(doc! { "index": range.clone() });
+        }
+
+        let mut accounts = Vec::new();
+        for (value, role, field) in [
+            (&request.account, None, "account"),
+            (&request.from, Some(ROLE_FROM), "from"),
+            (&request.to, Some(ROLE_TO), "to"),
+            (&request.spender, Some(ROLE_SPENDER), "spender"),
+        ] {
+            if let Some(value) = value {
+                let account = parse_account(value.trim())
+                    .map_err(|e| create_error(&format!("{} 无效: {}", field, e)))?;
+                accounts.push(AccountCondition { account: account.to_string(), role });
+            }
+        }
+
+        let sort = SearchSort::parse(request.sort.as_deref())?;
+        if !accounts.is_empty() && !sort.is_by_index() {
+            return Err(create_error("按账户搜索时只支持 index_desc 或 index_asc 排序"));
+        }
+
+        let limit = request.limit.unwrap_or(DEFAULT_LIMIT);
+        if limit <= 0 {
+            return Err(create_error("limit 必须大于 0"));
+        }
+        let skip = request.skip.unwrap_or(0);
+        if skip < 0 {
+            return Err(create_error("skip 不能小于 0"));
+        }
+        let cursor = pagination::parse_cursor(request.cursor.as_deref(), request.skip, &sort.scope())?;
+
+        let filter = match conditions.len() {
+            0 => Document::new(),
+            1 => conditions.remove(0),
+            _ => doc! { "$and": conditions },
+        };
+        Ok(SearchPlan { filter, matcher, index_range, time_range: (from_time, to_time), accounts, sort, cursor, limit, skip })
+    }
+}
+
+/// 交易条件的内存判断，与 SearchPlan::filter 中的条件一致（索引区间由边集合查询处理）
+#[derive(Debug, Clone, Default)]
+struct TransactionMatcher {
+    kinds: Option<Vec<String>>,
+    /// 可排序金额的下限和上限（含）
+    min_amount: Option<String>,
+    max_amount: Option<String>,
+    /// 时间区间（纳秒，含两端）
+    time_range: (Option<u64>, Option<u64>),
+    memo: Option<Vec<u8>>,
+}
+
+impl TransactionMatcher {
+    fn matches(&self, tx_doc: &Document) -> bool {
+        let kind = tx_doc.get_str("kind").unwrap_or_default();
+        if self.kinds.as_ref().is_some_and(|kinds| !kinds.iter().any(|k| k == kind)) {
+            return false;
+        }
+
+        if self.min_amount.is_some() || self.max_amount.is_some() {
+            let Ok(amount) = tx_doc.get_str("amount_sort") else {
+                return false;
+            };
+            if self.min_amount.as_deref().is_some_and(|min| amount < min)
+                || self.max_amount.as_deref().is_some_and(|max| amount > max) {
+                return false;
+            }
+        }
+
+        let (from_time, to_time) = self.time_range;
+        if from_time.is_some() || to_time.is_some() {
+            let Some(timestamp) = tx_doc.get_i64("timestamp").ok().and_then(|t| u64::try_from(t).ok()) else {
+                return false;
+            };
+            if from_time.is_some_and(|from| timestamp < from) || to_time.is_some_and(|to| timestamp > to) {
+                return false;
+            }
+        }
+
+        if let Some(memo) = &self.memo {
+            let stored: Option<Vec<u8>> = tx_doc.get_document(kind).ok()
+                .and_then(|detail| detail.get_array("memo").ok())
+                .map(|bytes| bytes.iter().filter_map(|b| b.as_i32().map(|b| b as u8).or_else(|| b.as_i64().map(|b| b as u8))).collect());
+            if stored.as_ref() != Some(memo) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+fn to_i64(value: u64, field: &str) -> Result<i64, Box<dyn Error + Send + Sync>> {
+    i64::try_from(value).map_err(|_| create_error(&format!("{} 超出范围: {}", field, value)))
+}
+
+/// 解析备注条件：十六进制或 UTF-8 文本，二者只能指定一个
+fn parse_memo(memo: Option<&str>, memo_text: Option<&str>) -> Result<Option<Vec<u8>>, Box<dyn Error + Send + Sync>> {
+    match (memo, memo_text) {
+        (Some(_), Some(_)) => Err(create_error("memo 和 memo_text 只能指定一个")),
+        (Some(hex_memo), None) => hex::decode(hex_memo.trim().trim_start_matches("0x"))
+            .map(Some)
+            .map_err(|e| create_error(&format!("memo 不是有效的十六进制: {}", e))),
+        (None, Some(text)) => Ok(Some(text.as_bytes().to_vec())),
+        (None, None) => Ok(None),
+    }
+}
+
+/// 执行查询计划
+pub async fn execute(
+    collections: &TokenCollections,
+    plan: &SearchPlan,
+) -> Result<SearchResult, Box<dyn Error + Send + Sync>> {
+    // 多查询一条用于判断是否还有下一页
+    let (transactions, truncated, partial) = if plan.accounts.is_empty() {
+        search_transactions(&collections.tx_col, plan).await?
+    } else {
+        let (transactions, truncated) = search_by_accounts(&collections.account_tx_col, &collections.tx_col, plan).await?;
+        (transactions, truncated, false)
+    };
+
+    let by_amount = !plan.sort.is_by_index();
+    let scope = plan.sort.scope();
+    let (transactions, mut page) = pagination::paginate(
+        transactions, plan.limit, plan.cursor.as_ref(), plan.skip > 0, &scope,
+        |tx| (
+            tx.index.map(|index| index as i64),
+            get_transaction_amount(tx).filter(|_| by_amount).map(to_sortable_amount),
+        ),
+    );
+    // 达到扫描上限时后面可能还有匹配的交易，从本页最后一条继续向后翻页
+    let forward = plan.cursor.as_ref().is_none_or(|cursor| cursor.direction == Direction::Next);
+    if truncated && forward && page.next_cursor.is_none() {
+        page.next_cursor = transactions.last()
+            .map(|tx| Cursor::new(&scope, Direction::Next, tx.index.map(|index| index as i64), None).encode());
+    }
+    Ok(SearchResult { transactions, truncated, partial, page })
+}
+
+/// 不含账户条件的搜索：直接查询交易集合
+///
+/// 按索引排序时，扫描区间中已归档的部分分块从冷存储读取并在内存中判断条件，与集合中的结果按查询顺序拼接；
+/// 按金额排序时无法与冷存储合并排序，查询区间包含已归档的交易时标记 partial。
+/// 返回按查询顺序排列的最多 limit + 1 条交易、是否达到扫描上限、结果是否不含已归档的交易
+async fn search_transactions(
+    tx_col: &Collection<Document>,
+    plan: &SearchPlan,
+) -> Result<(Vec<Transaction>, bool, bool), Box<dyn Error + Send + Sync>> {
+    let fields = plan.sort.fields();
+    let mut filter = plan.filter.clone();
+    if let Some(cursor) = &plan.cursor {
+        let keyset = pagination::keyset_filter(fields, cursor)?;
+        filter = if filter.is_empty() { keyset } else { doc! { "$and": [filter, keyset] } };
+    }
+    let sort = pagination::query_sort(fields, plan.cursor.as_ref());
+
+    // 查询区间与已归档区间不相交时只查询交易集合
+    let archived = match cold_storage_for(tx_col).and_then(|storage| storage.archived_until()) {
+        Some(until) => match scan_range(tx_col, plan).await? {
+            Some((range, direction)) => {
+                let (start, end) = inclusive_bounds(&range);
+                (start <= until && start <= end).then(|| (start, end.min(until), direction))
+            },
+            None => return Ok((Vec::new(), false, false)),
+        },
+        None => None,
+    };
+    let Some((start, end, direction)) = archived else {
+        let transactions = api::search_transactions(tx_col, filter, sort, Some(plan.limit + 1), Some(plan.skip)).await?;
+        return Ok((transactions, false, false));
+    };
+    if !plan.sort.is_by_index() {
+        debug!("按金额排序的搜索不包含冷存储中索引 {}-{} 的交易", start, end);
+        let transactions = api::search_transactions(tx_col, filter, sort, Some(plan.limit + 1), Some(plan.skip)).await?;
+        return Ok((transactions, false, true));
+    }
+
+    // 集合中只取归档区间之后的交易，已归档的交易可能还未从集合中删除
+    let hot_filter = doc! { "$and": [filter, { "index": { "$gt": end as i64 } }] };
+    let wanted = (plan.skip + plan.limit + 1) as usize;
+    let mut hot = Vec::new();
+    let mut matched = Vec::new();
+    if direction < 0 {
+        hot = api::search_transactions(tx_col, hot_filter.clone(), sort.clone(), Some(wanted as i64), Some(0)).await?;
+    }
+
+    let mut truncated = false;
+    if hot.len() < wanted {
+        let mut scanned = 0usize;
+        let mut next = if direction > 0 { Some(start) } else { Some(end) };
+        while let Some(position) = next {
+            let (chunk_start, chunk_end) = if direction > 0 {
+                (position, position.saturating_add(ARCHIVE_CHUNK_SIZE - 1).min(end))
+            } else {
+                (position.saturating_sub(ARCHIVE_CHUNK_SIZE - 1).max(start), position)
+            };
+            let mut docs = get_transaction_documents_by_index_range(tx_col, chunk_start, chunk_end).await?;
+            if direction < 0 {
+                docs.reverse();
+            }
+            for tx_doc in docs {
+                if plan.matcher.matches(&tx_doc) {
+                    matched.push(mongodb::bson::from_document(tx_doc)?);
+                }
+            }
+            scanned += (chunk_end - chunk_start + 1) as usize;
+
+            next = if direction > 0 {
+                chunk_end.checked_add(1).filter(|&next| next <= end)
+            } else {
+                chunk_start.checked_sub(1).filter(|&next| next >= start)
+            };
+            if hot.len() + matched.len() >= wanted || next.is_none() {
+                break;
+            }
+            if scanned >= MAX_SCANNED_INDICES {
+                truncated = true;
+                break;
+            }
+        }
+    }
+
+    // 升序时已归档的交易在前，区间扫描完成后再查询集合
+    if direction > 0 && !truncated && matched.len() < wanted {
+        hot = api::search_transactions(tx_col, hot_filter, sort, Some((wanted - matched.len()) as i64), Some(0)).await?;
+    }
+    let ordered: Vec<Transaction> = if direction > 0 {
+        matched.into_iter().chain(hot).collect()
+    } else {
+        hot.into_iter().chain(matched).collect()
+    };
+    let transactions = ordered.into_iter()
+        .skip(plan.skip as usize)
+        .take(plan.limit as usize + 1)
+        .collect();
+    Ok((transactions, truncated, false))
+}
+
+/// 合并索引区间、时间条件和游标条件，得到要扫描的交易索引区间和方向（1 为升序，-1 为降序）
+///
+/// 向前翻页时反向扫描；时间区间内没有交易时返回 None。
+async fn scan_range(
+    tx_col: &Collection<Document>,
+    plan: &SearchPlan,
+) -> Result<Option<(Option<Document>, i32)>, Box<dyn Error + Send + Sync>> {
+    let mut direction = if plan.sort == SearchSort::IndexAsc { 1 } else { -1 };
+
+    // 时间条件转换为交易索引区间
+    let mut index_range = plan.index_range.clone();
+    let (from_time, to_time) = plan.time_range;
+    if from_time.is_some() || to_time.is_some() {
+        match api::time_index_range(tx_col, from_time, to_time).await? {
+            api::TimeIndexRange::Empty => return Ok(None),
+            api::TimeIndexRange::Range(bounds) => {
+                let mut range = index_range.unwrap_or_default();
+                tighten_range(&mut range, &bounds);
+                index_range = Some(range);
+            },
+            api::TimeIndexRange::Unbounded => {},
+        }
+    }
+
+    // 游标条件并入索引区间（按金额排序的游标不限制索引）
+    if let Some(cursor) = &plan.cursor {
+        let keyset = pagination::keyset_filter(plan.sort.fields(), cursor)?;
+        if let Ok(bound) = keyset.get_document("index") {
+            let mut range = index_range.unwrap_or_default();
+            range.extend(bound.clone());
+            index_range = Some(range);
+        }
+        if cursor.direction == Direction::Prev {
+            direction = -direction;
+        }
+    }
+    Ok(Some((index_range, direction)))
+}
+
+/// 将索引区间条件（$gte / $gt / $lte / $lt）转换为闭区间，区间为空时起点大于终点
+fn inclusive_bounds(range: &Option<Document>) -> (u64, u64) {
+    let mut start = 0u64;
+    let mut end = u64::MAX;
+    for (op, value) in range.iter().flatten() {
+        let Some(value) = value.as_i64() else {
+            continue;
+        };
+        match op.as_str() {
+            "$gte" => start = start.max(value.max(0) as u64),
+            "$gt" => start = start.max(value.saturating_add(1).max(0) as u64),
+            "$lte" if value < 0 => return (1, 0),
+            "$lte" => end = end.min(value as u64),
+            "$lt" if value <= 0 => return (1, 0),
+            "$lt" => end = end.min(value as u64 - 1),
+            _ => {},
+        }
+    }
+    (start, end)
+}
+
+/// 含账户条件的搜索：沿第一个账户条件的边按交易索引顺序分块读取，
+/// 其余账户条件在同一块索引内取交集，最后按其余条件读取交易
+///
+/// 返回按查询顺序（向前翻页时为反向）排列的最多 limit + 1 条交易，以及是否达到扫描上限
+async fn search_by_accounts(
+    account_tx_col: &Collection<Document>,
+    tx_col: &Collection<Document>,
+    plan: &SearchPlan,
+) -> Result<(Vec<Transaction>, bool), Box<dyn Error + Send + Sync>> {
+    let Some((driving, others)) = plan.accounts.split_first() else {
+        return Ok((Vec::new(), false));
+    };
+    debug!("按账户搜索交易: {:?}，其余账户条件 {} 个", driving, others.len());
+
+    // 只扫描时间区间和游标条件内的边
+    let Some((index_range, direction)) = scan_range(tx_col, plan).await? else {
+        return Ok((Vec::new(), false));
+    };
+
+    let options = FindOptions::builder()
+        .sort(doc! { "index": direction })
+        .projection(doc! { "index": 1, "_id": 0 })
+        .batch_size(ACCOUNT_CHUNK_SIZE as u32)
+        .build();
+    let mut cursor = account_tx_col.find(driving.edge_filter(&index_range), options).await?;
+
+    let wanted = (plan.skip + plan.limit + 1) as usize;
+    let mut matched: Vec<Transaction> = Vec::new();
+    let mut chunk: Vec<i64> = Vec::with_capacity(ACCOUNT_CHUNK_SIZE);
+    let mut last_index: Option<i64> = None;
+    let mut scanned = 0usize;
+    let mut truncated = false;
+
+    loop {
+        let edge = cursor.try_next().await?;
+        let exhausted = edge.is_none();
+        if let Some(index) = edge.and_then(|edge| edge.get_i64("index").ok()) {
+            // 同一笔交易中账户可能有多个角色，结果按索引排序，重复索引必然相邻
+            if last_index != Some(index) {
+                last_index = Some(index);
+                chunk.push(index);
+                scanned += 1;
+            }
+        }
+        let limit_reached = scanned >= MAX_SCANNED_INDICES;
+        if chunk.len() < ACCOUNT_CHUNK_SIZE && !exhausted && !limit_reached {
+            continue;
+        }
+
+        // 交易可能已按保留策略归档到冷存储，读取后在内存中判断其余条件
+        let candidates = intersect_accounts(account_tx_col, others, std::mem::take(&mut chunk)).await?;
+        for tx_doc in find_transactions_by_indices(tx_col, &candidates, direction > 0).await? {
+            if plan.matcher.matches(&tx_doc) {
+                matched.push(mongodb::bson::from_document(tx_doc)?);
+            }
+        }
+
+        if matched.len() >= wanted || exhausted {
+            break;
+        }
+        if limit_reached {
+            truncated = true;
+            break;
+        }
+    }
+
+    let transactions = matched.into_iter()
+        .skip(plan.skip as usize)
+        .take(plan.limit as usize + 1)
+        .collect();
+    Ok((transactions, truncated))
+}
+
+/// 将索引区间 `bounds`（$gte / $lte）合并到 `range`，取更严格的边界
+fn tighten_range(range: &mut Document, bounds: &Document) {
+    for (op, value) in bounds {
+        let Some(value) = value.as_i64() else {
+            continue;
+        };
+        let merged = match (op.as_str(), range.get_i64(op)) {
+            ("$gte", Ok(current)) => current.max(value),
+            ("$lte", Ok(current)) => current.min(value),
+            _ => value,
+        };
+        range.insert(op.clone(), merged);
+    }
+}
+
+/// 保留同时满足其余账户条件的交易索引
+async fn intersect_accounts(
+    account_tx_col: &Collection<Document>,
+    conditions: &[AccountCondition],
+    mut indices: Vec<i64>,
+) -> Result<Vec<i64>, Box<dyn Error + Send + Sync>> {
+    for condition in conditions {
+        if indices.is_empty() {
+            break;
+        }
+        let mut filter = condition.edge_filter(&None);
+        filter.insert("index", doc! { "$in": indices.clone() });
+        let options = FindOptions::builder().projection(doc! { "index": 1, "_id": 0 }).build();
+        let edges: Vec<Document> = account_tx_col.find(filter, options).await?.try_collect().await?;
+        let present: HashSet<i64> = edges.iter().filter_map(|edge| edge.get_i64("index").ok()).collect();
+        indices.retain(|index| present.contains(index));
+    }
+    Ok(indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::to_sortable_amount;
+
+    const ACCOUNT: &str = "ryjl3-tyaaa-aaaaa-aaaba-cai";
+
+    fn build(request: SearchRequest) -> Result<SearchPlan, Box<dyn Error + Send + Sync>> {
+        SearchPlan::build(&request, 8)
+    }
+
+    #[test]
+    fn empty_request_matches_everything() {
+        let plan = build(SearchRequest::default()).unwrap();
+        assert!(plan.filter.is_empty());
+        assert_eq!(plan.sort, SearchSort::IndexDesc);
+        assert_eq!(plan.limit, DEFAULT_LIMIT);
+    }
+
+    #[test]
+    fn amount_range_uses_sortable_amounts_in_base_units() {
+        let plan = build(SearchRequest {
+            min_amount: Some("1.5".to_string()),
+            max_amount: Some("1000".to_string()),
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(plan.filter, doc! { "amount_sort": {
+            "$gte": to_sortable_amount(&candid::Nat::from(150_000_000u64)),
+            "$lte": to_sortable_amount(&candid::Nat::from(100_000_000_000u64)),
+        } });
+    }
+
+    #[test]
+    fn multiple_conditions_are_combined_with_and() {
+        let plan = build(SearchRequest {
+            kinds: Some(vec!["transfer".to_string()]),
+            start_index: Some(10),
+            end_index: Some(20),
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(plan.filter, doc! { "$and": [
+            { "kind": { "$in": ["transfer"] } },
+            { "index": { "$gte": 10i64, "$lte": 20i64 } },
+        ] });
+    }
+
+    #[test]
+    fn invalid_requests_are_rejected() {
+        let invalid = [
+            SearchRequest { kinds: Some(vec![]), ..Default::default() },
+            SearchRequest { kinds: Some(vec!["swap".to_string()]), ..Default::default() },
+            SearchRequest { min_amount: Some("1.123456789".to_string()), ..Default::default() },
+            SearchRequest { min_amount: Some("-1".to_string()), ..Default::default() },
+            SearchRequest { memo: Some("zz".to_string()), ..Default::default() },
+            SearchRequest { memo: Some("00".to_string()), memo_text: Some("a".to_string()), ..Default::default() },
+            SearchRequest { start_index: Some(5), end_index: Some(4), ..Default::default() },
+            SearchRequest { account: Some("not-an-account".to_string()), ..Default::default() },
+            SearchRequest { sort: Some("random".to_string()), ..Default::default() },
+            SearchRequest { account: Some(ACCOUNT.to_string()), sort: Some("amount_desc".to_string()), ..Default::default() },
+            SearchRequest { limit: Some(0), ..Default::default() },
+            SearchRequest { skip: Some(-1), ..Default::default() },
+        ];
+        for request in invalid {
+            assert!(build(request.clone()).is_err(), "应拒绝请求: {:?}", request);
+        }
+    }
+
+    #[test]
+    fn account_conditions_keep_their_roles() {
+        let plan = build(SearchRequest {
+            account: Some(ACCOUNT.to_string()),
+            from: Some(ACCOUNT.to_string()),
+            ..Default::default()
+        }).unwrap();
+        let roles: Vec<Option<&str>> = plan.accounts.iter().map(|condition| condition.role).collect();
+        assert_eq!(roles, vec![None, Some(ROLE_FROM)]);
+        assert!(plan.filter.is_empty());
+    }
+
+    #[test]
+    fn index_conditions_convert_to_inclusive_bounds() {
+        assert_eq!(inclusive_bounds(&None), (0, u64::MAX));
+        assert_eq!(inclusive_bounds(&Some(doc! { "$gte": 10i64, "$lte": 20i64, "$lt": 15i64 })), (10, 14));
+        assert_eq!(inclusive_bounds(&Some(doc! { "$gt": 10i64 })), (11, u64::MAX));
+        let (start, end) = inclusive_bounds(&Some(doc! { "$lt": 0i64 }));
+        assert!(start > end);
+    }
+
+    #[test]
+    fn amount_cursor_breaks_ties_by_index_in_sort_order() {
+        let fields = SearchSort::AmountDesc.fields();
+        let scope = SearchSort::AmountDesc.scope();
+
+        let next = Cursor::new(&scope, Direction::Next, Some(7), Some("0500".to_string()));
+        assert_eq!(pagination::keyset_filter(fields, &next).unwrap(), doc! {
+            "$or": [
+                { "amount_sort": { "$lt": "0500" } },
+                { "amount_sort": "0500", "index": { "$lt": 7i64 } },
+            ]
+        });
+
+        let prev = Cursor::new(&scope, Direction::Prev, Some(7), Some("0500".to_string()));
+        assert_eq!(pagination::keyset_filter(fields, &prev).unwrap(), doc! {
+            "$or": [
+                { "amount_sort": { "$gt": "0500" } },
+                { "amount_sort": "0500", "index": { "$gt": 7i64 } },
+            ]
+        });
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        assert!(serde_json::from_str::<SearchRequest>(r#"{"$where": "1"}"#).is_err());
+        assert!(serde_json::from_str::<SearchRequest>(r#"{"query": {"index": 1}}"#).is_err());
+    }
+}
@@ -0,0 +1,75 @@
+/**
+ * 文件描述: 优雅停止模块，处理 SIGTERM / SIGINT 信号
+ * 功能概述:
+ * - 收到停止信号后设置全局停止标记，同步流程在批次之间检查该标记，不再拉取新批次
+ * - 正在提交的批次继续完成（事务模式下被中断的批次由 MongoDB 回滚）
+ * - 再次收到停止信号时立即退出
+ *
+ * 主要组件:
+ * - listen_for_signals函数: 启动监听停止信号的后台任务
+ * - is_requested函数: 是否已收到停止信号，供同步循环在批次之间检查
+ * - requested函数: 等待停止信号
+ * - request函数: 主动发起停止
+ */
+
+use log::{info, warn};
+use tokio::sync::watch;
+
+/// 再次收到停止信号时的退出码
+const FORCED_EXIT_CODE: i32 = 130;
+
+lazy_static::lazy_static! {
+    /// 全局停止标记
+    static ref SHUTDOWN: watch::Sender<bool> = watch::channel(false).0;
+}
+
+/// 发起停止
+pub fn request() {
+    SHUTDOWN.send_replace(true);
+}
+
+/// 是否已收到停止信号
+pub fn is_requested() -> bool {
+    *SHUTDOWN.borrow()
+}
+
+/// 等待停止信号
+pub async fn requested() {
+    let mut receiver = SHUTDOWN.subscribe();
+    // 发送端是全局变量，不会被丢弃
+    let _ = receiver.wait_for(|stopped| *stopped).await;
+}
+
+/// 启动监听 SIGTERM / SIGINT 的后台任务
+///
+/// 第一次收到信号时开始优雅停止；停止过程中再次收到信号时立即退出。
+pub fn listen_for_signals() {
+    tokio::spawn(async move {
+        wait_for_signal().await;
+        info!("收到停止信号，开始优雅停止：不再拉取新的同步批次，等待进行中的批次提交完成...");
+        request();
+
+        wait_for_signal().await;
+        warn!("再次收到停止信号，立即退出");
+        std::process::exit(FORCED_EXIT_CODE);
+    });
+}
+
+/// 等待 SIGINT（Ctrl+C）或 SIGTERM
+async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {},
+                    _ = terminate.recv() => {},
+                }
+                return;
+            },
+            Err(e) => warn!("无法监听 SIGTERM 信号，只处理 Ctrl+C: {}", e),
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
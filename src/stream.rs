@@ -0,0 +1,279 @@
+/**
+ * 文件描述: 交易推送流模块，为 SSE（GET /api/stream）和 WebSocket（GET /api/ws）连接推送新交易
+ * 功能概述:
+ * - 客户端按代币、账户、交易类型和最小金额订阅，只推送满足条件的交易
+ * - 每个连接由一个后台任务产生交易：先从数据库补发 from_index 之后已保存的交易，
+ *   再接收同步流程提交批次后的进程内广播（见 sync::broadcast）
+ * - 没有广播时（只读 API 实例、代币由其他进程同步）定期轮询数据库，广播落后或出现索引缺口时同样从数据库补齐
+ * - 每笔交易最多推送一次且按交易索引升序，断线后客户端使用最后收到的索引续传
+ * - 客户端断开或收到停止信号时任务退出
+ *
+ * 主要组件:
+ * - StreamParams结构体: 订阅参数（SSE 和 WebSocket 共用）
+ * - StreamFilter结构体: 校验后的订阅条件
+ * - subscribe函数: 启动推送任务，返回交易接收端
+ * - ClientGuard结构体: 统计当前连接数
+ * - render_metrics函数: Prometheus 格式的连接数指标
+ */
+
+use std::error::Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use candid::Nat;
+use serde::Deserialize;
+use tokio::sync::{broadcast::error::RecvError, mpsc};
+use tokio::time::{Duration, MissedTickBehavior};
+use log::{debug, warn};
+use crate::db::TokenCollections;
+use crate::db::account_transactions::get_account_transaction_indices_after;
+use crate::db::transactions as tx_db;
+use crate::models::Transaction;
+use crate::search::TRANSACTION_KINDS;
+use crate::shutdown;
+use crate::sync::broadcast;
+use crate::utils::{create_error, get_transaction_account_roles, get_transaction_amount, parse_account, parse_token_amount};
+
+/// 轮询数据库的间隔
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// 从数据库补发时每次读取的交易数量
+const CATCH_UP_PAGE_SIZE: u64 = 500;
+/// 每个连接待发送的交易数量上限，客户端读取过慢时推送任务等待
+const CLIENT_BUFFER: usize = 256;
+
+static SSE_CLIENTS: AtomicUsize = AtomicUsize::new(0);
+static WS_CLIENTS: AtomicUsize = AtomicUsize::new(0);
+
+/// 订阅参数
+#[derive(Debug, Deserialize, Clone)]
+pub struct StreamParams {
+    /// 代币符号（可选，默认使用配置的第一个代币）
+    pub token: Option<String>,
+    /// 以任意角色参与交易的账户
+    pub account: Option<String>,
+    /// 交易类型，多个用逗号分隔，例如 transfer,mint
+    pub kind: Option<String>,
+    /// 最小金额（含），代币单位
+    pub min_amount: Option<String>,
+    /// 从该交易索引（含）开始推送，用于断线重连后续传；不指定时只推送连接之后提交的交易
+    pub from_index: Option<u64>,
+}
+
+/// 校验后的订阅条件
+#[derive(Debug, Clone, Default)]
+pub struct StreamFilter {
+    account: Option<String>,
+    kinds: Option<Vec<String>>,
+    min_amount: Option<Nat>,
+}
+
+impl StreamFilter {
+    /// 校验订阅参数，`decimals` 用于将金额换算为最小单位
+    pub fn build(params: &StreamParams, decimals: u8) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let account = params.account.as_deref()
+            .map(|value| parse_account(value.trim()).map(|account| account.to_string()))
+            .transpose()
+            .map_err(|e| create_error(&format!("account 无效: {}", e)))?;
+
+        let kinds = match params.kind.as_deref() {
+            Some(value) => {
+                let kinds: Vec<String> = value.split(',')
+                    .map(|kind| kind.trim().to_string())
+                    .filter(|kind| !kind.is_empty())
+                    .collect();
+                if let Some(kind) = kinds.iter().find(|kind| !TRANSACTION_KINDS.contains(&kind.as_str())) {
+                    return Err(create_error(&format!("不支持的交易类型: {}，可选值: {}", kind, TRANSACTION_KINDS.join(", "))));
+                }
+                if kinds.is_empty() { None } else { Some(kinds) }
+            },
+            None => None,
+        };
+
+        let min_amount = params.min_amount.as_deref()
+            .map(|amount| parse_token_amount(amount, decimals))
+            .transpose()?;
+
+        Ok(StreamFilter { account, kinds, min_amount })
+    }
+
+    fn matches(&self, tx: &Transaction) -> bool {
+        if self.kinds.as_ref().is_some_and(|kinds| !kinds.contains(&tx.kind)) {
+            return false;
+        }
+        if let Some(min_amount) = &self.min_amount {
+            if get_transaction_amount(tx).is_none_or(|amount| amount < min_amount) {
+                return false;
+            }
+        }
+        match &self.account {
+            Some(account) => get_transaction_account_roles(tx).iter().any(|(a, _)| a == account),
+            None => true,
+        }
+    }
+}
+
+/// 推送通道类型，用于连接数统计
+#[derive(Debug, Clone, Copy)]
+pub enum Transport {
+    Sse,
+    WebSocket,
+}
+
+impl Transport {
+    fn counter(self) -> &'static AtomicUsize {
+        match self {
+            Transport::Sse => &SSE_CLIENTS,
+            Transport::WebSocket => &WS_CLIENTS,
+        }
+    }
+}
+
+/// 连接数统计，推送任务退出时减少
+struct ClientGuard(Transport);
+
+impl ClientGuard {
+    fn new(transport: Transport) -> Self {
+        transport.counter().fetch_add(1, Ordering::Relaxed);
+        ClientGuard(transport)
+    }
+}
+
+impl Drop for ClientGuard {
+    fn drop(&mut self) {
+        self.0.counter().fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// 启动推送任务，返回按交易索引升序的交易接收端；接收端被丢弃（客户端断开）后任务退出
+pub fn subscribe(
+    collections: TokenCollections,
+    token_symbol: String,
+    filter: StreamFilter,
+    from_index: Option<u64>,
+    transport: Transport,
+) -> mpsc::Receiver<Transaction> {
+    let (sender, receiver) = mpsc::channel(CLIENT_BUFFER);
+    tokio::spawn(async move {
+        let _guard = ClientGuard::new(transport);
+        debug!("{}: 交易推送流已连接 ({:?}), 条件: {:?}, from_index: {:?}", token_symbol, transport, filter, from_index);
+        if let Err(e) = run(&collections, &token_symbol, &filter, from_index, &sender).await {
+            if !sender.is_closed() {
+                warn!("{}: 交易推送流异常结束: {}", token_symbol, e);
+            }
+        }
+        debug!("{}: 交易推送流已断开 ({:?})", token_symbol, transport);
+    });
+    receiver
+}
+
+async fn run(
+    collections: &TokenCollections,
+    token_symbol: &str,
+    filter: &StreamFilter,
+    from_index: Option<u64>,
+    sender: &mpsc::Sender<Transaction>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // 先订阅广播再读取数据库，两者之间提交的批次不会遗漏
+    let mut live = broadcast::subscribe();
+    let mut next = match from_index {
+        Some(index) => index,
+        None => tx_db::get_latest_transaction_index(&collections.tx_col).await?.map_or(0, |index| index + 1),
+    };
+    next = catch_up(collections, filter, next, sender).await?;
+
+    let mut poll = tokio::time::interval(POLL_INTERVAL);
+    poll.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = sender.closed() => return Ok(()),
+            _ = shutdown::requested() => return Ok(()),
+            message = live.recv() => match message {
+                Ok(batch) if batch.token_symbol == token_symbol => {
+                    for tx in &batch.transactions {
+                        let Some(index) = tx.index else { continue };
+                        if index < next {
+                            continue;
+                        }
+                        if index > next {
+                            // 广播之前的交易尚未推送（例如连接时正在提交的批次），从数据库补齐
+                            next = catch_up(collections, filter, next, sender).await?;
+                            if index < next {
+                                continue;
+                            }
+                        }
+                        if filter.matches(tx) {
+                            send(sender, tx.clone()).await?;
+                        }
+                        next = index + 1;
+                    }
+                },
+                Ok(_) => {},
+                Err(RecvError::Lagged(skipped)) => {
+                    debug!("{}: 交易推送流落后 {} 个批次，从数据库补齐", token_symbol, skipped);
+                    next = catch_up(collections, filter, next, sender).await?;
+                },
+                Err(RecvError::Closed) => return Ok(()),
+            },
+            _ = poll.tick() => {
+                next = catch_up(collections, filter, next, sender).await?;
+            },
+        }
+    }
+}
+
+/// 从数据库补发索引不小于 `next` 的已保存交易，返回下一个待推送的索引
+async fn catch_up(
+    collections: &TokenCollections,
+    filter: &StreamFilter,
+    mut next: u64,
+    sender: &mpsc::Sender<Transaction>,
+) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    // 先确定终点，之后提交的交易留给下一次补发或广播
+    let Some(end) = tx_db::get_latest_transaction_index(&collections.tx_col).await? else {
+        return Ok(next);
+    };
+
+    while next <= end {
+        let transactions = match &filter.account {
+            // 有账户条件时沿账户-交易边查找，不读取其他账户的交易
+            Some(account) => {
+                let indices: Vec<i64> = get_account_transaction_indices_after(
+                    &collections.account_tx_col, account, next.checked_sub(1), CATCH_UP_PAGE_SIZE as i64,
+                ).await?.into_iter().filter(|index| *index as u64 <= end).collect();
+                let docs = tx_db::find_transactions_by_indices(&collections.tx_col, &indices, true).await?;
+                next = match indices.last() {
+                    Some(last) => *last as u64 + 1,
+                    None => end + 1,
+                };
+                docs.into_iter()
+                    .filter_map(|doc| mongodb::bson::from_document::<Transaction>(doc).ok())
+                    .collect::<Vec<_>>()
+            },
+            None => {
+                let page_end = end.min(next + CATCH_UP_PAGE_SIZE - 1);
+                let transactions = tx_db::get_transactions_by_index_range(&collections.tx_col, next, page_end).await?;
+                next = page_end + 1;
+                transactions
+            },
+        };
+        for tx in transactions {
+            if filter.matches(&tx) {
+                send(sender, tx).await?;
+            }
+        }
+    }
+    Ok(next)
+}
+
+async fn send(sender: &mpsc::Sender<Transaction>, tx: Transaction) -> Result<(), Box<dyn Error + Send + Sync>> {
+    sender.send(tx).await.map_err(|_| create_error("客户端已断开"))
+}
+
+/// Prometheus 格式的推送流连接数指标
+pub fn render_metrics() -> String {
+    format!(
+        "# HELP index_stream_clients 当前交易推送流连接数\n# TYPE index_stream_clients gauge\n\
+         index_stream_clients{{transport=\"sse\"}} {}\nindex_stream_clients{{transport=\"ws\"}} {}\n",
+        SSE_CLIENTS.load(Ordering::Relaxed),
+        WS_CLIENTS.load(Ordering::Relaxed),
+    )
+}
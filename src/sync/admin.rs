@@ -7,12 +7,13 @@
  * 
  * 主要组件:
  * - reset_and_sync_all_transactions函数: 重置数据库并重新同步所有交易
- *   - 清空数据库集合
+ *   - 清空数据库集合、冷存储分段文件和分片回填任务
  *   - 同步归档交易
  *   - 同步主账本交易
  *   - 计算账户余额
  *   - 设置同步状态
  * - calculate_all_balances函数: 计算所有账户余额
+ * - prepare_token_sync函数: 启动代币同步前执行初始同步或校验同步状态，并继续未完成的派生数据重建和分片回填
  */
 
 use std::error::Error;
@@ -20,30 +21,39 @@ use ic_agent::Agent;
 use ic_agent::export::Principal;
 use log::{info, error, warn};
 use crate::db::transactions::clear_transactions;
+use crate::db::cold_storage::clear_cold_storage;
+use crate::db::backfill_jobs::{clear_backfill_jobs, has_unfinished_plan};
 use crate::db::accounts::clear_accounts;
+use crate::db::account_transactions::clear_account_transactions;
 use crate::db::balances::{clear_balances, calculate_all_balances as calc_balances};
-use crate::db::sync_status::{clear_sync_status, set_full_sync_mode, set_incremental_mode};
-use crate::db::create_indexes;
+use crate::db::sync_status::{clear_token_sync_status, get_sync_status, set_full_sync_mode, set_incremental_mode, take_clean_shutdown, update_balance_calculated_index, is_rebuild_pending};
+use crate::shutdown;
+use crate::db::transactions::get_latest_transaction_index;
 use crate::db::DbConnection;
-use crate::sync::archive::sync_archive_transactions;
-use crate::sync::ledger::sync_ledger_transactions;
+use crate::config::{parse_canister_id, get_token_decimals};
+use crate::models::TokenConfig;
+use crate::sync::{sync_ledger_transactions, sync_archive_transactions};
+use crate::sync::partial::resolve_partial_start;
+use crate::sync::rebuild::rebuild_derived_data;
+use crate::sync::backfill::finalize_backfill;
+use crate::sync::progress;
 use crate::blockchain::get_first_transaction_index;
 
 /// 重置数据库并完全重新同步所有交易
 /// 
-/// 注意：此函数只能通过命令行参数 --reset 触发，属于管理员功能
+/// 注意：此函数只能通过 reset 子命令触发，属于管理员功能；只清空该代币的数据和同步状态
 pub async fn reset_and_sync_all_transactions(
     agent: &Agent,
     canister_id: &Principal,
     db_conn: &DbConnection,
     token_config: &crate::models::TokenConfig,
-) -> Result<(), Box<dyn Error>> {
+) -> Result<(), Box<dyn Error + Send + Sync>> {
     let token_symbol = &token_config.symbol;
     let token_decimals = token_config.decimals.unwrap_or(8);
     info!("开始重置数据库并重新同步所有交易数据...");
     
     // 获取该代币的集合
-    let collections = match db_conn.collections.get(token_symbol) {
+    let collections = match db_conn.token_collections(token_symbol) {
         Some(cols) => cols,
         None => {
             return Err(format!("没有找到代币 {} 的集合", token_symbol).into());
@@ -54,36 +64,44 @@ pub async fn reset_and_sync_all_transactions(
     info!("清空交易集合...");
     clear_transactions(&collections.tx_col).await?;
     
+    info!("清空冷存储分段文件...");
+    clear_cold_storage(&collections.tx_col, token_symbol).await?;
+    
     info!("清空账户-交易关系集合...");
     clear_accounts(&collections.accounts_col).await?;
+    clear_account_transactions(&collections.account_tx_col).await?;
     
     info!("清空余额集合...");
     clear_balances(&collections.balances_col).await?;
     
-    info!("清空同步状态集合...");
-    clear_sync_status(&db_conn.sync_status_col).await?;
+    info!("清空同步状态和分片回填任务...");
+    progress::clear(token_symbol);
+    clear_token_sync_status(&db_conn.sync_status_col, token_symbol).await?;
+    clear_backfill_jobs(&db_conn.backfill_col, token_symbol).await?;
     
     // 设置为全量同步模式
     set_full_sync_mode(&db_conn.sync_status_col, token_symbol).await?;
     
-    // 重新创建索引
-    info!("重新创建索引...");
-    create_indexes(db_conn).await?;
+    // 清空使用 delete_many，集合索引和结构版本记录保持不变，无需重新创建
     
     // 第一阶段：同步交易数据
     info!("\n第一阶段：同步所有交易数据到数据库...");
     
+    // 部分索引时解析起点，起点之前的归档和主账本交易都不同步
+    let from_index = resolve_partial_start(agent, canister_id, db_conn, token_config).await?
+        .map(|start| start.index)
+        .unwrap_or(0);
+    
     // 先同步归档数据
     info!("{}: 同步归档交易...", token_symbol);
     let _archive_result = sync_archive_transactions(
         agent,
         canister_id,
-        &collections.tx_col,
-        &collections.accounts_col,
-        &collections.balances_col,
-        &collections.total_supply_col,
+        &collections,
         token_decimals,
-        false // 不计算余额，只保存交易
+        false, // 不计算余额，只保存交易
+        from_index,
+        token_symbol
     ).await?;
     
     // 同步ledger的交易
@@ -103,18 +121,21 @@ pub async fn reset_and_sync_all_transactions(
     let ledger_transactions = sync_ledger_transactions(
         agent,
         canister_id,
-        &collections.tx_col,
-        &collections.accounts_col,
-        &db_conn.sync_status_col,
-        &collections.total_supply_col,
+        db_conn,
         token_config,
-        true // 计算余额
+        false // 不在批次中计算余额，第二阶段统一计算
     ).await?;
     
+    // 中断时保持全量同步模式，下次启动将重新执行初始同步
+    if shutdown::is_requested() {
+        warn!("{}: 重置同步被中断，下次启动将重新执行初始同步", token_symbol);
+        return Ok(());
+    }
+    
     // 第二阶段：计算余额
     info!("{}: \n第二阶段：根据账户信息计算余额...", token_symbol);
     calculate_all_balances(
-        &db_conn,
+        db_conn,
         token_config
     ).await?;
     
@@ -136,6 +157,7 @@ pub async fn reset_and_sync_all_transactions(
         info!("{}: 重置完成：设置增量同步模式，最新索引: {}, 时间戳: {}", 
               token_symbol, latest_index, latest_timestamp);
         set_incremental_mode(&db_conn.sync_status_col, token_symbol, latest_index, latest_timestamp).await?;
+        update_balance_calculated_index(&db_conn.sync_status_col, token_symbol, latest_index).await?;
     } else {
         warn!("{}: 重置完成但未找到有效交易，保持全量同步模式", token_symbol);
         // 即使没有交易，也将同步模式设置为增量，避免重复全量同步
@@ -152,12 +174,12 @@ pub async fn reset_and_sync_all_transactions(
 pub async fn calculate_all_balances(
     db_conn: &DbConnection,
     token_config: &crate::models::TokenConfig,
-) -> Result<(), Box<dyn Error>> {
+) -> Result<(), Box<dyn Error + Send + Sync>> {
     let token_symbol = &token_config.symbol;
     info!("{}: 开始使用新算法计算所有账户余额...", token_symbol);
     
     // 获取该代币的集合
-    let collections = match db_conn.collections.get(token_symbol) {
+    let collections = match db_conn.token_collections(token_symbol) {
         Some(cols) => cols,
         None => {
             let msg = format!("未找到代币 {} 的集合", token_symbol);
@@ -168,6 +190,7 @@ pub async fn calculate_all_balances(
     
     match calc_balances(
         &collections.accounts_col,
+        &collections.account_tx_col,
         &collections.tx_col,
         &collections.balances_col,
         &collections.total_supply_col,
@@ -187,3 +210,215 @@ pub async fn calculate_all_balances(
     Ok(())
 }
 
+
+/// 启动代币同步前的准备：需要时执行初始同步，否则校验同步状态的完整性
+///
+/// 启动时对配置中的代币依次调用；通过管理接口添加的代币在其同步任务中调用。
+pub async fn prepare_token_sync(
+    agent: &Agent,
+    db_conn: &DbConnection,
+    token: &TokenConfig,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // 存在未完成的分片回填计划时，本进程也参与处理工作单元，全部完成后统一计算余额并切换到增量同步
+    if has_unfinished_plan(&db_conn.backfill_col, &token.symbol).await? {
+        info!("{}: 检测到未完成的分片回填计划，参与处理剩余的工作单元", token.symbol);
+        if !finalize_backfill(Some(agent), db_conn, token).await? {
+            warn!("{}: 分片回填被中断，下次启动将继续", token.symbol);
+            return Ok(());
+        }
+    }
+
+    let sync_status = get_sync_status(&db_conn.sync_status_col, &token.symbol).await;
+    let needs_initial_sync = match &sync_status {
+        Ok(Some(status)) => {
+            if status.sync_mode == "incremental" && status.last_synced_index > 0 {
+                info!("{}: 检测到有效的同步状态，上次同步索引：{}，上次同步时间：{}，将继续增量同步", 
+                      token.symbol, status.last_synced_index, 
+                      chrono::DateTime::from_timestamp(status.updated_at, 0)
+                         .unwrap_or_else(chrono::Utc::now)
+                         .format("%Y-%m-%d %H:%M:%S"));
+                false
+            } else {
+                info!("{}: 同步状态无效或为全量模式，需要进行初始同步", token.symbol);
+                true
+            }
+        },
+        _ => {
+            info!("{}: 未找到同步状态记录，将进行初始同步", token.symbol);
+            true
+        }
+    };
+
+    // 获取该代币的集合
+    let collections = match db_conn.token_collections(&token.symbol) {
+        Some(cols) => cols,
+        None => {
+            error!("{}: 没有找到代币的集合", token.symbol);
+            return Ok(());
+        }
+    };
+    
+    // 解析Canister ID
+    let canister_id = parse_canister_id(&token.canister_id)?;
+    
+    // 获取代币小数位数
+    let token_decimals = match token.decimals {
+        Some(decimals) => decimals,
+        None => {
+            match get_token_decimals(agent, &canister_id, &token.symbol).await {
+                Ok(decimals) => decimals,
+                Err(e) => {
+                    error!("{}: 获取代币小数位失败: {}", token.symbol, e);
+                    return Err(e);
+                }
+            }
+        }
+    };
+    
+    if needs_initial_sync {
+        // 正常模式：先同步所有交易，再统一计算余额
+        info!("{}: 阶段1：同步所有交易数据...", token.symbol);
+        
+        // 部分索引时解析起点，起点之前的归档和主账本交易都不同步
+        let from_index = resolve_partial_start(agent, &canister_id, db_conn, token).await?
+            .map(|start| start.index)
+            .unwrap_or(0);
+        
+        // 先同步归档数据
+        let _archives_result = sync_archive_transactions(
+            agent,
+            &canister_id,
+            &collections,
+            token_decimals,
+            false, // 不计算余额
+            from_index,
+            &token.symbol
+        ).await?;
+        
+        if shutdown::is_requested() {
+            warn!("{}: 初始同步被中断，下次启动将重新执行初始同步", token.symbol);
+            return Ok(());
+        }
+        
+        // 同步主账本数据
+        info!("{}: 开始同步ledger交易...", token.symbol);
+        // 拉取失败时保持全量同步模式，不在不完整的交易上计算余额，下次执行初始同步时重新同步
+        let ledger_txs = match sync_ledger_transactions(
+            agent,
+            &canister_id,
+            db_conn,
+            token,
+            false // 不计算余额
+        ).await {
+            Ok(txs) => txs,
+            Err(e) => {
+                error!("{}: 同步ledger交易失败，保持全量同步模式: {}", token.symbol, e);
+                return Err(e);
+            }
+        };
+        
+        // 中断时保持全量同步模式，已保存的交易在重新同步时覆盖写入
+        if shutdown::is_requested() {
+            warn!("{}: 初始同步被中断，下次启动将重新执行初始同步", token.symbol);
+            return Ok(());
+        }
+        
+        // 阶段2：根据账户交易记录计算余额，失败时保持全量同步模式，由监督任务重新执行初始同步
+        info!("{}: 阶段2：根据账户交易记录统一计算余额...", token.symbol);
+        if let Err(e) = calc_balances(
+            &collections.accounts_col,
+            &collections.account_tx_col,
+            &collections.tx_col,
+            &collections.balances_col,
+            &collections.total_supply_col,
+            &collections.balance_anomalies_col,
+            token
+        ).await {
+            error!("{}: 计算余额时出错，保持全量同步模式: {}", token.symbol, e);
+            return Err(e);
+        }
+        
+        // 全量余额计算完成后，记录余额计算进度，并设置增量同步模式；
+        // 批次提交已写入同步游标，主账本本次没有新交易时沿用游标位置
+        let cursor = match ledger_txs.last() {
+            Some(last_tx) => last_tx.index.map(|index| (index, last_tx.timestamp)),
+            None => get_sync_status(&db_conn.sync_status_col, &token.symbol).await?
+                .filter(|status| status.last_synced_index > 0)
+                .map(|status| (status.last_synced_index, status.last_synced_timestamp)),
+        };
+        if let Some((index, timestamp)) = cursor {
+            update_balance_calculated_index(&db_conn.sync_status_col, &token.symbol, index).await?;
+            info!("{}: 设置增量同步起点为最后一笔交易索引: {}", token.symbol, index);
+            set_incremental_mode(
+                &db_conn.sync_status_col,
+                &token.symbol,
+                index,
+                timestamp
+            ).await?;
+        }
+        
+        info!("{}: 初始同步和余额计算完成", token.symbol);
+        info!("============================================");
+    } else if let Ok(Some(status)) = sync_status {
+        // 上次运行正常停止时，同步游标和余额进度均已写入，无需推测
+        let clean_shutdown = match take_clean_shutdown(&db_conn.sync_status_col, &token.symbol).await {
+            Ok(clean) => clean,
+            Err(e) => {
+                warn!("{}: 读取正常停止标记失败: {}", token.symbol, e);
+                false
+            }
+        };
+        
+        if clean_shutdown {
+            info!("{}: 上次运行已正常停止，从同步游标 {} 继续同步", token.symbol, status.last_synced_index);
+        } else {
+            // 检查是否需要验证同步状态的完整性
+            info!("{}: 从断点继续同步，验证同步状态的完整性...", token.symbol);
+        
+            // 检查数据库中最新交易索引与同步状态是否一致
+            match get_latest_transaction_index(&collections.tx_col).await {
+                Ok(Some(db_latest_index)) => {
+                    if db_latest_index < status.last_synced_index {
+                        warn!("{}: 数据库最新交易索引 ({}) 小于同步状态记录的索引 ({}), 可能有数据被手动删除", 
+                             token.symbol, db_latest_index, status.last_synced_index);
+                        info!("{}: 将从数据库最新索引开始重新同步...", token.symbol);
+                    
+                        // 更新同步状态为数据库的最新索引
+                        if let Err(e) = set_incremental_mode(
+                            &db_conn.sync_status_col,
+                            &token.symbol,
+                            db_latest_index,
+                            status.last_synced_timestamp
+                        ).await {
+                            error!("{}: 更新同步状态失败: {}", token.symbol, e);
+                        }
+                    } else if db_latest_index > status.last_synced_index {
+                        // 批次提交时同步游标最后写入，单机模式下中断会留下游标之后的交易；
+                        // 保持游标不变，从游标处重放即可补齐账户关系和余额（各步骤均可重复执行）
+                        info!("{}: 数据库最新交易索引 ({}) 大于同步状态记录的索引 ({}), 将从同步状态处重放未完成的批次", 
+                              token.symbol, db_latest_index, status.last_synced_index);
+                    } else {
+                        info!("{}: 同步状态与数据库记录一致，索引: {}", token.symbol, db_latest_index);
+                    }
+                },
+                _ => {
+                    warn!("{}: 无法获取数据库最新交易索引，将使用同步状态记录的索引", token.symbol);
+                }
+            }
+        }
+        
+        // 上次派生数据重建未完成时，先根据已保存的交易重新执行重建
+        match is_rebuild_pending(&db_conn.sync_status_col, &token.symbol).await {
+            Ok(true) => {
+                warn!("{}: 检测到未完成的派生数据重建，重新执行重建", token.symbol);
+                rebuild_derived_data(db_conn, token).await?;
+            },
+            Ok(false) => {},
+            Err(e) => warn!("{}: 读取派生数据重建标记失败: {}", token.symbol, e),
+        }
+        
+        info!("{}: 跳过初始同步，直接进入增量同步模式", token.symbol);
+    }
+
+    Ok(())
+}
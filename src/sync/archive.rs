@@ -11,6 +11,8 @@
  *   - 获取归档canister信息
  *   - 按批次获取归档交易
  *   - 处理和保存交易数据
+ *   - 收到停止信号后在批次之间退出
+ *   - 按批次更新同步进度（见 sync::progress）
  * - process_single_archive函数: 处理单个归档canister的函数
  *   - 测试归档canister可用性
  *   - 分批处理归档交易
@@ -22,25 +24,29 @@ use ic_agent::Agent;
 use ic_agent::export::Principal;
 use tokio::time::Duration;
 use num_traits::ToPrimitive;
-use mongodb::{Collection, bson::Document};
-use crate::blockchain::{fetch_archives, fetch_archive_transactions, test_archive_transactions};
+use crate::db::TokenCollections;
+use crate::blockchain::{fetch_archives, fetch_archive_transactions, get_ledger_log_length, test_archive_transactions};
 use crate::db::transactions::save_transaction;
 use crate::db::accounts::save_account_transaction;
-use crate::utils::group_transactions_by_account;
+use crate::utils::get_transaction_account_roles;
+use crate::shutdown;
+use crate::sync::progress::{self, Phase};
+use crate::ic::client::circuit_retry_after;
 use crate::models::{ArchiveInfo, Transaction, ARCHIVE_BATCH_SIZE};
 use log::{info, debug, error, warn};
 
 /// 同步归档canister的交易数据
+///
+/// `from_index` 之前的交易不同步，完整索引时传 0。
 pub async fn sync_archive_transactions(
     agent: &Agent,
     canister_id: &Principal,
-    tx_col: &Collection<Document>,
-    accounts_col: &Collection<Document>,
-    _balances_col: &Collection<Document>,
-    _supply_col: &Collection<Document>,
+    collections: &TokenCollections,
     _token_decimals: u8,
     calculate_balance: bool,
-) -> Result<Vec<Transaction>, Box<dyn Error>> {
+    from_index: u64,
+    token_symbol: &str,
+) -> Result<Vec<Transaction>, Box<dyn Error + Send + Sync>> {
     info!("获取归档信息...");
     
     // 获取所有归档canister信息
@@ -68,17 +74,37 @@ pub async fn sync_archive_transactions(
         return Ok(Vec::new());
     }
     
+    // 归档阶段的进度区间为 [起点, 最后一个归档的终点]，同时记录链上最新索引用于计算落后量
+    let archives_end = archives.iter()
+        .map(|a| a.block_range_end.0.to_u64().unwrap_or(0))
+        .max()
+        .unwrap_or(0);
+    let archives_start = archives.iter()
+        .map(|a| a.block_range_start.0.to_u64().unwrap_or(0))
+        .min()
+        .unwrap_or(0)
+        .max(from_index);
+    progress::begin_phase(token_symbol, Phase::Archives, archives_start, archives_end);
+    match get_ledger_log_length(agent, canister_id).await {
+        Ok(log_length) => progress::set_log_length(token_symbol, log_length),
+        Err(e) => debug!("获取主账本日志长度失败: {}", e),
+    }
+    
     // 返回值，收集所有同步到的交易
     let mut all_transactions: Vec<Transaction> = Vec::new();
-    let mut archive_count = 1;
-    
-    for archive in &archives {
+    'archives: for (archive_count, archive) in (1..).zip(archives.iter()) {
         let start = archive.block_range_start.0.to_u64().unwrap_or(0);
         let end = archive.block_range_end.0.to_u64().unwrap_or(0);
         
+        // 部分索引时跳过起点之前的归档区间
+        if end < from_index {
+            debug!("归档 {} 的范围 {}-{} 早于起始索引 {}，跳过", archive.canister_id, start, end, from_index);
+            continue;
+        }
+        let start = start.max(from_index);
+        
         info!("处理归档 {}/{}: canister_id={}", archive_count, archives.len(), archive.canister_id);
         debug!("归档范围: {}-{}", start, end);
-        archive_count += 1;
         
         // 先尝试获取1笔交易，测试归档canister是否可用
         match test_archive_transactions(agent, &archive.canister_id, start, 1).await {
@@ -103,6 +129,12 @@ pub async fn sync_archive_transactions(
         let mut current = start;
         
         while current <= end {
+            // 收到停止信号后不再拉取新批次，由调用方决定是否完成初始同步
+            if shutdown::is_requested() {
+                info!("收到停止信号，归档同步停止在索引 {}", current);
+                break 'archives;
+            }
+            
             let length = if current + batch_size > end {
                 end - current + 1
             } else {
@@ -123,17 +155,14 @@ pub async fn sync_archive_transactions(
                         let mut fail = 0;
                         
                         for tx in &transactions {
-                            match save_transaction(tx_col, tx).await {
+                            match save_transaction(&collections.tx_col, tx).await {
                                 Ok(_) => {
                                     success += 1;
                                     
                                     // 更新账户-交易关系
                                     if let Some(index) = tx.index {
-                                        let tx_array = vec![tx.clone()];
-                                        let account_txs = group_transactions_by_account(&tx_array);
-                                        
-                                        for (account, _) in &account_txs {
-                                            if let Err(e) = save_account_transaction(accounts_col, account, index).await {
+                                        for (account, role) in get_transaction_account_roles(tx) {
+                                            if let Err(e) = save_account_transaction(&collections.accounts_col, &collections.account_tx_col, &account, index, role).await {
                                                 debug!("保存账户-交易关系失败 (账户: {}, 交易索引: {}): {}", 
                                                     account, index, e);
                                             }
@@ -163,9 +192,16 @@ pub async fn sync_archive_transactions(
                         debug!("批次 {}-{} 未获取到交易", current, current + length - 1);
                     }
                     
+                    progress::advance(token_symbol, current + length - 1);
                     current += length;
                 },
                 Err(e) => {
+                    // canister 已熔断时等待熔断结束后重试当前批次，不跳过
+                    if let Some(retry_after) = circuit_retry_after(e.as_ref()) {
+                        warn!("{}，等待 {:?} 后重试批次 {}-{}", e, retry_after, current, current + length - 1);
+                        tokio::time::sleep(retry_after).await;
+                        continue;
+                    }
                     error!("获取归档交易失败: {}", e);
                     // 尝试跳过当前批次，继续下一批次
                     current += length / 2;
@@ -189,10 +225,9 @@ async fn process_single_archive(
     archive_info: &ArchiveInfo,
     index: usize,
     total: usize,
-    tx_col: &Collection<Document>,
-    accounts_col: &Collection<Document>,
+    collections: &TokenCollections,
     _token_decimals: u8,
-) -> Result<Vec<Transaction>, Box<dyn Error>> {
+) -> Result<Vec<Transaction>, Box<dyn Error + Send + Sync>> {
     info!("\n处理归档 {}/{}: canister_id={}", index, total, archive_info.canister_id);
     let archive_canister_id = &archive_info.canister_id;
     let block_range_start = archive_info.block_range_start.0.to_u64().unwrap_or(0);
@@ -205,7 +240,7 @@ async fn process_single_archive(
     
     // 先测试单个交易的解码
     match test_archive_transactions(
-        &agent,
+        agent,
         archive_canister_id,
         block_range_start,
         1
@@ -225,6 +260,11 @@ async fn process_single_archive(
             let max_consecutive_errors = 3;
             
             while current_start <= block_range_end && error_count < max_consecutive_errors {
+                if shutdown::is_requested() {
+                    info!("收到停止信号，归档同步停止在索引 {}", current_start);
+                    break;
+                }
+                
                 let current_length = std::cmp::min(ARCHIVE_BATCH_SIZE, 
                               block_range_end.saturating_sub(current_start) + 1);
                               
@@ -237,7 +277,7 @@ async fn process_single_archive(
                         current_start + current_length - 1);
                 
                 match fetch_archive_transactions(
-                    &agent,
+                    agent,
                     archive_canister_id,
                     current_start,
                     current_length
@@ -268,7 +308,7 @@ async fn process_single_archive(
                         let mut save_error_count = 0;
                         
                         for tx in &sorted_transactions {
-                            match save_transaction(tx_col, tx).await {
+                            match save_transaction(&collections.tx_col, tx).await {
                                 Ok(_) => {
                                     success_count += 1;
                                     
@@ -276,12 +316,9 @@ async fn process_single_archive(
                                     synced_transactions.push(tx.clone());
                                     
                                     let index = tx.index.unwrap_or(0);
-                                    let tx_clone = tx.clone();
-                                    let tx_array = vec![tx_clone];
-                                    let account_txs = group_transactions_by_account(&tx_array);
                                     
-                                    for (account, _) in &account_txs {
-                                        if let Err(e) = save_account_transaction(accounts_col, account, index).await {
+                                    for (account, role) in get_transaction_account_roles(tx) {
+                                        if let Err(e) = save_account_transaction(&collections.accounts_col, &collections.account_tx_col, &account, index, role).await {
                                             error!("保存账户-交易关系失败: {}", e);
                                             save_error_count += 1;
                                         }
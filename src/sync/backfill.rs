@@ -0,0 +1,353 @@
+/**
+ * 文件描述: 分片回填模块，将超大账本的初始同步拆分给多个工作进程并行执行
+ * 功能概述:
+ * - 协调者按归档边界和单元大小将 [起点, 当前链上最新索引] 拆分为工作单元，保存到 backfill_jobs 集合
+ * - 工作进程认领单元，从归档或主账本拉取交易并写入交易和账户关系（不计算余额），完成后标记单元
+ * - 认领带过期时间，处理过程中按子区间续期；进程崩溃后单元过期，可被其他工作进程重新认领
+ * - 全部单元完成后由协调者统一计算余额和总供应量，并将同步状态切换为增量同步
+ * - 启动同步时检测到未完成的回填计划，同步进程本身也作为工作进程参与，处理完后完成收尾
+ *
+ * 主要组件:
+ * - plan_backfill函数: 创建代币的回填计划
+ * - split_units函数: 按归档边界和单元大小拆分工作单元
+ * - run_backfill_worker函数: 认领并处理工作单元，直到所选代币的单元全部完成
+ * - finalize_backfill函数: 协调者在全部单元完成后计算余额并切换到增量同步
+ */
+
+use std::error::Error;
+use std::time::Instant;
+use ic_agent::Agent;
+use ic_agent::export::Principal;
+use log::{info, warn, error};
+use mongodb::bson::Document;
+use tokio::time::Duration;
+use crate::blockchain::get_ledger_log_length;
+use crate::config::parse_canister_id;
+use crate::db::DbConnection;
+use crate::ic::client::circuit_retry_after;
+use crate::db::backfill_jobs::{
+    claim_unit, complete_unit, create_plan, fail_unit, finish_plan, get_plan, get_progress, renew_unit,
+    BackfillUnit, MAX_UNIT_ATTEMPTS,
+};
+use crate::db::balances::calculate_all_balances;
+use crate::db::leases;
+use crate::db::sync_status::{get_sync_status, set_full_sync_mode, set_incremental_mode, update_balance_calculated_index};
+use crate::db::transactions::find_transaction_by_index;
+use crate::models::TokenConfig;
+use crate::shutdown;
+use crate::sync::gaps::{backfill_range, load_archive_ranges, ArchiveRange};
+use crate::sync::partial::resolve_partial_start;
+use crate::utils::create_error;
+
+/// 默认每个工作单元包含的交易索引数量
+pub const DEFAULT_UNIT_SIZE: u64 = 100_000;
+/// 单元内每次拉取并保存的索引跨度，每个子区间完成后续期一次
+const UNIT_CHUNK_SIZE: u64 = 2000;
+/// 工作单元认领的有效期，处理每个子区间后续期
+const UNIT_CLAIM_TTL: Duration = Duration::from_secs(120);
+/// 没有可认领的单元时等待其他工作进程的间隔
+const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// 创建代币的回填计划，返回工作单元数量
+///
+/// 回填区间从部分索引起点（未配置时为0）到当前链上最新的交易索引；之后产生的交易由增量同步处理。
+/// 已完成初始同步的代币不能创建回填计划。
+pub async fn plan_backfill(
+    agent: &Agent,
+    db_conn: &DbConnection,
+    token: &TokenConfig,
+    unit_size: u64,
+) -> Result<usize, Box<dyn Error + Send + Sync>> {
+    let token_symbol = &token.symbol;
+    if let Some(status) = get_sync_status(&db_conn.sync_status_col, token_symbol).await? {
+        if status.sync_mode == "incremental" && status.last_synced_index > 0 {
+            return Err(create_error(&format!(
+                "{}: 已完成初始同步（同步到 {}），不需要分片回填；需要重新同步时先执行 reset",
+                token_symbol, status.last_synced_index
+            )));
+        }
+    }
+
+    let canister_id = parse_canister_id(&token.canister_id)?;
+    let start = resolve_partial_start(agent, &canister_id, db_conn, token).await?
+        .map(|start| start.index)
+        .unwrap_or(0);
+    let log_length = get_ledger_log_length(agent, &canister_id).await?;
+    if log_length <= start {
+        return Err(create_error(&format!("{}: 链上没有需要回填的交易（起点 {}，链上交易数 {}）", token_symbol, start, log_length)));
+    }
+    let end = log_length - 1;
+
+    let archives = load_archive_ranges(agent, &canister_id).await?;
+    let unit_size = unit_size.max(UNIT_CHUNK_SIZE);
+    let units = split_units(&archives, start, end, unit_size);
+
+    // 同步状态保持全量模式，回填完成前同步进程不会开始增量同步
+    set_full_sync_mode(&db_conn.sync_status_col, token_symbol).await?;
+    create_plan(&db_conn.backfill_col, token_symbol, start, end, unit_size, &units).await?;
+    info!("{}: 已创建回填计划: 索引 {} 到 {}，{} 个工作单元（每个最多 {} 笔）", token_symbol, start, end, units.len(), unit_size);
+    Ok(units.len())
+}
+
+/// 按归档边界和单元大小拆分 [start, end]，单元不跨越归档 canister 的边界
+pub fn split_units(archives: &[ArchiveRange], start: u64, end: u64, unit_size: u64) -> Vec<BackfillUnit> {
+    let mut units = Vec::new();
+    let mut current = start;
+    while current <= end {
+        let (segment_end, source) = match archives.iter().find(|a| a.start <= current && current <= a.end) {
+            Some(archive) => (archive.end.min(end), "archive"),
+            // 主账本区间延伸到下一个归档开始之前
+            None => {
+                let next_archive = archives.iter().map(|a| a.start).filter(|s| *s > current).min();
+                (next_archive.map(|s| s - 1).unwrap_or(end).min(end), "ledger")
+            },
+        };
+        let unit_end = segment_end.min(current.saturating_add(unit_size - 1));
+        units.push(BackfillUnit { unit_id: units.len() as i64, start: current, end: unit_end, source });
+        current = unit_end + 1;
+    }
+    units
+}
+
+/// 认领并处理所选代币的工作单元，直到这些代币的单元全部完成，返回是否全部完成
+///
+/// 没有可认领的单元但仍有其他工作进程在处理时等待；存在超过最大尝试次数的单元时返回错误。
+/// 收到停止信号时在子区间之间退出，已认领的单元释放后由其他工作进程继续。
+pub async fn run_backfill_worker(
+    agent: &Agent,
+    db_conn: &DbConnection,
+    tokens: &[&TokenConfig],
+    worker_id: &str,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    info!("回填工作进程 {} 已启动，代币: {}", worker_id,
+          tokens.iter().map(|t| t.symbol.as_str()).collect::<Vec<_>>().join(", "));
+    let mut canister_ids = Vec::new();
+    for token in tokens {
+        canister_ids.push(parse_canister_id(&token.canister_id)?);
+    }
+
+    loop {
+        if shutdown::is_requested() {
+            return Ok(false);
+        }
+
+        let mut claimed = false;
+        for (token, canister_id) in tokens.iter().zip(&canister_ids) {
+            if let Some(unit) = claim_unit(&db_conn.backfill_col, &token.symbol, worker_id, UNIT_CLAIM_TTL).await? {
+                claimed = true;
+                process_unit(agent, db_conn, token, canister_id, &unit, worker_id).await?;
+                break;
+            }
+        }
+        if claimed {
+            continue;
+        }
+
+        // 没有可认领的单元：全部完成时退出，否则等待其他工作进程或过期的认领
+        let mut all_complete = true;
+        for token in tokens {
+            if get_plan(&db_conn.backfill_col, &token.symbol).await?.is_none() {
+                continue;
+            }
+            let progress = get_progress(&db_conn.backfill_col, &token.symbol).await?;
+            if progress.is_complete() {
+                continue;
+            }
+            all_complete = false;
+            if progress.pending == 0 && progress.claimed == 0 && progress.failed > 0 {
+                return Err(create_error(&format!(
+                    "{}: {} 个工作单元超过最大尝试次数 ({})，请检查错误后执行 backfill retry",
+                    token.symbol, progress.failed, MAX_UNIT_ATTEMPTS
+                )));
+            }
+        }
+        if all_complete {
+            info!("回填工作进程 {}: 所选代币的工作单元均已完成", worker_id);
+            return Ok(true);
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(IDLE_POLL_INTERVAL) => {},
+            _ = shutdown::requested() => return Ok(false),
+        }
+    }
+}
+
+/// 处理一个已认领的工作单元，失败时释放单元并记录错误（不中断工作进程）
+async fn process_unit(
+    agent: &Agent,
+    db_conn: &DbConnection,
+    token: &TokenConfig,
+    canister_id: &Principal,
+    unit: &Document,
+    worker_id: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let token_symbol = &token.symbol;
+    let unit_id = unit.get_i64("unit_id")?;
+    let start = unit.get_i64("start")? as u64;
+    let end = unit.get_i64("end")? as u64;
+    let attempt = unit.get_i32("attempts").unwrap_or(1);
+    let collections = db_conn.token_collections(token_symbol)
+        .ok_or_else(|| create_error(&format!("没有找到代币 {} 的集合", token_symbol)))?;
+    info!("{}: 开始处理工作单元 #{} [{}-{}] (第 {} 次)", token_symbol, unit_id, start, end, attempt);
+
+    let started = Instant::now();
+    let result: Result<Option<u64>, Box<dyn Error + Send + Sync>> = async {
+        // 归档区间可能在计划创建后变化，每个单元重新读取
+        let archives = load_archive_ranges(agent, canister_id).await?;
+        let mut saved = 0u64;
+        let mut current = start;
+        while current <= end {
+            if shutdown::is_requested() {
+                return Ok(None);
+            }
+            let chunk_end = (current + UNIT_CHUNK_SIZE - 1).min(end);
+            saved += backfill_range(agent, canister_id, &archives, &collections, current, chunk_end).await?.len() as u64;
+            if !renew_unit(&db_conn.backfill_col, token_symbol, unit_id, worker_id, UNIT_CLAIM_TTL).await? {
+                return Err(create_error("工作单元的认领已过期并被其他工作进程接管"));
+            }
+            current = chunk_end + 1;
+        }
+        Ok(Some(saved))
+    }.await;
+
+    match result {
+        Ok(Some(saved)) => {
+            complete_unit(&db_conn.backfill_col, token_symbol, unit_id, worker_id, saved).await?;
+            info!("{}: 工作单元 #{} [{}-{}] 完成，写入 {} 笔交易，用时 {:.1} 秒",
+                  token_symbol, unit_id, start, end, saved, started.elapsed().as_secs_f64());
+        },
+        Ok(None) => {
+            fail_unit(&db_conn.backfill_col, token_symbol, unit_id, worker_id, "工作进程收到停止信号", false).await?;
+            warn!("{}: 收到停止信号，工作单元 #{} 已释放", token_symbol, unit_id);
+        },
+        Err(e) => {
+            error!("{}: 工作单元 #{} [{}-{}] 处理失败 (第 {}/{} 次): {}", token_symbol, unit_id, start, end, attempt, MAX_UNIT_ATTEMPTS, e);
+            // canister 熔断期间的失败不计入尝试次数，等待熔断结束后再认领
+            let retry_after = circuit_retry_after(e.as_ref());
+            fail_unit(&db_conn.backfill_col, token_symbol, unit_id, worker_id, &e.to_string(), retry_after.is_none()).await?;
+            if let Some(wait) = retry_after {
+                tokio::time::sleep(wait).await;
+            }
+        },
+    }
+    Ok(())
+}
+
+/// 协调者收尾：等待全部工作单元完成后计算余额和总供应量，并切换到增量同步，返回是否完成
+///
+/// `agent` 不为 None 时协调者本身也认领并处理工作单元。调用方需要持有代币的同步租约。
+pub async fn finalize_backfill(
+    agent: Option<&Agent>,
+    db_conn: &DbConnection,
+    token: &TokenConfig,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    let token_symbol = &token.symbol;
+    let plan = get_plan(&db_conn.backfill_col, token_symbol).await?
+        .ok_or_else(|| create_error(&format!("{}: 没有回填计划", token_symbol)))?;
+    if plan.get_bool("finished").unwrap_or(false) {
+        return Ok(true);
+    }
+    let end = plan.get_i64("end")? as u64;
+
+    match agent {
+        Some(agent) => {
+            let worker_id = format!("{}-coordinator", leases::instance_id());
+            if !run_backfill_worker(agent, db_conn, &[token], &worker_id).await? {
+                return Ok(false);
+            }
+        },
+        None => loop {
+            let progress = get_progress(&db_conn.backfill_col, token_symbol).await?;
+            if progress.is_complete() {
+                break;
+            }
+            info!("{}: 等待回填完成: {}/{} 个工作单元已完成，处理中 {}，待处理 {}，失败 {}",
+                  token_symbol, progress.done, progress.total, progress.claimed, progress.pending, progress.failed);
+            tokio::select! {
+                _ = tokio::time::sleep(IDLE_POLL_INTERVAL) => {},
+                _ = shutdown::requested() => return Ok(false),
+            }
+        },
+    }
+
+    let collections = db_conn.token_collections(token_symbol)
+        .ok_or_else(|| create_error(&format!("没有找到代币 {} 的集合", token_symbol)))?;
+    info!("{}: 所有工作单元已完成，统一计算余额和总供应量...", token_symbol);
+    let (success, failed) = calculate_all_balances(
+        &collections.accounts_col,
+        &collections.account_tx_col,
+        &collections.tx_col,
+        &collections.balances_col,
+        &collections.total_supply_col,
+        &collections.balance_anomalies_col,
+        token
+    ).await?;
+    if failed > 0 {
+        return Err(create_error(&format!("{}: {} 个账户的余额计算失败，回填计划保留，重新启动后会再次计算", token_symbol, failed)));
+    }
+
+    let timestamp = find_transaction_by_index(&collections.tx_col, end).await?
+        .and_then(|tx| tx.get_i64("timestamp").ok())
+        .unwrap_or(0) as u64;
+    update_balance_calculated_index(&db_conn.sync_status_col, token_symbol, end).await?;
+    set_incremental_mode(&db_conn.sync_status_col, token_symbol, end, timestamp).await?;
+    finish_plan(&db_conn.backfill_col, token_symbol).await?;
+    info!("{}: 分片回填完成: {} 个账户，从索引 {} 开始增量同步", token_symbol, success, end + 1);
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_agent::export::Principal;
+
+    fn archive(id: u8, start: u64, end: u64) -> ArchiveRange {
+        ArchiveRange { canister_id: Principal::from_slice(&[id]), start, end }
+    }
+
+    fn ranges(units: &[BackfillUnit]) -> Vec<(u64, u64, &'static str)> {
+        units.iter().map(|u| (u.start, u.end, u.source)).collect()
+    }
+
+    #[test]
+    fn splits_ledger_range_by_unit_size() {
+        let units = split_units(&[], 100, 349, 100);
+        assert_eq!(ranges(&units), vec![(100, 199, "ledger"), (200, 299, "ledger"), (300, 349, "ledger")]);
+        assert_eq!(units.iter().map(|u| u.unit_id).collect::<Vec<_>>(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn units_do_not_cross_archive_boundaries() {
+        let archives = [archive(1, 0, 149), archive(2, 150, 249)];
+        let units = split_units(&archives, 0, 399, 100);
+        assert_eq!(ranges(&units), vec![
+            (0, 99, "archive"),
+            (100, 149, "archive"),
+            (150, 249, "archive"),
+            (250, 349, "ledger"),
+            (350, 399, "ledger"),
+        ]);
+    }
+
+    #[test]
+    fn ledger_unit_stops_before_next_archive() {
+        // 起点落在归档之间的空隙，主账本单元在下一个归档开始前结束
+        let archives = [archive(1, 0, 99), archive(2, 200, 299)];
+        let units = split_units(&archives, 150, 250, 1000);
+        assert_eq!(ranges(&units), vec![(150, 199, "ledger"), (200, 250, "archive")]);
+    }
+
+    #[test]
+    fn units_cover_range_without_gaps() {
+        let archives = [archive(1, 0, 1234), archive(2, 1235, 5000)];
+        let units = split_units(&archives, 17, 7777, 500);
+        assert_eq!(units.first().unwrap().start, 17);
+        assert_eq!(units.last().unwrap().end, 7777);
+        for pair in units.windows(2) {
+            assert_eq!(pair[0].end + 1, pair[1].start);
+        }
+        assert!(units.iter().all(|u| u.end - u.start < 500));
+        let crosses = |u: &BackfillUnit, boundary: u64| u.start <= boundary && u.end > boundary;
+        assert!(units.iter().all(|u| !crosses(u, 1234) && !crosses(u, 5000)));
+    }
+}
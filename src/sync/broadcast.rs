@@ -0,0 +1,48 @@
+/**
+ * 文件描述: 已提交交易的进程内广播，供交易推送流（SSE / WebSocket）使用
+ * 功能概述:
+ * - 主账本同步每提交一个批次后，将批次中的交易广播给本进程内的所有订阅者
+ * - 广播在批次提交之后进行，订阅者收到的交易已经可以从数据库中读取
+ * - 订阅者处理过慢时会丢失较早的批次（Lagged），由订阅者从数据库补齐
+ * - 只有运行同步的进程会广播；只读 API 实例或代币由其他进程同步时，推送流改为轮询数据库
+ *
+ * 主要组件:
+ * - CommittedBatch结构体: 一个已提交的批次
+ * - publish函数: 广播已提交的批次
+ * - subscribe函数: 订阅之后提交的批次
+ */
+
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use crate::models::Transaction;
+
+/// 广播通道可缓存的批次数，订阅者落后更多时收到 Lagged
+const CHANNEL_CAPACITY: usize = 256;
+
+/// 已提交的批次
+#[derive(Debug)]
+pub struct CommittedBatch {
+    pub token_symbol: String,
+    /// 按交易索引升序
+    pub transactions: Vec<Transaction>,
+}
+
+lazy_static::lazy_static! {
+    static ref CHANNEL: broadcast::Sender<Arc<CommittedBatch>> = broadcast::channel(CHANNEL_CAPACITY).0;
+}
+
+/// 广播已提交的批次，没有订阅者时不做任何事
+pub fn publish(token_symbol: &str, transactions: &[Transaction]) {
+    if CHANNEL.receiver_count() == 0 || transactions.is_empty() {
+        return;
+    }
+    let _ = CHANNEL.send(Arc::new(CommittedBatch {
+        token_symbol: token_symbol.to_string(),
+        transactions: transactions.to_vec(),
+    }));
+}
+
+/// 订阅之后提交的批次（所有代币）
+pub fn subscribe() -> broadcast::Receiver<Arc<CommittedBatch>> {
+    CHANNEL.subscribe()
+}
@@ -0,0 +1,399 @@
+/**
+ * 文件描述: 交易索引缺口检测与定向回填模块
+ * 功能概述:
+ * - 在交易集合上通过聚合查询找出从链上第一笔交易到同步点之间所有缺失的索引区间
+ * - 将缺口记录到 sync_status 文档的 gap_scan 字段，便于通过 API 查看
+ * - 按缺口所在位置从对应的归档 canister 或主账本定向拉取缺失交易并重算受影响账户的余额
+ *
+ * 主要组件:
+ * - scan_gaps函数: 检测缺口并保存扫描结果
+ * - backfill_gaps函数: 回填最近一次扫描记录的缺口
+ * - find_missing_ranges函数: 查找指定索引区间内缺失的子区间
+ * - fetch_range函数: 按索引所在位置从归档或主账本拉取指定区间的交易（校验命令也使用）
+ * - backfill_range函数: 拉取并保存指定区间的交易和账户关系（分片回填也使用）
+ *
+ * 已归档到冷存储的区间不在扫描范围内。归档批次在连续出错后会被跳过，
+ * 这类遗漏以前只能靠人工发现，现在会在下一次扫描时被记录并自动回填。
+ */
+
+use std::error::Error;
+use ic_agent::Agent;
+use ic_agent::export::Principal;
+use chrono::Utc;
+use futures::stream::StreamExt;
+use log::{info, warn, debug};
+use mongodb::{Collection, bson::{doc, Bson, Document}};
+use mongodb::options::{AggregateOptions, FindOneOptions, FindOptions};
+use num_traits::ToPrimitive;
+use crate::ic::client::circuit_retry_after;
+use crate::blockchain::{fetch_archives, fetch_archive_transactions, fetch_ledger_transactions, get_first_transaction_index};
+use crate::db::{DbConnection, TokenCollections};
+use crate::db::accounts::save_account_transaction;
+use crate::db::balances::calculate_incremental_balances;
+use crate::db::cold_storage::cold_storage_for;
+use crate::db::sync_status::{get_gap_scan, get_partial_start, get_sync_status, save_gap_scan, GapRecord, GapScan};
+use crate::db::transactions::save_transaction;
+use crate::models::{Transaction, TokenConfig, ARCHIVE_BATCH_SIZE, BATCH_SIZE};
+use crate::utils::{create_error, get_transaction_account_roles};
+
+/// 单次扫描最多记录的缺口数量，避免 sync_status 文档过大
+const MAX_RECORDED_GAPS: usize = 1000;
+/// 单个缺口的最大回填尝试次数，超过后只记录不再自动回填
+pub const MAX_BACKFILL_ATTEMPTS: u32 = 5;
+/// 每次回填最多处理的交易索引数量
+const MAX_BACKFILL_PER_RUN: u64 = 20000;
+
+/// 归档 canister 覆盖的索引区间（含两端）
+pub struct ArchiveRange {
+    pub canister_id: Principal,
+    pub start: u64,
+    pub end: u64,
+}
+
+/// 获取按起始索引排序的归档区间
+pub async fn load_archive_ranges(
+    agent: &Agent,
+    canister_id: &Principal,
+) -> Result<Vec<ArchiveRange>, Box<dyn Error + Send + Sync>> {
+    let mut ranges: Vec<ArchiveRange> = fetch_archives(agent, canister_id).await?
+        .into_iter()
+        .map(|a| ArchiveRange {
+            canister_id: a.canister_id,
+            start: a.block_range_start.0.to_u64().unwrap_or(0),
+            end: a.block_range_end.0.to_u64().unwrap_or(0),
+        })
+        .collect();
+    ranges.sort_by_key(|r| r.start);
+    Ok(ranges)
+}
+
+/// 判断缺口应从哪里回填
+fn gap_source(archives: &[ArchiveRange], start: u64, end: u64) -> &'static str {
+    let in_archive = |index: u64| archives.iter().any(|a| a.start <= index && index <= a.end);
+    match (in_archive(start), in_archive(end)) {
+        (true, true) => "archive",
+        (false, false) => "ledger",
+        _ => "mixed",
+    }
+}
+
+fn bson_to_u64(value: Option<&Bson>) -> Option<u64> {
+    match value? {
+        Bson::Int64(v) => Some(*v as u64),
+        Bson::Int32(v) => Some(*v as u64),
+        Bson::Double(v) => Some(*v as u64),
+        _ => None,
+    }
+}
+
+/// 查找 [from, to] 区间内缺失的交易索引子区间
+///
+/// 优先使用 $setWindowFields 聚合（MongoDB 5.0+）由服务端比较相邻索引，
+/// 聚合不可用时退化为按索引顺序流式扫描。
+pub async fn find_missing_ranges(
+    tx_col: &Collection<Document>,
+    from: u64,
+    to: u64,
+) -> Result<Vec<(u64, u64)>, Box<dyn Error + Send + Sync>> {
+    if from > to {
+        return Ok(Vec::new());
+    }
+
+    match find_missing_ranges_aggregate(tx_col, from, to).await {
+        Ok(ranges) => Ok(ranges),
+        Err(e) => {
+            warn!("聚合查询缺口失败，改用顺序扫描: {}", e);
+            find_missing_ranges_scan(tx_col, from, to).await
+        }
+    }
+}
+
+async fn find_missing_ranges_aggregate(
+    tx_col: &Collection<Document>,
+    from: u64,
+    to: u64,
+) -> Result<Vec<(u64, u64)>, Box<dyn Error + Send + Sync>> {
+    let range_filter = doc! { "index": { "$gte": from as i64, "$lte": to as i64 } };
+    let pipeline = vec![
+        doc! { "$match": range_filter.clone() },
+        doc! { "$project": { "_id": 0, "index": 1 } },
+        doc! {
+            "$setWindowFields": {
+                "sortBy": { "index": 1 },
+                "output": {
+                    "prev": { "$shift": { "output": "$index", "by": -1, "default": from as i64 - 1 } }
+                }
+            }
+        },
+        doc! { "$match": { "$expr": { "$gt": [ { "$subtract": ["$index", "$prev"] }, 1 ] } } },
+        doc! {
+            "$project": {
+                "start": { "$add": ["$prev", 1] },
+                "end": { "$subtract": ["$index", 1] },
+            }
+        },
+        doc! { "$sort": { "start": 1 } },
+    ];
+    let options = AggregateOptions::builder().allow_disk_use(true).build();
+
+    let mut ranges = Vec::new();
+    let mut cursor = tx_col.aggregate(pipeline, options).await?;
+    while let Some(result) = cursor.next().await {
+        let gap_doc = result?;
+        if let (Some(start), Some(end)) = (bson_to_u64(gap_doc.get("start")), bson_to_u64(gap_doc.get("end"))) {
+            ranges.push((start, end));
+        }
+    }
+
+    // 区间末尾的缺口无法通过相邻索引比较得到，单独根据最大索引补上
+    let options = FindOneOptions::builder()
+        .sort(doc! { "index": -1 })
+        .projection(doc! { "index": 1 })
+        .build();
+    match tx_col.find_one(range_filter, options).await? {
+        Some(last) => {
+            let last_index = bson_to_u64(last.get("index")).unwrap_or(to);
+            if last_index < to {
+                ranges.push((last_index + 1, to));
+            }
+        },
+        None => ranges.push((from, to)),
+    }
+
+    Ok(ranges)
+}
+
+async fn find_missing_ranges_scan(
+    tx_col: &Collection<Document>,
+    from: u64,
+    to: u64,
+) -> Result<Vec<(u64, u64)>, Box<dyn Error + Send + Sync>> {
+    let options = FindOptions::builder()
+        .sort(doc! { "index": 1 })
+        .projection(doc! { "_id": 0, "index": 1 })
+        .batch_size(10000)
+        .build();
+    let mut cursor = tx_col.find(
+        doc! { "index": { "$gte": from as i64, "$lte": to as i64 } },
+        options
+    ).await?;
+
+    let mut ranges = Vec::new();
+    let mut expected = from;
+    while let Some(result) = cursor.next().await {
+        let index = match bson_to_u64(result?.get("index")) {
+            Some(index) => index,
+            None => continue,
+        };
+        if index > expected {
+            ranges.push((expected, index - 1));
+        }
+        expected = expected.max(index + 1);
+    }
+    if expected <= to {
+        ranges.push((expected, to));
+    }
+
+    Ok(ranges)
+}
+
+/// 检测缺口并保存扫描结果
+///
+/// 扫描范围从链上第一笔交易（部分索引时为起点，或冷存储归档之后）到 last_synced_index。
+/// 与上一次扫描相同的缺口保留其回填尝试次数和错误信息。
+pub async fn scan_gaps(
+    agent: &Agent,
+    canister_id: &Principal,
+    db_conn: &DbConnection,
+    collections: &TokenCollections,
+    token: &TokenConfig,
+) -> Result<GapScan, Box<dyn Error + Send + Sync>> {
+    let status = get_sync_status(&db_conn.sync_status_col, &token.symbol).await?
+        .ok_or_else(|| create_error("尚未同步，无法扫描缺口"))?;
+
+    let archives = load_archive_ranges(agent, canister_id).await?;
+    let chain_start = match archives.first() {
+        Some(first) => first.start,
+        None => get_first_transaction_index(agent, canister_id).await?,
+    };
+    // 部分索引时起点之前的交易不属于缺口
+    let chain_start = match get_partial_start(&db_conn.sync_status_col, &token.symbol).await? {
+        Some(start) => chain_start.max(start.index),
+        None => chain_start,
+    };
+    let from = match cold_storage_for(&collections.tx_col).and_then(|s| s.archived_until()) {
+        Some(until) => chain_start.max(until + 1),
+        None => chain_start,
+    };
+    let to = status.last_synced_index;
+
+    debug!("{}: 扫描交易索引缺口 [{}-{}]", token.symbol, from, to);
+    let mut ranges = find_missing_ranges(&collections.tx_col, from, to).await?;
+    if ranges.len() > MAX_RECORDED_GAPS {
+        warn!("{}: 发现 {} 个缺口，只记录前 {} 个", token.symbol, ranges.len(), MAX_RECORDED_GAPS);
+        ranges.truncate(MAX_RECORDED_GAPS);
+    }
+
+    let previous = get_gap_scan(&db_conn.sync_status_col, &token.symbol).await?
+        .map(|scan| scan.gaps)
+        .unwrap_or_default();
+    let now = Utc::now().timestamp();
+    let gaps: Vec<GapRecord> = ranges.into_iter()
+        .map(|(start, end)| {
+            match previous.iter().find(|g| g.start == start && g.end == end) {
+                Some(known) => known.clone(),
+                None => GapRecord {
+                    start,
+                    end,
+                    source: gap_source(&archives, start, end).to_string(),
+                    detected_at: now,
+                    attempts: 0,
+                    last_error: None,
+                },
+            }
+        })
+        .collect();
+
+    let scan = GapScan { scanned_at: now, from_index: from, to_index: to, gaps };
+    save_gap_scan(&db_conn.sync_status_col, &token.symbol, &scan).await?;
+
+    let missing: u64 = scan.gaps.iter().map(|g| g.count()).sum();
+    if missing > 0 {
+        warn!("{}: 发现 {} 个交易索引缺口，共缺失 {} 笔交易", token.symbol, scan.gaps.len(), missing);
+    } else {
+        debug!("{}: 交易索引 [{}-{}] 没有缺口", token.symbol, from, to);
+    }
+    Ok(scan)
+}
+
+/// 回填最近一次扫描记录的缺口，返回回填的交易数量
+///
+/// 已达到最大尝试次数的缺口会被跳过。回填的交易保存后按账户完整重算余额和总供应量。
+pub async fn backfill_gaps(
+    agent: &Agent,
+    canister_id: &Principal,
+    db_conn: &DbConnection,
+    collections: &TokenCollections,
+    token: &TokenConfig,
+) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    let mut scan = match get_gap_scan(&db_conn.sync_status_col, &token.symbol).await? {
+        Some(scan) => scan,
+        None => return Ok(0),
+    };
+    if !scan.gaps.iter().any(|g| g.attempts < MAX_BACKFILL_ATTEMPTS) {
+        return Ok(0);
+    }
+
+    let archives = load_archive_ranges(agent, canister_id).await?;
+    let mut budget = MAX_BACKFILL_PER_RUN;
+    let mut backfilled: Vec<Transaction> = Vec::new();
+    let mut remaining_gaps = Vec::new();
+
+    for mut gap in std::mem::take(&mut scan.gaps) {
+        if budget == 0 || gap.attempts >= MAX_BACKFILL_ATTEMPTS {
+            remaining_gaps.push(gap);
+            continue;
+        }
+
+        let end = gap.end.min(gap.start + budget - 1);
+        info!("{}: 回填缺口 [{}-{}] (来源: {})", token.symbol, gap.start, end, gap.source);
+        match backfill_range(agent, canister_id, &archives, collections, gap.start, end).await {
+            Ok(transactions) => {
+                budget -= end - gap.start + 1;
+                backfilled.extend(transactions);
+                if end < gap.end {
+                    // 本轮额度用完，剩余部分留到下一次回填
+                    gap.start = end + 1;
+                    remaining_gaps.push(gap);
+                }
+            },
+            Err(e) => {
+                // canister 熔断期间的失败不计入回填次数
+                if circuit_retry_after(e.as_ref()).is_none() {
+                    gap.attempts += 1;
+                }
+                gap.last_error = Some(e.to_string());
+                warn!("{}: 回填缺口 [{}-{}] 失败 (第 {}/{} 次): {}",
+                    token.symbol, gap.start, gap.end, gap.attempts, MAX_BACKFILL_ATTEMPTS, e);
+                remaining_gaps.push(gap);
+            }
+        }
+    }
+
+    scan.gaps = remaining_gaps;
+    save_gap_scan(&db_conn.sync_status_col, &token.symbol, &scan).await?;
+
+    if !backfilled.is_empty() {
+        calculate_incremental_balances(
+            &backfilled,
+            collections,
+            token
+        ).await?;
+        info!("{}: 已回填 {} 笔缺失交易并重算相关账户余额", token.symbol, backfilled.len());
+    }
+    Ok(backfilled.len() as u64)
+}
+
+/// 从归档或主账本拉取 [start, end] 区间的交易并保存（不计算余额，分片回填同样使用）
+pub async fn backfill_range(
+    agent: &Agent,
+    canister_id: &Principal,
+    archives: &[ArchiveRange],
+    collections: &TokenCollections,
+    start: u64,
+    end: u64,
+) -> Result<Vec<Transaction>, Box<dyn Error + Send + Sync>> {
+    let transactions = fetch_range(agent, canister_id, archives, start, end).await?;
+    for tx in &transactions {
+        save_transaction(&collections.tx_col, tx).await?;
+        if let Some(index) = tx.index {
+            for (account, role) in get_transaction_account_roles(tx) {
+                save_account_transaction(&collections.accounts_col, &collections.account_tx_col, &account, index, role).await?;
+            }
+        }
+    }
+    Ok(transactions)
+}
+
+/// 按索引所在位置从归档或主账本拉取 [start, end] 区间的交易，按索引升序
+pub async fn fetch_range(
+    agent: &Agent,
+    canister_id: &Principal,
+    archives: &[ArchiveRange],
+    start: u64,
+    end: u64,
+) -> Result<Vec<Transaction>, Box<dyn Error + Send + Sync>> {
+    let mut transactions = Vec::new();
+    let mut current = start;
+
+    while current <= end {
+        let fetched = match archives.iter().find(|a| a.start <= current && current <= a.end) {
+            Some(archive) => {
+                let length = ARCHIVE_BATCH_SIZE.min(archive.end - current + 1).min(end - current + 1);
+                fetch_archive_transactions(agent, &archive.canister_id, current, length).await?
+            },
+            None => {
+                let length = BATCH_SIZE.min(end - current + 1);
+                let (fetched, first_index, _log_length) = fetch_ledger_transactions(agent, canister_id, current, length).await?;
+                if current < first_index {
+                    return Err(create_error(&format!(
+                        "索引 {} 已从主账本移入归档，等待下一次扫描重新定位", current)));
+                }
+                fetched
+            },
+        };
+
+        let fetched: Vec<Transaction> = fetched.into_iter()
+            .filter(|tx| tx.index.map(|i| i >= current && i <= end).unwrap_or(false))
+            .collect();
+        let next = match fetched.iter().filter_map(|tx| tx.index).max() {
+            Some(last) => last + 1,
+            None => return Err(create_error(&format!("索引 {} 处未获取到交易", current))),
+        };
+
+        transactions.extend(fetched);
+        current = next;
+    }
+
+    Ok(transactions)
+}
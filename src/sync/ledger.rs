@@ -13,23 +13,32 @@
  *   - 同步状态检查: 检查和验证现有同步状态
  *   - 确定同步起点: 确定从哪个索引开始同步
  *   - 主同步循环: 循环获取和处理交易批次
- *   - 交易保存: 将交易保存到数据库
+ *   - 批次提交: 交易、账户关系、余额增量与同步游标一并提交
  *   - 错误恢复: 处理同步过程中的错误
+ *   - 优雅停止: 收到停止信号后在批次之间退出
+ *   - 租约检查: 本实例不再持有代币的同步租约时在批次之间退出（见 db::leases）
+ *   - 进度记录: 记录链上日志长度和已提交的索引（见 sync::progress）
+ *   - 交易广播: 批次提交后广播给本进程内的交易推送流（见 sync::broadcast）
  */
 
 use std::error::Error;
 use ic_agent::Agent;
 use ic_agent::export::Principal;
 use tokio::time::Duration;
-use mongodb::{Collection, bson::{doc, Document}};
+use mongodb::{Collection, bson::Document};
 use log::{info, error, warn, debug};
-use crate::db::transactions::get_latest_transaction_index;
+use crate::db::transactions::{find_transaction_by_index, get_latest_transaction_index};
 use crate::blockchain::{get_first_transaction_index, fetch_ledger_transactions};
-use crate::db::transactions::save_transaction;
-use crate::db::accounts::save_account_transaction;
-use crate::db::sync_status::{get_sync_status, set_incremental_mode};
-use crate::utils::{group_transactions_by_account};
+use crate::db::DbConnection;
+use crate::db::batch::commit_batch;
+use crate::db::leases;
+use crate::shutdown;
+use crate::sync::progress::{self, Phase};
+use crate::sync::broadcast;
+use crate::ic::client::circuit_retry_after;
+use crate::db::sync_status::{get_partial_start, get_sync_status, set_incremental_mode};
 use crate::models::{Transaction, BATCH_SIZE};
+use crate::utils::{create_error, nanos_to_datetime};
 
 /// 打印交易详细信息到日志
 fn log_transaction_details(tx: &Transaction) {
@@ -39,11 +48,7 @@ fn log_transaction_details(tx: &Transaction) {
     };
     
     // 将时间戳转换为可读时间格式
-    let timestamp = tx.timestamp;
-    let datetime = chrono::DateTime::from_timestamp(
-        (timestamp / 1_000_000_000) as i64, 
-        (timestamp % 1_000_000_000) as u32
-    ).unwrap_or_else(|| chrono::DateTime::from_timestamp(0, 0).unwrap());
+    let datetime = nanos_to_datetime(tx.timestamp);
     
     let time_str = datetime.format("%Y-%m-%d %H:%M:%S").to_string();
     
@@ -115,11 +120,7 @@ fn log_transaction_details(tx: &Transaction) {
                     info!("   ↪ 手续费: {}", fee);
                 }
                 if let Some(expires_at) = approve.expires_at {
-                    let expire_dt = chrono::DateTime::from_timestamp(
-                        (expires_at / 1_000_000_000) as i64, 
-                        (expires_at % 1_000_000_000) as u32
-                    ).unwrap_or_else(|| chrono::DateTime::from_timestamp(0, 0).unwrap());
-                    let expire_str = expire_dt.format("%Y-%m-%d %H:%M:%S").to_string();
+                    let expire_str = nanos_to_datetime(expires_at).format("%Y-%m-%d %H:%M:%S").to_string();
                     info!("   ↪ 过期时间: {}", expire_str);
                 }
                 if let Some(memo) = &approve.memo {
@@ -150,13 +151,13 @@ async fn verify_synced_transactions(
     _token_symbol: &str,
     last_synced_index: u64,
     verification_range: u64,
-) -> Result<(bool, u64), Box<dyn Error>> {
+) -> Result<(bool, u64), Box<dyn Error + Send + Sync>> {
     info!("验证同步点附近交易的完整性，从索引 {} 开始检查 {} 条记录", 
           last_synced_index.saturating_sub(verification_range), verification_range);
     
     // 验证最后同步的交易是否存在
-    let last_tx_exists = tx_col
-        .find_one(doc! { "index": last_synced_index as i64 }, None)
+    // 已按保留策略归档的交易从冷存储中查找
+    let last_tx_exists = find_transaction_by_index(tx_col, last_synced_index)
         .await?
         .is_some();
     
@@ -169,8 +170,7 @@ async fn verify_synced_transactions(
         let mut found_valid = false;
         
         for i in start_from..last_synced_index {
-            let tx_exists = tx_col
-                .find_one(doc! { "index": i as i64 }, None)
+            let tx_exists = find_transaction_by_index(tx_col, i)
                 .await?
                 .is_some();
             
@@ -199,8 +199,7 @@ async fn verify_synced_transactions(
     
     for i in 1..=check_limit {
         let index = last_synced_index - i;
-        let tx_exists = tx_col
-            .find_one(doc! { "index": index as i64 }, None)
+        let tx_exists = find_transaction_by_index(tx_col, index)
             .await?
             .is_some();
         
@@ -232,27 +231,42 @@ async fn verify_synced_transactions(
 }
 
 /// 直接使用已知的交易起点和偏移量查询数据
+///
+/// 每个批次的交易、账户关系、余额增量（`calculate_balance` 为 true 时）和同步游标通过
+/// `commit_batch` 一并提交，见 db/batch.rs。
 pub async fn sync_ledger_transactions(
     agent: &Agent,
     canister_id: &Principal,
-    tx_col: &Collection<Document>,
-    accounts_col: &Collection<Document>,
-    _balances_col: &Collection<Document>,
-    _supply_col: &Collection<Document>,
+    db_conn: &DbConnection,
     token_config: &crate::models::TokenConfig,
-    _calculate_balance: bool,
-) -> Result<Vec<Transaction>, Box<dyn Error>> {
+    calculate_balance: bool,
+) -> Result<Vec<Transaction>, Box<dyn Error + Send + Sync>> {
     // 从配置中提取代币符号和小数位数
     let token_symbol = &token_config.symbol;
     let _token_decimals = token_config.decimals.unwrap_or(8);
-    // 兼容现有API，第5个参数是sync_status_col
-    let sync_status_col = _balances_col;
+    let sync_status_col = &db_conn.sync_status_col;
+    
+    // 获取该代币的集合
+    let collections = match db_conn.token_collections(token_symbol) {
+        Some(cols) => cols,
+        None => {
+            return Err(format!("没有找到代币 {} 的集合", token_symbol).into());
+        }
+    };
+    let tx_col = &collections.tx_col;
     
     // 首先检查同步状态
     let mut start_from_sync_status = false;
     let mut sync_status_index = 0;
+    // 余额落后于同步游标时不在批次中计入余额，留给补算流程统一处理
+    let mut apply_balances = calculate_balance;
     
     if let Ok(Some(status)) = get_sync_status(sync_status_col, token_symbol).await {
+        if calculate_balance && status.pending_balance_start().is_some() {
+            warn!("{}: 余额计算进度 ({:?}) 落后于同步进度 ({})，本次同步不在批次中计入余额",
+                token_symbol, status.last_balance_calculated_index, status.last_synced_index);
+            apply_balances = false;
+        }
         if status.sync_mode == "incremental" && status.last_synced_index > 0 {
             info!("从同步状态恢复，上次同步到索引: {}", status.last_synced_index);
             start_from_sync_status = true;
@@ -301,9 +315,14 @@ pub async fn sync_ledger_transactions(
                 
                 // 先尝试获取ledger的状态，得到first_index
                 info!("获取区块链初始索引...");
+                let partial_start = get_partial_start(sync_status_col, token_symbol).await?
+                    .map(|start| start.index)
+                    .unwrap_or(0);
                 match get_first_transaction_index(agent, canister_id).await {
                     Ok(first_index) => {
                         info!("从区块链获取的初始索引为: {}", first_index);
+                        // 部分索引时从配置的起点开始
+                        let first_index = first_index.max(partial_start);
                         // 返回比first_index小1的值，这样current_index会从first_index开始
                         first_index.saturating_sub(1)
                     },
@@ -321,29 +340,35 @@ pub async fn sync_ledger_transactions(
     let mut current_index = latest_index + 1;
     let mut retry_count = 0;
     let max_retries = 5;  // 增加最大重试次数
-    let mut consecutive_empty = 0;
-    let max_consecutive_empty = 3;  // 增加连续空结果阈值
     
     // 收集所有同步到的新交易
     let mut all_new_transactions = Vec::new();
     
-    // 跟踪最新的交易索引和时间戳
+    // 跟踪最新的交易索引
     let mut latest_tx_index = latest_index;
-    let mut latest_tx_timestamp = 0;
-    
-    // 记录上次更新同步状态的索引
-    let mut last_status_update_index = latest_index;
-    let status_update_frequency: usize = 100;  // 每同步100笔交易更新一次状态
     
     info!("开始增量同步交易数据，从索引 {} 开始", current_index);
+    progress::begin_phase(token_symbol, Phase::Ledger, current_index, latest_index);
     
     // 尝试同步交易，每次获取一批
-    while retry_count < max_retries && consecutive_empty < max_consecutive_empty {
+    loop {
+        // 收到停止信号后不再拉取新批次，已提交的批次均已包含同步游标
+        if shutdown::is_requested() {
+            info!("{}: 收到停止信号，停止拉取新批次，同步游标保持在 {}", token_symbol, latest_tx_index);
+            break;
+        }
+        // 租约被其他实例接管或续期失败后不再写入，避免两个实例同时推进同步游标
+        if !leases::is_held(token_symbol) {
+            warn!("{}: 本实例不再持有同步租约，停止拉取新批次，同步游标保持在 {}", token_symbol, latest_tx_index);
+            break;
+        }
+        
         let length = BATCH_SIZE;
         debug!("查询交易批次: {}-{}", current_index, current_index + length - 1);
         
         match fetch_ledger_transactions(agent, canister_id, current_index, length).await {
             Ok((transactions, first_index, log_length)) => {
+                progress::set_log_length(token_symbol, log_length);
                 // 如果first_index大于current_index，说明有交易被跳过，应该从first_index开始查询
                 if first_index > current_index {
                     info!("检测到first_index ({}) 大于 current_index ({}), 调整查询索引", 
@@ -353,38 +378,21 @@ pub async fn sync_ledger_transactions(
                 }
                 
                 if transactions.is_empty() {
-                    consecutive_empty += 1;
-                    debug!("没有获取到新交易 ({}/{}), 可能已到达链上最新状态或索引有误", 
-                        consecutive_empty, max_consecutive_empty);
+                    debug!("没有获取到新交易，可能已到达链上最新状态或索引有误");
                     
-                    // 尝试跳到下一个可能的索引位置
+                    // 账本仍有未同步的交易却返回空批次时结束本轮同步，由调度器按错误退避后从当前索引重试，不跳过索引
                     if log_length > current_index {
-                        info!("日志长度 ({}) 大于当前索引 ({}), 尝试从新位置查询", log_length, current_index);
-                        current_index = log_length;
-                        consecutive_empty = 0; // 重置连续空计数
-                    } else {
-                        // 如果没有明确的新位置，小幅度向前尝试
-                        current_index += BATCH_SIZE / 10; 
-                        debug!("尝试从新位置 {} 查询", current_index);
-                    }
-                    
-                    // 检查是否应该更新同步状态 - 如果有新交易同步过
-                    if latest_tx_index > last_status_update_index {
-                        if let Err(e) = set_incremental_mode(sync_status_col, token_symbol, latest_tx_index, latest_tx_timestamp).await {
-                            warn!("连续空结果时更新同步状态失败: {}", e);
-                        } else {
-                            info!("已更新同步状态索引: {} -> {}", last_status_update_index, latest_tx_index);
-                            last_status_update_index = latest_tx_index;
-                        }
+                        warn!("{}: 日志长度 ({}) 大于当前索引 ({})，但账本返回了空批次，同步游标保持在 {}",
+                            token_symbol, log_length, current_index, latest_tx_index);
+                        return Err(create_error(&format!(
+                            "账本在索引 {} 处返回空批次（日志长度 {}）", current_index, log_length
+                        )));
                     }
-                    
-                    // 短暂等待避免过快查询
-                    tokio::time::sleep(Duration::from_secs(2)).await;
-                    continue; // 继续下一个循环迭代
+                    // 已追平链上最新交易，本轮结束，下一轮的等待时间由调度器决定
+                    debug!("已到达链上最新状态 (日志长度 {})", log_length);
+                    break;
                 }
                 
-                // 获取到新交易，重置计数
-                consecutive_empty = 0;
                 info!("获取到 {} 笔交易", transactions.len());
                 info!("🔄 开始处理交易批次: {}～{}", current_index, current_index + transactions.len() as u64 - 1);
                 
@@ -392,130 +400,65 @@ pub async fn sync_ledger_transactions(
                 let mut sorted_transactions = transactions.clone();
                 sorted_transactions.sort_by_key(|tx| tx.index.unwrap_or(0));
                 
-                // 保存交易到数据库并收集成功保存的交易
-                let mut success_count = 0;
-                let mut error_count = 0;
-                
                 for tx in &sorted_transactions {
-                    // 更新最新的交易索引和时间戳
-                    if let Some(index) = tx.index {
-                        if index > latest_tx_index {
-                            latest_tx_index = index;
-                            latest_tx_timestamp = tx.timestamp;
-                        }
-                    }
-                    
                     // 保存交易之前打印交易详细信息
                     log_transaction_details(tx);
-                    
-                    // 保存交易
-                    match save_transaction(tx_col, tx).await {
-                        Ok(_) => {
-                            success_count += 1;
-                            // 收集成功保存的交易，用于后续余额计算
-                            let tx_clone = tx.clone();
-                            all_new_transactions.push(tx_clone);
-                            
-                            // 更新账户-交易关系
-                            let index = tx.index.unwrap_or(0);
-                            let tx_array = vec![tx.clone()];
-                            let account_txs = group_transactions_by_account(&tx_array);
-                            
-                            for (account, _) in &account_txs {
-                                if let Err(e) = save_account_transaction(accounts_col, account, index).await {
-                                    error!("保存账户-交易关系失败 (账户: {}, 交易索引: {}): {}", account, index, e);
-                                    error_count += 1;
-                                }
-                            }
-                        },
-                        Err(e) => {
-                            error!("保存交易失败 (索引: {}): {}", tx.index.unwrap_or(0), e);
-                            error_count += 1;
-                        }
-                    }
                 }
                 
-                info!("成功保存 {} 笔交易，失败 {} 笔", success_count, error_count);
+                // 拉取（含重试）期间租约可能已过期并被其他实例接管，提交前再次确认
+                if !leases::is_held(token_symbol) {
+                    warn!("{}: 拉取批次期间失去同步租约，放弃提交，同步游标保持在 {}", token_symbol, latest_tx_index);
+                    break;
+                }
+                // 交易、账户关系、余额增量和同步游标一并提交
+                if let Err(e) = commit_batch(db_conn, &collections, token_symbol, &sorted_transactions, apply_balances).await {
+                    error!("{}: 批次提交失败，同步游标保持在 {}: {}", token_symbol, latest_tx_index, e);
+                    return Err(e);
+                }
+                
+                if let Some(index) = sorted_transactions.iter().filter_map(|tx| tx.index).max() {
+                    latest_tx_index = latest_tx_index.max(index);
+                    progress::advance(token_symbol, latest_tx_index);
+                }
+                // 推送给本进程内订阅了交易流的客户端
+                broadcast::publish(token_symbol, &sorted_transactions);
+                info!("成功提交 {} 笔交易", sorted_transactions.len());
+                all_new_transactions.extend(sorted_transactions);
                 info!("✅ 交易批次处理完成: {}～{}", current_index, current_index + transactions.len() as u64 - 1);
                 
                 // 更新当前索引并重置重试计数
                 current_index += transactions.len() as u64;
                 retry_count = 0;
                 
-                // 更频繁地更新同步状态
-                if latest_tx_index > last_status_update_index && 
-                   ((latest_tx_index - last_status_update_index) as usize >= status_update_frequency || 
-                    all_new_transactions.len() % status_update_frequency == 0) {
-                    if let Err(e) = set_incremental_mode(sync_status_col, token_symbol, latest_tx_index, latest_tx_timestamp).await {
-                        warn!("更新同步状态失败: {}", e);
-                    } else {
-                        info!("已更新同步状态索引: {} -> {}", last_status_update_index, latest_tx_index);
-                        last_status_update_index = latest_tx_index;
-                    }
-                }
-                
                 // 当前批次处理完成后，短暂休息以减轻系统负担
                 tokio::time::sleep(Duration::from_millis(100)).await;
             },
             Err(e) => {
-                warn!("获取交易失败: {}，重试 {}/{}", e, retry_count + 1, max_retries);
+                // canister 已熔断时结束本轮同步，由调度器按错误退避，不跳过索引
+                if circuit_retry_after(e.as_ref()).is_some() {
+                    warn!("{}: {}，同步游标保持在 {}", token_symbol, e, latest_tx_index);
+                    return Err(e);
+                }
                 retry_count += 1;
                 
-                // 错误恢复策略
+                // 达到最大重试次数后结束本轮同步，由调度器按错误退避，不跳过索引；
+                // 已提交的批次均已包含同步游标，下一轮从未提交的索引继续
                 if retry_count >= max_retries {
-                    // 检查是否有已获取的交易记录
-                    if latest_tx_index > last_status_update_index {
-                        warn!("达到最大重试次数但已有部分交易，将保存当前同步状态后重试...");
-                        
-                        // 保存当前同步状态
-                        if let Err(status_err) = set_incremental_mode(sync_status_col, token_symbol, latest_tx_index, latest_tx_timestamp).await {
-                            error!("错误恢复时保存同步状态失败: {}", status_err);
-                        } else {
-                            info!("错误恢复：已保存同步状态至索引 {}", latest_tx_index);
-                            last_status_update_index = latest_tx_index;
-                        }
-                        
-                        warn!("尝试跳过当前批次继续同步...");
-                        current_index += BATCH_SIZE / 4; // 跳过部分索引，尝试继续
-                        retry_count = 0; // 重置重试计数
-                        consecutive_empty = 0; // 重置连续空计数
-                        
-                        // 等待较长时间后重试
-                        let wait_time = Duration::from_secs(5);
-                        info!("等待 {:?} 后继续同步", wait_time);
-                        tokio::time::sleep(wait_time).await;
-                    } else {
-                        warn!("达到最大重试次数，尝试跳过当前批次...");
-                        current_index += BATCH_SIZE / 4; // 跳过部分索引，尝试继续
-                        retry_count = 0;
-                        consecutive_empty = 0;
-                        
-                        // 指数退避
-                        let wait_time = Duration::from_secs(5);
-                        debug!("等待 {:?} 后重试", wait_time);
-                        tokio::time::sleep(wait_time).await;
-                    }
-                } else {
-                    // 指数退避
-                    let wait_time = Duration::from_secs(2u64.pow(retry_count as u32));
-                    debug!("等待 {:?} 后重试", wait_time);
-                    tokio::time::sleep(wait_time).await;
+                    warn!("{}: 获取交易失败，已重试 {} 次，同步游标保持在 {}: {}", token_symbol, max_retries, latest_tx_index, e);
+                    return Err(e);
                 }
+                warn!("获取交易失败: {}，重试 {}/{}", e, retry_count, max_retries);
+                
+                // 指数退避
+                let wait_time = Duration::from_secs(2u64.pow(retry_count as u32));
+                debug!("等待 {:?} 后重试", wait_time);
+                tokio::time::sleep(wait_time).await;
             }
         }
     }
     
-    if consecutive_empty >= max_consecutive_empty {
-        info!("连续 {} 次获取空结果，认为已达到链上最新状态", consecutive_empty);
-    }
-    
-    // 完成同步后，更新同步状态
     if latest_tx_index > latest_index {
-        if let Err(e) = set_incremental_mode(sync_status_col, token_symbol, latest_tx_index, latest_tx_timestamp).await {
-            error!("最终更新同步状态失败: {}", e);
-        } else {
-            info!("同步状态已更新至最新索引: {} (共同步 {} 笔新交易)", latest_tx_index, all_new_transactions.len());
-        }
+        info!("同步状态已更新至最新索引: {} (共同步 {} 笔新交易)", latest_tx_index, all_new_transactions.len());
     } else {
         info!("无新交易，保持同步状态在索引: {}", latest_index);
     }
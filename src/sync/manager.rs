@@ -0,0 +1,530 @@
+/**
+ * 文件描述: 代币管理模块，负责在运行时增删、暂停和恢复代币
+ * 功能概述:
+ * - 持有本次运行管理的全部代币（来自 config.toml 和代币注册表）
+ * - 为每个同步中的代币维护一个可停止的监督任务（见 worker 模块）
+ * - 添加代币时创建集合和索引、登记冷存储并启动同步，不影响其他代币和 API 服务器
+ * - 暂停、恢复、移除代币时只启停该代币的同步任务，并同步更新代币注册表
+ * - 配置热更新时把 config.toml 中代币的增删改应用到运行中的服务
+ * - 只读模式（serve 命令）下不创建 IC Agent、不启动同步任务，定期从代币注册表刷新代币列表
+ *
+ * 主要组件:
+ * - TokenManager结构体: 代币列表与同步任务的管理器，API 服务器和主程序共享
+ * - start_active_workers方法: 启动所有处于同步状态的代币
+ * - add_token / pause_token / resume_token / remove_token方法: 管理接口对应的操作
+ * - apply_config_tokens方法: 应用重新加载的 config.toml 代币列表
+ * - read_only / refresh_from_registry / spawn_registry_refresh: 只读 API 实例使用的代币列表
+ * - shutdown方法: 优雅停止时停止全部同步任务
+ */
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, RwLock};
+use ic_agent::Agent;
+use mongodb::bson::Document;
+use tokio::sync::{watch, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
+use log::{info, warn, error};
+use candid::Encode;
+use crate::config::parse_canister_id;
+use crate::db::{DbConnection, TokenCollections};
+use crate::db::cold_storage::{clear_cold_storage, cold_storage_for, register_cold_storage};
+use crate::db::leases;
+use crate::db::backfill_jobs::clear_backfill_jobs;
+use crate::db::registry::{delete_registry_entry, get_registry_entry, load_registry, save_registry_entry, set_registry_status, RegistryEntry};
+use crate::db::schema::{delete_schema_version, migrate_token};
+use crate::ic::client::query_with_retry;
+use crate::db::sync_status::clear_token_sync_status;
+use crate::models::{TokenConfig, TokenSource, TokenStatus};
+use crate::sync::worker::supervise_token;
+use crate::sync::progress;
+use crate::shutdown;
+use crate::utils::create_error;
+
+/// 停止同步任务时等待其退出的最长时间，超时后强制终止
+const STOP_TIMEOUT: Duration = Duration::from_secs(30);
+/// 删除代币数据前等待其他实例释放同步租约的最长时间
+const REMOVE_LEASE_TIMEOUT: Duration = Duration::from_secs(120);
+/// 只读模式下从代币注册表刷新代币列表的间隔
+const REGISTRY_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// 强制终止同步任务，并等待任务确实结束（同步任务在监督任务内运行，随之一起结束）
+async fn abort_worker(handle: JoinHandle<()>) {
+    handle.abort();
+    let _ = handle.await;
+}
+
+/// 一个代币的同步任务
+struct TokenWorker {
+    stop: watch::Sender<bool>,
+    /// 最新的代币配置，同步任务在下一轮开始前读取
+    config: watch::Sender<TokenConfig>,
+    handle: JoinHandle<()>,
+}
+
+/// 代币列表与同步任务的管理器
+pub struct TokenManager {
+    /// 只读模式下为 None，不启动同步任务
+    agent: Option<Agent>,
+    db_conn: DbConnection,
+    cold_storage_dir: String,
+    /// 未移除的代币（同步中或已暂停），按注册顺序排列
+    entries: RwLock<Vec<RegistryEntry>>,
+    /// 正在运行的同步任务；同时用于串行化管理操作
+    workers: Mutex<HashMap<String, TokenWorker>>,
+}
+
+impl TokenManager {
+    pub fn new(agent: Agent, db_conn: DbConnection, cold_storage_dir: String, entries: Vec<RegistryEntry>) -> Self {
+        TokenManager {
+            agent: Some(agent),
+            db_conn,
+            cold_storage_dir,
+            entries: RwLock::new(entries),
+            workers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 只读管理器：只提供代币列表，不启动同步任务，也不接受增删改操作
+    pub fn read_only(db_conn: DbConnection, cold_storage_dir: String, entries: Vec<RegistryEntry>) -> Self {
+        TokenManager {
+            agent: None,
+            db_conn,
+            cold_storage_dir,
+            entries: RwLock::new(entries),
+            workers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 是否为只读管理器
+    pub fn is_read_only(&self) -> bool {
+        self.agent.is_none()
+    }
+
+    /// 只读管理器拒绝修改操作
+    fn agent(&self) -> Result<&Agent, Box<dyn Error + Send + Sync>> {
+        self.agent.as_ref()
+            .ok_or_else(|| create_error("只读 API 实例不能修改代币，请在同步进程上执行"))
+    }
+
+    /// 从代币注册表重新读取代币列表（只读模式使用）
+    ///
+    /// 新出现的代币登记集合和冷存储，已移除的代币不再提供查询；
+    /// 已有代币的冷存储清单被同步进程更新后重新加载。
+    pub async fn refresh_from_registry(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let entries: Vec<RegistryEntry> = load_registry(&self.db_conn.registry_col).await?
+            .into_iter()
+            .filter(|e| e.status != TokenStatus::Removed)
+            .collect();
+        if entries.is_empty() {
+            // 注册表为空说明同步进程尚未启动过，保留配置文件中的代币
+            return Ok(());
+        }
+
+        for entry in &entries {
+            if self.db_conn.token_collections(&entry.token.symbol).is_none() {
+                let collections = self.db_conn.add_token_collections(&entry.token);
+                register_cold_storage(&collections.tx_col, &self.cold_storage_dir, &entry.token.symbol).await?;
+                info!("{}: 代币注册表中出现新代币，开始提供查询", entry.token.symbol);
+            } else if let Some(storage) = self.db_conn.token_collections(&entry.token.symbol)
+                .and_then(|collections| cold_storage_for(&collections.tx_col))
+            {
+                match storage.reload_if_changed().await {
+                    Ok(true) => info!("{}: 冷存储清单已更新，归档至交易索引 {:?}", entry.token.symbol, storage.archived_until()),
+                    Ok(false) => {},
+                    Err(e) => warn!("{}: 重新加载冷存储清单失败: {}", entry.token.symbol, e),
+                }
+            }
+        }
+        for symbol in self.db_conn.token_symbols() {
+            if !entries.iter().any(|e| e.token.symbol == symbol) {
+                self.db_conn.remove_token_collections(&symbol);
+                info!("{}: 代币已从注册表移除，停止提供查询", symbol);
+            }
+        }
+        *self.entries.write().unwrap() = entries;
+        Ok(())
+    }
+
+    /// 定期从代币注册表刷新代币列表（只读模式使用），服务停止时退出
+    pub fn spawn_registry_refresh(self: &Arc<Self>) -> JoinHandle<()> {
+        let manager = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep(REGISTRY_REFRESH_INTERVAL) => {},
+                    _ = shutdown::requested() => return,
+                }
+                if let Err(e) = manager.refresh_from_registry().await {
+                    warn!("从代币注册表刷新代币列表失败: {}", e);
+                }
+            }
+        })
+    }
+
+    /// 可查询的代币配置（同步中和已暂停的代币）
+    pub fn tokens(&self) -> Vec<TokenConfig> {
+        self.entries.read().unwrap().iter().map(|e| e.token.clone()).collect()
+    }
+
+    /// 查找代币的注册信息
+    pub fn entry(&self, symbol: &str) -> Option<RegistryEntry> {
+        self.entries.read().unwrap().iter().find(|e| e.token.symbol == symbol).cloned()
+    }
+
+    /// 管理接口返回的代币列表，附带同步任务是否在运行
+    pub async fn registry_view(&self) -> Vec<Document> {
+        let workers = self.workers.lock().await;
+        let entries = self.entries.read().unwrap().clone();
+        entries.iter()
+            .map(|entry| {
+                let running = workers.get(&entry.token.symbol)
+                    .is_some_and(|worker| !worker.handle.is_finished());
+                let mut entry_doc = entry.to_document();
+                entry_doc.insert("running", running);
+                entry_doc
+            })
+            .collect()
+    }
+
+    /// 启动所有处于同步状态的代币
+    pub async fn start_active_workers(&self) {
+        let active: Vec<TokenConfig> = self.entries.read().unwrap().iter()
+            .filter(|e| e.status == TokenStatus::Active)
+            .map(|e| e.token.clone())
+            .collect();
+        let mut workers = self.workers.lock().await;
+        for token in active {
+            self.spawn_worker(&mut workers, token);
+        }
+    }
+
+    /// 为代币启动同步任务，初始同步或同步状态校验在同步任务中执行
+    fn spawn_worker(&self, workers: &mut HashMap<String, TokenWorker>, token: TokenConfig) {
+        let agent = match &self.agent {
+            Some(agent) => agent.clone(),
+            None => return,
+        };
+        if workers.get(&token.symbol).is_some_and(|worker| !worker.handle.is_finished()) {
+            return;
+        }
+        if shutdown::is_requested() {
+            warn!("{}: 服务正在停止，不再启动同步任务", token.symbol);
+            return;
+        }
+        let (stop_tx, stop_rx) = watch::channel(false);
+        let (config_tx, config_rx) = watch::channel(token.clone());
+        let db_conn = self.db_conn.clone();
+        let symbol = token.symbol.clone();
+        let handle = tokio::spawn(supervise_token(agent, db_conn, config_rx, stop_rx));
+        info!("{}: 已启动同步任务", symbol);
+        workers.insert(symbol, TokenWorker { stop: stop_tx, config: config_tx, handle });
+    }
+
+    /// 停止代币的同步任务，等待当前一轮同步结束，超时后强制终止
+    async fn stop_worker(&self, workers: &mut HashMap<String, TokenWorker>, symbol: &str) {
+        let mut worker = match workers.remove(symbol) {
+            Some(worker) => worker,
+            None => return,
+        };
+        let _ = worker.stop.send(true);
+        if tokio::time::timeout(STOP_TIMEOUT, &mut worker.handle).await.is_err() {
+            warn!("{}: 同步任务在 {:?} 内未退出，强制终止", symbol, STOP_TIMEOUT);
+            abort_worker(worker.handle).await;
+        }
+        // 同步任务正常退出时已释放租约，强制终止时在任务确实结束后在这里释放
+        if let Err(e) = leases::release(&self.db_conn.lease_col, symbol).await {
+            warn!("{}: 释放同步租约失败: {}", symbol, e);
+        }
+        info!("{}: 同步任务已停止", symbol);
+    }
+
+    /// 更新内存中代币的状态
+    fn set_status(&self, symbol: &str, status: TokenStatus) {
+        let mut entries = self.entries.write().unwrap();
+        if let Some(entry) = entries.iter_mut().find(|e| e.token.symbol == symbol) {
+            entry.status = status;
+        }
+    }
+
+    /// 添加代币：创建集合和索引，登记冷存储，写入注册表并启动同步
+    pub async fn add_token(&self, token: TokenConfig, source: TokenSource) -> Result<RegistryEntry, Box<dyn Error + Send + Sync>> {
+        let mut workers = self.workers.lock().await;
+        self.add_token_locked(&mut workers, token, source).await
+    }
+
+    async fn add_token_locked(
+        &self,
+        workers: &mut HashMap<String, TokenWorker>,
+        token: TokenConfig,
+        source: TokenSource,
+    ) -> Result<RegistryEntry, Box<dyn Error + Send + Sync>> {
+        let agent = self.agent()?;
+        validate_symbol(&token.symbol)?;
+        // 集合名使用符号的小写形式，大小写不同的符号也不能共存
+        let prefix = token.symbol.to_lowercase();
+        if self.db_conn.token_symbols().iter().any(|s| s.to_lowercase() == prefix) {
+            return Err(create_error(&format!("代币 {} 已存在", token.symbol)));
+        }
+        let canister_id = parse_canister_id(&token.canister_id)?;
+        if token.decimals.is_none() {
+            // 确认 canister 可访问，小数位在同步时再次查询；get_token_decimals 出错时回退到默认值，这里直接查询
+            query_with_retry(agent, &canister_id, "icrc1_decimals", &Encode!(&())?,
+                &format!("{}: 查询decimals", token.symbol)).await?;
+        }
+
+        let collections = self.db_conn.add_token_collections(&token);
+        let entry = RegistryEntry { token: token.clone(), status: TokenStatus::Active, source };
+        let registered = async {
+            migrate_token(&self.db_conn, &token.symbol, &collections).await?;
+            register_cold_storage(&collections.tx_col, &self.cold_storage_dir, &token.symbol).await?;
+            save_registry_entry(&self.db_conn.registry_col, &entry).await
+        }.await;
+        if let Err(e) = registered {
+            self.db_conn.remove_token_collections(&token.symbol);
+            return Err(e);
+        }
+        self.entries.write().unwrap().push(entry.clone());
+
+        self.spawn_worker(workers, token);
+        info!("{}: 已添加代币", entry.token.symbol);
+        Ok(entry)
+    }
+
+    /// 更新代币配置，运行中的同步任务在下一轮开始前应用；配置没有变化时返回 false
+    ///
+    /// canister_id 决定了已索引的数据属于哪个账本，不允许在运行时修改。
+    async fn update_token_locked(
+        &self,
+        workers: &mut HashMap<String, TokenWorker>,
+        token: TokenConfig,
+        source: TokenSource,
+    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let mut entry = self.entry(&token.symbol)
+            .ok_or_else(|| create_error(&format!("代币 {} 不存在", token.symbol)))?;
+        if entry.token.canister_id != token.canister_id {
+            return Err(create_error(&format!(
+                "{}: 不支持在运行时修改 canister_id ({} -> {})，请重启服务并使用 reset 命令重新同步",
+                token.symbol, entry.token.canister_id, token.canister_id
+            )));
+        }
+        if entry.token == token && entry.source == source {
+            return Ok(false);
+        }
+
+        entry.token = token.clone();
+        entry.source = source;
+        save_registry_entry(&self.db_conn.registry_col, &entry).await?;
+        if let Some(stored) = self.entries.write().unwrap().iter_mut().find(|e| e.token.symbol == token.symbol) {
+            *stored = entry;
+        }
+        if let Some(worker) = workers.get(&token.symbol) {
+            let _ = worker.config.send(token);
+        }
+        Ok(true)
+    }
+
+    /// 应用重新加载的 config.toml 代币列表
+    ///
+    /// 新增的代币创建集合并启动同步（已通过管理接口移除的除外），修改的代币更新配置，
+    /// 从配置文件中删除的代币停止同步并从注册表移除（数据保留）。单个代币出错不影响其他代币。
+    pub async fn apply_config_tokens(&self, tokens: &[TokenConfig]) {
+        // 只读实例的代币列表来自代币注册表，由同步进程维护
+        if self.is_read_only() {
+            return;
+        }
+        let mut workers = self.workers.lock().await;
+
+        for token in tokens {
+            if self.entry(&token.symbol).is_some() {
+                match self.update_token_locked(&mut workers, token.clone(), TokenSource::Config).await {
+                    Ok(true) => info!("{}: 已更新代币配置", token.symbol),
+                    Ok(false) => {},
+                    Err(e) => error!("{}: 更新代币配置失败: {}", token.symbol, e),
+                }
+                continue;
+            }
+            match get_registry_entry(&self.db_conn.registry_col, &token.symbol).await {
+                Ok(Some(stored)) if stored.status == TokenStatus::Removed => {
+                    info!("{}: 已通过管理接口移除，忽略 config.toml 中的配置", token.symbol);
+                    continue;
+                },
+                Ok(_) => {},
+                Err(e) => {
+                    error!("{}: 读取代币注册表失败: {}", token.symbol, e);
+                    continue;
+                },
+            }
+            if let Err(e) = self.add_token_locked(&mut workers, token.clone(), TokenSource::Config).await {
+                error!("{}: 添加代币失败: {}", token.symbol, e);
+            }
+        }
+
+        let retired: Vec<String> = self.entries.read().unwrap().iter()
+            .filter(|e| e.source == TokenSource::Config && !tokens.iter().any(|t| t.symbol == e.token.symbol))
+            .map(|e| e.token.symbol.clone())
+            .collect();
+        for symbol in retired {
+            self.stop_worker(&mut workers, &symbol).await;
+            self.entries.write().unwrap().retain(|e| e.token.symbol != symbol);
+            self.db_conn.remove_token_collections(&symbol);
+            if let Err(e) = delete_registry_entry(&self.db_conn.registry_col, &symbol).await {
+                error!("{}: 删除代币注册表记录失败: {}", symbol, e);
+            }
+            info!("{}: 已从 config.toml 中删除，停止同步（数据保留）", symbol);
+        }
+    }
+
+    /// 暂停代币的同步，数据仍可查询
+    pub async fn pause_token(&self, symbol: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.agent()?;
+        let mut workers = self.workers.lock().await;
+        self.entry(symbol).ok_or_else(|| create_error(&format!("代币 {} 不存在", symbol)))?;
+
+        // 先更新注册表再停止本实例的任务：其他实例（持有租约或热备）读取到暂停状态后停止同步，不会接管租约
+        set_registry_status(&self.db_conn.registry_col, symbol, TokenStatus::Paused).await?;
+        self.stop_worker(&mut workers, symbol).await;
+        self.set_status(symbol, TokenStatus::Paused);
+        info!("{}: 已暂停同步", symbol);
+        Ok(())
+    }
+
+    /// 恢复代币的同步
+    pub async fn resume_token(&self, symbol: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.agent()?;
+        let mut workers = self.workers.lock().await;
+        let entry = self.entry(symbol).ok_or_else(|| create_error(&format!("代币 {} 不存在", symbol)))?;
+
+        set_registry_status(&self.db_conn.registry_col, symbol, TokenStatus::Active).await?;
+        self.set_status(symbol, TokenStatus::Active);
+        self.spawn_worker(&mut workers, entry.token);
+        info!("{}: 已恢复同步", symbol);
+        Ok(())
+    }
+
+    /// 移除代币；`drop_data` 为 true 时同时删除该代币的集合、冷存储和同步状态
+    ///
+    /// 来自 config.toml 的代币在注册表中标记为已移除，重启后不会重新加入；
+    /// 需要恢复时从注册表中删除该记录即可。
+    ///
+    /// 先更新注册表，其他实例读取到后停止同步并释放租约。删除数据前本实例获取该代币的租约，
+    /// 确认没有其他实例仍在写入；在 REMOVE_LEASE_TIMEOUT 内获取不到时返回错误，数据保留，可以稍后重试。
+    pub async fn remove_token(&self, symbol: &str, drop_data: bool) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.agent()?;
+        let mut workers = self.workers.lock().await;
+        let entry = self.entry(symbol).ok_or_else(|| create_error(&format!("代币 {} 不存在", symbol)))?;
+
+        match entry.source {
+            TokenSource::Api => delete_registry_entry(&self.db_conn.registry_col, symbol).await?,
+            TokenSource::Config => set_registry_status(&self.db_conn.registry_col, symbol, TokenStatus::Removed).await?,
+        }
+        self.stop_worker(&mut workers, symbol).await;
+        progress::clear(symbol);
+
+        if drop_data && !self.acquire_for_removal(symbol).await? {
+            // 保留内存中的代币，稍后可以再次调用移除接口删除数据
+            self.set_status(symbol, TokenStatus::Removed);
+            let holder = leases::get_lease(&self.db_conn.lease_col, symbol).await?
+                .and_then(|lease| lease.get_str("holder").ok().map(|h| h.to_string()))
+                .unwrap_or_else(|| "-".to_string());
+            return Err(create_error(&format!(
+                "{}: 已停止同步，但实例 {} 在 {:?} 内仍未释放同步租约，数据未删除，请稍后重试",
+                symbol, holder, REMOVE_LEASE_TIMEOUT
+            )));
+        }
+
+        self.entries.write().unwrap().retain(|e| e.token.symbol != symbol);
+        let collections = self.db_conn.remove_token_collections(symbol);
+
+        if drop_data {
+            let result = self.drop_token_data(symbol, collections.as_ref()).await;
+            if let Err(e) = leases::release(&self.db_conn.lease_col, symbol).await {
+                warn!("{}: 释放同步租约失败: {}", symbol, e);
+            }
+            result?;
+            warn!("{}: 已删除代币的全部数据", symbol);
+        }
+
+        info!("{}: 已移除代币", symbol);
+        Ok(())
+    }
+
+    /// 删除数据前获取代币的租约，等待其他实例停止同步并释放租约
+    async fn acquire_for_removal(&self, symbol: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let deadline = tokio::time::Instant::now() + REMOVE_LEASE_TIMEOUT;
+        let mut logged = false;
+        loop {
+            if leases::acquire(&self.db_conn.lease_col, symbol).await? {
+                return Ok(true);
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return Ok(false);
+            }
+            if !logged {
+                info!("{}: 等待其他实例停止同步并释放租约后再删除数据", symbol);
+                logged = true;
+            }
+            tokio::time::sleep(leases::renew_interval()).await;
+        }
+    }
+
+    /// 删除代币的集合、冷存储、同步状态、回填任务和结构版本
+    async fn drop_token_data(
+        &self,
+        symbol: &str,
+        collections: Option<&TokenCollections>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if let Some(collections) = collections {
+            clear_cold_storage(&collections.tx_col, symbol).await?;
+            collections.drop_all().await?;
+        }
+        clear_token_sync_status(&self.db_conn.sync_status_col, symbol).await?;
+        clear_backfill_jobs(&self.db_conn.backfill_col, symbol).await?;
+        delete_schema_version(&self.db_conn.schema_version_col, symbol).await?;
+        Ok(())
+    }
+
+    /// 停止全部同步任务（优雅停止时使用）
+    ///
+    /// 先通知所有任务停止，再统一等待它们写入检查点，超过 STOP_TIMEOUT 仍未退出的任务被强制终止。
+    pub async fn shutdown(&self) {
+        let mut workers = self.workers.lock().await;
+        for worker in workers.values() {
+            let _ = worker.stop.send(true);
+        }
+        let deadline = tokio::time::Instant::now() + STOP_TIMEOUT;
+        for (symbol, mut worker) in workers.drain() {
+            if tokio::time::timeout_at(deadline, &mut worker.handle).await.is_err() {
+                warn!("{}: 同步任务在 {:?} 内未退出，强制终止", symbol, STOP_TIMEOUT);
+                abort_worker(worker.handle).await;
+                if let Err(e) = leases::release(&self.db_conn.lease_col, &symbol).await {
+                    warn!("{}: 释放同步租约失败: {}", symbol, e);
+                }
+            }
+        }
+        info!("所有同步任务已停止");
+    }
+
+    /// 等待所有同步任务结束（未启动 API 服务器时使用，此时不会再添加代币）
+    pub async fn wait_idle(&self) {
+        loop {
+            let idle = self.workers.lock().await.values().all(|worker| worker.handle.is_finished());
+            if idle {
+                return;
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    }
+}
+
+/// 检查代币符号可以用作集合名前缀
+fn validate_symbol(symbol: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if symbol.is_empty() || symbol.len() > 32 {
+        return Err(create_error("代币符号长度必须在1到32个字符之间"));
+    }
+    if !symbol.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        return Err(create_error(&format!("代币符号 {} 只能包含字母、数字、下划线和连字符", symbol)));
+    }
+    Ok(())
+}
@@ -8,11 +8,31 @@
  * - ledger模块: 负责同步主账本交易
  * - admin模块: 提供管理员功能，如重置和全量同步
  * - archive模块: 负责同步归档交易
+ * - worker模块: 每个代币独立的受监督同步任务
+ * - scheduler模块: 自适应轮询与错误退避
+ * - gaps模块: 交易索引缺口检测与定向回填
+ * - partial模块: 从配置起点开始的部分索引与期初余额
+ * - manager模块: 运行时增删、暂停和恢复代币
+ * - rebuild模块: 根据已保存的交易重建派生数据
+ * - verify模块: 将数据库中的交易与链上数据逐笔比对
+ * - backfill模块: 多个工作进程并行执行的分片初始同步
+ * - progress模块: 同步阶段、吞吐量和预计剩余时间
+ * - broadcast模块: 已提交批次的进程内广播（交易推送流使用）
  */
 
 pub mod ledger;
 pub mod admin;
 pub mod archive;
+pub mod worker;
+pub mod scheduler;
+pub mod gaps;
+pub mod partial;
+pub mod manager;
+pub mod rebuild;
+pub mod verify;
+pub mod backfill;
+pub mod progress;
+pub mod broadcast;
 
 // 重新导出常用同步功能，方便使用
 pub use ledger::sync_ledger_transactions;
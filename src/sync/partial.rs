@@ -0,0 +1,255 @@
+/**
+ * 文件描述: 部分索引模块，只索引配置起点（start_index / start_time）之后的交易
+ * 功能概述:
+ * - 把配置的起始索引或起始时间解析为具体的交易索引，记录到 sync_status 的 partial_start 字段
+ * - 为起点之后出现的账户补写期初余额，使余额和总供应量与链上一致
+ *
+ * 主要组件:
+ * - resolve_partial_start函数: 解析并记录部分索引起点，初始同步前调用
+ * - seed_opening_balances函数: 为尚未写入期初余额的账户补写期初余额
+ *
+ * ICRC-1 账本只能查询当前余额。期初余额分两步得到：先在账本日志长度稳定时查询余额，
+ * 记为 opening_pending（日志长度之前的全部交易之后的余额）；等这些交易全部同步后，
+ * 从中减去起点之后已索引交易的净变化，得到起点处的余额写入 opening_balance。
+ */
+
+use std::error::Error;
+use candid::Nat;
+use chrono::DateTime;
+use futures::stream::TryStreamExt;
+use ic_agent::Agent;
+use ic_agent::export::Principal;
+use log::{info, warn, debug};
+use mongodb::bson::{doc, Document};
+use mongodb::options::FindOptions;
+use crate::blockchain::{fetch_archive_transactions, fetch_balance_of, fetch_ledger_transactions, get_ledger_log_length};
+use crate::db::{DbConnection, TokenCollections};
+use crate::db::balances::{recalculate_account_balances, replay_account_balance};
+use crate::db::sync_status::{get_partial_start, get_sync_status, set_partial_start, PartialStart};
+use crate::models::{TokenConfig, Transaction};
+use crate::sync::gaps::{load_archive_ranges, ArchiveRange};
+use crate::utils::{create_error, parse_account};
+
+/// 每轮最多查询期初余额的账户数量
+const SEED_BATCH_SIZE: i64 = 100;
+
+/// 计算起点之后交易净变化时使用的基数，保证累计过程中余额不会出现负数
+fn replay_offset() -> Nat {
+    Nat::from(u128::MAX / 2)
+}
+
+/// 解析并记录部分索引起点
+///
+/// 已记录过起点时直接返回记录的值（修改配置后需要 reset 命令才会生效）；
+/// 未配置 start_index / start_time 时返回 None，表示完整索引。
+pub async fn resolve_partial_start(
+    agent: &Agent,
+    canister_id: &Principal,
+    db_conn: &DbConnection,
+    token: &TokenConfig,
+) -> Result<Option<PartialStart>, Box<dyn Error + Send + Sync>> {
+    if let Some(existing) = get_partial_start(&db_conn.sync_status_col, &token.symbol).await? {
+        if token.start_index.is_some_and(|index| index != existing.index) {
+            warn!("{}: 配置的起始索引与已记录的部分索引起点 ({}) 不同，需要执行 reset 命令后才会生效",
+                token.symbol, existing.index);
+        }
+        return Ok(Some(existing));
+    }
+    if token.start_index.is_none() && token.start_time.is_none() {
+        return Ok(None);
+    }
+
+    let archives = load_archive_ranges(agent, canister_id).await?;
+    let index = match (token.start_index, &token.start_time) {
+        (Some(index), _) => index,
+        (None, Some(start_time)) => {
+            let time = DateTime::parse_from_rfc3339(start_time)
+                .map_err(|e| create_error(&format!("{}: 无效的 start_time {}: {}", token.symbol, start_time, e)))?;
+            let time_ns = time.timestamp_nanos_opt()
+                .ok_or_else(|| create_error(&format!("{}: start_time 超出范围: {}", token.symbol, start_time)))?;
+            info!("{}: 按起始时间 {} 查找起始交易索引...", token.symbol, start_time);
+            find_index_at_time(agent, canister_id, &archives, time_ns.max(0) as u64).await?
+        },
+        (None, None) => unreachable!(),
+    };
+
+    let start_tx = fetch_transaction_at(agent, canister_id, &archives, index).await
+        .map_err(|e| create_error(&format!("{}: 无法读取起始交易 {}: {}", token.symbol, index, e)))?;
+    let start = PartialStart { index, timestamp: start_tx.timestamp };
+    set_partial_start(&db_conn.sync_status_col, &token.symbol, &start).await?;
+    info!("{}: 启用部分索引，从交易索引 {} 开始同步", token.symbol, index);
+    Ok(Some(start))
+}
+
+/// 二分查找第一笔时间戳不早于 `time_ns` 的交易索引
+async fn find_index_at_time(
+    agent: &Agent,
+    canister_id: &Principal,
+    archives: &[ArchiveRange],
+    time_ns: u64,
+) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    let log_length = get_ledger_log_length(agent, canister_id).await?;
+    let mut low = archives.first().map(|a| a.start).unwrap_or(0);
+    let mut high = log_length;
+
+    while low < high {
+        let mid = low + (high - low) / 2;
+        let tx = fetch_transaction_at(agent, canister_id, archives, mid).await?;
+        if tx.timestamp < time_ns {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+
+    if low >= log_length {
+        return Err(create_error("起始时间晚于链上最新交易"));
+    }
+    Ok(low)
+}
+
+/// 从归档或主账本读取指定索引的交易
+async fn fetch_transaction_at(
+    agent: &Agent,
+    canister_id: &Principal,
+    archives: &[ArchiveRange],
+    index: u64,
+) -> Result<Transaction, Box<dyn Error + Send + Sync>> {
+    let transactions = match archives.iter().find(|a| a.start <= index && index <= a.end) {
+        Some(archive) => fetch_archive_transactions(agent, &archive.canister_id, index, 1).await?,
+        None => fetch_ledger_transactions(agent, canister_id, index, 1).await?.0,
+    };
+    transactions.into_iter()
+        .find(|tx| tx.index == Some(index))
+        .ok_or_else(|| create_error(&format!("索引 {} 处没有交易", index)))
+}
+
+/// 为尚未写入期初余额的账户补写期初余额，返回本轮完成的账户数量
+///
+/// 每轮先为一批新账户查询余额，再为已同步到查询位置的账户计算期初余额并重算余额。
+pub async fn seed_opening_balances(
+    agent: &Agent,
+    canister_id: &Principal,
+    db_conn: &DbConnection,
+    collections: &TokenCollections,
+    token: &TokenConfig,
+    start: &PartialStart,
+) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    query_pending_balances(agent, canister_id, collections, token).await?;
+
+    let status = match get_sync_status(&db_conn.sync_status_col, &token.symbol).await? {
+        Some(status) => status,
+        None => return Ok(0),
+    };
+    // 索引 synced_until 之前的交易都已同步
+    let synced_until = status.last_synced_index + 1;
+
+    let options = FindOptions::builder().limit(SEED_BATCH_SIZE).build();
+    let pending: Vec<Document> = collections.accounts_col.find(
+        doc! {
+            "opening_balance": { "$exists": false },
+            "opening_pending.at_index": { "$lte": synced_until as i64 },
+        },
+        options
+    ).await?.try_collect().await?;
+    if pending.is_empty() {
+        return Ok(0);
+    }
+
+    let mut seeded = Vec::new();
+    for account_doc in pending {
+        let account = account_doc.get_str("account")?.to_string();
+        let pending_doc = account_doc.get_document("opening_pending")?;
+        let queried = Nat::parse(pending_doc.get_str("balance").unwrap_or("0").as_bytes())
+            .unwrap_or_else(|_| Nat::from(0u64));
+        let at_index = pending_doc.get_i64("at_index").unwrap_or(0) as u64;
+
+        // 查询余额时已包含 [起点, at_index) 之间的交易，减去这部分的净变化
+        let opening = if at_index > start.index {
+            let (replayed, _, _) = replay_account_balance(
+                &account,
+                &collections.account_tx_col,
+                &collections.tx_col,
+                token,
+                &collections.balance_anomalies_col,
+                replay_offset(),
+                Some(at_index - 1),
+            ).await?;
+            let total = queried + replay_offset();
+            if total < replayed {
+                warn!("{}: 账户 {} 的期初余额计算为负数，按0处理", token.symbol, account);
+                Nat::from(0u64)
+            } else {
+                Nat(total.0 - replayed.0)
+            }
+        } else {
+            queried
+        };
+
+        collections.accounts_col.update_one(
+            doc! { "account": &account },
+            doc! {
+                "$set": { "opening_balance": opening.0.to_string(), "opening_index": start.index as i64 },
+                "$unset": { "opening_pending": "" },
+            },
+            None
+        ).await?;
+        seeded.push(account);
+    }
+
+    let count = seeded.len() as u64;
+    recalculate_account_balances(
+        seeded,
+        collections,
+        token
+    ).await?;
+    info!("{}: 已为 {} 个账户写入期初余额", token.symbol, count);
+    Ok(count)
+}
+
+/// 为一批新账户查询当前余额，记为 opening_pending
+///
+/// 查询前后账本日志长度不一致时说明期间有新交易，放弃本轮结果等下一轮重试。
+async fn query_pending_balances(
+    agent: &Agent,
+    canister_id: &Principal,
+    collections: &TokenCollections,
+    token: &TokenConfig,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let options = FindOptions::builder().limit(SEED_BATCH_SIZE).build();
+    let accounts: Vec<Document> = collections.accounts_col.find(
+        doc! { "opening_balance": { "$exists": false }, "opening_pending": { "$exists": false } },
+        options
+    ).await?.try_collect().await?;
+    if accounts.is_empty() {
+        return Ok(());
+    }
+
+    let log_length = get_ledger_log_length(agent, canister_id).await?;
+    let mut balances = Vec::new();
+    for account_doc in &accounts {
+        let account = account_doc.get_str("account")?;
+        let balance = match parse_account(account) {
+            Ok(parsed) => fetch_balance_of(agent, canister_id, &parsed).await?,
+            Err(e) => {
+                warn!("{}: 无法解析账户 {}，期初余额按0处理: {}", token.symbol, account, e);
+                Nat::from(0u64)
+            }
+        };
+        balances.push((account.to_string(), balance));
+    }
+    if get_ledger_log_length(agent, canister_id).await? != log_length {
+        debug!("{}: 查询余额期间账本有新交易，下一轮重新查询", token.symbol);
+        return Ok(());
+    }
+
+    for (account, balance) in balances {
+        collections.accounts_col.update_one(
+            doc! { "account": &account },
+            doc! { "$set": { "opening_pending": { "balance": balance.0.to_string(), "at_index": log_length as i64 } } },
+            None
+        ).await?;
+    }
+    debug!("{}: 已查询 {} 个账户的当前余额", token.symbol, accounts.len());
+    Ok(())
+}
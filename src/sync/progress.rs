@@ -0,0 +1,250 @@
+/**
+ * 文件描述: 同步进度模块，记录各代币当前的同步阶段、链上最新索引、吞吐量和预计剩余时间
+ * 功能概述:
+ * - 同步流程在各阶段（归档、主账本、余额计算）开始和推进时更新本进程内的进度记录
+ * - 吞吐量按最近一分钟的推进量计算，预计剩余时间 = 阶段剩余量 / 吞吐量
+ * - 后台任务定期将有变化的进度写入 sync_status 文档的 progress 字段，
+ *   只读 API 实例和其他进程同样可以读取
+ *
+ * 主要组件:
+ * - Phase枚举: 同步阶段（archives / ledger / balances）
+ * - begin_phase函数: 开始一个同步阶段，设置阶段区间
+ * - advance函数: 推进当前阶段的进度
+ * - set_log_length函数: 记录主账本的日志长度（链上最新索引 + 1）
+ * - clear函数: 删除代币的进度记录（重置和删除代币数据时调用）
+ * - spawn_writer函数: 定期将进度写入 sync_status 集合
+ * - lag_of函数: 根据日志长度和同步游标计算落后的交易数量
+ */
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Instant;
+use chrono::Utc;
+use mongodb::Collection;
+use mongodb::bson::{doc, Bson, Document};
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
+use log::warn;
+use crate::db::sync_status::update_progress;
+
+/// 进度写入间隔
+const PERSIST_INTERVAL: Duration = Duration::from_secs(5);
+/// 计算吞吐量的时间窗口
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(60);
+/// 进度超过该时间（秒）未更新时视为过期，通常说明同步进程已停止
+pub const STALE_AFTER_SECS: i64 = 60;
+
+/// 同步阶段
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// 从归档 canister 拉取历史交易
+    Archives,
+    /// 从主账本拉取交易（初始同步的第二步和之后的增量同步）
+    Ledger,
+    /// 根据账户交易记录统一计算余额
+    Balances,
+}
+
+impl Phase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Phase::Archives => "archives",
+            Phase::Ledger => "ledger",
+            Phase::Balances => "balances",
+        }
+    }
+
+    /// 进度的计量单位
+    fn unit(&self) -> &'static str {
+        match self {
+            Phase::Archives | Phase::Ledger => "blocks",
+            Phase::Balances => "accounts",
+        }
+    }
+}
+
+/// 单个代币的进度记录
+#[derive(Debug)]
+struct TokenProgress {
+    phase: Phase,
+    /// 阶段区间（含两端），余额计算阶段为 [0, 账户数 - 1]
+    phase_start: u64,
+    phase_end: u64,
+    /// 阶段内已处理到的位置（不含），等于 phase_start 时表示尚未开始
+    position: u64,
+    phase_started_at: i64,
+    /// 已处理到的最新交易索引，余额计算阶段保留上一阶段的值
+    block_index: Option<u64>,
+    log_length: Option<u64>,
+    /// 最近一分钟的 (时间, 位置) 采样
+    samples: VecDeque<(Instant, u64)>,
+    /// 自上次写入后是否有变化
+    dirty: bool,
+}
+
+impl TokenProgress {
+    fn new(phase: Phase, start: u64, end: u64) -> Self {
+        TokenProgress {
+            phase,
+            phase_start: start,
+            phase_end: end,
+            position: start,
+            phase_started_at: Utc::now().timestamp(),
+            block_index: match phase {
+                Phase::Balances => None,
+                _ => start.checked_sub(1),
+            },
+            log_length: None,
+            samples: VecDeque::from([(Instant::now(), start)]),
+            dirty: true,
+        }
+    }
+
+    fn record_sample(&mut self) {
+        let now = Instant::now();
+        self.samples.push_back((now, self.position));
+        while self.samples.len() > 2 && self.samples.front().is_some_and(|(at, _)| now.duration_since(*at) > THROUGHPUT_WINDOW) {
+            self.samples.pop_front();
+        }
+    }
+
+    /// 最近一分钟的平均吞吐量（单位/秒），一分钟内没有推进时为 0
+    fn throughput(&self) -> f64 {
+        let now = Instant::now();
+        let Some((first_at, first)) = self.samples.iter().find(|(at, _)| now.duration_since(*at) <= THROUGHPUT_WINDOW) else {
+            return 0.0;
+        };
+        let elapsed = now.duration_since(*first_at).as_secs_f64();
+        if elapsed < 1.0 {
+            return 0.0;
+        }
+        self.position.saturating_sub(*first) as f64 / elapsed
+    }
+
+    fn to_document(&self) -> Document {
+        let total = (self.phase_end + 1).saturating_sub(self.phase_start);
+        let processed = self.position.saturating_sub(self.phase_start).min(total);
+        let remaining = total - processed;
+        let throughput = self.throughput();
+        let eta_secs = if remaining == 0 {
+            Bson::Int64(0)
+        } else if throughput > 0.0 {
+            Bson::Int64((remaining as f64 / throughput).ceil() as i64)
+        } else {
+            Bson::Null
+        };
+        let percent = if total == 0 { 100.0 } else { processed as f64 * 100.0 / total as f64 };
+        doc! {
+            "phase": self.phase.as_str(),
+            "unit": self.phase.unit(),
+            "phase_start": self.phase_start as i64,
+            "phase_end": self.phase_end as i64,
+            "processed": processed as i64,
+            "total": total as i64,
+            "percent": (percent * 10.0).round() / 10.0,
+            "throughput_per_sec": (throughput * 10.0).round() / 10.0,
+            "eta_secs": eta_secs,
+            "block_index": self.block_index.map(|index| index as i64),
+            "log_length": self.log_length.map(|length| length as i64),
+            "phase_started_at": self.phase_started_at,
+            "updated_at": Utc::now().timestamp(),
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    /// 本进程内各代币的同步进度
+    static ref PROGRESS: Mutex<HashMap<String, TokenProgress>> = Mutex::new(HashMap::new());
+}
+
+/// 开始一个同步阶段，区间为 [start, end]（含两端）
+///
+/// 已处于主账本阶段时只更新区间，保留吞吐量采样，避免增量同步每轮轮询都重新计算。
+pub fn begin_phase(token_symbol: &str, phase: Phase, start: u64, end: u64) {
+    let mut progress = PROGRESS.lock().unwrap();
+    match progress.get_mut(token_symbol) {
+        Some(entry) if phase == Phase::Ledger && entry.phase == Phase::Ledger => {
+            entry.phase_end = end.max(entry.position.saturating_sub(1));
+            entry.dirty = true;
+        },
+        Some(entry) => {
+            let previous_block_index = entry.block_index;
+            let log_length = entry.log_length;
+            *entry = TokenProgress::new(phase, start, end);
+            entry.block_index = entry.block_index.or(previous_block_index);
+            entry.log_length = log_length;
+        },
+        None => {
+            progress.insert(token_symbol.to_string(), TokenProgress::new(phase, start, end));
+        },
+    }
+}
+
+/// 推进当前阶段：`processed_through` 为已处理的最后一个位置（交易索引或账户序号）
+pub fn advance(token_symbol: &str, processed_through: u64) {
+    let mut progress = PROGRESS.lock().unwrap();
+    if let Some(entry) = progress.get_mut(token_symbol) {
+        entry.position = entry.position.max(processed_through + 1);
+        entry.phase_end = entry.phase_end.max(processed_through);
+        if entry.phase != Phase::Balances {
+            entry.block_index = Some(processed_through);
+        }
+        entry.record_sample();
+        entry.dirty = true;
+    }
+}
+
+/// 记录主账本的日志长度；主账本阶段同时将阶段终点延伸到链上最新索引
+pub fn set_log_length(token_symbol: &str, log_length: u64) {
+    let mut progress = PROGRESS.lock().unwrap();
+    if let Some(entry) = progress.get_mut(token_symbol) {
+        if entry.log_length != Some(log_length) {
+            entry.log_length = Some(log_length);
+            if entry.phase == Phase::Ledger {
+                entry.phase_end = entry.phase_end.max(log_length.saturating_sub(1));
+            }
+            entry.dirty = true;
+        }
+    }
+}
+
+/// 删除代币的进度记录，之后不再写入
+pub fn clear(token_symbol: &str) {
+    PROGRESS.lock().unwrap().remove(token_symbol);
+}
+
+/// 落后于链上最新索引的交易数量
+pub fn lag_of(log_length: u64, synced_index: Option<u64>) -> u64 {
+    match synced_index {
+        Some(index) => log_length.saturating_sub(index + 1),
+        None => log_length,
+    }
+}
+
+/// 定期将有变化的进度写入 sync_status 集合
+pub fn spawn_writer(sync_status_col: Collection<Document>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(PERSIST_INTERVAL).await;
+            let pending: Vec<(String, Document)> = PROGRESS.lock().unwrap().iter_mut()
+                .filter(|(_, entry)| entry.dirty)
+                .map(|(token, entry)| {
+                    entry.dirty = false;
+                    (token.clone(), entry.to_document())
+                })
+                .collect();
+            for (token_symbol, progress_doc) in pending {
+                // 写入期间代币可能已被删除，不再重新创建同步状态
+                if !PROGRESS.lock().unwrap().contains_key(&token_symbol) {
+                    continue;
+                }
+                if let Err(e) = update_progress(&sync_status_col, &token_symbol, progress_doc).await {
+                    warn!("{}: 写入同步进度失败: {}", token_symbol, e);
+                    if let Some(entry) = PROGRESS.lock().unwrap().get_mut(&token_symbol) {
+                        entry.dirty = true;
+                    }
+                }
+            }
+        }
+    })
+}
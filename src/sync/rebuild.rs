@@ -0,0 +1,179 @@
+/**
+ * 文件描述: 根据已保存的交易重建派生数据，不从链上重新获取交易
+ * 功能概述:
+ * - 保留交易集合和冷存储分段文件，按索引顺序读取全部已保存的交易
+ * - 重新生成账户登记和账户-交易边，清空余额异常记录后重新计算余额和总供应量
+ * - 部分索引时保留账户的期初余额字段
+ * - 按批次输出进度、速度和预计剩余时间；收到停止信号时在批次之间中断
+ * - 重建期间在同步状态中保留标记，中断后下次启动会重新执行重建
+ *
+ * 主要组件:
+ * - rebuild_derived_data函数: 重建单个代币的派生数据
+ * - rebuild_account_relations函数: 从交易重新生成账户登记和账户-交易边
+ */
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::time::Instant;
+use log::{info, warn};
+use mongodb::bson::{doc, Document};
+use crate::db::{DbConnection, TokenCollections};
+use crate::db::account_transactions::clear_account_transactions;
+use crate::db::balances::calculate_all_balances;
+use crate::db::sync_status::{get_sync_status, set_rebuild_pending, update_balance_calculated_index};
+use crate::db::transactions::{get_earliest_transaction_index, get_latest_transaction_index, get_transaction_documents_by_index_range};
+use crate::models::{TokenConfig, Transaction};
+use crate::shutdown;
+use crate::utils::{create_error, get_transaction_account_roles};
+
+/// 每批读取的交易索引跨度
+const REBUILD_CHUNK_SIZE: u64 = 5000;
+
+/// 重建单个代币的派生数据，返回是否完成（收到停止信号时返回 false）
+///
+/// 交易集合保持不变；账户登记、账户-交易边、余额、总供应量和余额异常记录全部按已保存的交易重新生成。
+/// 重建期间不能同时运行该代币的同步任务。
+pub async fn rebuild_derived_data(
+    db_conn: &DbConnection,
+    token: &TokenConfig,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    let token_symbol = &token.symbol;
+    let collections = db_conn.token_collections(token_symbol)
+        .ok_or_else(|| create_error(&format!("没有找到代币 {} 的集合", token_symbol)))?;
+    let status = get_sync_status(&db_conn.sync_status_col, token_symbol).await?
+        .ok_or_else(|| create_error(&format!("{}: 没有同步状态记录，尚未完成初始同步，正常启动即可", token_symbol)))?;
+
+    let started = Instant::now();
+    info!("{}: 开始根据已保存的交易重建派生数据（交易集合保持不变）", token_symbol);
+    set_rebuild_pending(&db_conn.sync_status_col, token_symbol, true).await?;
+
+    // 第一阶段：账户登记和账户-交易边
+    if !rebuild_account_relations(&collections, token_symbol).await? {
+        warn!("{}: 派生数据重建被中断，下次启动将重新执行重建", token_symbol);
+        return Ok(false);
+    }
+
+    // 第二阶段：余额异常记录在余额计算时重新生成
+    let removed = collections.balance_anomalies_col.delete_many(doc! {}, None).await?.deleted_count;
+    info!("{}: 已清空 {} 条余额异常记录", token_symbol, removed);
+
+    // 第三阶段：余额和总供应量
+    info!("{}: 重新计算所有账户余额和总供应量...", token_symbol);
+    let (success, failed) = calculate_all_balances(
+        &collections.accounts_col,
+        &collections.account_tx_col,
+        &collections.tx_col,
+        &collections.balances_col,
+        &collections.total_supply_col,
+        &collections.balance_anomalies_col,
+        token
+    ).await?;
+    if failed > 0 {
+        warn!("{}: {} 个账户的余额计算失败，重建标记保留，下次启动将重新执行重建", token_symbol, failed);
+        return Ok(false);
+    }
+
+    // 余额已包含同步游标之前的全部交易，之后的增量同步从游标继续
+    update_balance_calculated_index(&db_conn.sync_status_col, token_symbol, status.last_synced_index).await?;
+    set_rebuild_pending(&db_conn.sync_status_col, token_symbol, false).await?;
+    info!("{}: 派生数据重建完成: {} 个账户，用时 {:.1} 秒", token_symbol, success, started.elapsed().as_secs_f64());
+    Ok(true)
+}
+
+/// 从已保存的交易重新生成账户登记和账户-交易边，收到停止信号时返回 false
+///
+/// 部分索引时账户文档中的期初余额（opening_balance / opening_pending）无法从交易推导，
+/// 这些账户只清除首末交易索引，其余账户文档直接删除。
+async fn rebuild_account_relations(
+    collections: &TokenCollections,
+    token_symbol: &str,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    info!("{}: 清空账户登记和账户-交易边...", token_symbol);
+    clear_account_transactions(&collections.account_tx_col).await?;
+    collections.accounts_col.delete_many(
+        doc! { "opening_balance": { "$exists": false }, "opening_pending": { "$exists": false } },
+        None
+    ).await?;
+    collections.accounts_col.update_many(
+        doc! {},
+        doc! { "$unset": { "first_tx_index": "", "last_tx_index": "" } },
+        None
+    ).await?;
+
+    let (first, last) = match (
+        get_earliest_transaction_index(&collections.tx_col).await?,
+        get_latest_transaction_index(&collections.tx_col).await?,
+    ) {
+        (Some(first), Some(last)) => (first, last),
+        _ => {
+            info!("{}: 没有已保存的交易，跳过账户关系重建", token_symbol);
+            return Ok(true);
+        }
+    };
+
+    let total = last - first + 1;
+    let started = Instant::now();
+    let mut processed_txs = 0u64;
+    let mut edge_count = 0u64;
+    let mut current = first;
+    info!("{}: 从交易索引 {} 到 {} 重建账户关系", token_symbol, first, last);
+
+    while current <= last {
+        if shutdown::is_requested() {
+            return Ok(false);
+        }
+        let end = (current + REBUILD_CHUNK_SIZE - 1).min(last);
+        let docs = get_transaction_documents_by_index_range(&collections.tx_col, current, end).await?;
+
+        // 本批次内按账户合并首末索引，每个账户只写一次
+        let mut accounts: HashMap<String, (i64, i64)> = HashMap::new();
+        let mut edges: Vec<Document> = Vec::new();
+        for tx_doc in docs.iter() {
+            let index = tx_doc.get_i64("index")?;
+            let tx: Transaction = mongodb::bson::from_document(tx_doc.clone())?;
+            let mut roles = get_transaction_account_roles(&tx);
+            roles.sort();
+            roles.dedup();
+            for (account, role) in roles {
+                if account.trim().is_empty() {
+                    continue;
+                }
+                let range = accounts.entry(account.clone()).or_insert((index, index));
+                range.0 = range.0.min(index);
+                range.1 = range.1.max(index);
+                edges.push(doc! { "account": account, "index": index, "role": role });
+            }
+        }
+
+        for (account, (min_index, max_index)) in accounts {
+            collections.accounts_col.update_one(
+                doc! { "account": &account },
+                doc! {
+                    "$set": { "account": &account },
+                    "$min": { "first_tx_index": min_index },
+                    "$max": { "last_tx_index": max_index }
+                },
+                mongodb::options::UpdateOptions::builder().upsert(true).build()
+            ).await?;
+        }
+        edge_count += edges.len() as u64;
+        if !edges.is_empty() {
+            collections.account_tx_col.insert_many(edges, None).await?;
+        }
+
+        processed_txs += docs.len() as u64;
+        let done = end - first + 1;
+        let elapsed = started.elapsed().as_secs_f64();
+        let rate = if elapsed > 0.0 { done as f64 / elapsed } else { 0.0 };
+        let remaining = if rate > 0.0 { (total - done) as f64 / rate } else { 0.0 };
+        info!("{}: 重建账户关系 {}/{} ({:.1}%)，交易 {} 笔，边 {} 条，{:.0} 索引/秒，预计剩余 {:.0} 秒",
+              token_symbol, done, total, done as f64 * 100.0 / total as f64,
+              processed_txs, edge_count, rate, remaining);
+
+        current = end + 1;
+    }
+
+    let account_count = collections.accounts_col.count_documents(doc! {}, None).await?;
+    info!("{}: 账户关系重建完成: {} 笔交易，{} 个账户，{} 条边", token_symbol, processed_txs, account_count, edge_count);
+    Ok(true)
+}
@@ -0,0 +1,220 @@
+/**
+ * 文件描述: 自适应同步调度模块，根据账本动态决定下一次增量同步的等待时间
+ * 功能概述:
+ * - 追赶模式: 一轮同步拿到整批交易，说明链上积压较多，立即开始下一轮
+ * - 跟随模式: 链上有新交易但已追平，按 poll_interval_secs 轮询
+ * - 空闲模式: 没有新交易时逐步加倍等待时间，最长 max_poll_interval_secs
+ * - 退避模式: 同步出错时按带抖动的指数退避等待，最长 max_error_backoff_secs
+ *
+ * 主要组件:
+ * - SchedulerMode枚举: 调度模式
+ * - SyncScheduler结构体: 单个代币的调度状态，由同步任务持有
+ *
+ * 调度状态写入 sync_status 文档的 scheduler 字段，便于观察每个代币当前的轮询节奏。
+ */
+
+use std::time::Duration;
+use chrono::Utc;
+use mongodb::bson::{doc, Document};
+use rand::Rng;
+use crate::models::{TokenConfig, BATCH_SIZE};
+
+/// 默认跟随模式轮询间隔（秒）
+const DEFAULT_POLL_INTERVAL_SECS: u64 = 3;
+/// 默认空闲模式最长轮询间隔（秒）
+const DEFAULT_MAX_POLL_INTERVAL_SECS: u64 = 60;
+/// 默认错误退避基数（秒）
+const DEFAULT_ERROR_BACKOFF_SECS: u64 = 5;
+/// 默认错误退避上限（秒）
+const DEFAULT_MAX_ERROR_BACKOFF_SECS: u64 = 300;
+
+/// 调度模式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SchedulerMode {
+    CatchUp,
+    Following,
+    Idle,
+    Backoff,
+}
+
+impl SchedulerMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SchedulerMode::CatchUp => "catch_up",
+            SchedulerMode::Following => "following",
+            SchedulerMode::Idle => "idle",
+            SchedulerMode::Backoff => "backoff",
+        }
+    }
+}
+
+/// 单个代币的同步调度状态
+pub struct SyncScheduler {
+    poll_interval: Duration,
+    max_poll_interval: Duration,
+    backoff_base: Duration,
+    backoff_max: Duration,
+    mode: SchedulerMode,
+    interval: Duration,
+    consecutive_errors: u32,
+    last_error: Option<String>,
+}
+
+impl SyncScheduler {
+    pub fn new(token: &TokenConfig) -> Self {
+        let poll_interval = Duration::from_secs(token.poll_interval_secs.unwrap_or(DEFAULT_POLL_INTERVAL_SECS));
+        let max_poll_interval = Duration::from_secs(token.max_poll_interval_secs.unwrap_or(DEFAULT_MAX_POLL_INTERVAL_SECS))
+            .max(poll_interval);
+        SyncScheduler {
+            poll_interval,
+            max_poll_interval,
+            backoff_base: Duration::from_secs(token.error_backoff_secs.unwrap_or(DEFAULT_ERROR_BACKOFF_SECS)),
+            backoff_max: Duration::from_secs(token.max_error_backoff_secs.unwrap_or(DEFAULT_MAX_ERROR_BACKOFF_SECS)),
+            mode: SchedulerMode::Following,
+            interval: poll_interval,
+            consecutive_errors: 0,
+            last_error: None,
+        }
+    }
+
+    /// 配置热更新后应用新的轮询和退避参数，保留当前调度模式和错误计数
+    pub fn reconfigure(&mut self, token: &TokenConfig) {
+        let updated = SyncScheduler::new(token);
+        self.poll_interval = updated.poll_interval;
+        self.max_poll_interval = updated.max_poll_interval;
+        self.backoff_base = updated.backoff_base;
+        self.backoff_max = updated.backoff_max;
+        self.interval = match self.mode {
+            SchedulerMode::Backoff => self.interval.min(self.backoff_max),
+            _ => self.interval.clamp(self.poll_interval, self.max_poll_interval),
+        };
+    }
+
+    pub fn mode(&self) -> SchedulerMode {
+        self.mode
+    }
+
+    pub fn consecutive_errors(&self) -> u32 {
+        self.consecutive_errors
+    }
+
+    /// 一轮同步成功后计算等待时间
+    pub fn on_success(&mut self, new_tx_count: usize) -> Duration {
+        self.consecutive_errors = 0;
+        self.last_error = None;
+
+        if new_tx_count as u64 >= BATCH_SIZE {
+            self.mode = SchedulerMode::CatchUp;
+            self.interval = Duration::ZERO;
+        } else if new_tx_count > 0 {
+            self.mode = SchedulerMode::Following;
+            self.interval = self.poll_interval;
+        } else {
+            // 连续空闲时从轮询间隔开始逐步加倍
+            self.interval = match self.mode {
+                SchedulerMode::Idle => (self.interval * 2).min(self.max_poll_interval),
+                _ => self.poll_interval,
+            };
+            self.mode = SchedulerMode::Idle;
+        }
+        self.interval
+    }
+
+    /// 一轮同步失败后计算等待时间
+    ///
+    /// 第 n 次连续错误的退避上界为 base * 2^(n-1)（不超过上限），实际等待在上界的一半到上界之间随机取值，
+    /// 避免多个代币同时出错后在同一时刻集中重试。
+    pub fn on_error(&mut self, error: &str) -> Duration {
+        self.consecutive_errors += 1;
+        self.last_error = Some(error.to_string());
+        self.mode = SchedulerMode::Backoff;
+
+        let factor = 1u32 << self.consecutive_errors.saturating_sub(1).min(16);
+        let ceiling = self.backoff_base.saturating_mul(factor).min(self.backoff_max);
+        let ceiling_ms = ceiling.as_millis() as u64;
+        let jittered_ms = if ceiling_ms > 1 {
+            rand::thread_rng().gen_range(ceiling_ms / 2..=ceiling_ms)
+        } else {
+            ceiling_ms
+        };
+        self.interval = Duration::from_millis(jittered_ms);
+        self.interval
+    }
+
+    /// 写入 sync_status 的调度状态
+    pub fn status_doc(&self) -> Document {
+        let now = Utc::now().timestamp_millis();
+        doc! {
+            "mode": self.mode.as_str(),
+            "interval_ms": self.interval.as_millis() as i64,
+            "consecutive_errors": self.consecutive_errors as i64,
+            "last_error": self.last_error.clone(),
+            "last_poll_at": now / 1000,
+            "next_poll_at": (now + self.interval.as_millis() as i64) / 1000,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scheduler(poll: u64, max_poll: u64, backoff: u64, max_backoff: u64) -> SyncScheduler {
+        let token: TokenConfig = serde_json::from_value(serde_json::json!({
+            "symbol": "TEST",
+            "name": "Test",
+            "canister_id": "ryjl3-tyaaa-aaaaa-aaaba-cai",
+            "poll_interval_secs": poll,
+            "max_poll_interval_secs": max_poll,
+            "error_backoff_secs": backoff,
+            "max_error_backoff_secs": max_backoff,
+        })).unwrap();
+        SyncScheduler::new(&token)
+    }
+
+    #[test]
+    fn full_batch_catches_up_and_partial_batch_follows() {
+        let mut scheduler = scheduler(3, 60, 5, 300);
+        assert_eq!(scheduler.on_success(BATCH_SIZE as usize), Duration::ZERO);
+        assert_eq!(scheduler.mode(), SchedulerMode::CatchUp);
+        assert_eq!(scheduler.on_success(1), Duration::from_secs(3));
+        assert_eq!(scheduler.mode(), SchedulerMode::Following);
+    }
+
+    #[test]
+    fn idle_interval_doubles_up_to_the_maximum() {
+        let mut scheduler = scheduler(3, 20, 5, 300);
+        let waits: Vec<u64> = (0..6).map(|_| scheduler.on_success(0).as_secs()).collect();
+        assert_eq!(waits, vec![3, 6, 12, 20, 20, 20]);
+        assert_eq!(scheduler.mode(), SchedulerMode::Idle);
+
+        // 出现新交易后回到轮询间隔，再次空闲时重新从轮询间隔开始加倍
+        assert_eq!(scheduler.on_success(5), Duration::from_secs(3));
+        assert_eq!(scheduler.on_success(0), Duration::from_secs(3));
+        assert_eq!(scheduler.on_success(0), Duration::from_secs(6));
+    }
+
+    #[test]
+    fn error_backoff_grows_exponentially_within_jitter_bounds() {
+        let mut scheduler = scheduler(3, 60, 5, 300);
+        for n in 1..=12u32 {
+            let ceiling = (5_000u64 << (n - 1)).min(300_000);
+            let wait = scheduler.on_error("boom").as_millis() as u64;
+            assert!(wait >= ceiling / 2 && wait <= ceiling, "第 {} 次错误等待 {}ms，应在 {}..={}ms", n, wait, ceiling / 2, ceiling);
+            assert_eq!(scheduler.consecutive_errors(), n);
+            assert_eq!(scheduler.mode(), SchedulerMode::Backoff);
+        }
+    }
+
+    #[test]
+    fn success_resets_error_backoff() {
+        let mut scheduler = scheduler(3, 60, 5, 300);
+        for _ in 0..5 {
+            scheduler.on_error("boom");
+        }
+        scheduler.on_success(1);
+        assert_eq!(scheduler.consecutive_errors(), 0);
+        let wait = scheduler.on_error("boom").as_millis() as u64;
+        assert!((2_500..=5_000).contains(&wait));
+    }
+}
@@ -0,0 +1,126 @@
+/**
+ * 文件描述: 交易数据校验模块，将数据库中保存的交易与链上数据逐笔比对
+ * 功能概述:
+ * - 按索引所在位置从归档或主账本拉取指定区间的交易（见 gaps::fetch_range）
+ * - 与数据库（含冷存储）中的交易比对，记录缺失、不一致和链上不存在的索引
+ * - 按批次输出进度；收到停止信号时在批次之间中断
+ *
+ * 主要组件:
+ * - VerifyReport结构体: 校验结果
+ * - verify_range函数: 校验单个代币指定区间的交易
+ */
+
+use std::collections::HashMap;
+use std::error::Error;
+use ic_agent::Agent;
+use log::{info, warn};
+use mongodb::bson::to_bson;
+use crate::config::parse_canister_id;
+use crate::db::DbConnection;
+use crate::db::transactions::get_transaction_documents_by_index_range;
+use crate::models::{TokenConfig, Transaction};
+use crate::shutdown;
+use crate::sync::gaps::{fetch_range, load_archive_ranges};
+use crate::utils::create_error;
+
+/// 每批比对的交易索引跨度
+const VERIFY_CHUNK_SIZE: u64 = 2000;
+/// 报告中每类问题最多列出的索引数量
+const MAX_REPORTED_INDICES: usize = 100;
+
+/// 校验结果
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    /// 已比对的交易数量
+    pub checked: u64,
+    /// 链上存在、数据库中缺失的交易数量
+    pub missing: u64,
+    /// 内容与链上不一致的交易数量
+    pub mismatched: u64,
+    /// 数据库中存在、链上返回结果中没有的交易数量
+    pub unexpected: u64,
+    /// 有问题的索引（每类最多 MAX_REPORTED_INDICES 个）
+    pub missing_indices: Vec<u64>,
+    pub mismatched_indices: Vec<u64>,
+    pub unexpected_indices: Vec<u64>,
+    /// 收到停止信号，未比对完整个区间
+    pub interrupted: bool,
+}
+
+impl VerifyReport {
+    /// 是否全部一致
+    pub fn is_consistent(&self) -> bool {
+        self.missing == 0 && self.mismatched == 0 && self.unexpected == 0
+    }
+}
+
+/// 记录一个问题索引，超过上限时只计数
+fn record(count: &mut u64, indices: &mut Vec<u64>, index: u64) {
+    *count += 1;
+    if indices.len() < MAX_REPORTED_INDICES {
+        indices.push(index);
+    }
+}
+
+/// 校验代币 [start, end] 区间内数据库中的交易与链上是否一致
+pub async fn verify_range(
+    agent: &Agent,
+    db_conn: &DbConnection,
+    token: &TokenConfig,
+    start: u64,
+    end: u64,
+) -> Result<VerifyReport, Box<dyn Error + Send + Sync>> {
+    let token_symbol = &token.symbol;
+    let collections = db_conn.token_collections(token_symbol)
+        .ok_or_else(|| create_error(&format!("没有找到代币 {} 的集合", token_symbol)))?;
+    let canister_id = parse_canister_id(&token.canister_id)?;
+    let archives = load_archive_ranges(agent, &canister_id).await?;
+
+    info!("{}: 开始校验交易索引 {} 到 {}", token_symbol, start, end);
+    let mut report = VerifyReport::default();
+    let mut current = start;
+
+    while current <= end {
+        if shutdown::is_requested() {
+            warn!("{}: 收到停止信号，校验在索引 {} 处中断", token_symbol, current);
+            report.interrupted = true;
+            break;
+        }
+        let chunk_end = (current + VERIFY_CHUNK_SIZE - 1).min(end);
+
+        let chain: Vec<Transaction> = fetch_range(agent, &canister_id, &archives, current, chunk_end).await?;
+        let mut stored: HashMap<u64, Transaction> = HashMap::new();
+        for tx_doc in get_transaction_documents_by_index_range(&collections.tx_col, current, chunk_end).await? {
+            let index = tx_doc.get_i64("index")? as u64;
+            stored.insert(index, mongodb::bson::from_document(tx_doc)?);
+        }
+
+        for chain_tx in &chain {
+            let index = match chain_tx.index {
+                Some(index) => index,
+                None => continue,
+            };
+            report.checked += 1;
+            match stored.remove(&index) {
+                None => record(&mut report.missing, &mut report.missing_indices, index),
+                Some(stored_tx) => {
+                    if to_bson(&stored_tx)? != to_bson(chain_tx)? {
+                        record(&mut report.mismatched, &mut report.mismatched_indices, index);
+                    }
+                },
+            }
+        }
+        let mut unexpected: Vec<u64> = stored.into_keys().collect();
+        unexpected.sort_unstable();
+        for index in unexpected {
+            record(&mut report.unexpected, &mut report.unexpected_indices, index);
+        }
+
+        info!("{}: 已校验到索引 {} ({:.1}%)，缺失 {}，不一致 {}，链上不存在 {}",
+              token_symbol, chunk_end, (chunk_end - start + 1) as f64 * 100.0 / (end - start + 1) as f64,
+              report.missing, report.mismatched, report.unexpected);
+        current = chunk_end + 1;
+    }
+
+    Ok(report)
+}
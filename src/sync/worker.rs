@@ -0,0 +1,479 @@
+/**
+ * 文件描述: 代币同步任务模块，每个代币在独立的 Tokio 任务中执行增量同步
+ * 功能概述:
+ * - 为每个代币启动一个受监督的同步任务，互不阻塞；初始同步同样在各自的任务中执行，失败时按重启策略重试
+ * - 每个代币使用自己的调度器决定轮询节奏和错误退避（见 scheduler 模块），以及自己的重启策略
+ * - 所有任务共享同一个 IC Agent 和数据库连接池
+ * - 任务可通过停止信号结束（暂停或移除代币时使用，见 manager 模块），不影响其他代币
+ * - 配置热更新后，同步任务在下一轮开始前应用新的轮询间隔等代币配置，不中断同步
+ * - 停止时补齐余额计算进度并记录正常停止标记，下次启动无需推测同步状态是否一致
+ * - 多实例部署时只在持有代币租约期间同步（见 db::leases），未持有时作为热备定期尝试接管
+ * - 每轮同步和热备等待前读取代币注册表，代币被其他实例暂停或移除后停止同步并释放租约
+ *
+ * 主要组件:
+ * - supervise_token函数: 监督单个代币的同步任务，任务退出或 panic 时按重启策略重启
+ * - run_token_worker函数: 单个代币的增量同步循环
+ * - catch_up_pending_balances函数: 补算已同步但尚未计入余额的交易区间
+ * - wait_for_lease函数: 热备等待，接管租约后校验同步状态再开始同步
+ * - is_registry_active函数: 代币在注册表中是否仍处于同步状态
+ * - checkpoint_on_stop函数: 停止前写入余额计算进度和正常停止标记
+ * - MaintenanceTimers结构体: 记录保留策略和缺口扫描等周期任务的上次执行时间
+ */
+
+use std::any::Any;
+use std::error::Error;
+use std::panic::AssertUnwindSafe;
+use std::time::Instant;
+use futures::FutureExt;
+use ic_agent::Agent;
+use tokio::sync::watch;
+use tokio::time::Duration;
+use log::{info, error, warn, debug};
+use crate::config::{parse_canister_id, get_token_decimals};
+use crate::db::{DbConnection, TokenCollections};
+use crate::db::batch::commit_pending_balances;
+use crate::db::cold_storage::run_retention;
+use crate::db::leases;
+use crate::db::registry::get_registry_entry;
+use crate::db::sync_status::{get_sync_status, mark_clean_shutdown, update_scheduler_status};
+use crate::db::transactions::get_transactions_by_index_range;
+use crate::models::{RestartPolicy, TokenConfig, TokenStatus, BATCH_SIZE};
+use crate::sync::admin::prepare_token_sync;
+use crate::sync::ledger::sync_ledger_transactions;
+use crate::sync::scheduler::SyncScheduler;
+use crate::sync::gaps::{scan_gaps, backfill_gaps};
+use crate::sync::partial::seed_opening_balances;
+use crate::db::sync_status::get_partial_start;
+use crate::shutdown;
+use crate::utils::create_error;
+
+/// 默认重启等待时间（秒）
+const DEFAULT_RESTART_DELAY_SECS: u64 = 10;
+/// 保留策略执行间隔
+const RETENTION_INTERVAL: Duration = Duration::from_secs(3600);
+/// 默认缺口扫描间隔（秒）
+const DEFAULT_GAP_SCAN_INTERVAL_SECS: u64 = 3600;
+/// 同步任务连续运行超过该时间后退出，视为稳定运行过，重启计数从零开始
+const STABLE_RUN_DURATION: Duration = Duration::from_secs(600);
+/// 读取代币注册表失败时的尝试次数
+const REGISTRY_READ_ATTEMPTS: u32 = 3;
+/// 读取代币注册表失败后的重试间隔
+const REGISTRY_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// 同步任务中周期执行的维护任务的上次执行时间
+#[derive(Default)]
+struct MaintenanceTimers {
+    retention: Option<Instant>,
+    gap_scan: Option<Instant>,
+}
+
+impl MaintenanceTimers {
+    /// 判断任务是否到期，到期时记录本次执行时间；从未执行过的任务视为到期
+    fn due(last_run: &mut Option<Instant>, interval: Duration) -> bool {
+        let due = last_run.map(|t| t.elapsed() >= interval).unwrap_or(true);
+        if due {
+            *last_run = Some(Instant::now());
+        }
+        due
+    }
+}
+
+/// 监督单个代币的同步任务
+///
+/// 同步任务的 panic 被捕获后按重启策略处理，不会影响监督任务和其他代币。
+/// 收到停止信号后同步任务在当前一轮结束时退出，监督任务不再重启。
+/// 重启策略和重启等待时间每次按最新的代币配置计算。
+/// max_restarts 限制的是连续重启次数：任务稳定运行 STABLE_RUN_DURATION 以上再退出时重新计数。
+pub async fn supervise_token(
+    agent: Agent,
+    db_conn: DbConnection,
+    config: watch::Receiver<TokenConfig>,
+    mut stop: watch::Receiver<bool>,
+) {
+    let mut restarts = 0u32;
+    let mut registry_active = true;
+
+    loop {
+        // 同步任务在监督任务内运行（不另起任务），强制终止监督任务时同步任务随之结束
+        let started_at = Instant::now();
+        let worker_result = AssertUnwindSafe(run_token_worker(&agent, &db_conn, config.clone(), stop.clone()))
+            .catch_unwind()
+            .await;
+
+        let token = config.borrow().clone();
+        let policy = token.restart_policy.clone().unwrap_or_default();
+        let restart_delay = Duration::from_secs(token.restart_delay_secs.unwrap_or(DEFAULT_RESTART_DELAY_SECS));
+
+        let failed = match worker_result {
+            Ok(Ok(())) => {
+                info!("{}: 同步任务已退出", token.symbol);
+                false
+            },
+            Ok(Err(e)) => {
+                error!("{}: 同步任务出错退出: {}", token.symbol, e);
+                true
+            },
+            Err(panic) => {
+                error!("{}: 同步任务异常终止: {}", token.symbol, panic_message(panic.as_ref()));
+                true
+            },
+        };
+
+        if *stop.borrow() {
+            info!("{}: 同步任务已停止", token.symbol);
+            return;
+        }
+        if !is_registry_active(&db_conn, &token.symbol, &mut registry_active).await {
+            info!("{}: 代币已在注册表中暂停或移除，不再重启同步任务", token.symbol);
+            return;
+        }
+
+        let restart = match policy {
+            RestartPolicy::Always => true,
+            RestartPolicy::OnFailure => failed,
+            RestartPolicy::Never => false,
+        };
+        if !restart {
+            warn!("{}: 按重启策略 {:?} 不再重启同步任务", token.symbol, policy);
+            return;
+        }
+        if started_at.elapsed() >= STABLE_RUN_DURATION {
+            restarts = 0;
+        }
+        restarts += 1;
+        if let Some(max_restarts) = token.max_restarts {
+            if restarts > max_restarts {
+                error!("{}: 同步任务重启次数超过上限 ({})，停止该代币的同步", token.symbol, max_restarts);
+                return;
+            }
+        }
+
+        warn!("{}: {:?} 后重启同步任务 (连续第 {} 次)", token.symbol, restart_delay, restarts);
+        tokio::select! {
+            _ = tokio::time::sleep(restart_delay) => {},
+            _ = stop.changed() => {
+                info!("{}: 同步任务已停止", token.symbol);
+                return;
+            },
+        }
+    }
+}
+
+/// panic 信息的文本内容
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    panic.downcast_ref::<&str>().map(|message| message.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "未知 panic".to_string())
+}
+
+/// 单个代币的增量同步循环
+///
+/// 开始前执行初始同步或校验同步状态（见 prepare_token_sync），慢或失败的代币不影响其他代币启动。
+/// 配置错误（集合不存在、canister ID 无效）直接返回错误；每轮结束后由调度器决定等待时间，
+/// 等待期间收到停止信号时结束循环，收到配置更新时立即开始下一轮。
+/// 每轮开始前确认代币在注册表中仍处于同步状态（其他实例可能已暂停或移除该代币），
+/// 并确认仍持有代币的租约，未持有时转为热备等待接管。
+/// 循环结束后写入停止检查点并释放租约，再返回。
+async fn run_token_worker(
+    agent: &Agent,
+    db_conn: &DbConnection,
+    mut config: watch::Receiver<TokenConfig>,
+    mut stop: watch::Receiver<bool>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut token = config.borrow_and_update().clone();
+    let collections = db_conn.token_collections(&token.symbol)
+        .ok_or_else(|| format!("{}: 没有找到代币的集合", token.symbol))?;
+    let canister_id = parse_canister_id(&token.canister_id)?;
+
+    let mut scheduler = SyncScheduler::new(&token);
+    let mut timers = MaintenanceTimers::default();
+    let mut registry_active = true;
+
+    info!("{}: 同步任务已启动", token.symbol);
+
+    // 持有租约时先执行初始同步或校验同步状态，失败时由监督任务按重启策略重试；
+    // 其他实例持有租约时跳过，由热备等待在接管后执行
+    match leases::acquire(&db_conn.lease_col, &token.symbol).await {
+        Ok(true) => {
+            tokio::select! {
+                result = prepare_token_sync(agent, db_conn, &token) => result?,
+                _ = stop.changed() => {
+                    info!("{}: 初始同步被中止", token.symbol);
+                    if let Err(e) = leases::release(&db_conn.lease_col, &token.symbol).await {
+                        warn!("{}: 释放同步租约失败，其他实例将在租约过期后接管: {}", token.symbol, e);
+                    }
+                    return Ok(());
+                },
+            }
+        },
+        Ok(false) => info!("{}: 同步租约由其他实例持有，本实例作为热备", token.symbol),
+        Err(e) => warn!("{}: 获取同步租约失败，稍后重试: {}", token.symbol, e),
+    }
+
+    while !*stop.borrow() {
+        let latest = config.borrow_and_update().clone();
+        if latest != token {
+            token = latest;
+            scheduler.reconfigure(&token);
+            info!("{}: 已应用更新后的代币配置", token.symbol);
+        }
+
+        if !is_registry_active(db_conn, &token.symbol, &mut registry_active).await {
+            info!("{}: 代币已在注册表中暂停或移除，停止同步", token.symbol);
+            break;
+        }
+
+        if !leases::is_held(&token.symbol) {
+            if !wait_for_lease(agent, db_conn, &token, &mut stop, &mut registry_active).await? {
+                break;
+            }
+            continue;
+        }
+
+        let wait = match sync_once(agent, &canister_id, db_conn, &collections, &token, &mut timers).await {
+            Ok(new_tx_count) => {
+                let wait = scheduler.on_success(new_tx_count);
+                debug!("{}: 调度模式 {}，{:?} 后进行下一轮同步", token.symbol, scheduler.mode().as_str(), wait);
+                wait
+            },
+            Err(e) => {
+                let wait = scheduler.on_error(&e.to_string());
+                error!("{}: 增量同步出错 (连续第 {} 次)，{:?} 后重试: {}",
+                    token.symbol, scheduler.consecutive_errors(), wait, e);
+                wait
+            },
+        };
+        if let Err(e) = update_scheduler_status(&db_conn.sync_status_col, &token.symbol, scheduler.status_doc()).await {
+            debug!("{}: 保存调度状态失败: {}", token.symbol, e);
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {},
+            Ok(()) = config.changed() => {},
+            changed = stop.changed() => {
+                // 发送端被丢弃时同样视为停止
+                if changed.is_err() {
+                    break;
+                }
+            },
+        }
+    }
+
+    // 未持有租约时同步状态由其他实例维护，不写入检查点
+    if leases::is_held(&token.symbol) {
+        checkpoint_on_stop(db_conn, &collections, &token).await;
+        if let Err(e) = leases::release(&db_conn.lease_col, &token.symbol).await {
+            warn!("{}: 释放同步租约失败，其他实例将在租约过期后接管: {}", token.symbol, e);
+        }
+    }
+    Ok(())
+}
+
+/// 热备等待：按续期间隔尝试获取代币的租约，收到停止信号或代币已不处于同步状态时返回 false
+///
+/// 接管租约后，上一个持有者可能在批次中途退出，先校验同步状态（必要时继续初始同步）再开始增量同步。
+async fn wait_for_lease(
+    agent: &Agent,
+    db_conn: &DbConnection,
+    token: &TokenConfig,
+    stop: &mut watch::Receiver<bool>,
+    registry_active: &mut bool,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    let mut logged = false;
+    loop {
+        if *stop.borrow() {
+            return Ok(false);
+        }
+        if !is_registry_active(db_conn, &token.symbol, registry_active).await {
+            info!("{}: 代币已在注册表中暂停或移除，停止热备等待", token.symbol);
+            return Ok(false);
+        }
+        match leases::acquire(&db_conn.lease_col, &token.symbol).await {
+            Ok(true) => break,
+            Ok(false) => {
+                if !logged {
+                    let holder = leases::get_lease(&db_conn.lease_col, &token.symbol).await.ok().flatten()
+                        .and_then(|lease| lease.get_str("holder").ok().map(|h| h.to_string()))
+                        .unwrap_or_else(|| "-".to_string());
+                    info!("{}: 同步租约由实例 {} 持有，本实例作为热备等待接管", token.symbol, holder);
+                    logged = true;
+                }
+            },
+            Err(e) => warn!("{}: 获取同步租约失败: {}", token.symbol, e),
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(leases::renew_interval()) => {},
+            changed = stop.changed() => {
+                if changed.is_err() {
+                    return Ok(false);
+                }
+            },
+        }
+    }
+
+    tokio::select! {
+        result = prepare_token_sync(agent, db_conn, token) => result?,
+        _ = stop.changed() => return Ok(false),
+    }
+    Ok(!*stop.borrow())
+}
+
+/// 代币在注册表中是否仍处于同步状态
+///
+/// 暂停和移除可能由其他实例执行，只有注册表记录是各实例共享的。
+/// 读取失败时短暂等待后重试，仍然失败时沿用上次读取到的状态（`last_known`），
+/// 既不会因为数据库抖动停止同步，也不会让已被暂停的代币在读取失败期间继续同步。
+async fn is_registry_active(db_conn: &DbConnection, token_symbol: &str, last_known: &mut bool) -> bool {
+    for attempt in 1..=REGISTRY_READ_ATTEMPTS {
+        match get_registry_entry(&db_conn.registry_col, token_symbol).await {
+            Ok(entry) => {
+                *last_known = entry.is_some_and(|entry| entry.status == TokenStatus::Active);
+                return *last_known;
+            },
+            Err(e) => {
+                warn!("{}: 读取代币注册表失败 ({}/{}): {}", token_symbol, attempt, REGISTRY_READ_ATTEMPTS, e);
+                if attempt < REGISTRY_READ_ATTEMPTS {
+                    tokio::time::sleep(REGISTRY_RETRY_DELAY).await;
+                }
+            },
+        }
+    }
+    warn!("{}: 无法读取代币注册表，沿用上次读取到的状态 ({})", token_symbol,
+        if *last_known { "同步中" } else { "已暂停或移除" });
+    *last_known
+}
+
+/// 停止前写入检查点
+///
+/// 已提交的批次均已包含同步游标，这里补齐落后的余额计算进度；
+/// 余额进度追平同步游标后记录正常停止标记，否则下次启动时按原流程校验同步状态。
+async fn checkpoint_on_stop(
+    db_conn: &DbConnection,
+    collections: &TokenCollections,
+    token: &TokenConfig,
+) {
+    if let Err(e) = catch_up_pending_balances(db_conn, collections, token).await {
+        warn!("{}: 停止前补算余额失败: {}", token.symbol, e);
+    }
+
+    match get_sync_status(&db_conn.sync_status_col, &token.symbol).await {
+        Ok(Some(status)) if status.pending_balance_start().is_none() => {
+            if let Err(e) = mark_clean_shutdown(&db_conn.sync_status_col, &token.symbol, status.last_synced_index).await {
+                warn!("{}: 记录正常停止标记失败: {}", token.symbol, e);
+            }
+        },
+        Ok(Some(status)) => {
+            warn!("{}: 余额计算进度 ({:?}) 落后于同步游标 ({})，下次启动时将校验同步状态",
+                token.symbol, status.last_balance_calculated_index, status.last_synced_index);
+        },
+        Ok(None) => {},
+        Err(e) => warn!("{}: 读取同步状态失败，未记录正常停止标记: {}", token.symbol, e),
+    }
+}
+
+/// 执行一轮增量同步
+async fn sync_once(
+    agent: &Agent,
+    canister_id: &ic_agent::export::Principal,
+    db_conn: &DbConnection,
+    collections: &TokenCollections,
+    token: &TokenConfig,
+    timers: &mut MaintenanceTimers,
+) -> Result<usize, Box<dyn Error + Send + Sync>> {
+    debug!("{}: 执行定时增量同步...", token.symbol);
+
+    // 配置中未指定小数位时从 canister 查询（只用于校验 canister 可访问）
+    if token.decimals.is_none() {
+        get_token_decimals(agent, canister_id, &token.symbol).await
+            .map_err(|e| create_error(&format!("获取代币小数位失败: {}", e)))?;
+    }
+
+    // 在进行增量同步前，检查是否存在尚未计算余额的已同步交易；补算失败时本轮不再同步新交易
+    catch_up_pending_balances(db_conn, collections, token).await?;
+
+    // 增量同步交易数据，余额增量随每个批次一并提交
+    let new_transactions = sync_ledger_transactions(agent, canister_id, db_conn, token, true).await?;
+
+    // 余额计算进度落后时（例如补算失败），批次中不会计入余额，在这里补算
+    catch_up_pending_balances(db_conn, collections, token).await?;
+
+    // 收到停止信号后跳过期初余额、保留策略和缺口回填等耗时的维护任务
+    if shutdown::is_requested() {
+        return Ok(new_transactions.len());
+    }
+
+    // 部分索引时为新出现的账户补写期初余额
+    if let Some(start) = get_partial_start(&db_conn.sync_status_col, &token.symbol).await? {
+        if let Err(e) = seed_opening_balances(agent, canister_id, db_conn, collections, token, &start).await {
+            warn!("{}: 写入期初余额失败: {}", token.symbol, e);
+        }
+    }
+
+    // 按保留策略归档旧交易，每个代币每小时最多执行一次
+    if let Some(retention) = &token.retention {
+        if MaintenanceTimers::due(&mut timers.retention, RETENTION_INTERVAL) {
+            match run_retention(&collections.tx_col, &db_conn.sync_status_col, token, retention).await {
+                Ok(0) => debug!("{}: 没有需要归档的交易", token.symbol),
+                Ok(count) => info!("{}: 已归档 {} 笔旧交易到冷存储", token.symbol, count),
+                Err(e) => warn!("{}: 执行保留策略失败: {}", token.symbol, e),
+            }
+        }
+    }
+
+    // 定期扫描交易索引缺口，任务启动后的第一轮即执行一次
+    let gap_scan_interval = Duration::from_secs(token.gap_scan_interval_secs.unwrap_or(DEFAULT_GAP_SCAN_INTERVAL_SECS));
+    if MaintenanceTimers::due(&mut timers.gap_scan, gap_scan_interval) {
+        if let Err(e) = scan_gaps(agent, canister_id, db_conn, collections, token).await {
+            warn!("{}: 扫描交易缺口失败: {}", token.symbol, e);
+        }
+    }
+
+    // 回填已记录的缺口，每轮有处理上限，较大的缺口会在后续几轮中继续回填
+    if let Err(e) = backfill_gaps(agent, canister_id, db_conn, collections, token).await {
+        warn!("{}: 回填交易缺口失败: {}", token.symbol, e);
+    }
+
+    if !new_transactions.is_empty() {
+        info!("🏁 代币 {} 增量同步完成，本次同步 {} 笔新交易", token.symbol, new_transactions.len());
+    } else {
+        debug!("{}: 没有新交易", token.symbol);
+    }
+    Ok(new_transactions.len())
+}
+
+/// 补算已同步但尚未计入余额的交易区间
+///
+/// 批次提交在余额计算进度落后时不会计入余额，由这里按 last_balance_calculated_index 补齐。
+/// 区间按 BATCH_SIZE 分段读取和提交，每段通过 commit_pending_balances 提交并推进余额计算进度，
+/// 与批次一样以租约记录为界。任一段失败时返回错误，调用方结束本轮同步，
+/// 避免后续批次在错误的余额上继续累计。
+async fn catch_up_pending_balances(
+    db_conn: &DbConnection,
+    collections: &TokenCollections,
+    token: &TokenConfig,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let status = match get_sync_status(&db_conn.sync_status_col, &token.symbol).await? {
+        Some(status) => status,
+        None => return Ok(()),
+    };
+    let pending_start = match status.pending_balance_start() {
+        Some(start) => start,
+        None => return Ok(()),
+    };
+    let pending_end = status.last_synced_index;
+    info!("{}: 发现未计算余额的交易区间 [{}-{}]，开始补算...", token.symbol, pending_start, pending_end);
+
+    let mut chunk_start = pending_start;
+    while chunk_start <= pending_end {
+        let chunk_end = pending_end.min(chunk_start + BATCH_SIZE - 1);
+        // 区间内没有交易时同样推进进度，避免后续批次一直跳过余额计算
+        let pending_txs = get_transactions_by_index_range(&collections.tx_col, chunk_start, chunk_end).await
+            .map_err(|e| create_error(&format!("查询待补算交易 [{}-{}] 失败: {}", chunk_start, chunk_end, e)))?;
+        commit_pending_balances(db_conn, collections, &token.symbol, &pending_txs, chunk_end).await
+            .map_err(|e| create_error(&format!("补算余额 [{}-{}] 失败: {}", chunk_start, chunk_end, e)))?;
+        debug!("{}: 已补算余额至索引 {}", token.symbol, chunk_end);
+        chunk_start = chunk_end + 1;
+    }
+
+    info!("{}: 补算余额完成，余额计算进度: {}", token.symbol, pending_end);
+    Ok(())
+}
@@ -6,15 +6,23 @@
  * - 创建错误对象
  * 
  * 主要组件:
- * - format_token_amount函数 (第19-44行): 格式化代币金额为人类可读形式，添加小数点
- * - group_transactions_by_account函数 (第46-80行): 将交易按关联账户分组
- * - create_error函数 (第82-85行): 创建标准错误对象
+ * - format_token_amount函数: 格式化代币金额为人类可读形式，添加小数点
+ * - to_sortable_amount函数: 将金额转换为定宽补零字符串，便于范围查询和排序
+ * - parse_token_amount函数: 将以代币单位表示的金额（如 "1000.5"）转换为最小单位
+ * - get_transaction_amount / get_transaction_fee函数: 提取交易的金额和手续费
+ * - get_transaction_account_roles函数: 提取交易涉及的账户及其角色
+ * - parse_account函数: 将账户字符串（principal 或 principal:0x子账户）解析为 Account
+ * - nanos_to_datetime / nanos_to_rfc3339函数: 将纳秒时间戳转换为 UTC 时间和 RFC 3339 字符串
+ * - parse_time函数: 解析纳秒时间戳或 RFC 3339 格式的时间参数
+ * - create_error函数: 创建标准错误对象
  */
 
 use std::error::Error;
-use std::collections::HashMap;
 use candid::Nat;
-use crate::models::{Transaction};
+use chrono::{DateTime, SecondsFormat, Utc};
+use ic_agent::export::Principal;
+use crate::models::{Account, Transaction};
+use crate::db::account_transactions::{ROLE_FROM, ROLE_TO, ROLE_SPENDER};
 
 /// 格式化代币金额为人类可读形式
 pub fn format_token_amount(amount: &Nat, decimals: u8) -> String {
@@ -43,43 +51,212 @@ pub fn format_token_amount(amount: &Nat, decimals: u8) -> String {
     }
 }
 
-/// 将交易按账户分组
-pub fn group_transactions_by_account(transactions: &[Transaction]) -> HashMap<String, Vec<&Transaction>> {
-    let mut map: HashMap<String, Vec<&Transaction>> = HashMap::new();
-    for tx in transactions {
-        // 收集所有相关账户
-        let mut accounts = Vec::new();
-        if let Some(ref transfer) = tx.transfer {
-            accounts.push(transfer.from.to_string());
-            accounts.push(transfer.to.to_string());
-            if let Some(ref spender) = transfer.spender {
-                accounts.push(spender.to_string());
-            }
-        }
-        if let Some(ref mint) = tx.mint {
-            accounts.push(mint.to.to_string());
+/// 可排序金额字符串的宽度，足以容纳 256 位无符号整数（78位十进制）
+pub const SORTABLE_AMOUNT_WIDTH: usize = 78;
+
+/// 将金额转换为定宽补零的十进制字符串
+///
+/// 等宽字符串的字典序与数值大小一致，可直接用于 MongoDB 的范围查询、排序和索引。
+/// 超出宽度的金额（实际账本中不会出现）按最大值处理。
+pub fn to_sortable_amount(amount: &Nat) -> String {
+    let digits = amount.0.to_string();
+    if digits.len() > SORTABLE_AMOUNT_WIDTH {
+        log::warn!("金额 {} 超出可排序表示的范围，按最大值存储", digits);
+        return "9".repeat(SORTABLE_AMOUNT_WIDTH);
+    }
+    format!("{:0>width$}", digits, width = SORTABLE_AMOUNT_WIDTH)
+}
+
+/// 将以代币单位表示的金额转换为最小单位
+///
+/// 例如 decimals 为 8 时 "1000.5" 转换为 100050000000。小数位超过 decimals 时返回错误。
+pub fn parse_token_amount(amount: &str, decimals: u8) -> Result<Nat, Box<dyn Error + Send + Sync>> {
+    let amount = amount.trim();
+    let (integer_part, fraction_part) = match amount.split_once('.') {
+        Some((integer, fraction)) => (integer, fraction),
+        None => (amount, ""),
+    };
+    
+    let is_digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+    if (integer_part.is_empty() && fraction_part.is_empty()) || !is_digits(integer_part) || !is_digits(fraction_part) {
+        return Err(create_error(&format!("无效的金额: {}", amount)));
+    }
+    if fraction_part.len() > decimals as usize {
+        return Err(create_error(&format!("金额 {} 的小数位超过代币精度 {}", amount, decimals)));
+    }
+    
+    let digits = format!(
+        "{}{:0<width$}",
+        if integer_part.is_empty() { "0" } else { integer_part },
+        fraction_part,
+        width = decimals as usize
+    );
+    Nat::parse(digits.as_bytes()).map_err(|e| create_error(&format!("无效的金额 {}: {}", amount, e)))
+}
+
+/// 提取交易金额（转账/铸币/销毁金额或授权额度）
+pub fn get_transaction_amount(tx: &Transaction) -> Option<&Nat> {
+    if let Some(ref transfer) = tx.transfer {
+        return Some(&transfer.amount);
+    }
+    if let Some(ref mint) = tx.mint {
+        return Some(&mint.amount);
+    }
+    if let Some(ref burn) = tx.burn {
+        return Some(&burn.amount);
+    }
+    tx.approve.as_ref().map(|approve| &approve.amount)
+}
+
+/// 提取交易手续费
+pub fn get_transaction_fee(tx: &Transaction) -> Option<&Nat> {
+    if let Some(ref transfer) = tx.transfer {
+        return transfer.fee.as_ref();
+    }
+    tx.approve.as_ref().and_then(|approve| approve.fee.as_ref())
+}
+
+/// 提取交易涉及的账户及其角色，结果按 (账户, 角色) 去重
+pub fn get_transaction_account_roles(tx: &Transaction) -> Vec<(String, &'static str)> {
+    let mut roles = Vec::new();
+    if let Some(ref transfer) = tx.transfer {
+        roles.push((transfer.from.to_string(), ROLE_FROM));
+        roles.push((transfer.to.to_string(), ROLE_TO));
+        if let Some(ref spender) = transfer.spender {
+            roles.push((spender.to_string(), ROLE_SPENDER));
         }
-        if let Some(ref approve) = tx.approve {
-            accounts.push(approve.from.to_string());
-            accounts.push(approve.spender.to_string());
+    }
+    if let Some(ref mint) = tx.mint {
+        roles.push((mint.to.to_string(), ROLE_TO));
+    }
+    if let Some(ref approve) = tx.approve {
+        roles.push((approve.from.to_string(), ROLE_FROM));
+        roles.push((approve.spender.to_string(), ROLE_SPENDER));
+    }
+    if let Some(ref burn) = tx.burn {
+        roles.push((burn.from.to_string(), ROLE_FROM));
+        if let Some(ref spender) = burn.spender {
+            roles.push((spender.to_string(), ROLE_SPENDER));
         }
-        if let Some(ref burn) = tx.burn {
-            accounts.push(burn.from.to_string());
-            if let Some(ref spender) = burn.spender {
-                accounts.push(spender.to_string());
+    }
+    // 去重
+    roles.sort();
+    roles.dedup();
+    roles
+}
+
+/// 将账户字符串解析为 Account
+///
+/// 格式与 Account 的 Display 实现一致：`principal` 或 `principal:0x{64位十六进制子账户}`。
+pub fn parse_account(account: &str) -> Result<Account, Box<dyn Error + Send + Sync>> {
+    let (owner, subaccount) = match account.split_once(':') {
+        Some((owner, sub)) => {
+            let bytes = hex::decode(sub.trim_start_matches("0x"))
+                .map_err(|e| create_error(&format!("无效的子账户 {}: {}", sub, e)))?;
+            if bytes.len() != 32 {
+                return Err(create_error(&format!("子账户长度应为32字节: {}", sub)));
             }
-        }
-        // 去重
-        accounts.sort();
-        accounts.dedup();
-        for acc in accounts {
-            map.entry(acc).or_default().push(tx);
-        }
+            (owner, Some(bytes))
+        },
+        None => (account, None),
+    };
+    let owner = Principal::from_text(owner)
+        .map_err(|e| create_error(&format!("无效的账户 {}: {}", account, e)))?;
+    Ok(Account { owner, subaccount })
+}
+
+/// 将纳秒时间戳转换为 UTC 时间
+///
+/// 交易时间戳、created_at_time 和 expires_at 都是自 Unix 纪元起的纳秒数。
+pub fn nanos_to_datetime(nanos: u64) -> DateTime<Utc> {
+    DateTime::from_timestamp((nanos / 1_000_000_000) as i64, (nanos % 1_000_000_000) as u32)
+        .unwrap_or_default()
+}
+
+/// 将纳秒时间戳格式化为 RFC 3339 字符串（UTC，保留非零的小数秒）
+pub fn nanos_to_rfc3339(nanos: u64) -> String {
+    nanos_to_datetime(nanos).to_rfc3339_opts(SecondsFormat::AutoSi, true)
+}
+
+/// 解析时间参数：纳秒时间戳（如 `1716342900000000000`）或 RFC 3339 字符串（如 `2024-05-22T01:55:00Z`）
+pub fn parse_time(value: &str) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    let value = value.trim();
+    if let Ok(nanos) = value.parse::<u64>() {
+        return Ok(nanos);
     }
-    map
+    let time = DateTime::parse_from_rfc3339(value)
+        .map_err(|_| create_error(&format!("无效的时间: {}，应为纳秒时间戳或 RFC 3339 格式", value)))?;
+    time.timestamp_nanos_opt()
+        .and_then(|nanos| u64::try_from(nanos).ok())
+        .ok_or_else(|| create_error(&format!("时间超出范围: {}", value)))
 }
 
 /// 创建错误
-pub fn create_error(message: &str) -> Box<dyn Error> {
-    Box::new(std::io::Error::new(std::io::ErrorKind::Other, message))
+pub fn create_error(message: &str) -> Box<dyn Error + Send + Sync> {
+    Box::new(std::io::Error::other(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nat(value: &str) -> Nat {
+        Nat::parse(value.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn sortable_amount_is_fixed_width() {
+        assert_eq!(to_sortable_amount(&nat("0")), "0".repeat(SORTABLE_AMOUNT_WIDTH));
+        assert_eq!(to_sortable_amount(&nat("42")).len(), SORTABLE_AMOUNT_WIDTH);
+        assert!(to_sortable_amount(&nat("42")).ends_with("00042"));
+    }
+
+    #[test]
+    fn sortable_amount_order_matches_numeric_order() {
+        // u64 边界、u128 边界附近以及 256 位最大值（78 位十进制）
+        let max_u256 = "115792089237316195423570985008687907853269984665640564039457584007913129639935";
+        assert_eq!(max_u256.len(), SORTABLE_AMOUNT_WIDTH);
+        let values = [
+            "0", "9", "10", "99999999", "100000000",
+            "18446744073709551615", "18446744073709551616",
+            "340282366920938463463374607431768211455", "340282366920938463463374607431768211456",
+            max_u256,
+        ];
+        let sortable: Vec<String> = values.iter().map(|v| to_sortable_amount(&nat(v))).collect();
+        for pair in sortable.windows(2) {
+            assert!(pair[0] < pair[1], "{} 应小于 {}", pair[0], pair[1]);
+        }
+        assert_eq!(sortable.last().unwrap(), max_u256);
+    }
+
+    #[test]
+    fn sortable_amount_clamps_values_wider_than_78_digits() {
+        let too_wide = format!("1{}", "0".repeat(SORTABLE_AMOUNT_WIDTH));
+        assert_eq!(to_sortable_amount(&nat(&too_wide)), "9".repeat(SORTABLE_AMOUNT_WIDTH));
+    }
+
+    #[test]
+    fn nanos_format_as_rfc3339() {
+        assert_eq!(nanos_to_rfc3339(1_716_342_900_000_000_000), "2024-05-22T01:55:00Z");
+        assert_eq!(nanos_to_rfc3339(1_716_342_900_123_000_000), "2024-05-22T01:55:00.123Z");
+        assert_eq!(nanos_to_rfc3339(0), "1970-01-01T00:00:00Z");
+        assert_eq!(nanos_to_datetime(1_716_342_900_000_000_001).timestamp_subsec_nanos(), 1);
+    }
+
+    #[test]
+    fn parse_time_accepts_nanos_and_rfc3339() {
+        assert_eq!(parse_time("1716342900000000000").unwrap(), 1_716_342_900_000_000_000);
+        assert_eq!(parse_time("2024-05-22T01:55:00Z").unwrap(), 1_716_342_900_000_000_000);
+        assert_eq!(parse_time(" 2024-05-22T09:55:00.5+08:00 ").unwrap(), 1_716_342_900_500_000_000);
+        let nanos = 1_716_342_900_123_456_789;
+        assert_eq!(parse_time(&nanos_to_rfc3339(nanos)).unwrap(), nanos);
+    }
+
+    #[test]
+    fn parse_time_rejects_invalid_and_pre_epoch_times() {
+        assert!(parse_time("1969-12-31T23:59:59Z").is_err());
+        assert!(parse_time("-1").is_err());
+        assert!(parse_time("2024-05-22").is_err());
+        assert!(parse_time("yesterday").is_err());
+    }
 }